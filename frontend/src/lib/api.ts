@@ -11,7 +11,7 @@ export async function login(username: string, password: string) {
 	
 	if (!response.ok) {
 		// Show server message for user errors, generic for server errors
-		if ([400, 401, 404, 409].includes(response.status)) {
+		if ([400, 401, 404, 409, 429].includes(response.status)) {
 			throw new Error(data.error || 'Invalid credentials');
 		} else {
 			throw new Error('Unable to login. Please try again later.');
@@ -21,12 +21,43 @@ export async function login(username: string, password: string) {
 	return data;
 }
 
-export async function signup(username: string, email: string, password: string, bio: string, emailNotifications?: boolean) {
+async function postTwoFactor(path: string, body: object) {
+	const response = await fetch(path, {
+		method: 'POST',
+		headers: { 'Content-Type': 'application/json' },
+		credentials: 'include',
+		body: JSON.stringify(body)
+	});
+
+	const data = await response.json();
+
+	if (!response.ok) {
+		if ([400, 401, 403, 429].includes(response.status)) {
+			throw new Error(data.error || 'Invalid code');
+		} else {
+			throw new Error('Unable to login. Please try again later.');
+		}
+	}
+
+	return data;
+}
+
+// Finish a login with a code from the authenticator app or a recovery code
+export async function loginTwoFactor(challenge: string, code: string) {
+	return postTwoFactor('/api/auth/login/2fa', { challenge, code });
+}
+
+// Enroll an authenticator during login, for accounts whose role requires it
+export async function startTwoFactorSetup(challenge: string) {
+	return postTwoFactor('/api/auth/login/2fa/setup', { challenge });
+}
+
+export async function signup(username: string, email: string, password: string, bio: string, emailNotifications?: boolean, agreementVersion?: number) {
 	const response = await fetch('/api/auth/signup', {
 		method: 'POST',
 		headers: { 'Content-Type': 'application/json' },
 		credentials: 'include',
-		body: JSON.stringify({ username, email, password, bio, email_notifications: emailNotifications || false })
+		body: JSON.stringify({ username, email, password, bio, email_notifications: emailNotifications || false, agreement_version: agreementVersion })
 	});
 	
 	const data = await response.json();
@@ -51,6 +82,18 @@ export async function logout() {
 	return response.json();
 }
 
+export async function refreshSession() {
+	try {
+		const response = await fetch('/api/auth/refresh', {
+			method: 'POST',
+			credentials: 'include'
+		});
+		return response.ok;
+	} catch {
+		return false;
+	}
+}
+
 export async function resetPassword(email: string) {
 	const response = await fetch('/api/auth/reset-password', {
 		method: 'POST',
@@ -58,14 +101,24 @@ export async function resetPassword(email: string) {
 		credentials: 'include',
 		body: JSON.stringify({ email })
 	});
-	return response.json();
+	const data = await response.json();
+	if (response.status === 429) {
+		throw new Error(data.error);
+	}
+	return data;
 }
 
 export async function getCurrentUser() {
 	try {
-		const response = await fetch('/api/auth/me', {
+		let response = await fetch('/api/auth/me', {
 			credentials: 'include'
 		});
+		// The access token is short-lived; try to renew it once before giving up
+		if (response.status === 401 && (await refreshSession())) {
+			response = await fetch('/api/auth/me', {
+				credentials: 'include'
+			});
+		}
 		if (response.ok) {
 			return response.json();
 		}
@@ -1,5 +1,5 @@
 import { writable } from 'svelte/store';
-import { getCurrentUser, logout as apiLogout } from './api.js';
+import { getCurrentUser, logout as apiLogout, refreshSession } from './api.js';
 import { toast } from './toast.js';
 import { goto } from '$app/navigation';
 
@@ -7,10 +7,29 @@ interface User {
 	username: string;
 }
 
+// Renew the access token a few minutes before it expires (tokens last 15 minutes)
+const REFRESH_INTERVAL_MS = 10 * 60 * 1000;
+
 // Auth store to manage user state
 export const user = writable<User | null>(null);
 export const isLoading = writable(true);
 
+let refreshTimer: ReturnType<typeof setInterval> | null = null;
+
+user.subscribe((currentUser) => {
+	if (typeof window === 'undefined') return;
+	if (currentUser && !refreshTimer) {
+		refreshTimer = setInterval(async () => {
+			if (!(await refreshSession())) {
+				user.set(null);
+			}
+		}, REFRESH_INTERVAL_MS);
+	} else if (!currentUser && refreshTimer) {
+		clearInterval(refreshTimer);
+		refreshTimer = null;
+	}
+});
+
 // Check if user is logged in
 export async function checkAuth() {
 	isLoading.set(true);
@@ -5,9 +5,10 @@ export const unreadCount = writable(0);
 export const isNotificationPanelOpen = writable(false);
 
 // API functions
+// Only unread notifications are shown, so only those are fetched
 export async function fetchNotifications() {
     try {
-        const response = await fetch('/api/notifications', {
+        const response = await fetch('/api/notifications?read=false', {
             credentials: 'include'
         });
         
@@ -88,6 +89,30 @@ export async function markAllNotificationsAsRead() {
     }
 }
 
+export async function deleteNotifications(notificationIds) {
+    try {
+        const response = await fetch('/api/notifications/delete', {
+            method: 'POST',
+            headers: {
+                'Content-Type': 'application/json',
+            },
+            credentials: 'include',
+            body: JSON.stringify({ notification_ids: notificationIds })
+        });
+        
+        if (!response.ok) {
+            throw new Error(`HTTP error! status: ${response.status}`);
+        }
+        
+        // Refresh notifications after deleting
+        await fetchNotifications();
+        return response.json();
+    } catch (error) {
+        console.error('Failed to delete notifications:', error);
+        throw error;
+    }
+}
+
 // Helper functions
 export function toggleNotificationPanel() {
     isNotificationPanelOpen.update(open => !open);
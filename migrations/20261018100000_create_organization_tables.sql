@@ -0,0 +1,42 @@
+-- Add organization tables for shared query workspaces
+CREATE TABLE organization (
+	organization_id INTEGER PRIMARY KEY AUTOINCREMENT,
+	name TEXT COLLATE NOCASE NOT NULL UNIQUE CHECK(LENGTH(name) >= 3 AND LENGTH(name) <= 100),
+	query_quota INTEGER DEFAULT NULL CHECK(query_quota IS NULL OR query_quota >= 0),
+	retention_days INTEGER DEFAULT NULL CHECK(retention_days IS NULL OR retention_days > 0),
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE organization_member (
+	organization_id INTEGER NOT NULL,
+	user_id INTEGER NOT NULL,
+	role TEXT NOT NULL CHECK(role IN ('owner', 'member', 'viewer')) DEFAULT 'member',
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (organization_id, user_id),
+	FOREIGN KEY (organization_id) REFERENCES organization(organization_id) ON DELETE CASCADE,
+	FOREIGN KEY (user_id) REFERENCES user(user_id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_organization_member_user_id ON organization_member(user_id);
+
+CREATE TABLE organization_invitation (
+	invitation_id INTEGER PRIMARY KEY AUTOINCREMENT,
+	organization_id INTEGER NOT NULL,
+	email TEXT COLLATE NOCASE NOT NULL,
+	role TEXT NOT NULL CHECK(role IN ('owner', 'member', 'viewer')) DEFAULT 'member',
+	invited_by INTEGER NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	accepted_at TIMESTAMP DEFAULT NULL,
+	FOREIGN KEY (organization_id) REFERENCES organization(organization_id) ON DELETE CASCADE,
+	FOREIGN KEY (invited_by) REFERENCES user(user_id) ON DELETE CASCADE
+);
+
+-- Only one pending invitation per email and organization
+CREATE UNIQUE INDEX idx_organization_invitation_pending
+	ON organization_invitation(organization_id, email) WHERE accepted_at IS NULL;
+
+-- Queries can optionally be owned by an organization workspace
+ALTER TABLE query ADD COLUMN organization_id INTEGER DEFAULT NULL
+	REFERENCES organization(organization_id) ON DELETE SET NULL;
+
+CREATE INDEX idx_query_organization_id ON query(organization_id);
@@ -0,0 +1,3 @@
+-- Add role and disabled state to users
+ALTER TABLE user ADD COLUMN role TEXT NOT NULL DEFAULT 'user' CHECK(role IN ('user', 'reviewer', 'admin'));
+ALTER TABLE user ADD COLUMN disabled_at TIMESTAMP DEFAULT NULL;
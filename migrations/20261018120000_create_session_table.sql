@@ -0,0 +1,15 @@
+-- Add session table for refresh tokens and revocable logins
+CREATE TABLE session (
+	session_id TEXT PRIMARY KEY NOT NULL,
+	user_id INTEGER NOT NULL,
+	refresh_token_hash TEXT NOT NULL,
+	user_agent TEXT NOT NULL DEFAULT '',
+	ip_address TEXT NOT NULL DEFAULT '',
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	last_used_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	expires_at TIMESTAMP NOT NULL,
+	revoked_at TIMESTAMP DEFAULT NULL,
+	FOREIGN KEY (user_id) REFERENCES user(user_id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_session_user_id ON session(user_id);
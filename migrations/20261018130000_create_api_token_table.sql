@@ -0,0 +1,16 @@
+-- Add personal API tokens for scripted access
+CREATE TABLE api_token (
+	api_token_id INTEGER PRIMARY KEY NOT NULL,
+	user_id INTEGER NOT NULL,
+	name TEXT NOT NULL,
+	token_prefix TEXT NOT NULL,
+	token_hash TEXT NOT NULL UNIQUE,
+	scopes TEXT NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	last_used_at TIMESTAMP DEFAULT NULL,
+	expires_at TIMESTAMP DEFAULT NULL,
+	revoked_at TIMESTAMP DEFAULT NULL,
+	FOREIGN KEY (user_id) REFERENCES user(user_id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_api_token_user_id ON api_token(user_id);
@@ -0,0 +1,14 @@
+-- Add single-use password reset tokens
+CREATE TABLE password_reset (
+	password_reset_id INTEGER PRIMARY KEY NOT NULL,
+	user_id INTEGER NOT NULL,
+	token_hash TEXT NOT NULL UNIQUE,
+	-- Hash of the password hash at the time of the request, so a changed password voids the token
+	password_fingerprint TEXT NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	expires_at TIMESTAMP NOT NULL,
+	used_at TIMESTAMP DEFAULT NULL,
+	FOREIGN KEY (user_id) REFERENCES user(user_id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_password_reset_user_id ON password_reset(user_id);
@@ -0,0 +1,6 @@
+-- Track verified email addresses and unconfirmed email changes
+ALTER TABLE user ADD COLUMN email_verified_at TIMESTAMP DEFAULT NULL;
+ALTER TABLE user ADD COLUMN pending_email TEXT DEFAULT NULL;
+
+-- Accounts created before verification existed keep working
+UPDATE user SET email_verified_at = CURRENT_TIMESTAMP;
@@ -0,0 +1,48 @@
+-- New accounts wait for an administrator to approve them
+ALTER TABLE user ADD COLUMN status TEXT NOT NULL DEFAULT 'pending_approval'
+	CHECK (status IN ('pending_approval', 'active', 'rejected'));
+
+-- Accounts created before approval existed stay active
+UPDATE user SET status = 'active';
+
+-- Versioned data-use agreement; the highest version is the current one
+CREATE TABLE data_use_agreement (
+	version INTEGER PRIMARY KEY NOT NULL,
+	body TEXT NOT NULL,
+	published_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO data_use_agreement (version, body) VALUES (1,
+	'GLAD provides access to summary data derived from controlled-access dbGaP cohorts. By using GLAD you agree to use the data for research purposes only, not to attempt to re-identify individuals, not to redistribute data obtained through GLAD, and to acknowledge the contributing cohorts in any publication.');
+
+CREATE TABLE data_use_acceptance (
+	user_id INTEGER NOT NULL,
+	version INTEGER NOT NULL,
+	accepted_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (user_id, version),
+	FOREIGN KEY (user_id) REFERENCES user(user_id) ON DELETE CASCADE,
+	FOREIGN KEY (version) REFERENCES data_use_agreement(version)
+);
+
+-- Account notifications are not about a query, so query_id becomes optional
+CREATE TABLE notifications_new (
+    notification_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    query_id INTEGER,
+    title TEXT NOT NULL,
+    message TEXT NOT NULL,
+    is_read BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES user(user_id),
+    FOREIGN KEY (query_id) REFERENCES query(query_id) ON DELETE CASCADE
+);
+
+INSERT INTO notifications_new (notification_id, user_id, query_id, title, message, is_read, created_at)
+	SELECT notification_id, user_id, query_id, title, message, is_read, created_at FROM notifications;
+
+DROP TABLE notifications;
+ALTER TABLE notifications_new RENAME TO notifications;
+
+CREATE INDEX idx_notifications_user_id ON notifications(user_id);
+CREATE INDEX idx_notifications_user_read ON notifications(user_id, is_read);
+CREATE INDEX idx_notifications_query_id ON notifications(query_id);
@@ -0,0 +1,31 @@
+-- Track login and password reset attempts for brute-force protection
+CREATE TABLE auth_attempt (
+	auth_attempt_id INTEGER PRIMARY KEY NOT NULL,
+	kind TEXT NOT NULL CHECK (kind IN ('login', 'password_reset')),
+	-- The username or email address the attempt was made for, as entered
+	account TEXT NOT NULL,
+	ip_address TEXT NOT NULL,
+	succeeded BOOLEAN NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_auth_attempt_account ON auth_attempt(kind, account, created_at);
+CREATE INDEX idx_auth_attempt_ip_address ON auth_attempt(kind, ip_address, created_at);
+
+-- Temporary lockouts after repeated failed logins
+-- Keyed by the username entered so unknown usernames lock out the same way as real ones
+CREATE TABLE account_lockout (
+	account_lockout_id INTEGER PRIMARY KEY NOT NULL,
+	account TEXT NOT NULL,
+	user_id INTEGER DEFAULT NULL,
+	-- The failed attempt that triggered the lockout; failures up to it no longer count afterwards
+	auth_attempt_id INTEGER NOT NULL,
+	unlock_token_hash TEXT NOT NULL UNIQUE,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	locked_until TIMESTAMP NOT NULL,
+	unlocked_at TIMESTAMP DEFAULT NULL,
+	FOREIGN KEY (user_id) REFERENCES user(user_id) ON DELETE CASCADE,
+	FOREIGN KEY (auth_attempt_id) REFERENCES auth_attempt(auth_attempt_id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_account_lockout_account ON account_lockout(account);
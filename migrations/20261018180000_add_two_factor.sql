@@ -0,0 +1,27 @@
+-- TOTP two-factor authentication
+CREATE TABLE user_totp (
+	user_id INTEGER PRIMARY KEY NOT NULL,
+	-- Base32 shared secret, kept in plain text since codes are computed from it
+	secret TEXT NOT NULL,
+	-- NULL until the user proves their authenticator app works
+	confirmed_at TIMESTAMP DEFAULT NULL,
+	-- Last accepted time step, so a code cannot be replayed
+	last_used_step INTEGER NOT NULL DEFAULT 0,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	FOREIGN KEY (user_id) REFERENCES user(user_id) ON DELETE CASCADE
+);
+
+CREATE TABLE totp_recovery_code (
+	totp_recovery_code_id INTEGER PRIMARY KEY NOT NULL,
+	user_id INTEGER NOT NULL,
+	code_hash TEXT NOT NULL,
+	used_at TIMESTAMP DEFAULT NULL,
+	FOREIGN KEY (user_id) REFERENCES user(user_id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_totp_recovery_code_user_id ON totp_recovery_code(user_id);
+
+-- Roles whose members must use two-factor authentication
+CREATE TABLE two_factor_policy (
+	role TEXT PRIMARY KEY NOT NULL CHECK(role IN ('user', 'reviewer', 'admin'))
+);
@@ -0,0 +1,24 @@
+-- External OpenID Connect identities linked to local accounts
+CREATE TABLE user_identity (
+	user_identity_id INTEGER PRIMARY KEY NOT NULL,
+	user_id INTEGER NOT NULL,
+	issuer TEXT NOT NULL,
+	subject TEXT NOT NULL,
+	email TEXT DEFAULT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	last_login_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	UNIQUE (issuer, subject),
+	FOREIGN KEY (user_id) REFERENCES user(user_id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_user_identity_user_id ON user_identity(user_id);
+
+-- Logins waiting for the provider to redirect back
+CREATE TABLE oidc_login (
+	state TEXT PRIMARY KEY NOT NULL,
+	-- PKCE verifier, sent with the authorization code to prove this server started the login
+	code_verifier TEXT NOT NULL,
+	nonce TEXT NOT NULL,
+	redirect_to TEXT NOT NULL,
+	expires_at TIMESTAMP NOT NULL
+);
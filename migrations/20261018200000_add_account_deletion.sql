@@ -0,0 +1,5 @@
+-- Accounts waiting out the grace period before they are deleted for good
+ALTER TABLE user ADD COLUMN deletion_requested_at TIMESTAMP DEFAULT NULL;
+
+CREATE INDEX idx_user_deletion_requested_at ON user(deletion_requested_at)
+	WHERE deletion_requested_at IS NOT NULL;
@@ -0,0 +1,32 @@
+-- Append-only record of security-relevant actions
+-- Actors are not foreign keys, so events outlive the accounts they mention
+CREATE TABLE audit_event (
+	audit_event_id INTEGER PRIMARY KEY AUTOINCREMENT,
+	actor_user_id INTEGER DEFAULT NULL,
+	actor TEXT DEFAULT NULL,
+	action TEXT NOT NULL,
+	target_type TEXT DEFAULT NULL,
+	target_id TEXT DEFAULT NULL,
+	ip_address TEXT NOT NULL DEFAULT '',
+	user_agent TEXT NOT NULL DEFAULT '',
+	outcome TEXT NOT NULL CHECK (outcome IN ('success', 'failure', 'denied')),
+	detail TEXT DEFAULT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_audit_event_created_at ON audit_event(created_at);
+CREATE INDEX idx_audit_event_actor_user_id ON audit_event(actor_user_id);
+CREATE INDEX idx_audit_event_action ON audit_event(action);
+CREATE INDEX idx_audit_event_target ON audit_event(target_type, target_id);
+
+CREATE TRIGGER audit_event_no_update BEFORE UPDATE ON audit_event
+BEGIN
+	SELECT RAISE(ABORT, 'audit events cannot be changed');
+END;
+
+-- Only the retention sweep removes events, and never recent ones
+CREATE TRIGGER audit_event_no_recent_delete BEFORE DELETE ON audit_event
+	WHEN OLD.created_at > datetime('now', '-30 days')
+BEGIN
+	SELECT RAISE(ABORT, 'audit events are kept for at least 30 days');
+END;
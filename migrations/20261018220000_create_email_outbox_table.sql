@@ -0,0 +1,21 @@
+-- Emails waiting to be sent, or kept briefly after sending
+-- next_attempt_at is also the lease on a message being sent, so one stuck in
+-- 'sending' after a crash is picked up again
+CREATE TABLE email_outbox (
+	email_id INTEGER PRIMARY KEY AUTOINCREMENT,
+	user_id INTEGER DEFAULT NULL REFERENCES user(user_id) ON DELETE CASCADE,
+	kind TEXT NOT NULL,
+	recipient_name TEXT NOT NULL,
+	recipient_email TEXT NOT NULL,
+	subject TEXT NOT NULL,
+	text_body TEXT NOT NULL,
+	status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'sending', 'sent', 'bounced', 'dead')),
+	attempts INTEGER NOT NULL DEFAULT 0,
+	next_attempt_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	last_error TEXT DEFAULT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	sent_at TIMESTAMP DEFAULT NULL
+);
+
+CREATE INDEX idx_email_outbox_due ON email_outbox(status, next_attempt_at);
+CREATE INDEX idx_email_outbox_user_id ON email_outbox(user_id);
@@ -0,0 +1,3 @@
+-- Language emails are written in for each user, and HTML versions of queued emails
+ALTER TABLE user ADD COLUMN locale TEXT NOT NULL DEFAULT 'en' CHECK(locale IN ('en', 'es', 'pt'));
+ALTER TABLE email_outbox ADD COLUMN html_body TEXT DEFAULT NULL;
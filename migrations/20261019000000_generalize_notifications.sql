@@ -0,0 +1,46 @@
+-- Notifications have a kind, an optional target and a JSON payload, so they are
+-- no longer only about finished queries
+ALTER TABLE notifications ADD COLUMN kind TEXT NOT NULL DEFAULT 'announcement'
+	CHECK (kind IN ('query_started', 'query_retrying', 'query_completed', 'query_failed', 'results_expiring',
+		'account_approved', 'account_rejected', 'cohort_added', 'announcement'));
+ALTER TABLE notifications ADD COLUMN target_type TEXT DEFAULT NULL;
+ALTER TABLE notifications ADD COLUMN target_id INTEGER DEFAULT NULL;
+ALTER TABLE notifications ADD COLUMN payload TEXT NOT NULL DEFAULT '{}';
+
+UPDATE notifications SET kind = CASE title
+	WHEN 'Query Completed' THEN 'query_completed'
+	WHEN 'Query Error' THEN 'query_failed'
+	WHEN 'Account Approved' THEN 'account_approved'
+	WHEN 'Account Not Approved' THEN 'account_rejected'
+	ELSE 'announcement' END;
+UPDATE notifications SET target_type = 'query', target_id = query_id,
+	payload = json_object('query_title', (SELECT title FROM query WHERE query.query_id = notifications.query_id))
+	WHERE query_id IS NOT NULL;
+
+-- How each user wants to hear about each kind of notification; kinds without a
+-- row use their default channel
+CREATE TABLE notification_preference (
+	user_id INTEGER NOT NULL,
+	kind TEXT NOT NULL,
+	channel TEXT NOT NULL CHECK (channel IN ('in_app', 'email', 'none')),
+	PRIMARY KEY (user_id, kind),
+	FOREIGN KEY (user_id) REFERENCES user(user_id) ON DELETE CASCADE
+);
+
+INSERT INTO notification_preference (user_id, kind, channel)
+	SELECT user_id, 'query_completed', 'email' FROM user WHERE email_notifications = TRUE;
+INSERT INTO notification_preference (user_id, kind, channel)
+	SELECT user_id, 'query_failed', 'email' FROM user WHERE email_notifications = TRUE;
+
+ALTER TABLE user DROP COLUMN email_notifications;
+
+-- The last query status its recipients were notified of, so each change is
+-- announced once even to users who turned that kind of notification off
+ALTER TABLE query ADD COLUMN notified_state TEXT DEFAULT NULL;
+ALTER TABLE query ADD COLUMN expiry_notified_at TIMESTAMP DEFAULT NULL;
+
+UPDATE query SET notified_state = CASE
+	WHEN internal_status = 'retry_pending' THEN 'retry_pending:' || retry_count
+	ELSE user_visible_status END
+	WHERE user_visible_status = 'processing'
+	OR query_id IN (SELECT query_id FROM notifications WHERE query_id IS NOT NULL);
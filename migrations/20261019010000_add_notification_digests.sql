@@ -0,0 +1,42 @@
+-- Notifications of kinds on the digest channel are emailed together in a daily
+-- or weekly summary instead of one at a time
+CREATE TABLE notification_preference_new (
+	user_id INTEGER NOT NULL,
+	kind TEXT NOT NULL,
+	channel TEXT NOT NULL CHECK (channel IN ('in_app', 'email', 'digest', 'none')),
+	PRIMARY KEY (user_id, kind),
+	FOREIGN KEY (user_id) REFERENCES user(user_id) ON DELETE CASCADE
+);
+INSERT INTO notification_preference_new (user_id, kind, channel)
+	SELECT user_id, kind, channel FROM notification_preference;
+DROP TABLE notification_preference;
+ALTER TABLE notification_preference_new RENAME TO notification_preference;
+
+-- When each user's digest is sent, in their local time; users without a row
+-- get the default schedule when they first choose the digest channel
+CREATE TABLE notification_digest_schedule (
+	user_id INTEGER PRIMARY KEY,
+	frequency TEXT NOT NULL CHECK (frequency IN ('daily', 'weekly')) DEFAULT 'daily',
+	-- HH:MM in the user's time zone
+	local_time TEXT NOT NULL DEFAULT '08:00',
+	-- Day of weekly digests, 0 for Sunday to 6 for Saturday
+	weekday INTEGER NOT NULL CHECK (weekday BETWEEN 0 AND 6) DEFAULT 1,
+	utc_offset_minutes INTEGER NOT NULL CHECK (utc_offset_minutes BETWEEN -840 AND 840) DEFAULT 0,
+	next_digest_at TIMESTAMP NOT NULL,
+	FOREIGN KEY (user_id) REFERENCES user(user_id) ON DELETE CASCADE
+);
+CREATE INDEX idx_notification_digest_schedule_next_digest_at ON notification_digest_schedule(next_digest_at);
+
+CREATE TABLE notification_digest (
+	digest_id INTEGER PRIMARY KEY AUTOINCREMENT,
+	user_id INTEGER NOT NULL,
+	notification_count INTEGER NOT NULL,
+	email_id INTEGER DEFAULT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	FOREIGN KEY (user_id) REFERENCES user(user_id) ON DELETE CASCADE
+);
+
+-- The digest a notification was sent in; a notification is claimed by at most one
+ALTER TABLE notifications ADD COLUMN digest_id INTEGER DEFAULT NULL
+	REFERENCES notification_digest(digest_id) ON DELETE SET NULL;
+CREATE INDEX idx_notifications_digest_pending ON notifications(user_id, digest_id, is_read);
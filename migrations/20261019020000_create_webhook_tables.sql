@@ -0,0 +1,37 @@
+-- Webhooks are called when a query changes status. Each belongs to a user, for
+-- their own queries, or to an organization, for its queries
+CREATE TABLE webhook (
+	webhook_id INTEGER PRIMARY KEY AUTOINCREMENT,
+	user_id INTEGER DEFAULT NULL,
+	organization_id INTEGER DEFAULT NULL,
+	url TEXT NOT NULL,
+	-- Key for the HMAC-SHA256 signature of each request
+	secret TEXT NOT NULL,
+	description TEXT NOT NULL DEFAULT '',
+	created_by INTEGER DEFAULT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	CHECK ((user_id IS NULL) != (organization_id IS NULL)),
+	FOREIGN KEY (user_id) REFERENCES user(user_id) ON DELETE CASCADE,
+	FOREIGN KEY (organization_id) REFERENCES organization(organization_id) ON DELETE CASCADE,
+	FOREIGN KEY (created_by) REFERENCES user(user_id) ON DELETE SET NULL
+);
+CREATE INDEX idx_webhook_user_id ON webhook(user_id);
+CREATE INDEX idx_webhook_organization_id ON webhook(organization_id);
+
+-- Every event sent to a webhook, with the outcome of its latest attempt
+CREATE TABLE webhook_delivery (
+	delivery_id INTEGER PRIMARY KEY AUTOINCREMENT,
+	webhook_id INTEGER NOT NULL,
+	event TEXT NOT NULL,
+	payload TEXT NOT NULL,
+	status TEXT NOT NULL CHECK (status IN ('pending', 'sending', 'delivered', 'failed')) DEFAULT 'pending',
+	attempts INTEGER NOT NULL DEFAULT 0,
+	next_attempt_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	response_status INTEGER DEFAULT NULL,
+	last_error TEXT DEFAULT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	delivered_at TIMESTAMP DEFAULT NULL,
+	FOREIGN KEY (webhook_id) REFERENCES webhook(webhook_id) ON DELETE CASCADE
+);
+CREATE INDEX idx_webhook_delivery_due ON webhook_delivery(status, next_attempt_at);
+CREATE INDEX idx_webhook_delivery_webhook_id ON webhook_delivery(webhook_id);
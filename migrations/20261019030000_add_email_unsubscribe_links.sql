@@ -0,0 +1,3 @@
+-- One-click unsubscribe address sent in the List-Unsubscribe header of
+-- notification emails
+ALTER TABLE email_outbox ADD COLUMN unsubscribe_url TEXT DEFAULT NULL;
@@ -0,0 +1,5 @@
+-- Read notifications are archived after a while, hiding them from the
+-- notification list without deleting them
+ALTER TABLE notifications ADD COLUMN archived_at TIMESTAMP DEFAULT NULL;
+
+CREATE INDEX idx_notifications_user_archived ON notifications(user_id, archived_at, notification_id);
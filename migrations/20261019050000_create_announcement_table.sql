@@ -0,0 +1,22 @@
+-- Site-wide announcements shown in a banner while they are active, such as
+-- downtime for a data release
+CREATE TABLE announcement (
+	announcement_id INTEGER PRIMARY KEY AUTOINCREMENT,
+	title TEXT NOT NULL,
+	message TEXT NOT NULL,
+	severity TEXT NOT NULL DEFAULT 'info' CHECK (severity IN ('info', 'warning', 'critical')),
+	-- Who sees it: everyone, or only users with queries still pending or processing
+	audience TEXT NOT NULL DEFAULT 'all' CHECK (audience IN ('all', 'pending_queries')),
+	-- Query submissions are refused while a maintenance announcement is active
+	maintenance BOOLEAN NOT NULL DEFAULT FALSE,
+	starts_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	ends_at TIMESTAMP DEFAULT NULL,
+	-- Also sent as a notification to its audience once it starts
+	notify BOOLEAN NOT NULL DEFAULT FALSE,
+	notified_at TIMESTAMP DEFAULT NULL,
+	created_by INTEGER DEFAULT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	CHECK (ends_at IS NULL OR ends_at > starts_at),
+	FOREIGN KEY (created_by) REFERENCES user(user_id) ON DELETE SET NULL
+);
+CREATE INDEX idx_announcement_active ON announcement(starts_at, ends_at);
@@ -0,0 +1,100 @@
+use axum::{
+    http::header,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+
+use crate::api::auth::clear_session_cookies;
+use crate::api::two_factor::confirm_password;
+use crate::api::{ApiError, ApiResult};
+use crate::auth::{AuthenticatedUser, ClientInfo};
+use crate::models::{AccountDeletion, AuditEvent, PersonalData};
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteAccountRequest {
+    pub password: String,
+}
+
+/// Download everything stored about the user as a ZIP archive
+pub async fn export(user: AuthenticatedUser, client: ClientInfo) -> ApiResult<Response> {
+    let data = PersonalData::collect(&user.username).await?;
+    let archive = tokio::task::spawn_blocking(move || data.to_zip())
+        .await
+        .map_err(|_| ApiError::InternalServerError)?
+        .map_err(|e| {
+            tracing::error!("Failed to build data export: {}", e);
+            ApiError::InternalServerError
+        })?;
+
+    AuditEvent::action("account.export", &client)
+        .actor(user.user_id, &user.username)
+        .record()
+        .await;
+
+    let filename = format!(
+        "glad-{}-{}.zip",
+        user.username,
+        sqlx::types::chrono::Utc::now().format("%Y%m%d")
+    );
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
+        ],
+        archive,
+    )
+        .into_response())
+}
+
+/// Schedule the user's account for deletion and log them out everywhere
+pub async fn delete_account(
+    user: AuthenticatedUser,
+    client: ClientInfo,
+    Json(payload): Json<DeleteAccountRequest>,
+) -> ApiResult<Response> {
+    confirm_password(&user, &client, payload.password).await?;
+
+    let scheduled_for = AccountDeletion::schedule(user.user_id).await?;
+    tracing::info!("Account {} scheduled for deletion on {}", user.username, scheduled_for);
+    AuditEvent::action("account.delete", &client)
+        .actor(user.user_id, &user.username)
+        .detail(format!("Scheduled for {}", scheduled_for))
+        .record()
+        .await;
+
+    let mut response = Json(serde_json::json!({
+        "message": format!(
+            "Your account will be deleted on {} UTC. Until then it cannot submit queries; log in and cancel the deletion to keep it.",
+            scheduled_for
+        ),
+        "deletion_scheduled_for": scheduled_for,
+    }))
+    .into_response();
+    clear_session_cookies(&mut response);
+
+    Ok(response)
+}
+
+/// Cancel a pending account deletion
+pub async fn cancel_deletion(
+    user: AuthenticatedUser,
+    client: ClientInfo,
+) -> ApiResult<Json<serde_json::Value>> {
+    let cancelled = AccountDeletion::cancel(user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if !cancelled {
+        return Err(ApiError::ValidationError(
+            "Account deletion was not requested".to_string(),
+        ));
+    }
+    AuditEvent::action("account.delete_cancel", &client)
+        .actor(user.user_id, &user.username)
+        .record()
+        .await;
+
+    Ok(Json(serde_json::json!({
+        "message": "Account deletion cancelled"
+    })))
+}
@@ -0,0 +1,640 @@
+use axum::{
+    extract::{Path, Query as QueryParams},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::{ApiError, ApiResult},
+    auth::{
+        role::{AdminUser, ReviewerUser},
+        ClientInfo,
+    },
+    models::{
+        AccountLockout, AccountStatus, Announcement, AttemptKind, AuditEvent, AuditFilter, AuthAttempt, Cohort,
+        DataUseAgreement, EmailOutbox, NewAnnouncement,
+        NewNotification, Notification, NotificationKind, Query, Role, Session, TwoFactor, User, UserSummary,
+    },
+};
+
+#[derive(Debug, Serialize)]
+pub struct UsersResponse {
+    pub users: Vec<UserSummary>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UsersFilter {
+    pub status: Option<AccountStatus>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeadLettersFilter {
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RejectUserRequest {
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthAttemptsFilter {
+    pub kind: Option<AttemptKind>,
+    pub account: Option<String>,
+    pub ip_address: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TwoFactorPolicy {
+    /// Roles whose members must use two-factor authentication
+    pub required_roles: Vec<Role>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PublishAgreementRequest {
+    pub body: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateRoleRequest {
+    pub role: Role,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueriesFilter {
+    pub status: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueriesResponse {
+    pub queries: Vec<Query>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateCohortRequest {
+    pub cohort_name: String,
+}
+
+/// List all user accounts, optionally filtered by approval status
+pub async fn get_users(
+    _admin: AdminUser,
+    QueryParams(filter): QueryParams<UsersFilter>,
+) -> ApiResult<Json<UsersResponse>> {
+    let users = User::list_all(filter.status)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(UsersResponse { users }))
+}
+
+async fn set_user_disabled(
+    admin: AdminUser,
+    client: ClientInfo,
+    user_id: i64,
+    disabled: bool,
+) -> ApiResult<Json<serde_json::Value>> {
+    if disabled && user_id == admin.user_id {
+        return Err(ApiError::ValidationError(
+            "You cannot disable your own account".to_string(),
+        ));
+    }
+
+    let updated = User::set_disabled(user_id, disabled)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if !updated {
+        return Err(ApiError::UserNotFound);
+    }
+
+    tracing::info!(
+        "Admin {} {} user {}",
+        admin.username,
+        if disabled { "disabled" } else { "enabled" },
+        user_id
+    );
+    AuditEvent::action(if disabled { "admin.user.disable" } else { "admin.user.enable" }, &client)
+        .actor(admin.user_id, &admin.username)
+        .target("user", user_id)
+        .record()
+        .await;
+
+    Ok(Json(serde_json::json!({
+        "message": if disabled { "Account disabled" } else { "Account enabled" }
+    })))
+}
+
+/// Disable a user account, preventing login and API access
+pub async fn disable_user(
+    admin: AdminUser,
+    client: ClientInfo,
+    Path(user_id): Path<i64>,
+) -> ApiResult<Json<serde_json::Value>> {
+    set_user_disabled(admin, client, user_id, true).await
+}
+
+/// Re-enable a disabled user account
+pub async fn enable_user(
+    admin: AdminUser,
+    client: ClientInfo,
+    Path(user_id): Path<i64>,
+) -> ApiResult<Json<serde_json::Value>> {
+    set_user_disabled(admin, client, user_id, false).await
+}
+
+/// Let a pending account submit queries
+pub async fn approve_user(
+    admin: AdminUser,
+    client: ClientInfo,
+    Path(user_id): Path<i64>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let updated = User::set_status(user_id, AccountStatus::Active)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if !updated {
+        return Err(ApiError::UserNotFound);
+    }
+
+    tracing::info!("Admin {} approved user {}", admin.username, user_id);
+    AuditEvent::action("admin.user.approve", &client)
+        .actor(admin.user_id, &admin.username)
+        .target("user", user_id)
+        .record()
+        .await;
+
+    Notification::create(
+        user_id,
+        &NewNotification {
+            kind: NotificationKind::AccountApproved,
+            title: "Account Approved".to_string(),
+            message: "Your GLAD account has been approved. Once you have accepted the data-use agreement you can submit queries.".to_string(),
+            target: None,
+            payload: serde_json::json!({}),
+        },
+    )
+    .await
+    .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(serde_json::json!({ "message": "Account approved" })))
+}
+
+/// Turn down an account application and log the user out everywhere
+pub async fn reject_user(
+    admin: AdminUser,
+    client: ClientInfo,
+    Path(user_id): Path<i64>,
+    Json(request): Json<RejectUserRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    if user_id == admin.user_id {
+        return Err(ApiError::ValidationError(
+            "You cannot reject your own account".to_string(),
+        ));
+    }
+
+    let updated = User::set_status(user_id, AccountStatus::Rejected)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if !updated {
+        return Err(ApiError::UserNotFound);
+    }
+
+    tracing::info!("Admin {} rejected user {}", admin.username, user_id);
+    AuditEvent::action("admin.user.reject", &client)
+        .actor(admin.user_id, &admin.username)
+        .target("user", user_id)
+        .record()
+        .await;
+
+    let reason = request.reason.as_deref().map(str::trim).filter(|r| !r.is_empty());
+    let mut message = "Your GLAD account application was not approved.".to_string();
+    if let Some(reason) = reason {
+        message.push_str(&format!(" Reason: {}", reason));
+    }
+    Notification::create(
+        user_id,
+        &NewNotification {
+            kind: NotificationKind::AccountRejected,
+            title: "Account Not Approved".to_string(),
+            message,
+            target: None,
+            payload: serde_json::json!({ "reason": reason }),
+        },
+    )
+    .await
+    .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Session::revoke_all_for_user(user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(serde_json::json!({ "message": "Account rejected" })))
+}
+
+/// List recent login and password reset attempts
+pub async fn get_auth_attempts(
+    _admin: AdminUser,
+    QueryParams(filter): QueryParams<AuthAttemptsFilter>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let limit = filter.limit.unwrap_or(100).clamp(1, 1000);
+    let attempts = AuthAttempt::list(
+        filter.kind,
+        filter.account.as_deref(),
+        filter.ip_address.as_deref(),
+        limit,
+    )
+    .await
+    .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(serde_json::json!({ "attempts": attempts })))
+}
+
+/// Search the audit log, newest first
+pub async fn get_audit_events(
+    _admin: AdminUser,
+    QueryParams(filter): QueryParams<AuditFilter>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let events = AuditEvent::list(&filter)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(serde_json::json!({ "events": events })))
+}
+
+/// Summarize the email outbox and list the emails that could not be delivered
+pub async fn get_email_outbox(
+    _admin: AdminUser,
+    QueryParams(filter): QueryParams<DeadLettersFilter>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let limit = filter.limit.unwrap_or(100).clamp(1, 1000);
+    let counts = EmailOutbox::counts()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    let dead_letters = EmailOutbox::dead_letters(limit)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(serde_json::json!({
+        "counts": counts,
+        "dead_letters": dead_letters,
+    })))
+}
+
+/// Send a bounced or dead email again
+pub async fn retry_email(
+    admin: AdminUser,
+    client: ClientInfo,
+    Path(email_id): Path<i64>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let retried = EmailOutbox::retry(email_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if !retried {
+        return Err(ApiError::NotFound(
+            "Email not found among undelivered emails".to_string(),
+        ));
+    }
+
+    tracing::info!("Admin {} requeued email {}", admin.username, email_id);
+    AuditEvent::action("admin.email.retry", &client)
+        .actor(admin.user_id, &admin.username)
+        .target("email", email_id)
+        .record()
+        .await;
+
+    Ok(Json(serde_json::json!({ "message": "Email queued for another attempt" })))
+}
+
+/// List accounts that are currently locked out
+pub async fn get_lockouts(_admin: AdminUser) -> ApiResult<Json<serde_json::Value>> {
+    let lockouts = AccountLockout::active()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(serde_json::json!({ "lockouts": lockouts })))
+}
+
+/// Lift a login lockout before it expires
+pub async fn unlock_user(
+    admin: AdminUser,
+    client: ClientInfo,
+    Path(user_id): Path<i64>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let unlocked = AccountLockout::clear_for_user(user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if unlocked == 0 {
+        return Err(ApiError::NotFound("Account is not locked".to_string()));
+    }
+
+    tracing::info!("Admin {} unlocked user {}", admin.username, user_id);
+    AuditEvent::action("admin.user.unlock", &client)
+        .actor(admin.user_id, &admin.username)
+        .target("user", user_id)
+        .record()
+        .await;
+
+    Ok(Json(serde_json::json!({ "message": "Account unlocked" })))
+}
+
+/// Get the roles that must use two-factor authentication
+pub async fn get_two_factor_policy(_admin: AdminUser) -> ApiResult<Json<TwoFactorPolicy>> {
+    let required_roles = TwoFactor::required_roles()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(TwoFactorPolicy { required_roles }))
+}
+
+/// Change the roles that must use two-factor authentication
+/// Affected users without it are logged out and must enroll at their next login
+pub async fn update_two_factor_policy(
+    admin: AdminUser,
+    client: ClientInfo,
+    Json(policy): Json<TwoFactorPolicy>,
+) -> ApiResult<Json<TwoFactorPolicy>> {
+    TwoFactor::set_required_roles(&policy.required_roles)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    let mut logged_out = 0;
+    for (user_id, role) in TwoFactor::unenrolled_users()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+    {
+        if role.granted().iter().any(|granted| policy.required_roles.contains(granted)) {
+            logged_out += Session::revoke_all_for_user(user_id)
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        }
+    }
+
+    tracing::info!(
+        "Admin {} required two-factor authentication for {:?}, revoking {} sessions",
+        admin.username,
+        policy.required_roles,
+        logged_out
+    );
+    AuditEvent::action("admin.two_factor_policy.update", &client)
+        .actor(admin.user_id, &admin.username)
+        .detail(format!("Required for {:?}", policy.required_roles))
+        .record()
+        .await;
+
+    get_two_factor_policy(admin).await
+}
+
+/// Remove a user's second factor, for when they lost both their device and recovery codes
+pub async fn reset_user_two_factor(
+    admin: AdminUser,
+    client: ClientInfo,
+    Path(user_id): Path<i64>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let removed = TwoFactor::disable(user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if !removed {
+        return Err(crate::models::DatabaseError::TwoFactorNotEnabled.into());
+    }
+
+    // Whoever held the old factor must not keep a session
+    Session::revoke_all_for_user(user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    tracing::info!("Admin {} reset two-factor authentication for user {}", admin.username, user_id);
+    AuditEvent::action("admin.user.two_factor_reset", &client)
+        .actor(admin.user_id, &admin.username)
+        .target("user", user_id)
+        .record()
+        .await;
+
+    Ok(Json(serde_json::json!({ "message": "Two-factor authentication reset" })))
+}
+
+/// Publish a new version of the data-use agreement
+/// Every user must accept it before their next submission
+pub async fn publish_agreement(
+    admin: AdminUser,
+    client: ClientInfo,
+    Json(request): Json<PublishAgreementRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let version = DataUseAgreement::publish(&request.body).await?;
+
+    tracing::info!(
+        "Admin {} published data-use agreement version {}",
+        admin.username,
+        version
+    );
+    AuditEvent::action("admin.agreement.publish", &client)
+        .actor(admin.user_id, &admin.username)
+        .target("agreement", version)
+        .record()
+        .await;
+
+    Ok(Json(serde_json::json!({
+        "version": version,
+        "message": format!("Published data-use agreement version {}", version)
+    })))
+}
+
+/// Change a user's site-wide role
+pub async fn update_user_role(
+    admin: AdminUser,
+    client: ClientInfo,
+    Path(user_id): Path<i64>,
+    Json(request): Json<UpdateRoleRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    if user_id == admin.user_id && request.role != Role::Admin {
+        return Err(ApiError::ValidationError(
+            "You cannot remove your own admin role".to_string(),
+        ));
+    }
+
+    let updated = User::set_role(user_id, request.role)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if !updated {
+        return Err(ApiError::UserNotFound);
+    }
+
+    tracing::info!(
+        "Admin {} set role of user {} to {:?}",
+        admin.username,
+        user_id,
+        request.role
+    );
+    AuditEvent::action("admin.user.role", &client)
+        .actor(admin.user_id, &admin.username)
+        .target("user", user_id)
+        .detail(format!("Role set to {:?}", request.role))
+        .record()
+        .await;
+
+    Ok(Json(
+        serde_json::json!({ "message": "Role updated successfully" }),
+    ))
+}
+
+/// List all queries, optionally filtered by status
+pub async fn get_queries(
+    _reviewer: ReviewerUser,
+    QueryParams(filter): QueryParams<QueriesFilter>,
+) -> ApiResult<Json<QueriesResponse>> {
+    let queries = Query::all(filter.status)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(QueriesResponse { queries }))
+}
+
+/// Put a finished or failed query back in the pipeline queue
+pub async fn requeue_query(
+    admin: AdminUser,
+    client: ClientInfo,
+    Path(query_id): Path<i64>,
+) -> ApiResult<Json<serde_json::Value>> {
+    Query::for_query(query_id)
+        .await
+        .map_err(|_| ApiError::NotFound("Query not found".to_string()))?;
+
+    let requeued = Query::requeue(query_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if !requeued {
+        return Err(ApiError::Conflict(
+            "Query is already pending or processing".to_string(),
+        ));
+    }
+
+    tracing::info!("Admin {} requeued query {}", admin.username, query_id);
+    AuditEvent::action("admin.query.requeue", &client)
+        .actor(admin.user_id, &admin.username)
+        .target("query", query_id)
+        .record()
+        .await;
+
+    Ok(Json(serde_json::json!({ "message": "Query requeued" })))
+}
+
+/// Add a cohort to the list of cohorts users can exclude
+pub async fn create_cohort(
+    admin: AdminUser,
+    client: ClientInfo,
+    Json(request): Json<CreateCohortRequest>,
+) -> ApiResult<Json<Cohort>> {
+    let cohort_name = request.cohort_name.trim();
+    if cohort_name.is_empty() {
+        return Err(ApiError::ValidationError(
+            "Cohort name is required".to_string(),
+        ));
+    }
+
+    let cohort = Cohort::insert(cohort_name).await.map_err(|e| match e {
+        sqlx::Error::Database(db_error) if db_error.is_unique_violation() => {
+            ApiError::Conflict("Cohort already exists".to_string())
+        }
+        _ => ApiError::DatabaseError(e.to_string()),
+    })?;
+    AuditEvent::action("admin.cohort.create", &client)
+        .actor(admin.user_id, &admin.username)
+        .target("cohort", cohort.cohort_id)
+        .detail(cohort_name)
+        .record()
+        .await;
+
+    // Telling every user can take a while, so it happens after responding
+    let notification = NewNotification {
+        kind: NotificationKind::CohortAdded,
+        title: "New Cohort Available".to_string(),
+        message: format!("The {} cohort is now available for new queries.", cohort_name),
+        target: Some(("cohort", cohort.cohort_id)),
+        payload: serde_json::json!({ "cohort_name": cohort_name }),
+    };
+    tokio::spawn(async move {
+        if let Err(e) = Notification::broadcast(&notification).await {
+            tracing::error!("Failed to notify users of new cohort: {}", e);
+        }
+    });
+
+    Ok(Json(cohort))
+}
+
+/// Every announcement, past and future
+pub async fn get_announcements(_admin: AdminUser) -> ApiResult<Json<serde_json::Value>> {
+    let announcements = Announcement::list()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(serde_json::json!({ "announcements": announcements })))
+}
+
+/// Publish an announcement; it is sent as a notification once it starts if `notify` is set
+pub async fn create_announcement(
+    admin: AdminUser,
+    client: ClientInfo,
+    Json(request): Json<NewAnnouncement>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let announcement_id = Announcement::create(admin.user_id, &request).await?;
+    AuditEvent::action("admin.announcement.create", &client)
+        .actor(admin.user_id, &admin.username)
+        .target("announcement", announcement_id)
+        .detail(request.title.trim())
+        .record()
+        .await;
+
+    Ok(Json(serde_json::json!({
+        "announcement_id": announcement_id,
+        "message": "Announcement created"
+    })))
+}
+
+/// Replace an announcement, e.g. to end it early
+pub async fn update_announcement(
+    Path(announcement_id): Path<i64>,
+    admin: AdminUser,
+    client: ClientInfo,
+    Json(request): Json<NewAnnouncement>,
+) -> ApiResult<Json<serde_json::Value>> {
+    Announcement::update(announcement_id, &request).await?;
+    AuditEvent::action("admin.announcement.update", &client)
+        .actor(admin.user_id, &admin.username)
+        .target("announcement", announcement_id)
+        .detail(request.title.trim())
+        .record()
+        .await;
+
+    Ok(Json(serde_json::json!({
+        "message": "Announcement updated"
+    })))
+}
+
+pub async fn delete_announcement(
+    Path(announcement_id): Path<i64>,
+    admin: AdminUser,
+    client: ClientInfo,
+) -> ApiResult<Json<serde_json::Value>> {
+    Announcement::delete(announcement_id).await?;
+    AuditEvent::action("admin.announcement.delete", &client)
+        .actor(admin.user_id, &admin.username)
+        .target("announcement", announcement_id)
+        .record()
+        .await;
+
+    Ok(Json(serde_json::json!({
+        "message": "Announcement deleted"
+    })))
+}
+
+/// Manually trigger notification processing
+pub async fn process_pending_notifications(
+    _admin: AdminUser,
+) -> ApiResult<Json<serde_json::Value>> {
+    let created_count = Notification::process_pending_notifications()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(serde_json::json!({
+        "created_count": created_count,
+        "message": format!("Processed {} pending notifications", created_count)
+    })))
+}
@@ -0,0 +1,19 @@
+use axum::Json;
+
+use crate::{
+    api::{ApiError, ApiResult},
+    auth::AuthenticatedUser,
+    models::Announcement,
+};
+
+/// The announcements to show in the site banner right now
+/// Public, but announcements for users with pending queries need a login
+pub async fn get_active_announcements(
+    user: Option<AuthenticatedUser>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let announcements = Announcement::active_for(user.map(|user| user.user_id))
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(serde_json::json!({ "announcements": announcements })))
+}
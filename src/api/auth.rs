@@ -1,17 +1,30 @@
 use axum::{
-    extract::Request,
-    http::{header, HeaderValue},
-    response::IntoResponse,
+    extract::Path,
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
     Json,
 };
 use serde::{Deserialize, Serialize};
 
+use crate::api::two_factor::confirm_password;
 use crate::api::{ApiError, ApiResult};
-use crate::auth::jwt::{encode_token, TokenClaims};
-use crate::models::{User, verify_password};
+use crate::auth::cookie::{self, REFRESH_COOKIE};
+use crate::auth::jwt::{decode_token, encode_token, TokenClaims, TokenPurpose};
+use crate::auth::{AuthenticatedUser, ClientInfo};
+use crate::mail::templates::EmailTemplate;
+use crate::models::{
+    AccountDeletion, AccountLockout, AccountStatus, AuditEvent, AuditOutcome, AttemptKind, AuthAttempt, DataUseAgreement, DatabaseError, EmailOutbox,
+    Locale, NewEmail, NotificationChannel, NotificationKind, NotificationPreference, PasswordReset, Role, Session, TwoFactor, TwoFactorEnrollment, User, verify_password,
+};
+
+// Access token duration in seconds (15 minutes); sessions are extended with the refresh token
+const ACCESS_TOKEN_DURATION: usize = 900;
 
-// Token duration in seconds (1 hour)
-const TOKEN_DURATION: usize = 3600;
+// Email verification link duration in seconds (24 hours)
+const EMAIL_VERIFICATION_DURATION: usize = 86400;
+
+// Time allowed between the password step and the two-factor step of a login (5 minutes)
+pub(crate) const TWO_FACTOR_CHALLENGE_DURATION: usize = 300;
 
 #[derive(Debug, Deserialize)]
 pub struct LoginRequest {
@@ -23,6 +36,31 @@ pub struct LoginRequest {
 pub struct LoginResponse {
     pub message: String,
     pub username: String,
+    /// Set when the password was correct but a second step is needed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub two_factor: Option<TwoFactorChallenge>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TwoFactorChallenge {
+    /// Short-lived token proving the password step succeeded
+    pub challenge: String,
+    /// The account must enroll an authenticator before it can log in
+    pub setup_required: bool,
+}
+
+/// The challenge is left out after single sign-on, which sends it in a cookie instead
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorSetupRequest {
+    #[serde(default)]
+    pub challenge: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorLoginRequest {
+    #[serde(default)]
+    pub challenge: String,
+    pub code: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -31,7 +69,15 @@ pub struct SignupRequest {
     pub email: String,
     pub password: String,
     pub bio: String,
+    /// Email the user when their queries complete or fail
+    #[serde(default)]
     pub email_notifications: bool,
+    /// Language for emails, taken from `Accept-Language` if unset
+    #[serde(default)]
+    pub locale: Option<Locale>,
+    /// Version of the data-use agreement accepted while signing up
+    #[serde(default)]
+    pub agreement_version: Option<i64>,
 }
 
 #[derive(Debug, Serialize)]
@@ -45,7 +91,17 @@ pub struct UserResponse {
     pub username: String,
     pub email: String,
     pub bio: String,
-    pub email_notifications: bool,
+    pub locale: Locale,
+    pub role: Role,
+    pub email_verified: bool,
+    pub pending_email: Option<String>,
+    pub status: AccountStatus,
+    pub agreement_accepted: bool,
+    pub two_factor_enabled: bool,
+    /// False for accounts created through single sign-on, which confirm sensitive changes by logging in again
+    pub has_password: bool,
+    /// When the account will be deleted, if its owner asked for that
+    pub deletion_scheduled_for: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -76,7 +132,12 @@ pub struct UpdateSettingsRequest {
     pub email: String,
     pub password: String,
     pub confirm_password: String,
-    pub email_notifications: bool,
+    /// Needed to change the email address or password
+    #[serde(default)]
+    pub current_password: String,
+    /// Language for emails, unchanged if unset
+    #[serde(default)]
+    pub locale: Option<Locale>,
 }
 
 #[derive(Debug, Serialize)]
@@ -84,105 +145,652 @@ pub struct UpdateSettingsResponse {
     pub message: String,
 }
 
-pub async fn login(Json(payload): Json<LoginRequest>) -> ApiResult<impl IntoResponse> {
+#[derive(Debug, Deserialize)]
+pub struct AcceptAgreementRequest {
+    pub version: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnlockAccountRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionResponse {
+    #[serde(flatten)]
+    pub session: Session,
+    pub current: bool,
+}
+
+/// Issue a short-lived access token tied to a session
+fn access_token(username: &str, session_id: &str) -> ApiResult<String> {
+    encode_token(TokenClaims {
+        sub: username.to_string(),
+        exp: (sqlx::types::chrono::Utc::now().timestamp() as usize) + ACCESS_TOKEN_DURATION,
+        purpose: TokenPurpose::Access,
+        sid: Some(session_id.to_string()),
+        jti: None,
+        email: None,
+        category: None,
+    })
+    .map_err(|_| ApiError::InternalServerError)
+}
+
+/// Set the access and refresh token cookies on a response
+fn set_session_cookies(response: &mut Response, access_token: &str, refresh_token: &str) {
+    let headers = response.headers_mut();
+    headers.append(header::SET_COOKIE, cookie::access_cookie(access_token));
+    headers.append(
+        header::SET_COOKIE,
+        cookie::refresh_cookie(refresh_token, Session::DURATION_DAYS * 24 * 60 * 60),
+    );
+}
+
+pub(crate) fn clear_session_cookies(response: &mut Response) {
+    let headers = response.headers_mut();
+    headers.append(header::SET_COOKIE, cookie::remove_access_cookie());
+    headers.append(header::SET_COOKIE, cookie::remove_refresh_cookie());
+}
+
+/// Public keys other services can use to verify tokens issued here
+pub async fn jwks() -> Json<serde_json::Value> {
+    Json(crate::auth::jwt::key_ring().jwks())
+}
+
+/// Queue an email with a link proving the user owns `email`
+async fn send_verification(user_id: i64, username: &str, email: &str) -> ApiResult<()> {
+    let token = encode_token(TokenClaims {
+        sub: username.to_string(),
+        exp: (sqlx::types::chrono::Utc::now().timestamp() as usize) + EMAIL_VERIFICATION_DURATION,
+        purpose: TokenPurpose::EmailVerification,
+        sid: None,
+        jti: None,
+        email: Some(email.to_string()),
+        category: None,
+    })
+    .map_err(|_| ApiError::InternalServerError)?;
+
+    let site_base_url = std::env::var("SITE_BASE_URL")
+        .map_err(|_| ApiError::InternalServerError)?;
+    let verify_url = format!("{}/verify-email?token={}", site_base_url, token);
+
+    User::send_verification_email(user_id, username, email, &verify_url)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))
+}
+
+/// Queue an email to the owner of a locked account with a link to unlock it
+async fn send_unlock(user_id: i64, username: &str, email: &str, secret: &str) -> ApiResult<()> {
+    let site_base_url = std::env::var("SITE_BASE_URL")
+        .map_err(|_| ApiError::InternalServerError)?;
+    let unlock_url = format!("{}/unlock-account?token={}", site_base_url, secret);
+
+    AccountLockout::send_unlock_email(user_id, username, email, &unlock_url)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))
+}
+
+/// Start a new session for a user and attach its cookies to the response
+pub(crate) async fn start_session(
+    response: &mut Response,
+    user_id: i64,
+    username: &str,
+    client: &ClientInfo,
+) -> ApiResult<()> {
+    let (session_id, refresh_token) = Session::create(user_id, &client.user_agent, &client.ip_address)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    let token = access_token(username, &session_id)?;
+
+    set_session_cookies(response, &token, &refresh_token);
+    Ok(())
+}
+
+pub async fn login(
+    client: ClientInfo,
+    Json(payload): Json<LoginRequest>,
+) -> ApiResult<impl IntoResponse> {
     // Validate input
     if payload.username.is_empty() || payload.password.is_empty() {
         return Err(ApiError::ValidationError("Username and password are required".to_string()));
     }
 
+    // Refuse locked accounts and slow down repeated failures
+    AuthAttempt::check_login(&payload.username, &client.ip_address).await?;
+
     // Get user password hash from database
-    let hash = sqlx::query_scalar!(
-        "SELECT password FROM user WHERE username = $1",
+    let account = sqlx::query!(
+        r#"SELECT user_id AS "user_id!", password, disabled_at, status AS "status: AccountStatus", role AS "role: Role" FROM user WHERE username = $1"#,
         payload.username
     )
-    .fetch_one(crate::database::get_db())
+    .fetch_optional(crate::database::get_db())
     .await
-    .map_err(|_| ApiError::InvalidCredentials)?;
+    .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
 
     // Verify password
-    verify_password(payload.password, hash)
+    let verified = match &account {
+        Some(account) => verify_password(payload.password, account.password.clone()).await.is_ok(),
+        None => false,
+    };
+    let Some(account) = account.filter(|_| verified) else {
+        return Err(login_failed("auth.login", &payload.username, &client, ApiError::InvalidCredentials).await);
+    };
+
+    let refused = if account.disabled_at.is_some() {
+        Some("Account is disabled")
+    } else if account.status == AccountStatus::Rejected {
+        Some("Account application was rejected")
+    } else {
+        None
+    };
+    if let Some(reason) = refused {
+        AuditEvent::action("auth.login", &client)
+            .actor(account.user_id, &payload.username)
+            .outcome(AuditOutcome::Denied)
+            .detail(reason)
+            .record()
+            .await;
+        return Err(ApiError::AuthenticationError(reason.to_string()));
+    }
+
+    // Accounts with two-factor authentication, or whose role requires it, need a second step
+    let two_factor_enabled = TwoFactor::is_enabled(account.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    let two_factor_required = TwoFactor::is_required_for(account.role)
         .await
-        .map_err(|_| ApiError::InvalidCredentials)?;
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if two_factor_enabled || two_factor_required {
+        let challenge = two_factor_challenge(&payload.username)?;
+        AuditEvent::action("auth.login", &client)
+            .actor(account.user_id, &payload.username)
+            .detail("Password accepted, second factor required")
+            .record()
+            .await;
+
+        return Ok(Json(LoginResponse {
+            message: "Two-factor authentication required".to_string(),
+            username: payload.username.clone(),
+            two_factor: Some(TwoFactorChallenge {
+                challenge,
+                setup_required: !two_factor_enabled,
+            }),
+        }).into_response());
+    }
 
-    // Generate JWT token
-    let token = encode_token(TokenClaims {
-        sub: payload.username.clone(),
-        exp: (sqlx::types::chrono::Utc::now().timestamp() as usize) + TOKEN_DURATION,
-    })
-    .map_err(|_| ApiError::InternalServerError)?;
+    AuthAttempt::record(AttemptKind::Login, &payload.username, &client.ip_address, true)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    AuditEvent::action("auth.login", &client)
+        .actor(account.user_id, &payload.username)
+        .record()
+        .await;
 
-    // Create response with cookie
-    let cookie = format!("token={}; Path=/; HttpOnly; SameSite=Strict", token);
+    // Create response with session cookies
     let mut response = Json(LoginResponse {
         message: "Login successful".to_string(),
-        username: payload.username,
+        username: payload.username.clone(),
+        two_factor: None,
     }).into_response();
+    start_session(&mut response, account.user_id, &payload.username, &client).await?;
 
-    response.headers_mut().insert(
-        header::SET_COOKIE,
-        HeaderValue::from_str(&cookie).unwrap(),
-    );
+    Ok(response)
+}
+
+/// Record a failed login step and pick the error to return, locking the account
+/// and emailing an unlock link once it has failed too often
+async fn login_failed(action: &'static str, username: &str, client: &ClientInfo, error: ApiError) -> ApiError {
+    AuditEvent::action(action, client)
+        .account(username)
+        .outcome(AuditOutcome::Failure)
+        .record()
+        .await;
+
+    let unlock_secret = match AuthAttempt::login_failed(username, &client.ip_address).await {
+        Ok(Some(unlock_secret)) => unlock_secret,
+        Ok(None) => return error,
+        Err(e) => return ApiError::DatabaseError(e.to_string()),
+    };
+
+    tracing::warn!("Locked account {} after repeated failed logins", username);
+    AuditEvent::action("auth.lockout", client)
+        .account(username)
+        .detail(format!("Locked for {} minutes", AccountLockout::DURATION_MINUTES))
+        .record()
+        .await;
+    if let Ok(user) = User::get(username.to_string()).await {
+        if let Err(e) = send_unlock(user.user_id(), &user.username(), &user.email(), &unlock_secret).await {
+            tracing::error!("Unlock email not sent: {:?}", e);
+        }
+    }
+    DatabaseError::AccountLocked(AccountLockout::DURATION_MINUTES * 60).into()
+}
+
+/// Issue a token proving the first login step succeeded for a user
+pub(crate) fn two_factor_challenge(username: &str) -> ApiResult<String> {
+    encode_token(TokenClaims {
+        sub: username.to_string(),
+        exp: (sqlx::types::chrono::Utc::now().timestamp() as usize) + TWO_FACTOR_CHALLENGE_DURATION,
+        purpose: TokenPurpose::TwoFactorChallenge,
+        sid: None,
+        jti: None,
+        email: None,
+        category: None,
+    })
+    .map_err(|_| ApiError::InternalServerError)
+}
+
+/// Decode a two-factor challenge and load the account it was issued for
+///
+/// Falls back to the challenge cookie set by single sign-on when the body has none.
+async fn two_factor_account(challenge: &str, headers: &HeaderMap) -> ApiResult<User> {
+    let challenge = match challenge {
+        "" => cookie::cookie_value(headers, cookie::TWO_FACTOR_COOKIE).unwrap_or_default(),
+        challenge => challenge.to_string(),
+    };
+    let claims = decode_token(&challenge, TokenPurpose::TwoFactorChallenge)
+        .map_err(|_| ApiError::AuthenticationError("Login expired, please sign in again".to_string()))?
+        .claims;
+    let user = User::get(claims.sub).await?;
+
+    if user.disabled() {
+        return Err(ApiError::AuthenticationError("Account is disabled".to_string()));
+    }
+    if user.status() == AccountStatus::Rejected {
+        return Err(ApiError::AuthenticationError("Account application was rejected".to_string()));
+    }
+
+    Ok(user)
+}
+
+/// Start two-factor enrollment during login, for accounts whose role requires it
+pub async fn login_two_factor_setup(
+    headers: HeaderMap,
+    Json(payload): Json<TwoFactorSetupRequest>,
+) -> ApiResult<Json<TwoFactorEnrollment>> {
+    let user = two_factor_account(&payload.challenge, &headers).await?;
+
+    let required = TwoFactor::is_required_for(user.role())
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if !required {
+        return Err(ApiError::ValidationError(
+            "Two-factor authentication can be set up from your settings after logging in".to_string(),
+        ));
+    }
+
+    let enrollment = TwoFactor::begin_enrollment(user.user_id(), &user.username()).await?;
+    Ok(Json(enrollment))
+}
+
+/// Finish a login with a TOTP or recovery code
+///
+/// If the account is still enrolling, a valid code also confirms the new
+/// authenticator and the response carries the recovery codes.
+pub async fn login_two_factor(
+    client: ClientInfo,
+    headers: HeaderMap,
+    Json(payload): Json<TwoFactorLoginRequest>,
+) -> ApiResult<impl IntoResponse> {
+    let user = two_factor_account(&payload.challenge, &headers).await?;
+    let username = user.username();
+
+    AuthAttempt::check_login(&username, &client.ip_address).await?;
+
+    let enabled = TwoFactor::is_enabled(user.user_id())
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    let recovery_codes = if enabled {
+        let verified = TwoFactor::verify(user.user_id(), &payload.code)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        if !verified {
+            let error = ApiError::AuthenticationError(DatabaseError::InvalidTwoFactorCode.to_string());
+            return Err(login_failed("auth.login.two_factor", &username, &client, error).await);
+        }
+        None
+    } else {
+        match TwoFactor::confirm_enrollment(user.user_id(), &payload.code).await {
+            Ok(codes) => Some(codes),
+            Err(DatabaseError::InvalidTwoFactorCode) => {
+                let error = ApiError::AuthenticationError(DatabaseError::InvalidTwoFactorCode.to_string());
+                return Err(login_failed("auth.login.two_factor", &username, &client, error).await);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+
+    AuthAttempt::record(AttemptKind::Login, &username, &client.ip_address, true)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    let mut event = AuditEvent::action("auth.login.two_factor", &client).actor(user.user_id(), &username);
+    if recovery_codes.is_some() {
+        event = event.detail("Enrolled an authenticator");
+    }
+    event.record().await;
+
+    let mut response = Json(serde_json::json!({
+        "message": "Login successful",
+        "username": username,
+        "recovery_codes": recovery_codes,
+    })).into_response();
+    response
+        .headers_mut()
+        .append(header::SET_COOKIE, cookie::remove_two_factor_cookie());
+    start_session(&mut response, user.user_id(), &username, &client).await?;
 
     Ok(response)
 }
 
-pub async fn logout() -> impl IntoResponse {
-    let remove_cookie = "token=; Path=/; HttpOnly; SameSite=Strict; Max-Age=0";
+/// End the current session, identified by the refresh cookie or the access token
+pub async fn logout(client: ClientInfo, headers: HeaderMap) -> ApiResult<impl IntoResponse> {
+    let mut event = AuditEvent::action("auth.logout", &client);
+    if let Some(claims) = crate::auth::extractor::token_from_headers(&headers)
+        .and_then(|token| decode_token(&token, TokenPurpose::Access).ok())
+    {
+        event = event.account(&claims.claims.sub);
+    }
+    event.record().await;
+
+    if let Some(refresh_token) = cookie::cookie_value(&headers, REFRESH_COOKIE) {
+        Session::revoke_by_refresh_token(&refresh_token)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    } else if let Some(claims) = crate::auth::extractor::token_from_headers(&headers)
+        .and_then(|token| decode_token(&token, TokenPurpose::Access).ok())
+        .map(|data| data.claims)
+    {
+        if let (Some(session_id), Ok(user)) = (claims.sid, User::get(claims.sub).await) {
+            Session::revoke(&session_id, user.user_id())
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        }
+    }
+
     let mut response = Json(serde_json::json!({
         "message": "Logout successful"
     })).into_response();
+    clear_session_cookies(&mut response);
 
-    response.headers_mut().insert(
-        header::SET_COOKIE,
-        HeaderValue::from_str(remove_cookie).unwrap(),
-    );
+    Ok(response)
+}
 
-    response
+/// Exchange the refresh cookie for a new access token and refresh token
+pub async fn refresh(client: ClientInfo, headers: HeaderMap) -> ApiResult<impl IntoResponse> {
+    let refresh_token = cookie::cookie_value(&headers, REFRESH_COOKIE)
+        .ok_or(ApiError::AuthenticationError("Not authenticated".to_string()))?;
+
+    let (session_id, user_id, refresh_token) =
+        Session::rotate(&refresh_token, &client.ip_address).await?;
+
+    let account = sqlx::query!(
+        "SELECT username, disabled_at FROM user WHERE user_id = $1",
+        user_id
+    )
+    .fetch_one(crate::database::get_db())
+    .await
+    .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    if account.disabled_at.is_some() {
+        Session::revoke(&session_id, user_id)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        return Err(ApiError::AuthenticationError("Account is disabled".to_string()));
+    }
+
+    let token = access_token(&account.username, &session_id)?;
+    let mut response = Json(serde_json::json!({
+        "message": "Session refreshed",
+        "username": account.username,
+    })).into_response();
+    set_session_cookies(&mut response, &token, &refresh_token);
+
+    Ok(response)
+}
+
+/// List the authenticated user's active sessions
+pub async fn get_sessions(user: AuthenticatedUser) -> ApiResult<Json<serde_json::Value>> {
+    let sessions: Vec<SessionResponse> = Session::for_user(user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+        .into_iter()
+        .map(|session| SessionResponse {
+            current: user.session_id.as_deref() == Some(session.session_id.as_str()),
+            session,
+        })
+        .collect();
+
+    Ok(Json(serde_json::json!({ "sessions": sessions })))
+}
+
+/// Revoke one of the authenticated user's sessions
+pub async fn revoke_session(
+    Path(session_id): Path<String>,
+    user: AuthenticatedUser,
+    client: ClientInfo,
+) -> ApiResult<impl IntoResponse> {
+    let revoked = Session::revoke(&session_id, user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if !revoked {
+        return Err(ApiError::NotFound("Session not found".to_string()));
+    }
+    AuditEvent::action("auth.session.revoke", &client)
+        .actor(user.user_id, &user.username)
+        .target("session", &session_id)
+        .record()
+        .await;
+
+    let mut response = Json(serde_json::json!({
+        "message": "Session revoked"
+    })).into_response();
+    if user.session_id.as_deref() == Some(session_id.as_str()) {
+        clear_session_cookies(&mut response);
+    }
+
+    Ok(response)
+}
+
+/// Revoke every session of the authenticated user, logging out all devices
+pub async fn revoke_all_sessions(user: AuthenticatedUser, client: ClientInfo) -> ApiResult<impl IntoResponse> {
+    let revoked = Session::revoke_all_for_user(user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    AuditEvent::action("auth.session.revoke_all", &client)
+        .actor(user.user_id, &user.username)
+        .detail(format!("Revoked {} sessions", revoked))
+        .record()
+        .await;
+
+    let mut response = Json(serde_json::json!({
+        "message": format!("Revoked {} sessions", revoked)
+    })).into_response();
+    clear_session_cookies(&mut response);
+
+    Ok(response)
 }
 
-pub async fn signup(Json(payload): Json<SignupRequest>) -> ApiResult<impl IntoResponse> {
+pub async fn signup(
+    client: ClientInfo,
+    headers: HeaderMap,
+    Json(payload): Json<SignupRequest>,
+) -> ApiResult<impl IntoResponse> {
+    let locale = payload.locale.unwrap_or_else(|| {
+        headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Locale::from_accept_language)
+            .unwrap_or_default()
+    });
+
     // Validate and create user
+    let email = payload.email.trim().to_string();
     let user = User::default()
         .set_username(payload.username.clone())
         .map_err(|e| ApiError::ValidationError(e.to_string()))?
-        .set_email(payload.email)
+        .set_email(email.clone())
         .map_err(|e| ApiError::ValidationError(e.to_string()))?
         .set_password(payload.password)
         .map_err(|e| ApiError::ValidationError(e.to_string()))?
         .set_bio(payload.bio)
         .map_err(|e| ApiError::ValidationError(e.to_string()))?
-        .set_email_notifications(payload.email_notifications);
+        .set_locale(locale);
+
+    if let Some(version) = payload.agreement_version {
+        let current = DataUseAgreement::current()
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        if current.version != version {
+            return Err(crate::models::DatabaseError::AgreementVersionMismatch.into());
+        }
+    }
 
     // Insert user into database
-    user.insert().await?;
+    let user_id = user.insert().await?.last_insert_rowid();
 
-    // Generate JWT token
-    let token = encode_token(TokenClaims {
-        sub: payload.username.clone(),
-        exp: (sqlx::types::chrono::Utc::now().timestamp() as usize) + TOKEN_DURATION,
-    })
-    .map_err(|_| ApiError::InternalServerError)?;
+    if let Some(version) = payload.agreement_version {
+        DataUseAgreement::accept(user_id, version).await?;
+    }
+    if payload.email_notifications {
+        let preferences = [NotificationKind::QueryCompleted, NotificationKind::QueryFailed].map(|kind| {
+            NotificationPreference {
+                kind,
+                channel: NotificationChannel::Email,
+            }
+        });
+        NotificationPreference::set(user_id, &preferences)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    }
+    AuditEvent::action("auth.signup", &client)
+        .actor(user_id, &payload.username)
+        .target("user", user_id)
+        .record()
+        .await;
 
-    // Create response with cookie
-    let cookie = format!("token={}; Path=/; HttpOnly; SameSite=Strict", token);
+    // Create response with session cookies
     let mut response = Json(SignupResponse {
         message: "Signup successful".to_string(),
-        username: payload.username,
+        username: payload.username.clone(),
     }).into_response();
+    start_session(&mut response, user_id, &payload.username, &client).await?;
 
-    response.headers_mut().insert(
-        header::SET_COOKIE,
-        HeaderValue::from_str(&cookie).unwrap(),
-    );
+    // The account works without a verified address, but cannot submit queries yet
+    if let Err(e) = send_verification(user_id, &payload.username, &email).await {
+        tracing::error!("Signup verification email not sent: {:?}", e);
+    }
 
     Ok(response)
 }
 
-pub async fn me(request: Request) -> ApiResult<Json<UserResponse>> {
-    let username = crate::auth::middleware::get_username_from_request(&request)
-        .ok_or(ApiError::AuthenticationError("Not authenticated".to_string()))?;
+/// Confirm an email address from a verification link
+pub async fn verify_email(
+    client: ClientInfo,
+    Json(payload): Json<VerifyEmailRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let claims = decode_token(&payload.token, TokenPurpose::EmailVerification)
+        .map_err(|_| crate::models::DatabaseError::InvalidVerificationToken)?
+        .claims;
+    let email = claims
+        .email
+        .ok_or(crate::models::DatabaseError::InvalidVerificationToken)?;
+
+    User::verify_email(&claims.sub, &email).await?;
+    AuditEvent::action("auth.email.verify", &client)
+        .account(&claims.sub)
+        .detail(email)
+        .record()
+        .await;
+
+    Ok(Json(serde_json::json!({
+        "message": "Email address verified"
+    })))
+}
 
-    let user = User::get(username)
+/// Lift a login lockout from an unlock link
+pub async fn unlock_account(
+    client: ClientInfo,
+    Json(payload): Json<UnlockAccountRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let account = AccountLockout::unlock(&payload.token).await?;
+    tracing::info!("Account {} unlocked from email link", account);
+    AuditEvent::action("auth.unlock", &client)
+        .account(&account)
+        .record()
+        .await;
+
+    Ok(Json(serde_json::json!({
+        "message": "Account unlocked. You can log in again."
+    })))
+}
+
+/// Send a new verification link for the pending or unverified address
+pub async fn resend_verification(user: AuthenticatedUser) -> ApiResult<Json<serde_json::Value>> {
+    let user = User::get(user.username).await?;
+
+    let email = match user.pending_email() {
+        Some(pending_email) => pending_email,
+        None if !user.email_verified() => user.email(),
+        None => {
+            return Err(ApiError::ValidationError(
+                "Email address is already verified".to_string(),
+            ))
+        }
+    };
+
+    send_verification(user.user_id(), &user.username(), &email).await?;
+
+    Ok(Json(serde_json::json!({
+        "message": format!("Verification email sent to {}", email)
+    })))
+}
+
+/// Get the current data-use agreement
+pub async fn get_agreement() -> ApiResult<Json<DataUseAgreement>> {
+    let agreement = DataUseAgreement::current()
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(agreement))
+}
+
+/// Accept the current data-use agreement
+pub async fn accept_agreement(
+    user: AuthenticatedUser,
+    client: ClientInfo,
+    Json(payload): Json<AcceptAgreementRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    DataUseAgreement::accept(user.user_id, payload.version).await?;
+    AuditEvent::action("agreement.accept", &client)
+        .actor(user.user_id, &user.username)
+        .target("agreement", payload.version)
+        .record()
+        .await;
+
+    Ok(Json(serde_json::json!({
+        "message": "Data-use agreement accepted"
+    })))
+}
+
+pub async fn me(user: AuthenticatedUser) -> ApiResult<Json<UserResponse>> {
+    let agreement_accepted = DataUseAgreement::has_accepted_current(user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    let two_factor_enabled = TwoFactor::is_enabled(user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    let deletion_scheduled_for = AccountDeletion::scheduled_for(user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    let has_password = User::has_password(user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    let user = User::get(user.username)
         .await
         .map_err(|_| ApiError::UserNotFound)?;
 
@@ -190,28 +798,52 @@ pub async fn me(request: Request) -> ApiResult<Json<UserResponse>> {
         username: user.username(),
         email: user.email(),
         bio: user.bio().unwrap_or_default(),
-        email_notifications: user.email_notifications(),
+        locale: user.locale(),
+        role: user.role(),
+        email_verified: user.email_verified(),
+        pending_email: user.pending_email(),
+        status: user.status(),
+        agreement_accepted,
+        two_factor_enabled,
+        has_password,
+        deletion_scheduled_for,
     }))
 }
 
-pub async fn reset_password(Json(payload): Json<ResetPasswordRequest>) -> ApiResult<Json<ResetPasswordResponse>> {
+pub async fn reset_password(
+    client: ClientInfo,
+    Json(payload): Json<ResetPasswordRequest>,
+) -> ApiResult<Json<ResetPasswordResponse>> {
     use std::env;
-    
+
+    // Limit how many reset emails an address or a client can trigger
+    let account = payload.email.trim().to_lowercase();
+    AuthAttempt::check_password_reset(&account, &client.ip_address).await?;
+
     // Check if user exists
-    match crate::models::User::get_email(payload.email.clone()).await {
-        Ok(_) => {
-            // User exists, proceed with email sending
-            let mailer_email = env::var("MAILER_EMAIL")
-                .map_err(|_| ApiError::InternalServerError)?;
-            let mailer_passwd = env::var("MAILER_PASSWD")
-                .map_err(|_| ApiError::InternalServerError)?;
-            let smtp_server = env::var("MAILER_SMTP_SERVER")
-                .map_err(|_| ApiError::InternalServerError)?;
+    let user = crate::models::User::get_email(payload.email.clone()).await;
+    AuthAttempt::record(AttemptKind::PasswordReset, &account, &client.ip_address, user.is_ok())
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    let event = AuditEvent::action("auth.password_reset.request", &client).account(&account);
+    match &user {
+        Ok(user) => event.target("user", user.user_id()).record().await,
+        Err(_) => event.outcome(AuditOutcome::Failure).detail("No account with this email").record().await,
+    }
 
-            // Generate JWT token for password reset (valid for 1 hour)
+    match user {
+        Ok(user) => {
+            // Generate single-use JWT token for password reset (valid for 1 hour)
+            let secret = PasswordReset::create(user.user_id()).await?;
             let token = encode_token(TokenClaims {
-                sub: payload.email.clone(),
-                exp: (sqlx::types::chrono::Utc::now().timestamp() as usize) + 3600,
+                sub: user.username(),
+                exp: (sqlx::types::chrono::Utc::now().timestamp() as usize)
+                    + PasswordReset::DURATION_SECONDS as usize,
+                purpose: TokenPurpose::PasswordReset,
+                sid: None,
+                jti: Some(secret),
+                email: None,
+                category: None,
             })
             .map_err(|_| ApiError::InternalServerError)?;
 
@@ -220,34 +852,23 @@ pub async fn reset_password(Json(payload): Json<ResetPasswordRequest>) -> ApiRes
                 .map_err(|_| ApiError::InternalServerError)?;
             let reset_url = format!("{}/reset-password?token={}", site_base_url, token);
 
-            // Build email message
-            let message = mail_send::mail_builder::MessageBuilder::new()
-                .from(("GLAD", mailer_email.as_str()))
-                .to(vec![("User", payload.email.as_str())])
-                .subject("Password Reset - GLAD")
-                .text_body(format!(
-                    "You can reset your password by clicking the following link: {}\n\nThis link will expire in 1 hour.",
-                    reset_url
-                ));
-
-            // Send email
-            match mail_send::SmtpClientBuilder::new(smtp_server.as_str(), 587)
-                .implicit_tls(false)
-                .credentials((mailer_email.as_str(), mailer_passwd.as_str()))
-                .connect()
-                .await
-            {
-                Ok(mut client) => {
-                    if let Err(e) = client.send(message).await {
-                        tracing::error!("Failed to send email: {}", e);
-                        return Err(ApiError::InternalServerError);
-                    }
-                }
-                Err(e) => {
-                    tracing::error!("Failed to connect to SMTP server: {}", e);
-                    return Err(ApiError::InternalServerError);
-                }
-            }
+            let email = EmailTemplate::PasswordReset.render(
+                user.locale(),
+                &[("username", &user.username()), ("reset_url", &reset_url)],
+            );
+
+            EmailOutbox::enqueue(NewEmail {
+                user_id: Some(user.user_id()),
+                kind: "password_reset",
+                recipient_name: &user.username(),
+                recipient_email: &user.email(),
+                subject: email.subject,
+                text_body: email.text_body,
+                html_body: Some(email.html_body),
+                unsubscribe_url: None,
+            })
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
         }
         Err(_) => {
             // User doesn't exist, but don't reveal this for security
@@ -261,84 +882,93 @@ pub async fn reset_password(Json(payload): Json<ResetPasswordRequest>) -> ApiRes
     }))
 }
 
-pub async fn reset_password_confirm(Json(payload): Json<ResetPasswordConfirmRequest>) -> ApiResult<Json<ResetPasswordConfirmResponse>> {
+pub async fn reset_password_confirm(
+    client: ClientInfo,
+    Json(payload): Json<ResetPasswordConfirmRequest>,
+) -> ApiResult<Json<ResetPasswordConfirmResponse>> {
     // Validate passwords match
     if payload.password != payload.confirm {
         return Err(ApiError::ValidationError("Passwords do not match".to_string()));
     }
 
-    // Validate password strength using shared validation
-    crate::models::User::validate_password(&payload.password)
-        .map_err(|e| ApiError::ValidationError(e.to_string()))?;
-
     // Decode and validate token
-    let claims = crate::auth::jwt::decode_token(&payload.token)
-        .map_err(|_| ApiError::ValidationError("Invalid or expired reset token".to_string()))?;
-
-    let email = claims.claims.sub;
+    let claims = decode_token(&payload.token, TokenPurpose::PasswordReset)
+        .map_err(|_| ApiError::ValidationError("Invalid or expired reset token".to_string()))?
+        .claims;
+    let secret = claims
+        .jti
+        .ok_or(ApiError::ValidationError("Invalid or expired reset token".to_string()))?;
+    let username = PasswordReset::username_for(&secret).await?;
+    let user = User::get(username).await?;
+
+    // Check the new password before using up the link, so a refused password can be retried
+    User::validate_password(&payload.password, &[&user.username(), &user.email()])
+        .map_err(|e| ApiError::ValidationError(e.to_string()))?;
+    PasswordReset::complete(&secret, &payload.password).await?;
 
-    // Get user by email
-    let mut user = crate::models::User::get_email(email.clone())
+    // Log out every device that used the old password
+    Session::revoke_all_for_user(user.user_id())
         .await
-        .map_err(|_| ApiError::UserNotFound)?;
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
 
-    // Update password
-    user = user.set_password(payload.password)
-        .map_err(|e| ApiError::ValidationError(e.to_string()))?;
-
-    // Save updated user
-    user.update()
+    // Proving control of the mailbox also lifts a lockout
+    AccountLockout::clear_for_user(user.user_id())
         .await
-        .map_err(|e| {
-            tracing::error!("Failed to update user password: {}", e);
-            ApiError::InternalServerError
-        })?;
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    AuditEvent::action("auth.password_reset.complete", &client)
+        .actor(user.user_id(), &user.username())
+        .record()
+        .await;
 
     Ok(Json(ResetPasswordConfirmResponse {
         message: "Password successfully reset. You can now log in with your new password.".to_string(),
     }))
 }
 
-pub async fn update_settings(request: Request) -> ApiResult<Json<UpdateSettingsResponse>> {
-    let username = crate::auth::middleware::get_username_from_request(&request)
-        .ok_or(ApiError::AuthenticationError("Not authenticated".to_string()))?;
-
-    // Extract JSON payload from request
-    let payload: UpdateSettingsRequest = {
-        let bytes = axum::body::to_bytes(request.into_body(), usize::MAX)
-            .await
-            .map_err(|_| ApiError::ValidationError("Invalid request body".to_string()))?;
-        serde_json::from_slice(&bytes)
-            .map_err(|_| ApiError::ValidationError("Invalid JSON payload".to_string()))?
-    };
+pub async fn update_settings(
+    current_user: AuthenticatedUser,
+    client: ClientInfo,
+    Json(payload): Json<UpdateSettingsRequest>,
+) -> ApiResult<impl IntoResponse> {
+    let password_changed = !payload.password.is_empty();
 
     // Get current user
-    let mut user = User::get(username)
+    let mut user = User::get(current_user.username.clone())
         .await
         .map_err(|_| ApiError::UserNotFound)?;
 
-    // Update bio, email, and email notifications
+    // Everything is checked before anything is saved
+    if password_changed && payload.password != payload.confirm_password {
+        return Err(ApiError::ValidationError("Passwords do not match".to_string()));
+    }
+
+    // Update bio and language
+    let locale = payload.locale.unwrap_or(user.locale());
     user = user.set_bio(payload.bio)
         .map_err(|e| ApiError::ValidationError(e.to_string()))?
-        .set_email(payload.email)
-        .map_err(|e| ApiError::ValidationError(e.to_string()))?
-        .set_email_notifications(payload.email_notifications);
+        .set_locale(locale);
+
+    // Changing where reset links go, or the password itself, would let a stolen session take the account
+    let new_email = payload.email.trim().to_string();
+    let email_changed = new_email != user.email();
+    if email_changed || password_changed {
+        confirm_password(&current_user, &client, payload.current_password).await?;
+    }
+
+    // A new email address only takes effect once it is verified
+    if email_changed {
+        user.check_email_available(&new_email).await?;
+        user = user.set_pending_email(new_email.clone())?;
+    }
 
     // If password is provided, validate and update it
-    if !payload.password.is_empty() {
-        if payload.password != payload.confirm_password {
-            return Err(ApiError::ValidationError("Passwords do not match".to_string()));
-        }
-        
-        // Validate password strength using shared validation
-        crate::models::User::validate_password(&payload.password)
-            .map_err(|e| ApiError::ValidationError(e.to_string()))?;
-        
+    if password_changed {
         user = user.set_password(payload.password)
             .map_err(|e| ApiError::ValidationError(e.to_string()))?;
     }
 
-    // Save updated user
+    // Save every change in one statement
     user.update()
         .await
         .map_err(|e| {
@@ -346,7 +976,38 @@ pub async fn update_settings(request: Request) -> ApiResult<Json<UpdateSettingsR
             ApiError::from(crate::models::DatabaseError::from(e))
         })?;
 
-    Ok(Json(UpdateSettingsResponse {
-        message: "Settings updated successfully".to_string(),
-    }))
+    if email_changed {
+        AuditEvent::action("auth.email.change", &client)
+            .actor(current_user.user_id, &current_user.username)
+            .detail(format!("Requested change to {}", new_email))
+            .record()
+            .await;
+    }
+    if password_changed {
+        AuditEvent::action("auth.password.change", &client)
+            .actor(current_user.user_id, &current_user.username)
+            .record()
+            .await;
+    }
+
+    let message = if email_changed {
+        if let Err(e) = send_verification(current_user.user_id, &current_user.username, &new_email).await {
+            tracing::error!("Email change verification not sent: {:?}", e);
+        }
+        format!("Settings updated. Check {} to confirm your new email address", new_email)
+    } else {
+        "Settings updated successfully".to_string()
+    };
+
+    let mut response = Json(UpdateSettingsResponse { message }).into_response();
+
+    // A new password logs out every other device; this one gets a fresh session
+    if password_changed {
+        Session::revoke_all_for_user(current_user.user_id)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        start_session(&mut response, current_user.user_id, &current_user.username, &client).await?;
+    }
+
+    Ok(response)
 }
\ No newline at end of file
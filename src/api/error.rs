@@ -1,5 +1,5 @@
 use axum::{
-    http::StatusCode,
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
@@ -10,6 +10,14 @@ pub enum ApiError {
     ValidationError(String),
     DatabaseError(String),
     AuthenticationError(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    QuotaExceeded(String),
+    /// Rate limited, with the number of seconds to send in `Retry-After`
+    TooManyRequests(String, i64),
+    /// Temporarily unavailable, with the number of seconds to send in `Retry-After` if known
+    ServiceUnavailable(String, Option<i64>),
     UserNotFound,
     UsernameAlreadyExists,
     EmailAlreadyExists,
@@ -19,10 +27,22 @@ pub enum ApiError {
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
+        let retry_after = match &self {
+            ApiError::TooManyRequests(_, seconds) => Some(*seconds),
+            ApiError::ServiceUnavailable(_, seconds) => *seconds,
+            _ => None,
+        };
+
         let (status, error_message) = match self {
             ApiError::ValidationError(msg) => (StatusCode::BAD_REQUEST, msg),
             ApiError::DatabaseError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
             ApiError::AuthenticationError(msg) => (StatusCode::UNAUTHORIZED, msg),
+            ApiError::Forbidden(msg) => (StatusCode::FORBIDDEN, msg),
+            ApiError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
+            ApiError::Conflict(msg) => (StatusCode::CONFLICT, msg),
+            ApiError::QuotaExceeded(msg) => (StatusCode::TOO_MANY_REQUESTS, msg),
+            ApiError::TooManyRequests(msg, _) => (StatusCode::TOO_MANY_REQUESTS, msg),
+            ApiError::ServiceUnavailable(msg, _) => (StatusCode::SERVICE_UNAVAILABLE, msg),
             ApiError::UserNotFound => (StatusCode::NOT_FOUND, "User not found".to_string()),
             ApiError::UsernameAlreadyExists => (StatusCode::CONFLICT, "Username already exists".to_string()),
             ApiError::EmailAlreadyExists => (StatusCode::CONFLICT, "Email already exists".to_string()),
@@ -35,7 +55,13 @@ impl IntoResponse for ApiError {
             "status": status.as_u16()
         }));
 
-        (status, body).into_response()
+        let mut response = (status, body).into_response();
+        if let Some(seconds) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(seconds.max(1)));
+        }
+        response
     }
 }
 
@@ -49,12 +75,64 @@ impl From<crate::models::DatabaseError> for ApiError {
             
             // Map validation errors to ValidationError with their display message
             crate::models::DatabaseError::UsernameTooShort => ApiError::ValidationError(error.to_string()),
-            crate::models::DatabaseError::PasswordTooWeak => ApiError::ValidationError(error.to_string()),
+            crate::models::DatabaseError::PasswordTooWeak(_) => ApiError::ValidationError(error.to_string()),
             crate::models::DatabaseError::InvalidEmail => ApiError::ValidationError(error.to_string()),
             
             // Map query errors appropriately
             crate::models::DatabaseError::QueryNotFound => ApiError::UserNotFound, // or create new variant
             crate::models::DatabaseError::CohortNotFound => ApiError::UserNotFound, // or create new variant
+
+            // Map organization errors
+            crate::models::DatabaseError::OrganizationNotFound
+            | crate::models::DatabaseError::MemberNotFound
+            | crate::models::DatabaseError::InvitationNotFound => ApiError::NotFound(error.to_string()),
+            crate::models::DatabaseError::OrganizationNameAlreadyExists
+            | crate::models::DatabaseError::InvitationAlreadyExists
+            | crate::models::DatabaseError::LastOrganizationOwner => ApiError::Conflict(error.to_string()),
+            crate::models::DatabaseError::InvalidOrganizationName
+            | crate::models::DatabaseError::InvalidOrganizationSettings => ApiError::ValidationError(error.to_string()),
+
+            // Map session errors
+            crate::models::DatabaseError::InvalidSession => ApiError::AuthenticationError(error.to_string()),
+            crate::models::DatabaseError::InvalidResetToken
+            | crate::models::DatabaseError::InvalidVerificationToken => ApiError::ValidationError(error.to_string()),
+
+            // Map account approval errors
+            crate::models::DatabaseError::InvalidAgreement => ApiError::ValidationError(error.to_string()),
+            crate::models::DatabaseError::AgreementVersionMismatch => ApiError::Conflict(error.to_string()),
+
+            // Map API token errors
+            crate::models::DatabaseError::ApiTokenNotFound => ApiError::NotFound(error.to_string()),
+            crate::models::DatabaseError::InvalidApiTokenName
+            | crate::models::DatabaseError::InvalidApiTokenScopes
+            | crate::models::DatabaseError::InvalidApiTokenExpiry => ApiError::ValidationError(error.to_string()),
+
+            // Map webhook errors
+            crate::models::DatabaseError::WebhookNotFound => ApiError::NotFound(error.to_string()),
+            crate::models::DatabaseError::InvalidWebhookUrl
+            | crate::models::DatabaseError::InternalWebhookUrl
+            | crate::models::DatabaseError::InvalidWebhookDescription
+            | crate::models::DatabaseError::InvalidWebhookSecret => ApiError::ValidationError(error.to_string()),
+
+            // Map announcement errors
+            crate::models::DatabaseError::AnnouncementNotFound => ApiError::NotFound(error.to_string()),
+            crate::models::DatabaseError::InvalidAnnouncementText
+            | crate::models::DatabaseError::InvalidAnnouncementSchedule => ApiError::ValidationError(error.to_string()),
+
+            // Map brute-force protection errors
+            crate::models::DatabaseError::TooManyAttempts(seconds)
+            | crate::models::DatabaseError::AccountLocked(seconds) => ApiError::TooManyRequests(error.to_string(), seconds),
+            crate::models::DatabaseError::InvalidUnlockToken => ApiError::ValidationError(error.to_string()),
+
+            // Map two-factor authentication errors
+            crate::models::DatabaseError::InvalidTwoFactorCode
+            | crate::models::DatabaseError::TwoFactorNotEnabled
+            | crate::models::DatabaseError::TwoFactorNotPending => ApiError::ValidationError(error.to_string()),
+            crate::models::DatabaseError::TwoFactorAlreadyEnabled => ApiError::Conflict(error.to_string()),
+            crate::models::DatabaseError::TwoFactorRequired => ApiError::Forbidden(error.to_string()),
+
+            // Map account deletion errors
+            crate::models::DatabaseError::AccountDeletionScheduled => ApiError::Forbidden(error.to_string()),
         }
     }
 }
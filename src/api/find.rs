@@ -1,6 +1,5 @@
 use axum::{
-    extract::{Path, Request, Multipart},
-    http::HeaderMap,
+    extract::{Path, Multipart},
     Json,
 };
 use serde::{Deserialize, Serialize};
@@ -8,7 +7,9 @@ use std::path::PathBuf;
 use tokio::fs;
 
 use crate::api::{ApiError, ApiResult};
-use crate::models::{Cohort, Query};
+use crate::auth::scope::{QueryReader, QueryWriter};
+use crate::auth::ClientInfo;
+use crate::models::{AccountDeletion, AccountStatus, Announcement, AuditEvent, AuditOutcome, Cohort, DataUseAgreement, Organization, Query, Role, User};
 
 #[derive(Debug, Deserialize)]
 pub struct FindControlsRequest {
@@ -41,20 +42,61 @@ pub async fn get_cohorts() -> ApiResult<Json<CohortsResponse>> {
     Ok(Json(CohortsResponse { cohorts }))
 }
 
-pub async fn submit_find_controls(headers: HeaderMap, mut multipart: Multipart) -> ApiResult<Json<FindControlsResponse>> {
-    // Use existing function to get username from headers
-    let username = crate::auth::get_username_from_headers(&headers)
-        .ok_or(ApiError::AuthenticationError("Not authenticated".to_string()))?;
+pub async fn submit_find_controls(
+    user: QueryWriter,
+    client: ClientInfo,
+    mut multipart: Multipart,
+) -> ApiResult<Json<FindControlsResponse>> {
+    // user_id is used for the directory structure
+    let user_id = user.user_id;
 
-    // Get user_id for directory structure
-    let user_id = sqlx::query_scalar!("SELECT user_id FROM user WHERE username = $1", username)
-        .fetch_one(crate::database::get_db())
+    // Nothing is accepted while the pipeline is down for maintenance
+    if let Some(maintenance) = Announcement::maintenance()
         .await
-        .map_err(|e| {
-            tracing::error!("Failed to get user_id: {}", e);
-            ApiError::UserNotFound
-        })?
-        .ok_or(ApiError::UserNotFound)?;
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+    {
+        let retry = match &maintenance.ends_at {
+            Some(ends_at) => format!("Please try again after {} UTC.", ends_at),
+            None => "Please try again later.".to_string(),
+        };
+        return Err(ApiError::ServiceUnavailable(
+            format!(
+                "Query submissions are paused for maintenance: {} {}",
+                maintenance.message, retry
+            ),
+            maintenance.seconds_remaining(),
+        ));
+    }
+
+    // Submitting needs a verified email, an approved account and the current data-use agreement
+    let account = User::get(user.username.clone()).await?;
+    if !account.email_verified() {
+        return Err(ApiError::Forbidden(
+            "Please verify your email address before submitting queries".to_string(),
+        ));
+    }
+    match account.status() {
+        AccountStatus::Active => {}
+        AccountStatus::PendingApproval => {
+            return Err(ApiError::Forbidden(
+                "Your account is awaiting administrator approval".to_string(),
+            ))
+        }
+        AccountStatus::Rejected => {
+            return Err(ApiError::Forbidden(
+                "Your account application was rejected".to_string(),
+            ))
+        }
+    }
+    if !DataUseAgreement::has_accepted_current(user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+    {
+        return Err(ApiError::Forbidden(
+            "Please accept the current data-use agreement before submitting queries".to_string(),
+        ));
+    }
+    AccountDeletion::ensure_not_scheduled(user_id).await?;
 
     // Parse form fields
     let mut title = String::new();
@@ -62,6 +104,7 @@ pub async fn submit_find_controls(headers: HeaderMap, mut multipart: Multipart)
     let mut self_described_latino = false;
     let mut n_controls = 100usize;
     let mut excluded_cohorts = Vec::new();
+    let mut organization_id: Option<i64> = None;
     let mut file_data: Option<Vec<u8>> = None;
 
     while let Some(field) = multipart.next_field().await
@@ -109,6 +152,18 @@ pub async fn submit_find_controls(headers: HeaderMap, mut multipart: Multipart)
                 excluded_cohorts = serde_json::from_str(&value).map_err(|_|
                     ApiError::ValidationError("Invalid excluded_cohorts JSON".to_string()))?;
             },
+            "organization_id" => {
+                let data = field.bytes().await.map_err(|_|
+                    ApiError::ValidationError("Failed to read organization_id".to_string()))?;
+                let value = String::from_utf8(data.to_vec()).map_err(|_|
+                    ApiError::ValidationError("Invalid organization_id encoding".to_string()))?;
+                organization_id = if value.trim().is_empty() {
+                    None
+                } else {
+                    Some(value.trim().parse().map_err(|_|
+                        ApiError::ValidationError("Invalid organization_id format".to_string()))?)
+                };
+            },
             "query_file" => {
                 file_data = Some(field.bytes().await.map_err(|_|
                     ApiError::ValidationError("Failed to read file data".to_string()))?.to_vec());
@@ -141,6 +196,30 @@ pub async fn submit_find_controls(headers: HeaderMap, mut multipart: Multipart)
         return Err(ApiError::ValidationError("File size exceeds 10MB limit".to_string()));
     }
 
+    // Organization queries require submit rights and must fit within the organization quota
+    if let Some(organization_id) = organization_id {
+        let role = Organization::role_for_user(organization_id, user_id)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+            .ok_or(ApiError::NotFound("Organization not found".to_string()))?;
+        if !role.can_submit() {
+            return Err(ApiError::Forbidden("Viewers cannot submit queries to this organization".to_string()));
+        }
+
+        let organization = Organization::get(organization_id).await?;
+        if let Some(quota) = organization.query_quota {
+            let recent = Organization::recent_query_count(organization_id)
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+            if recent >= quota {
+                return Err(ApiError::QuotaExceeded(format!(
+                    "{} has reached its quota of {} queries per {} days",
+                    organization.name, quota, Organization::QUOTA_WINDOW_DAYS
+                )));
+            }
+        }
+    }
+
     // Get query root path from environment
     let query_root = std::env::var("QUERY_PATH_ROOT")
         .map_err(|_| ApiError::InternalServerError)?;
@@ -168,7 +247,8 @@ pub async fn submit_find_controls(headers: HeaderMap, mut multipart: Multipart)
 
     // Insert query with the temporary file path (will be updated later)
     let query_id = Query::insert(
-        username.clone(),
+        user_id,
+        organization_id,
         title.trim().to_string(),
         description,
         self_described_latino,
@@ -202,7 +282,7 @@ pub async fn submit_find_controls(headers: HeaderMap, mut multipart: Multipart)
         })?;
 
     // Clean up temporary directory (remove the entire UUID directory)
-    let temp_uuid_dir = PathBuf::from(&query_root).join(&user_id.to_string()).join(&temp_query_uuid);
+    let temp_uuid_dir = PathBuf::from(&query_root).join(user_id.to_string()).join(&temp_query_uuid);
     let _ = fs::remove_dir_all(&temp_uuid_dir).await;
 
     // Update the query with the correct file path
@@ -215,6 +295,12 @@ pub async fn submit_find_controls(headers: HeaderMap, mut multipart: Multipart)
             ApiError::InternalServerError
         })?;
 
+    AuditEvent::action("query.submit", &client)
+        .actor(user.user_id, &user.username)
+        .target("query", query_id)
+        .record()
+        .await;
+
     Ok(Json(FindControlsResponse {
         query_id,
         message: "Query submitted successfully".to_string(),
@@ -226,11 +312,8 @@ pub struct UserQueriesResponse {
     pub queries: Vec<Query>,
 }
 
-pub async fn get_user_queries(request: Request) -> ApiResult<Json<UserQueriesResponse>> {
-    let username = crate::auth::middleware::get_username_from_request(&request)
-        .ok_or(ApiError::AuthenticationError("Not authenticated".to_string()))?;
-
-    let queries = Query::for_user_profile(username)
+pub async fn get_user_queries(user: QueryReader) -> ApiResult<Json<UserQueriesResponse>> {
+    let queries = Query::for_user_profile(user.user_id)
         .await
         .map_err(|e| {
             tracing::error!("Failed to retrieve user queries: {}", e);
@@ -240,10 +323,11 @@ pub async fn get_user_queries(request: Request) -> ApiResult<Json<UserQueriesRes
     Ok(Json(UserQueriesResponse { queries }))
 }
 
-pub async fn get_query_details(Path(query_id): Path<i64>, request: Request) -> ApiResult<Json<Query>> {
-    let username = crate::auth::middleware::get_username_from_request(&request)
-        .ok_or(ApiError::AuthenticationError("Not authenticated".to_string()))?;
-
+pub async fn get_query_details(
+    Path(query_id): Path<i64>,
+    user: QueryReader,
+    client: ClientInfo,
+) -> ApiResult<Json<Query>> {
     let query = Query::for_query(query_id)
         .await
         .map_err(|e| {
@@ -251,21 +335,31 @@ pub async fn get_query_details(Path(query_id): Path<i64>, request: Request) -> A
             ApiError::UserNotFound
         })?;
 
-    // Verify the query belongs to the authenticated user
-    let user = crate::models::User::get(username.clone())
-        .await
-        .map_err(|_| ApiError::UserNotFound)?;
-    
-    // Convert user_id to i64 for comparison (assuming user table has integer IDs)
-    let user_id = sqlx::query_scalar!("SELECT user_id FROM user WHERE username = $1", username)
-        .fetch_one(crate::database::get_db())
-        .await
-        .map_err(|_| ApiError::UserNotFound)?;
-
-    let user_id = user_id.ok_or(ApiError::UserNotFound)?;
+    // Verify the query belongs to the authenticated user or one of their organizations
+    // Reviewers and admins can view any query
+    let accessible = user.has_role(Role::Reviewer)
+        || query
+            .is_accessible_by(user.user_id)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if !accessible {
+        AuditEvent::action("query.view", &client)
+            .actor(user.user_id, &user.username)
+            .target("query", query_id)
+            .outcome(AuditOutcome::Denied)
+            .record()
+            .await;
+        return Err(ApiError::Forbidden("Access denied".to_string()));
+    }
 
-    if query.user_id != user_id {
-        return Err(ApiError::AuthenticationError("Access denied".to_string()));
+    // Someone else's query, seen through an organization or a reviewer role
+    if query.user_id != user.user_id {
+        AuditEvent::action("query.view", &client)
+            .actor(user.user_id, &user.username)
+            .target("query", query_id)
+            .detail(format!("Query owned by user {}", query.user_id))
+            .record()
+            .await;
     }
 
     Ok(Json(query))
@@ -1,8 +1,16 @@
+pub mod account;
+pub mod admin;
+pub mod announcements;
 pub mod auth;
 pub mod error;
 pub mod explore;
 pub mod find;
 pub mod notifications;
+pub mod oidc;
+pub mod organizations;
 pub mod publication;
+pub mod tokens;
+pub mod two_factor;
+pub mod webhooks;
 
 pub use error::{ApiError, ApiResult};
\ No newline at end of file
@@ -1,66 +1,70 @@
 use axum::{
+    extract::Query,
     response::Json,
 };
 use serde::{Deserialize, Serialize};
 
 use crate::{
     api::{ApiError, ApiResult},
-    auth::get_username_from_headers,
-    models::Notification,
+    auth::{
+        jwt::{decode_token, TokenPurpose},
+        AuthenticatedUser, ClientInfo,
+    },
+    models::{
+        AuditEvent, DigestSchedule, Notification, NotificationChannel, NotificationFilter, NotificationPage,
+        NotificationPreference, User,
+    },
 };
 
 #[derive(Serialize)]
 pub struct NotificationsResponse {
-    pub notifications: Vec<Notification>,
+    #[serde(flatten)]
+    pub page: NotificationPage,
     pub unread_count: i64,
 }
 
 #[derive(Deserialize)]
-pub struct MarkAsReadRequest {
+pub struct NotificationIdsRequest {
     pub notification_ids: Vec<i64>,
 }
 
-/// Get all notifications for the authenticated user
+#[derive(Deserialize)]
+pub struct UnsubscribeParams {
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PreferencesBody {
+    pub preferences: Vec<NotificationPreference>,
+    /// When digests are sent; left as it is if missing
+    #[serde(default)]
+    pub digest: Option<DigestSchedule>,
+}
+
+/// A page of the authenticated user's notifications, newest first
 pub async fn get_notifications(
-    headers: axum::http::HeaderMap,
+    user: AuthenticatedUser,
+    Query(filter): Query<NotificationFilter>,
 ) -> ApiResult<Json<NotificationsResponse>> {
-    let username = get_username_from_headers(&headers)
-        .ok_or(ApiError::AuthenticationError("Not authenticated".to_string()))?;
-    
-    let user_id = sqlx::query_scalar!("SELECT user_id FROM user WHERE username = $1", username)
-        .fetch_one(crate::database::get_db())
-        .await
-        .map_err(|_| ApiError::UserNotFound)?
-        .ok_or(ApiError::UserNotFound)?;
-
-    let notifications = Notification::for_user(user_id)
+    let page = Notification::for_user(user.user_id, &filter)
         .await
         .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
 
-    let unread_count = Notification::unread_count_for_user(user_id)
+    let unread_count = Notification::unread_count_for_user(user.user_id)
         .await
         .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
 
     Ok(Json(NotificationsResponse {
-        notifications,
+        page,
         unread_count,
     }))
 }
 
 /// Get unread notification count for the authenticated user
 pub async fn get_unread_count(
-    headers: axum::http::HeaderMap,
+    user: AuthenticatedUser,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let username = get_username_from_headers(&headers)
-        .ok_or(ApiError::AuthenticationError("Not authenticated".to_string()))?;
-    
-    let user_id = sqlx::query_scalar!("SELECT user_id FROM user WHERE username = $1", username)
-        .fetch_one(crate::database::get_db())
-        .await
-        .map_err(|_| ApiError::UserNotFound)?
-        .ok_or(ApiError::UserNotFound)?;
-
-    let unread_count = Notification::unread_count_for_user(user_id)
+    let unread_count = Notification::unread_count_for_user(user.user_id)
         .await
         .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
 
@@ -69,27 +73,12 @@ pub async fn get_unread_count(
 
 /// Mark specific notifications as read
 pub async fn mark_as_read(
-    headers: axum::http::HeaderMap,
-    Json(request): Json<MarkAsReadRequest>,
+    user: AuthenticatedUser,
+    Json(request): Json<NotificationIdsRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let username = get_username_from_headers(&headers)
-        .ok_or(ApiError::AuthenticationError("Not authenticated".to_string()))?;
-    
-    let user_id = sqlx::query_scalar!("SELECT user_id FROM user WHERE username = $1", username)
-        .fetch_one(crate::database::get_db())
+    let marked_count = Notification::mark_as_read(&request.notification_ids, user.user_id)
         .await
-        .map_err(|_| ApiError::UserNotFound)?
-        .ok_or(ApiError::UserNotFound)?;
-
-    let mut marked_count = 0;
-    for notification_id in request.notification_ids {
-        if Notification::mark_as_read(notification_id, user_id)
-            .await
-            .map_err(|e| ApiError::DatabaseError(e.to_string()))?
-        {
-            marked_count += 1;
-        }
-    }
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
 
     Ok(Json(serde_json::json!({ 
         "marked_count": marked_count,
@@ -99,18 +88,9 @@ pub async fn mark_as_read(
 
 /// Mark all notifications as read for the authenticated user
 pub async fn mark_all_as_read(
-    headers: axum::http::HeaderMap,
+    user: AuthenticatedUser,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let username = get_username_from_headers(&headers)
-        .ok_or(ApiError::AuthenticationError("Not authenticated".to_string()))?;
-    
-    let user_id = sqlx::query_scalar!("SELECT user_id FROM user WHERE username = $1", username)
-        .fetch_one(crate::database::get_db())
-        .await
-        .map_err(|_| ApiError::UserNotFound)?
-        .ok_or(ApiError::UserNotFound)?;
-
-    let marked_count = Notification::mark_all_as_read(user_id)
+    let marked_count = Notification::mark_all_as_read(user.user_id)
         .await
         .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
 
@@ -120,16 +100,97 @@ pub async fn mark_all_as_read(
     })))
 }
 
-/// Manually trigger notification processing (for testing/admin purposes)
-pub async fn process_pending_notifications(
-    _headers: axum::http::HeaderMap, // Authentication not required for now, but structure is ready
+/// Delete specific notifications
+pub async fn delete_notifications(
+    user: AuthenticatedUser,
+    Json(request): Json<NotificationIdsRequest>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let created_count = Notification::process_pending_notifications()
+    let deleted_count = Notification::delete(&request.notification_ids, user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(serde_json::json!({
+        "deleted_count": deleted_count,
+        "message": format!("Deleted {} notifications", deleted_count)
+    })))
+}
+
+/// How the authenticated user hears about each kind of notification
+pub async fn get_preferences(
+    user: AuthenticatedUser,
+) -> ApiResult<Json<PreferencesBody>> {
+    let preferences = NotificationPreference::for_user(user.user_id)
         .await
         .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
 
+    let digest = DigestSchedule::for_user(user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(PreferencesBody {
+        preferences,
+        digest: Some(digest),
+    }))
+}
+
+/// Change the channel for some kinds of notification, leaving the rest as they
+/// are, and optionally the digest schedule
+pub async fn update_preferences(
+    user: AuthenticatedUser,
+    Json(request): Json<PreferencesBody>,
+) -> ApiResult<Json<PreferencesBody>> {
+    if let Some(digest) = &request.digest {
+        digest.validate().map_err(ApiError::ValidationError)?;
+    }
+
+    NotificationPreference::set(user.user_id, &request.preferences)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    match &request.digest {
+        Some(digest) => DigestSchedule::set(user.user_id, digest).await,
+        None if request
+            .preferences
+            .iter()
+            .any(|preference| preference.channel == NotificationChannel::Digest) =>
+        {
+            DigestSchedule::ensure(user.user_id).await
+        }
+        None => Ok(()),
+    }
+    .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    get_preferences(user).await
+}
+
+/// Stop the category of email an unsubscribe link was made for
+/// Needs no login, so mail clients can unsubscribe with one `POST` (RFC 8058);
+/// the form body they send is ignored
+pub async fn unsubscribe(
+    client: ClientInfo,
+    Query(params): Query<UnsubscribeParams>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let invalid = || ApiError::ValidationError("Invalid or expired unsubscribe link".to_string());
+    let claims = decode_token(&params.token, TokenPurpose::Unsubscribe)
+        .map_err(|_| invalid())?
+        .claims;
+    let category = claims.category.ok_or_else(invalid)?;
+    let user = User::get(claims.sub.clone()).await.map_err(|_| invalid())?;
+
+    if !NotificationPreference::unsubscribe(user.user_id(), &category)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+    {
+        return Err(invalid());
+    }
+    AuditEvent::action("notification.unsubscribe", &client)
+        .account(&claims.sub)
+        .detail(category.clone())
+        .record()
+        .await;
+
     Ok(Json(serde_json::json!({
-        "created_count": created_count,
-        "message": format!("Processed {} pending notifications", created_count)
+        "category": category,
+        "message": "You will no longer receive these emails"
     })))
-}
\ No newline at end of file
+}
@@ -0,0 +1,306 @@
+use axum::{
+    extract::Query,
+    http::{header, HeaderMap},
+    response::{IntoResponse, Redirect, Response},
+    Json,
+};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Deserialize;
+
+use crate::api::auth::{start_session, two_factor_challenge, TWO_FACTOR_CHALLENGE_DURATION};
+use crate::api::{ApiError, ApiResult};
+use crate::auth::cookie::{self, OIDC_STATE_COOKIE};
+use crate::auth::oidc::{self, IdentityClaims, OidcConfig};
+use crate::auth::secret::generate_secret;
+use crate::auth::ClientInfo;
+use crate::models::{
+    AccountStatus, AttemptKind, AuditEvent, AuditOutcome, AuthAttempt, DatabaseError, ExternalIdentity, OidcLogin, TwoFactor, User,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct LoginParams {
+    /// Page to return to after logging in
+    pub redirect: Option<String>,
+    /// Make the provider ask for credentials even if the user is logged in there,
+    /// to confirm a sensitive change on an account without a password
+    #[serde(default)]
+    pub reauthenticate: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackParams {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+/// Send the browser back to the login page with an error to show
+fn login_error(message: &str) -> Response {
+    let mut response = Redirect::to(&format!(
+        "/login?error={}",
+        utf8_percent_encode(message, NON_ALPHANUMERIC)
+    ))
+    .into_response();
+    response
+        .headers_mut()
+        .append(header::SET_COOKIE, cookie::remove_oidc_state_cookie());
+    response
+}
+
+/// Whether a redirect target stays on this site
+///
+/// Browsers treat `\` like `/` and drop tabs and newlines from URLs, so `/\evil.example`
+/// or a `/` followed by a tab and `/evil.example` would leave the site like `//evil.example` does.
+fn is_local_path(path: &str) -> bool {
+    path.starts_with('/')
+        && !path.starts_with("//")
+        && !path.contains('\\')
+        && !path.chars().any(char::is_control)
+}
+
+/// Whether single sign-on is offered, and what to call it
+pub async fn get_config() -> Json<serde_json::Value> {
+    let config = OidcConfig::from_env();
+    Json(serde_json::json!({
+        "enabled": config.is_some(),
+        "provider_name": config.map(|config| config.provider_name),
+    }))
+}
+
+/// Start a login at the identity provider
+pub async fn login(Query(params): Query<LoginParams>) -> ApiResult<Response> {
+    let config = OidcConfig::from_env().ok_or(ApiError::NotFound("Single sign-on is not configured".to_string()))?;
+    let metadata = oidc::metadata(&config).await.map_err(|e| {
+        tracing::error!("OIDC discovery failed: {}", e);
+        ApiError::InternalServerError
+    })?;
+
+    let redirect_to = params
+        .redirect
+        .filter(|path| is_local_path(path))
+        .unwrap_or_else(|| "/".to_string());
+
+    let code_verifier = generate_secret();
+    let nonce = generate_secret();
+    let state = OidcLogin::create(&code_verifier, &nonce, &redirect_to)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    let url = oidc::authorization_url(
+        &config,
+        &metadata,
+        &state,
+        &nonce,
+        &code_verifier,
+        params.reauthenticate,
+    )
+    .map_err(|e| {
+        tracing::error!("OIDC authorization URL: {}", e);
+        ApiError::InternalServerError
+    })?;
+
+    let mut response = Redirect::to(&url).into_response();
+    response.headers_mut().append(
+        header::SET_COOKIE,
+        cookie::oidc_state_cookie(&state, OidcLogin::DURATION_SECONDS),
+    );
+    Ok(response)
+}
+
+/// Find the local account for an identity, linking or creating one on first login
+///
+/// An identity is only linked to an existing account when both the provider and
+/// this site have verified the email address, so nobody can claim an account by
+/// registering its address at the provider.
+async fn resolve_user(issuer: &str, claims: &IdentityClaims) -> Result<User, String> {
+    if let Some(user_id) = ExternalIdentity::find_user(issuer, &claims.sub)
+        .await
+        .map_err(|e| e.to_string())?
+    {
+        return User::get_by_id(user_id).await.map_err(|e| e.to_string());
+    }
+
+    let email = match (&claims.email, claims.email_verified) {
+        (Some(email), true) => email.clone(),
+        _ => {
+            return Err("Your identity provider did not share a verified email address".to_string());
+        }
+    };
+
+    let user = match User::get_email(email.clone()).await {
+        Ok(user) => {
+            if !user.email_verified() {
+                return Err(
+                    "An account with this email exists but its address is not verified. Log in with your password and verify it first".to_string(),
+                );
+            }
+            user
+        }
+        Err(sqlx::Error::RowNotFound) => {
+            let preferred_username = claims
+                .preferred_username
+                .clone()
+                .unwrap_or_else(|| email.split('@').next().unwrap_or_default().to_string());
+            let (user_id, username) = User::create_external(&preferred_username, &email)
+                .await
+                .map_err(|e| e.to_string())?;
+            tracing::info!("Created account {} from single sign-on", username);
+            User::get_by_id(user_id).await.map_err(|e| e.to_string())?
+        }
+        Err(e) => return Err(e.to_string()),
+    };
+
+    ExternalIdentity::link(user.user_id(), issuer, &claims.sub, Some(&email))
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(user)
+}
+
+/// Finish a login when the identity provider redirects back
+pub async fn callback(
+    client: ClientInfo,
+    headers: HeaderMap,
+    Query(params): Query<CallbackParams>,
+) -> ApiResult<Response> {
+    let config = OidcConfig::from_env().ok_or(ApiError::NotFound("Single sign-on is not configured".to_string()))?;
+
+    if let Some(error) = params.error {
+        tracing::warn!("OIDC login refused by provider: {}", error);
+        return Ok(login_error(
+            params.error_description.as_deref().unwrap_or("Single sign-on was cancelled"),
+        ));
+    }
+    let (Some(code), Some(state)) = (params.code, params.state) else {
+        return Ok(login_error("Single sign-on response was incomplete"));
+    };
+
+    // The state must come back to the browser that started the login
+    if cookie::cookie_value(&headers, OIDC_STATE_COOKIE).as_deref() != Some(state.as_str()) {
+        return Ok(login_error("Single sign-on expired, please try again"));
+    }
+    let Some(pending) = OidcLogin::consume(&state)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+    else {
+        return Ok(login_error("Single sign-on expired, please try again"));
+    };
+
+    let metadata = oidc::metadata(&config).await.map_err(|e| {
+        tracing::error!("OIDC discovery failed: {}", e);
+        ApiError::InternalServerError
+    })?;
+    let claims = match oidc::exchange_code(&config, &metadata, &code, &pending.code_verifier, &pending.nonce).await {
+        Ok(claims) => claims,
+        Err(e) => {
+            tracing::warn!("OIDC code exchange failed: {}", e);
+            AuditEvent::action("auth.login.oidc", &client)
+                .outcome(AuditOutcome::Failure)
+                .detail(e.to_string())
+                .record()
+                .await;
+            return Ok(login_error("Single sign-on failed"));
+        }
+    };
+
+    let user = match resolve_user(&metadata.issuer, &claims).await {
+        Ok(user) => user,
+        Err(message) => {
+            AuditEvent::action("auth.login.oidc", &client)
+                .account(claims.email.as_deref().unwrap_or(&claims.sub))
+                .outcome(AuditOutcome::Failure)
+                .detail(message.clone())
+                .record()
+                .await;
+            return Ok(login_error(&message));
+        }
+    };
+    let username = user.username();
+
+    let refused = if user.disabled() {
+        Some("Account is disabled".to_string())
+    } else if user.status() == AccountStatus::Rejected {
+        Some("Account application was rejected".to_string())
+    } else {
+        match AuthAttempt::check_login(&username, &client.ip_address).await {
+            Err(e @ DatabaseError::AccountLocked(_)) => Some(e.to_string()),
+            _ => None,
+        }
+    };
+    if let Some(reason) = refused {
+        AuditEvent::action("auth.login.oidc", &client)
+            .actor(user.user_id(), &username)
+            .outcome(AuditOutcome::Denied)
+            .detail(reason.clone())
+            .record()
+            .await;
+        return Ok(login_error(&reason));
+    }
+    let event = AuditEvent::action("auth.login.oidc", &client)
+        .actor(user.user_id(), &username)
+        .target("identity", format!("{} {}", metadata.issuer, claims.sub));
+
+    // Single sign-on replaces the password, not the second factor
+    let two_factor_enabled = TwoFactor::is_enabled(user.user_id())
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    let two_factor_required = TwoFactor::is_required_for(user.role())
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if two_factor_enabled || two_factor_required {
+        let challenge = two_factor_challenge(&username)?;
+        event.detail("Identity accepted, second factor required").record().await;
+        let mut response = Redirect::to(&format!(
+            "/login?two_factor=1&setup_required={}&username={}&redirect={}",
+            !two_factor_enabled,
+            utf8_percent_encode(&username, NON_ALPHANUMERIC),
+            utf8_percent_encode(&pending.redirect_to, NON_ALPHANUMERIC)
+        ))
+        .into_response();
+        response
+            .headers_mut()
+            .append(header::SET_COOKIE, cookie::remove_oidc_state_cookie());
+        response.headers_mut().append(
+            header::SET_COOKIE,
+            cookie::two_factor_cookie(&challenge, TWO_FACTOR_CHALLENGE_DURATION),
+        );
+        return Ok(response);
+    }
+
+    AuthAttempt::record(AttemptKind::Login, &username, &client.ip_address, true)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    event.record().await;
+
+    let mut response = Redirect::to(&pending.redirect_to).into_response();
+    response
+        .headers_mut()
+        .append(header::SET_COOKIE, cookie::remove_oidc_state_cookie());
+    start_session(&mut response, user.user_id(), &username, &client).await?;
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_paths_are_accepted() {
+        assert!(is_local_path("/"));
+        assert!(is_local_path("/queries/12?tab=results"));
+    }
+
+    #[test]
+    fn paths_leaving_the_site_are_rejected() {
+        for path in [
+            "",
+            "https://evil.example",
+            "//evil.example",
+            "/\\evil.example",
+            "/\t/evil.example",
+            "/\n/evil.example",
+        ] {
+            assert!(!is_local_path(path), "{:?}", path);
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use axum::{extract::Path, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::{ApiError, ApiResult},
+    auth::AuthenticatedUser,
+    models::{Organization, OrganizationRole, User},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateOrganizationRequest {
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateOrganizationResponse {
+    pub organization_id: i64,
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateOrganizationSettingsRequest {
+    pub name: String,
+    pub query_quota: Option<i64>,
+    pub retention_days: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InviteMemberRequest {
+    pub email: String,
+    pub role: OrganizationRole,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateMemberRoleRequest {
+    pub role: OrganizationRole,
+}
+
+/// Get the user's role in an organization, failing if they are not a member
+async fn require_membership(organization_id: i64, user_id: i64) -> ApiResult<OrganizationRole> {
+    Organization::role_for_user(organization_id, user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+        .ok_or(ApiError::NotFound("Organization not found".to_string()))
+}
+
+async fn require_owner(organization_id: i64, user_id: i64) -> ApiResult<()> {
+    if !require_membership(organization_id, user_id)
+        .await?
+        .can_manage()
+    {
+        return Err(ApiError::Forbidden(
+            "Only organization owners can perform this action".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// List the organizations the authenticated user belongs to
+pub async fn get_organizations(user: AuthenticatedUser) -> ApiResult<Json<serde_json::Value>> {
+    let organizations = Organization::for_user(user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(serde_json::json!({ "organizations": organizations })))
+}
+
+/// Create an organization owned by the authenticated user
+pub async fn create_organization(
+    user: AuthenticatedUser,
+    Json(request): Json<CreateOrganizationRequest>,
+) -> ApiResult<Json<CreateOrganizationResponse>> {
+    let organization_id = Organization::create(&request.name, user.user_id).await?;
+
+    Ok(Json(CreateOrganizationResponse {
+        organization_id,
+        message: "Organization created successfully".to_string(),
+    }))
+}
+
+/// Get an organization with its members
+pub async fn get_organization(
+    Path(organization_id): Path<i64>,
+    user: AuthenticatedUser,
+) -> ApiResult<Json<serde_json::Value>> {
+    let role = require_membership(organization_id, user.user_id).await?;
+
+    let organization = Organization::get(organization_id).await?;
+    let members = Organization::members(organization_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(serde_json::json!({
+        "organization": organization,
+        "role": role,
+        "members": members,
+    })))
+}
+
+/// Update an organization's name, query quota and retention settings
+pub async fn update_organization_settings(
+    Path(organization_id): Path<i64>,
+    user: AuthenticatedUser,
+    Json(request): Json<UpdateOrganizationSettingsRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    require_owner(organization_id, user.user_id).await?;
+
+    let mut organization = Organization::get(organization_id).await?;
+    organization.name = request.name;
+    organization.query_quota = request.query_quota;
+    organization.retention_days = request.retention_days;
+    organization.update_settings().await?;
+
+    Ok(Json(serde_json::json!({
+        "message": "Organization settings updated successfully"
+    })))
+}
+
+/// Invite an email address to join an organization
+pub async fn invite_member(
+    Path(organization_id): Path<i64>,
+    user: AuthenticatedUser,
+    Json(request): Json<InviteMemberRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    require_owner(organization_id, user.user_id).await?;
+
+    let email = request.email.trim().to_string();
+    if !User::validate_email(&email) {
+        return Err(crate::models::DatabaseError::InvalidEmail.into());
+    }
+
+    let organization = Organization::get(organization_id).await?;
+    let invitation_id =
+        Organization::invite(organization_id, &email, request.role, user.user_id).await?;
+
+    if let Err(e) =
+        Organization::send_invitation_email(&organization.name, &email, &user.username).await
+    {
+        tracing::error!("Failed to send invitation email: {}", e);
+        // The invitee can still accept from their account
+    }
+
+    Ok(Json(serde_json::json!({
+        "invitation_id": invitation_id,
+        "message": format!("Invitation sent to {}", email)
+    })))
+}
+
+/// Change the role of an organization member
+pub async fn update_member_role(
+    Path((organization_id, member_id)): Path<(i64, i64)>,
+    user: AuthenticatedUser,
+    Json(request): Json<UpdateMemberRoleRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    require_owner(organization_id, user.user_id).await?;
+
+    Organization::set_member_role(organization_id, member_id, request.role).await?;
+
+    Ok(Json(serde_json::json!({
+        "message": "Member role updated successfully"
+    })))
+}
+
+/// Remove a member from an organization
+/// Owners can remove anyone; other members can only remove themselves
+pub async fn remove_member(
+    Path((organization_id, member_id)): Path<(i64, i64)>,
+    user: AuthenticatedUser,
+) -> ApiResult<Json<serde_json::Value>> {
+    if member_id == user.user_id {
+        require_membership(organization_id, user.user_id).await?;
+    } else {
+        require_owner(organization_id, user.user_id).await?;
+    }
+
+    Organization::remove_member(organization_id, member_id).await?;
+
+    Ok(Json(serde_json::json!({
+        "message": "Member removed successfully"
+    })))
+}
+
+/// List pending invitations addressed to the authenticated user's email
+pub async fn get_invitations(user: AuthenticatedUser) -> ApiResult<Json<serde_json::Value>> {
+    let email = User::get(user.username).await?.email();
+    let invitations = Organization::pending_invitations(&email)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(serde_json::json!({ "invitations": invitations })))
+}
+
+/// Accept a pending invitation
+pub async fn accept_invitation(
+    Path(invitation_id): Path<i64>,
+    user: AuthenticatedUser,
+) -> ApiResult<Json<serde_json::Value>> {
+    if !User::get(user.username).await?.email_verified() {
+        return Err(ApiError::Forbidden(
+            "Please verify your email address before accepting invitations".to_string(),
+        ));
+    }
+    let organization_id = Organization::accept_invitation(invitation_id, user.user_id).await?;
+
+    Ok(Json(serde_json::json!({
+        "organization_id": organization_id,
+        "message": "Invitation accepted"
+    })))
+}
@@ -1,6 +1,5 @@
 use axum::{extract::Query, http::StatusCode, response::Json};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 
 #[derive(Debug, Deserialize)]
 pub struct CitationsQuery {
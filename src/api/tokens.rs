@@ -0,0 +1,79 @@
+use axum::{extract::Path, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::{ApiError, ApiResult},
+    auth::{AuthenticatedUser, ClientInfo},
+    models::{AccountDeletion, ApiScope, ApiToken, AuditEvent},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+    /// Days until the token expires; tokens without an expiry last until revoked
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateTokenResponse {
+    pub api_token_id: i64,
+    /// The token itself. It is not stored and cannot be shown again
+    pub token: String,
+    pub message: String,
+}
+
+/// List the authenticated user's API tokens
+pub async fn get_tokens(user: AuthenticatedUser) -> ApiResult<Json<serde_json::Value>> {
+    let tokens = ApiToken::for_user(user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(serde_json::json!({ "tokens": tokens })))
+}
+
+/// Create a personal API token for the authenticated user
+pub async fn create_token(
+    user: AuthenticatedUser,
+    client: ClientInfo,
+    Json(request): Json<CreateTokenRequest>,
+) -> ApiResult<Json<CreateTokenResponse>> {
+    AccountDeletion::ensure_not_scheduled(user.user_id).await?;
+    let (api_token_id, token) = ApiToken::create(
+        user.user_id,
+        &request.name,
+        &request.scopes,
+        request.expires_in_days,
+    )
+    .await?;
+    AuditEvent::action("api_token.create", &client)
+        .actor(user.user_id, &user.username)
+        .target("api_token", api_token_id)
+        .detail(request.name)
+        .record()
+        .await;
+
+    Ok(Json(CreateTokenResponse {
+        api_token_id,
+        token,
+        message: "Token created. Copy it now, it will not be shown again".to_string(),
+    }))
+}
+
+/// Revoke one of the authenticated user's API tokens
+pub async fn revoke_token(
+    Path(api_token_id): Path<i64>,
+    user: AuthenticatedUser,
+    client: ClientInfo,
+) -> ApiResult<Json<serde_json::Value>> {
+    ApiToken::revoke(api_token_id, user.user_id).await?;
+    AuditEvent::action("api_token.revoke", &client)
+        .actor(user.user_id, &user.username)
+        .target("api_token", api_token_id)
+        .record()
+        .await;
+
+    Ok(Json(serde_json::json!({
+        "message": "Token revoked"
+    })))
+}
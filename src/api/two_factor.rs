@@ -0,0 +1,173 @@
+use axum::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::{ApiError, ApiResult},
+    auth::{AuthenticatedUser, ClientInfo},
+    models::{
+        verify_password, AuditEvent, AuditOutcome, DatabaseError, Role, Session, TwoFactor,
+        TwoFactorEnrollment, TwoFactorStatus,
+    },
+};
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmTwoFactorRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordConfirmation {
+    pub password: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesResponse {
+    /// Single-use codes for when the authenticator is unavailable. They cannot be shown again
+    pub recovery_codes: Vec<String>,
+    pub message: String,
+}
+
+/// How recently an account without a password must have logged in through single sign-on
+/// to make sensitive changes
+pub(crate) const REAUTHENTICATION_WINDOW_SECONDS: i64 = 10 * 60;
+
+/// Sensitive changes need the current password, not just a session
+/// Wrong passwords are audited, since a stolen session may be guessing
+///
+/// Accounts created through single sign-on have no password. They confirm by logging in
+/// at the identity provider again, so the browser session must have just been started.
+pub(crate) async fn confirm_password(
+    user: &AuthenticatedUser,
+    client: &ClientInfo,
+    password: String,
+) -> ApiResult<()> {
+    let hash = sqlx::query_scalar!("SELECT password FROM user WHERE user_id = $1", user.user_id)
+        .fetch_one(crate::database::get_db())
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    if hash.is_empty() {
+        let recent = match &user.session_id {
+            Some(session_id) => {
+                Session::started_within(session_id, user.user_id, REAUTHENTICATION_WINDOW_SECONDS)
+                    .await
+                    .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+            }
+            None => false,
+        };
+        if !recent {
+            AuditEvent::action("auth.password.confirm", client)
+                .actor(user.user_id, &user.username)
+                .outcome(AuditOutcome::Failure)
+                .detail("No recent single sign-on login")
+                .record()
+                .await;
+            return Err(ApiError::AuthenticationError(
+                "Log in again with single sign-on to confirm this change".to_string(),
+            ));
+        }
+        return Ok(());
+    }
+
+    if verify_password(password, hash).await.is_err() {
+        AuditEvent::action("auth.password.confirm", client)
+            .actor(user.user_id, &user.username)
+            .outcome(AuditOutcome::Failure)
+            .record()
+            .await;
+        return Err(ApiError::AuthenticationError("Incorrect password".to_string()));
+    }
+
+    Ok(())
+}
+
+/// The highest role granted to the user
+fn user_role(user: &AuthenticatedUser) -> Role {
+    user.roles.iter().copied().max().unwrap_or_default()
+}
+
+/// Get the authenticated user's two-factor status
+pub async fn get_status(user: AuthenticatedUser) -> ApiResult<Json<TwoFactorStatus>> {
+    let status = TwoFactor::status(user.user_id, user_role(&user))
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(status))
+}
+
+/// Start enrolling an authenticator app
+pub async fn setup(user: AuthenticatedUser) -> ApiResult<Json<TwoFactorEnrollment>> {
+    let enrollment = TwoFactor::begin_enrollment(user.user_id, &user.username).await?;
+    Ok(Json(enrollment))
+}
+
+/// Turn on two-factor authentication with a code from the new authenticator
+pub async fn confirm(
+    user: AuthenticatedUser,
+    client: ClientInfo,
+    Json(request): Json<ConfirmTwoFactorRequest>,
+) -> ApiResult<Json<RecoveryCodesResponse>> {
+    let recovery_codes = TwoFactor::confirm_enrollment(user.user_id, &request.code).await?;
+    tracing::info!("User {} enabled two-factor authentication", user.username);
+    AuditEvent::action("auth.two_factor.enable", &client)
+        .actor(user.user_id, &user.username)
+        .record()
+        .await;
+
+    Ok(Json(RecoveryCodesResponse {
+        recovery_codes,
+        message: "Two-factor authentication enabled".to_string(),
+    }))
+}
+
+/// Replace the recovery codes, invalidating the old ones
+pub async fn regenerate_recovery_codes(
+    user: AuthenticatedUser,
+    client: ClientInfo,
+    Json(request): Json<PasswordConfirmation>,
+) -> ApiResult<Json<RecoveryCodesResponse>> {
+    confirm_password(&user, &client, request.password).await?;
+    let recovery_codes = TwoFactor::regenerate_recovery_codes(user.user_id).await?;
+    AuditEvent::action("auth.two_factor.recovery_codes", &client)
+        .actor(user.user_id, &user.username)
+        .record()
+        .await;
+
+    Ok(Json(RecoveryCodesResponse {
+        recovery_codes,
+        message: "New recovery codes generated".to_string(),
+    }))
+}
+
+/// Turn off two-factor authentication, unless the user's role requires it
+pub async fn disable(
+    user: AuthenticatedUser,
+    client: ClientInfo,
+    Json(request): Json<PasswordConfirmation>,
+) -> ApiResult<Json<serde_json::Value>> {
+    confirm_password(&user, &client, request.password).await?;
+
+    let required = TwoFactor::is_required_for(user_role(&user))
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if required {
+        return Err(DatabaseError::TwoFactorRequired.into());
+    }
+
+    let disabled = TwoFactor::disable(user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if !disabled {
+        return Err(DatabaseError::TwoFactorNotEnabled.into());
+    }
+
+    tracing::info!("User {} disabled two-factor authentication", user.username);
+    AuditEvent::action("auth.two_factor.disable", &client)
+        .actor(user.user_id, &user.username)
+        .record()
+        .await;
+
+    Ok(Json(serde_json::json!({
+        "message": "Two-factor authentication disabled"
+    })))
+}
@@ -0,0 +1,134 @@
+use axum::{extract::Path, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::{ApiError, ApiResult},
+    auth::{AuthenticatedUser, ClientInfo},
+    models::{AuditEvent, Organization, Webhook, WebhookEvent},
+};
+
+/// Deliveries shown in a webhook's log
+const DELIVERY_LOG_LIMIT: i64 = 50;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateWebhookRequest {
+    pub url: String,
+    #[serde(default)]
+    pub description: String,
+    /// Signing secret; one is generated if not given
+    pub secret: Option<String>,
+    /// Register the webhook for an organization's queries instead of the user's own
+    pub organization_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateWebhookResponse {
+    pub webhook_id: i64,
+    /// The signing secret. It cannot be shown again
+    pub secret: String,
+    pub message: String,
+}
+
+/// List the authenticated user's webhooks and those of the organizations they own
+pub async fn get_webhooks(user: AuthenticatedUser) -> ApiResult<Json<serde_json::Value>> {
+    let webhooks = Webhook::for_user(user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(serde_json::json!({ "webhooks": webhooks })))
+}
+
+/// Register a webhook for the authenticated user's queries or an organization's
+pub async fn create_webhook(
+    user: AuthenticatedUser,
+    client: ClientInfo,
+    Json(request): Json<CreateWebhookRequest>,
+) -> ApiResult<Json<CreateWebhookResponse>> {
+    if let Some(organization_id) = request.organization_id {
+        let role = Organization::role_for_user(organization_id, user.user_id)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+            .ok_or(ApiError::NotFound("Organization not found".to_string()))?;
+        if !role.can_manage() {
+            return Err(ApiError::Forbidden(
+                "Only organization owners can perform this action".to_string(),
+            ));
+        }
+    }
+
+    let (webhook_id, secret) = Webhook::create(
+        user.user_id,
+        request.organization_id,
+        &request.url,
+        &request.description,
+        request.secret.as_deref(),
+    )
+    .await?;
+    AuditEvent::action("webhook.create", &client)
+        .actor(user.user_id, &user.username)
+        .target("webhook", webhook_id)
+        .detail(request.url)
+        .record()
+        .await;
+
+    Ok(Json(CreateWebhookResponse {
+        webhook_id,
+        secret,
+        message: "Webhook created. Copy the secret now, it will not be shown again".to_string(),
+    }))
+}
+
+/// Delete a webhook and its delivery log
+pub async fn delete_webhook(
+    Path(webhook_id): Path<i64>,
+    user: AuthenticatedUser,
+    client: ClientInfo,
+) -> ApiResult<Json<serde_json::Value>> {
+    let webhook = Webhook::get_for_user(webhook_id, user.user_id).await?;
+    Webhook::delete(webhook.webhook_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    AuditEvent::action("webhook.delete", &client)
+        .actor(user.user_id, &user.username)
+        .target("webhook", webhook_id)
+        .detail(webhook.url)
+        .record()
+        .await;
+
+    Ok(Json(serde_json::json!({
+        "message": "Webhook deleted"
+    })))
+}
+
+/// The most recent deliveries to a webhook
+pub async fn get_deliveries(
+    Path(webhook_id): Path<i64>,
+    user: AuthenticatedUser,
+) -> ApiResult<Json<serde_json::Value>> {
+    let webhook = Webhook::get_for_user(webhook_id, user.user_id).await?;
+    let deliveries = Webhook::deliveries(webhook.webhook_id, DELIVERY_LOG_LIMIT)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(serde_json::json!({ "deliveries": deliveries })))
+}
+
+/// Send a `ping` event to a webhook, to check it receives and verifies requests
+pub async fn send_test_event(
+    Path(webhook_id): Path<i64>,
+    user: AuthenticatedUser,
+) -> ApiResult<Json<serde_json::Value>> {
+    let webhook = Webhook::get_for_user(webhook_id, user.user_id).await?;
+    let delivery_id = Webhook::enqueue(
+        webhook.webhook_id,
+        WebhookEvent::Ping,
+        serde_json::json!({ "webhook_id": webhook.webhook_id }),
+    )
+    .await
+    .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(serde_json::json!({
+        "delivery_id": delivery_id,
+        "message": "Test event queued, check the delivery log for the result"
+    })))
+}
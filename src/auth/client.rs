@@ -0,0 +1,88 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header, request::Parts, HeaderMap},
+};
+
+/// Maximum stored length of a user agent string
+const USER_AGENT_MAX_LENGTH: usize = 255;
+
+/// Where a request came from, used to describe sessions to the user
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub ip_address: String,
+    pub user_agent: String,
+}
+
+/// Whether to trust X-Forwarded-For / X-Real-IP set by a reverse proxy
+fn trust_proxy_headers() -> bool {
+    std::env::var("TRUST_PROXY_HEADERS")
+        .map(|value| value == "true" || value == "1")
+        .unwrap_or(false)
+}
+
+/// Number of reverse proxies in front of the server, from `TRUSTED_PROXY_HOPS`
+fn trusted_proxy_hops() -> usize {
+    std::env::var("TRUSTED_PROXY_HOPS")
+        .ok()
+        .and_then(|hops| hops.parse().ok())
+        .unwrap_or(1)
+        .max(1)
+}
+
+/// The client address as seen by the outermost trusted proxy
+///
+/// Each proxy appends the address it received the request from to
+/// `X-Forwarded-For`, so only the right-most `hops` entries can be trusted;
+/// anything to their left was sent by the client. Falls back to `X-Real-IP`
+/// when there is no `X-Forwarded-For`.
+fn forwarded_ip(headers: &HeaderMap, hops: usize) -> Option<String> {
+    let ip = match headers.get("x-forwarded-for") {
+        Some(value) => {
+            let entries: Vec<&str> = value.to_str().ok()?.split(',').collect();
+            entries[entries.len().checked_sub(hops)?]
+        }
+        None => headers.get("x-real-ip")?.to_str().ok()?,
+    };
+
+    ip.trim().parse::<IpAddr>().ok().map(|ip| ip.to_string())
+}
+
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let peer_ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string());
+
+        let ip_address = if trust_proxy_headers() {
+            forwarded_ip(&parts.headers, trusted_proxy_hops()).or(peer_ip)
+        } else {
+            peer_ip
+        }
+        .unwrap_or_default();
+
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .chars()
+            .take(USER_AGENT_MAX_LENGTH)
+            .collect();
+
+        Ok(Self {
+            ip_address,
+            user_agent,
+        })
+    }
+}
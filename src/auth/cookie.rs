@@ -0,0 +1,145 @@
+use axum::http::{header, HeaderMap, HeaderValue};
+
+/// Cookie holding the short-lived access token
+pub static AUTH_COOKIE: &str = "token";
+
+/// Name of the access token cookie when cookies are Secure
+/// The `__Host-` prefix makes browsers refuse it unless it was set over HTTPS for this exact host
+pub static SECURE_AUTH_COOKIE: &str = "__Host-token";
+
+/// Cookie holding the refresh token, only sent to the auth endpoints
+pub static REFRESH_COOKIE: &str = "refresh_token";
+
+const REFRESH_COOKIE_PATH: &str = "/api/auth";
+
+/// Whether cookies are marked `Secure`
+/// Set `COOKIE_SECURE`, or leave it unset to follow whether `SITE_BASE_URL` is HTTPS
+pub fn secure_cookies() -> bool {
+    match std::env::var("COOKIE_SECURE") {
+        Ok(value) => value == "true" || value == "1",
+        Err(_) => std::env::var("SITE_BASE_URL")
+            .map(|url| url.starts_with("https://"))
+            .unwrap_or(false),
+    }
+}
+
+/// Name of the cookie holding the access token
+pub fn access_cookie_name() -> &'static str {
+    if secure_cookies() {
+        SECURE_AUTH_COOKIE
+    } else {
+        AUTH_COOKIE
+    }
+}
+
+/// Attributes shared by every cookie the server sets
+fn attributes(path: &str, same_site: &str) -> String {
+    let secure = if secure_cookies() { "; Secure" } else { "" };
+    format!("Path={}; HttpOnly; SameSite={}{}", path, same_site, secure)
+}
+
+pub fn access_cookie(token: &str) -> HeaderValue {
+    HeaderValue::from_str(&format!(
+        "{}={}; {}",
+        access_cookie_name(),
+        token,
+        attributes("/", "Strict")
+    ))
+    .expect("token is a valid header value")
+}
+
+pub fn refresh_cookie(token: &str, max_age_seconds: i64) -> HeaderValue {
+    HeaderValue::from_str(&format!(
+        "{}={}; {}; Max-Age={}",
+        REFRESH_COOKIE,
+        token,
+        attributes(REFRESH_COOKIE_PATH, "Strict"),
+        max_age_seconds
+    ))
+    .expect("token is a valid header value")
+}
+
+pub fn remove_access_cookie() -> HeaderValue {
+    HeaderValue::from_str(&format!(
+        "{}=; {}; Max-Age=0",
+        access_cookie_name(),
+        attributes("/", "Strict")
+    ))
+    .expect("cookie is a valid header value")
+}
+
+pub fn remove_refresh_cookie() -> HeaderValue {
+    HeaderValue::from_str(&format!(
+        "{}=; {}; Max-Age=0",
+        REFRESH_COOKIE,
+        attributes(REFRESH_COOKIE_PATH, "Strict")
+    ))
+    .expect("cookie is a valid header value")
+}
+
+/// Get the value of a cookie by name
+pub fn cookie_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// Cookie carrying the two-factor challenge after single sign-on
+///
+/// The challenge would otherwise travel in the login page URL, where it ends up in
+/// browser history and proxy logs. Only sent to the second login step.
+pub static TWO_FACTOR_COOKIE: &str = "two_factor_challenge";
+
+const TWO_FACTOR_COOKIE_PATH: &str = "/api/auth/login/2fa";
+
+pub fn two_factor_cookie(challenge: &str, max_age_seconds: usize) -> HeaderValue {
+    HeaderValue::from_str(&format!(
+        "{}={}; {}; Max-Age={}",
+        TWO_FACTOR_COOKIE,
+        challenge,
+        attributes(TWO_FACTOR_COOKIE_PATH, "Strict"),
+        max_age_seconds
+    ))
+    .expect("challenge is a valid header value")
+}
+
+pub fn remove_two_factor_cookie() -> HeaderValue {
+    HeaderValue::from_str(&format!(
+        "{}=; {}; Max-Age=0",
+        TWO_FACTOR_COOKIE,
+        attributes(TWO_FACTOR_COOKIE_PATH, "Strict")
+    ))
+    .expect("cookie is a valid header value")
+}
+
+/// Cookie tying an OpenID Connect login to the browser that started it
+pub static OIDC_STATE_COOKIE: &str = "oidc_state";
+
+const OIDC_COOKIE_PATH: &str = "/api/auth/oidc";
+
+/// Lax rather than Strict, since the provider redirects back with a cross-site navigation
+pub fn oidc_state_cookie(state: &str, max_age_seconds: i64) -> HeaderValue {
+    HeaderValue::from_str(&format!(
+        "{}={}; {}; Max-Age={}",
+        OIDC_STATE_COOKIE,
+        state,
+        attributes(OIDC_COOKIE_PATH, "Lax"),
+        max_age_seconds
+    ))
+    .expect("state is a valid header value")
+}
+
+pub fn remove_oidc_state_cookie() -> HeaderValue {
+    HeaderValue::from_str(&format!(
+        "{}=; {}; Max-Age=0",
+        OIDC_STATE_COOKIE,
+        attributes(OIDC_COOKIE_PATH, "Lax")
+    ))
+    .expect("cookie is a valid header value")
+}
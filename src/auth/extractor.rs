@@ -0,0 +1,184 @@
+use axum::{
+    extract::{FromRequestParts, OptionalFromRequestParts},
+    http::{header, request::Parts, HeaderMap},
+};
+
+use crate::api::ApiError;
+use crate::auth::cookie::{access_cookie_name, cookie_value};
+use crate::auth::jwt::{decode_token, TokenPurpose};
+use crate::models::{ApiScope, ApiToken, Role};
+
+/// The user making an authenticated request
+///
+/// Extracting this from a request decodes the `token` cookie or the
+/// `Authorization: Bearer` header, loads the user and their session from the
+/// database and rejects the request with 401 if either step fails, the
+/// session was revoked or the account is disabled. The user is cached in the
+/// request extensions so it is only loaded once per request.
+///
+/// Personal API tokens are rejected with 403 here; routes that accept them
+/// use [`RequireScope`](crate::auth::scope::RequireScope) instead.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub user_id: i64,
+    pub username: String,
+    /// Every role granted to the user, including the ones implied by a higher role
+    pub roles: Vec<Role>,
+    /// The session the access token was issued for, if logged in from a browser
+    pub session_id: Option<String>,
+    /// The scopes of the API token used, if any. Browser sessions are not restricted
+    pub scopes: Option<Vec<ApiScope>>,
+}
+
+impl AuthenticatedUser {
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role)
+    }
+
+    pub fn has_scope(&self, scope: ApiScope) -> bool {
+        self.scopes
+            .as_ref()
+            .is_none_or(|scopes| scopes.contains(&scope))
+    }
+
+    async fn load_session(token: &str) -> Result<Self, ApiError> {
+        let claims = decode_token(token, TokenPurpose::Access)
+            .map_err(|_| ApiError::AuthenticationError("Invalid or expired token".to_string()))?
+            .claims;
+
+        let session_id = claims.sid.ok_or(ApiError::AuthenticationError(
+            "Invalid or expired token".to_string(),
+        ))?;
+
+        let user = sqlx::query!(
+            r#"SELECT u.user_id AS "user_id!", u.username, u.role AS "role: Role", u.disabled_at
+               FROM session s JOIN user u ON u.user_id = s.user_id
+               WHERE s.session_id = $1 AND u.username = $2
+                 AND s.revoked_at IS NULL AND s.expires_at > CURRENT_TIMESTAMP"#,
+            session_id,
+            claims.sub
+        )
+        .fetch_optional(crate::database::get_db())
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+        .ok_or(ApiError::AuthenticationError(
+            "Not authenticated".to_string(),
+        ))?;
+
+        if user.disabled_at.is_some() {
+            return Err(ApiError::AuthenticationError(
+                "Account is disabled".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            user_id: user.user_id,
+            username: user.username,
+            roles: user.role.granted(),
+            session_id: Some(session_id),
+            scopes: None,
+        })
+    }
+
+    async fn load_api_token(token: &str) -> Result<Self, ApiError> {
+        let grant = ApiToken::authenticate(token)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+            .ok_or(ApiError::AuthenticationError(
+                "Invalid, expired or revoked API token".to_string(),
+            ))?;
+
+        let user = sqlx::query!(
+            r#"SELECT username, role AS "role: Role", disabled_at FROM user WHERE user_id = $1"#,
+            grant.user_id
+        )
+        .fetch_one(crate::database::get_db())
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+        if user.disabled_at.is_some() {
+            return Err(ApiError::AuthenticationError(
+                "Account is disabled".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            user_id: grant.user_id,
+            username: user.username,
+            roles: user.role.granted(),
+            session_id: None,
+            scopes: Some(grant.scopes),
+        })
+    }
+
+    /// Authenticate a request with either a session or an API token
+    pub(crate) async fn authenticate(parts: &mut Parts) -> Result<Self, ApiError> {
+        if let Some(user) = parts.extensions.get::<Self>() {
+            return Ok(user.clone());
+        }
+
+        let token = token_from_headers(&parts.headers).ok_or(ApiError::AuthenticationError(
+            "Not authenticated".to_string(),
+        ))?;
+        let user = if token.starts_with(ApiToken::PREFIX) {
+            Self::load_api_token(&token).await?
+        } else {
+            Self::load_session(&token).await?
+        };
+
+        parts.extensions.insert(user.clone());
+        Ok(user)
+    }
+}
+
+impl<S> FromRequestParts<S> for AuthenticatedUser
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let user = Self::authenticate(parts).await?;
+
+        if user.scopes.is_some() {
+            return Err(ApiError::Forbidden(
+                "API tokens cannot be used for this endpoint".to_string(),
+            ));
+        }
+
+        Ok(user)
+    }
+}
+
+/// Lets public routes tailor their response to whoever is logged in
+///
+/// Requests without credentials, or with ones that are no longer valid, stay
+/// anonymous instead of being rejected.
+impl<S> OptionalFromRequestParts<S> for AuthenticatedUser
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Option<Self>, Self::Rejection> {
+        if token_from_headers(&parts.headers).is_none() {
+            return Ok(None);
+        }
+
+        Ok(Self::authenticate(parts).await.ok())
+    }
+}
+
+/// Get the access token from the `Authorization: Bearer` header or the access token cookie
+pub fn token_from_headers(headers: &HeaderMap) -> Option<String> {
+    bearer_token(headers).or_else(|| cookie_value(headers, access_cookie_name()))
+}
+
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty())
+}
@@ -1,26 +1,261 @@
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use once_cell::sync::OnceCell;
+use ring::signature::{Ed25519KeyPair, KeyPair, RsaKeyPair};
 use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+
+/// Key id given to `JWT_SECRET`, and assumed for tokens issued before keys had ids
+pub const DEFAULT_KID: &str = "default";
+
+/// What a token may be used for, so a token issued for one flow is never accepted by another
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenPurpose {
+    Access,
+    PasswordReset,
+    EmailVerification,
+    /// Issued after the password step of a login, exchanged for a session with a TOTP code
+    TwoFactorChallenge,
+    /// Carried by the unsubscribe links in notification emails
+    Unsubscribe,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TokenClaims {
     pub sub: String, // Subject (username)
     pub exp: usize,  // Expiration time
+    pub purpose: TokenPurpose,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>, // Session id, checked against revocation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>, // Token id, for single-use tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>, // Address being verified
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>, // Emails to unsubscribe from
+}
+
+/// One signing key in the key ring
+pub struct SigningKey {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    /// Public half for the JWKS endpoint; HMAC keys are never published
+    jwk: Option<serde_json::Value>,
+    /// A retired key keeps verifying tokens until then, so rotating does not log everyone out
+    verify_until: Option<DateTime<Utc>>,
+}
+
+/// The keys tokens are signed and verified with, loaded once at startup
+///
+/// Keys are read from the JSON file named by `JWT_KEYS_FILE`:
+///
+/// ```json
+/// {
+///   "active": "2026-10",
+///   "keys": [
+///     { "kid": "2026-10", "alg": "EdDSA", "private_key_file": "keys/jwt-2026-10.pem" },
+///     { "kid": "2026-04", "alg": "RS256", "private_key_file": "keys/jwt-2026-04.pem", "verify_until": "2026-10-25T00:00:00Z" }
+///   ]
+/// }
+/// ```
+///
+/// Private keys are PEM files. HMAC keys take a `secret` instead. `JWT_SECRET`, when set,
+/// is added as an HS256 key with the id `default`, and is the active key if nothing else is.
+/// `JWT_ACTIVE_KID` overrides the active key.
+pub struct KeyRing {
+    active: String,
+    keys: Vec<SigningKey>,
+}
+
+#[derive(Deserialize)]
+struct KeyRingFile {
+    active: Option<String>,
+    keys: Vec<KeyFile>,
+}
+
+#[derive(Deserialize)]
+struct KeyFile {
+    kid: String,
+    alg: Algorithm,
+    secret: Option<String>,
+    private_key_file: Option<String>,
+    verify_until: Option<String>,
+}
+
+static KEY_RING: OnceCell<KeyRing> = OnceCell::new();
+
+/// The key ring, loading it on first use
+pub fn key_ring() -> &'static KeyRing {
+    KEY_RING.get_or_init(|| KeyRing::from_env().unwrap_or_else(|e| panic!("Invalid JWT keys: {}", e)))
+}
+
+impl SigningKey {
+    fn hmac(kid: String, algorithm: Algorithm, secret: &[u8], verify_until: Option<DateTime<Utc>>) -> Self {
+        Self {
+            kid,
+            algorithm,
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+            jwk: None,
+            verify_until,
+        }
+    }
+
+    fn load(file: KeyFile) -> Result<Self, String> {
+        let verify_until = file
+            .verify_until
+            .as_deref()
+            .map(|value| {
+                DateTime::parse_from_rfc3339(value)
+                    .map(|date| date.with_timezone(&Utc))
+                    .map_err(|e| format!("key {}: invalid verify_until: {}", file.kid, e))
+            })
+            .transpose()?;
+
+        if matches!(file.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+            let secret = file
+                .secret
+                .ok_or_else(|| format!("key {}: HMAC keys need a secret", file.kid))?;
+            return Ok(Self::hmac(file.kid, file.alg, secret.as_bytes(), verify_until));
+        }
+
+        let path = file
+            .private_key_file
+            .ok_or_else(|| format!("key {}: private_key_file is required", file.kid))?;
+        let contents = std::fs::read(&path).map_err(|e| format!("key {}: {}: {}", file.kid, path, e))?;
+        let der = pem::parse(&contents)
+            .map_err(|e| format!("key {}: {}", file.kid, e))?
+            .into_contents();
+        let b64 = |bytes: &[u8]| data_encoding::BASE64URL_NOPAD.encode(bytes);
+
+        let (encoding, decoding, jwk) = match file.alg {
+            Algorithm::EdDSA => {
+                let pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der)
+                    .map_err(|e| format!("key {}: not an Ed25519 PKCS#8 key: {}", file.kid, e))?;
+                let x = b64(pair.public_key().as_ref());
+                let decoding = DecodingKey::from_ed_components(&x).map_err(|e| e.to_string())?;
+                let jwk = serde_json::json!({
+                    "kty": "OKP", "crv": "Ed25519", "x": x,
+                    "kid": file.kid, "alg": "EdDSA", "use": "sig",
+                });
+                (EncodingKey::from_ed_der(&der), decoding, jwk)
+            }
+            Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 => {
+                let pair = RsaKeyPair::from_pkcs8(&der)
+                    .or_else(|_| RsaKeyPair::from_der(&der))
+                    .map_err(|e| format!("key {}: not an RSA private key: {}", file.kid, e))?;
+                let components: ring::rsa::PublicKeyComponents<Vec<u8>> = pair.public().into();
+                let (n, e) = (b64(&components.n), b64(&components.e));
+                let decoding = DecodingKey::from_rsa_components(&n, &e).map_err(|e| e.to_string())?;
+                let jwk = serde_json::json!({
+                    "kty": "RSA", "n": n, "e": e,
+                    "kid": file.kid, "alg": format!("{:?}", file.alg), "use": "sig",
+                });
+                let encoding = EncodingKey::from_rsa_pem(&contents).map_err(|e| e.to_string())?;
+                (encoding, decoding, jwk)
+            }
+            other => return Err(format!("key {}: unsupported algorithm {:?}", file.kid, other)),
+        };
+
+        Ok(Self {
+            kid: file.kid,
+            algorithm: file.alg,
+            encoding,
+            decoding,
+            jwk: Some(jwk),
+            verify_until,
+        })
+    }
+
+    /// Whether tokens signed with this key are still accepted
+    fn accepts(&self) -> bool {
+        self.verify_until.is_none_or(|until| Utc::now() < until)
+    }
+}
+
+impl KeyRing {
+    pub fn from_env() -> Result<Self, String> {
+        let mut keys = Vec::new();
+        let mut active = None;
+
+        if let Ok(path) = std::env::var("JWT_KEYS_FILE") {
+            let contents = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+            let file: KeyRingFile = serde_json::from_str(&contents).map_err(|e| format!("{}: {}", path, e))?;
+            active = file.active;
+            for key in file.keys {
+                keys.push(SigningKey::load(key)?);
+            }
+        }
+
+        if let Ok(secret) = std::env::var("JWT_SECRET") {
+            if !keys.iter().any(|key| key.kid == DEFAULT_KID) {
+                keys.push(SigningKey::hmac(DEFAULT_KID.to_string(), Algorithm::HS256, secret.as_bytes(), None));
+            }
+        }
+
+        let active = std::env::var("JWT_ACTIVE_KID")
+            .ok()
+            .or(active)
+            .or_else(|| keys.first().map(|key| key.kid.clone()))
+            .ok_or("JWT_SECRET or JWT_KEYS_FILE must be set")?;
+        match keys.iter().find(|key| key.kid == active) {
+            Some(key) if key.verify_until.is_some() => {
+                return Err(format!("active key {} has a verify_until date", active));
+            }
+            Some(_) => {}
+            None => return Err(format!("active key {} is not in the key ring", active)),
+        }
+
+        Ok(Self { active, keys })
+    }
+
+    /// The key new tokens are signed with
+    pub fn active(&self) -> &SigningKey {
+        self.get(&self.active).expect("active key is in the key ring")
+    }
+
+    fn get(&self, kid: &str) -> Option<&SigningKey> {
+        self.keys.iter().find(|key| key.kid == kid)
+    }
+
+    /// Public keys of asymmetric keys still in use, as a JWK Set
+    pub fn jwks(&self) -> serde_json::Value {
+        let keys: Vec<&serde_json::Value> = self
+            .keys
+            .iter()
+            .filter(|key| key.accepts())
+            .filter_map(|key| key.jwk.as_ref())
+            .collect();
+        serde_json::json!({ "keys": keys })
+    }
 }
 
 pub fn encode_token(claims: TokenClaims) -> jsonwebtoken::errors::Result<String> {
-    let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_bytes()),
-    )
-}
-
-pub fn decode_token(token: &str) -> jsonwebtoken::errors::Result<jsonwebtoken::TokenData<TokenClaims>> {
-    let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-    decode::<TokenClaims>(
-        token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &Validation::default(),
-    )
-}
\ No newline at end of file
+    let key = key_ring().active();
+    let mut header = Header::new(key.algorithm);
+    header.kid = Some(key.kid.clone());
+    encode(&header, &claims, &key.encoding)
+}
+
+/// Decode a token, rejecting it unless it was issued for `purpose`
+pub fn decode_token(
+    token: &str,
+    purpose: TokenPurpose,
+) -> jsonwebtoken::errors::Result<jsonwebtoken::TokenData<TokenClaims>> {
+    let header = decode_header(token)?;
+    let key = key_ring()
+        .get(header.kid.as_deref().unwrap_or(DEFAULT_KID))
+        .filter(|key| key.accepts())
+        .ok_or(jsonwebtoken::errors::ErrorKind::InvalidToken)?;
+
+    // The algorithm comes from the key, never from the token
+    let data = decode::<TokenClaims>(token, &key.decoding, &Validation::new(key.algorithm))?;
+
+    if data.claims.purpose != purpose {
+        return Err(jsonwebtoken::errors::ErrorKind::InvalidToken.into());
+    }
+
+    Ok(data)
+}
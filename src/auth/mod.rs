@@ -1,5 +1,12 @@
+pub mod client;
+pub mod cookie;
+pub mod extractor;
 pub mod jwt;
-pub mod middleware;
-pub mod server;
+pub mod oidc;
+pub mod origin;
+pub mod role;
+pub mod scope;
+pub mod secret;
 
-pub use server::{get_username_from_headers, get_username_from_request};
\ No newline at end of file
+pub use client::ClientInfo;
+pub use extractor::AuthenticatedUser;
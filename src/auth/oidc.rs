@@ -0,0 +1,263 @@
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Deserializer};
+use sha2::{Digest, Sha256};
+
+/// Relying-party settings for an OpenID Connect provider
+///
+/// OIDC login is only offered when `OIDC_ISSUER_URL` and `OIDC_CLIENT_ID` are set.
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    pub issuer_url: String,
+    pub client_id: String,
+    /// Confidential clients authenticate to the token endpoint; public clients rely on PKCE alone
+    pub client_secret: Option<String>,
+    pub redirect_url: String,
+    pub scopes: String,
+    /// Shown on the login button, e.g. the university name
+    pub provider_name: String,
+}
+
+impl OidcConfig {
+    pub fn from_env() -> Option<Self> {
+        let issuer_url = std::env::var("OIDC_ISSUER_URL").ok().filter(|v| !v.is_empty())?;
+        let client_id = std::env::var("OIDC_CLIENT_ID").ok().filter(|v| !v.is_empty())?;
+        let redirect_url = std::env::var("OIDC_REDIRECT_URL").ok().or_else(|| {
+            std::env::var("SITE_BASE_URL")
+                .ok()
+                .map(|base| format!("{}/api/auth/oidc/callback", base.trim_end_matches('/')))
+        })?;
+
+        Some(Self {
+            issuer_url: issuer_url.trim_end_matches('/').to_string(),
+            client_id,
+            client_secret: std::env::var("OIDC_CLIENT_SECRET").ok().filter(|v| !v.is_empty()),
+            redirect_url,
+            scopes: std::env::var("OIDC_SCOPES").unwrap_or_else(|_| "openid email profile".to_string()),
+            provider_name: std::env::var("OIDC_PROVIDER_NAME").unwrap_or_else(|_| "Single Sign-On".to_string()),
+        })
+    }
+}
+
+/// The parts of the provider's discovery document we use
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: Option<String>,
+    pub jwks_uri: String,
+}
+
+/// The provider's discovery document rarely changes, so it is fetched once
+static METADATA: OnceCell<ProviderMetadata> = OnceCell::new();
+
+#[derive(Debug)]
+pub enum OidcError {
+    Request(String),
+    InvalidResponse(String),
+    InvalidIdToken(String),
+}
+
+impl std::fmt::Display for OidcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OidcError::Request(msg) => write!(f, "Request to identity provider failed: {}", msg),
+            OidcError::InvalidResponse(msg) => write!(f, "Invalid response from identity provider: {}", msg),
+            OidcError::InvalidIdToken(msg) => write!(f, "Invalid ID token: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for OidcError {}
+
+impl From<reqwest::Error> for OidcError {
+    fn from(error: reqwest::Error) -> Self {
+        OidcError::Request(error.to_string())
+    }
+}
+
+/// Who the provider says logged in
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdentityClaims {
+    pub sub: String,
+    pub email: Option<String>,
+    /// Some providers send this as a string
+    #[serde(default, deserialize_with = "bool_or_string")]
+    pub email_verified: bool,
+    pub preferred_username: Option<String>,
+    pub nonce: Option<String>,
+}
+
+fn bool_or_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    Ok(match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Bool(value) => value,
+        serde_json::Value::String(value) => value.eq_ignore_ascii_case("true"),
+        _ => false,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+    access_token: String,
+}
+
+/// Fetch the provider's discovery document
+pub async fn metadata(config: &OidcConfig) -> Result<ProviderMetadata, OidcError> {
+    if let Some(metadata) = METADATA.get() {
+        return Ok(metadata.clone());
+    }
+
+    let url = format!("{}/.well-known/openid-configuration", config.issuer_url);
+    let response = reqwest::get(&url).await?.error_for_status()?;
+    let metadata: ProviderMetadata = response
+        .json()
+        .await
+        .map_err(|e| OidcError::InvalidResponse(e.to_string()))?;
+
+    if metadata.issuer.trim_end_matches('/') != config.issuer_url {
+        return Err(OidcError::InvalidResponse(format!(
+            "discovery document is for issuer {}",
+            metadata.issuer
+        )));
+    }
+
+    Ok(METADATA.get_or_init(|| metadata).clone())
+}
+
+/// S256 PKCE challenge for a verifier (RFC 7636, section 4.2)
+pub fn pkce_challenge(code_verifier: &str) -> String {
+    data_encoding::BASE64URL_NOPAD.encode(&Sha256::digest(code_verifier.as_bytes()))
+}
+
+/// Where to send the browser to log in at the provider
+pub fn authorization_url(
+    config: &OidcConfig,
+    metadata: &ProviderMetadata,
+    state: &str,
+    nonce: &str,
+    code_verifier: &str,
+    reauthenticate: bool,
+) -> Result<String, OidcError> {
+    let code_challenge = pkce_challenge(code_verifier);
+    let mut url = reqwest::Url::parse_with_params(
+        &metadata.authorization_endpoint,
+        &[
+            ("response_type", "code"),
+            ("client_id", config.client_id.as_str()),
+            ("redirect_uri", config.redirect_url.as_str()),
+            ("scope", config.scopes.as_str()),
+            ("state", state),
+            ("nonce", nonce),
+            ("code_challenge", code_challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .map_err(|e| OidcError::InvalidResponse(e.to_string()))?;
+    if reauthenticate {
+        url.query_pairs_mut().append_pair("prompt", "login");
+    }
+
+    Ok(url.to_string())
+}
+
+/// Redeem an authorization code and return the validated identity
+pub async fn exchange_code(
+    config: &OidcConfig,
+    metadata: &ProviderMetadata,
+    code: &str,
+    code_verifier: &str,
+    nonce: &str,
+) -> Result<IdentityClaims, OidcError> {
+    let client = reqwest::Client::new();
+    let mut request = client.post(&metadata.token_endpoint).form(&[
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", config.redirect_url.as_str()),
+        ("client_id", config.client_id.as_str()),
+        ("code_verifier", code_verifier),
+    ]);
+    if let Some(client_secret) = &config.client_secret {
+        request = request.basic_auth(&config.client_id, Some(client_secret));
+    }
+
+    let tokens: TokenResponse = request
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+        .map_err(|e| OidcError::InvalidResponse(e.to_string()))?;
+
+    let mut claims = validate_id_token(config, metadata, &tokens.id_token).await?;
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err(OidcError::InvalidIdToken("nonce does not match".to_string()));
+    }
+
+    // Providers may leave the email out of the ID token and only return it from userinfo
+    if claims.email.is_none() {
+        if let Some(userinfo_endpoint) = &metadata.userinfo_endpoint {
+            let userinfo: IdentityClaims = client
+                .get(userinfo_endpoint)
+                .bearer_auth(&tokens.access_token)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await
+                .map_err(|e| OidcError::InvalidResponse(e.to_string()))?;
+            if userinfo.sub != claims.sub {
+                return Err(OidcError::InvalidResponse("userinfo is for another subject".to_string()));
+            }
+            claims.email = userinfo.email;
+            claims.email_verified = userinfo.email_verified;
+        }
+    }
+
+    Ok(claims)
+}
+
+/// Check the ID token's signature, issuer, audience and expiry
+///
+/// HMAC-signed tokens use the client secret as the key, as OIDC Core section 10.1
+/// specifies; other algorithms use the provider's published keys.
+async fn validate_id_token(
+    config: &OidcConfig,
+    metadata: &ProviderMetadata,
+    id_token: &str,
+) -> Result<IdentityClaims, OidcError> {
+    let header = decode_header(id_token).map_err(|e| OidcError::InvalidIdToken(e.to_string()))?;
+
+    let key = match header.alg {
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+            let client_secret = config.client_secret.as_ref().ok_or(OidcError::InvalidIdToken(
+                "HMAC-signed tokens need a client secret".to_string(),
+            ))?;
+            DecodingKey::from_secret(client_secret.as_bytes())
+        }
+        _ => {
+            let jwks: JwkSet = reqwest::get(&metadata.jwks_uri)
+                .await?
+                .error_for_status()?
+                .json()
+                .await
+                .map_err(|e| OidcError::InvalidResponse(e.to_string()))?;
+            let jwk = match &header.kid {
+                Some(kid) => jwks.find(kid),
+                None if jwks.keys.len() == 1 => jwks.keys.first(),
+                None => None,
+            }
+            .ok_or(OidcError::InvalidIdToken("signing key not found".to_string()))?;
+            DecodingKey::from_jwk(jwk).map_err(|e| OidcError::InvalidIdToken(e.to_string()))?
+        }
+    };
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[metadata.issuer.as_str()]);
+    validation.set_audience(&[config.client_id.as_str()]);
+
+    decode::<IdentityClaims>(id_token, &key, &validation)
+        .map(|data| data.claims)
+        .map_err(|e| OidcError::InvalidIdToken(e.to_string()))
+}
@@ -0,0 +1,151 @@
+use axum::{
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue, Method},
+    middleware::Next,
+    response::Response,
+};
+use reqwest::Url;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::api::ApiError;
+use crate::auth::cookie::{cookie_value, AUTH_COOKIE, REFRESH_COOKIE, SECURE_AUTH_COOKIE};
+use crate::auth::extractor::bearer_token;
+
+const DEFAULT_CORS_METHODS: &str = "GET,POST,PUT,DELETE";
+
+/// Reduce a URL to its origin, e.g. `https://glad.example.org`
+fn origin_of(url: &str) -> Option<String> {
+    let origin = Url::parse(url.trim()).ok()?.origin();
+    origin.is_tuple().then(|| origin.ascii_serialization())
+}
+
+fn env_list(name: &str) -> Vec<String> {
+    std::env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Other sites allowed to call the API from the browser, from `CORS_ALLOWED_ORIGINS`
+/// `*` allows any site, but then credentials cannot be allowed
+pub fn cors_origins() -> Vec<String> {
+    env_list("CORS_ALLOWED_ORIGINS")
+        .into_iter()
+        .map(|origin| {
+            if origin == "*" {
+                origin
+            } else {
+                origin_of(&origin)
+                    .unwrap_or_else(|| panic!("Invalid origin in CORS_ALLOWED_ORIGINS: {}", origin))
+            }
+        })
+        .collect()
+}
+
+/// Build the CORS layer from `CORS_ALLOWED_ORIGINS`, `CORS_ALLOWED_METHODS` and `CORS_ALLOW_CREDENTIALS`
+/// With no origins configured, cross-origin requests get no CORS headers
+/// Panics if the configuration is invalid
+pub fn cors_layer() -> CorsLayer {
+    let origins = cors_origins();
+    let methods = std::env::var("CORS_ALLOWED_METHODS")
+        .unwrap_or_else(|_| DEFAULT_CORS_METHODS.to_string())
+        .split(',')
+        .map(str::trim)
+        .filter(|method| !method.is_empty())
+        .map(|method| {
+            Method::from_bytes(method.to_uppercase().as_bytes())
+                .unwrap_or_else(|_| panic!("Invalid method in CORS_ALLOWED_METHODS: {}", method))
+        })
+        .collect::<Vec<_>>();
+    let allow_credentials = std::env::var("CORS_ALLOW_CREDENTIALS")
+        .map(|value| value == "true" || value == "1")
+        .unwrap_or(false);
+
+    let allow_origin = if origins.iter().any(|origin| origin == "*") {
+        if allow_credentials {
+            panic!("CORS_ALLOW_CREDENTIALS cannot be used with CORS_ALLOWED_ORIGINS=*");
+        }
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            origins
+                .iter()
+                .map(|origin| HeaderValue::from_str(origin).expect("origin is a valid header value")),
+        )
+    };
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(methods)
+        .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE])
+        .allow_credentials(allow_credentials)
+}
+
+/// Origins trusted to make state-changing requests with the user's cookies:
+/// the site itself and the configured CORS origins
+fn is_trusted_origin(origin: &str, headers: &HeaderMap) -> bool {
+    // Same origin as the request, however the site is reached
+    let host = headers.get(header::HOST).and_then(|host| host.to_str().ok());
+    if let (Some(host), Ok(url)) = (host, Url::parse(origin)) {
+        let origin_host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        if origin_host.eq_ignore_ascii_case(host) {
+            return true;
+        }
+    }
+
+    std::env::var("SITE_BASE_URL")
+        .ok()
+        .and_then(|url| origin_of(&url))
+        .into_iter()
+        .chain(cors_origins().into_iter().filter(|origin| origin != "*"))
+        .any(|trusted| trusted == origin)
+}
+
+/// Refuse cross-site requests that change state, to protect cookie sessions from CSRF
+///
+/// Unsafe methods must come with an `Origin` (or, failing that, `Referer`) header from
+/// a trusted origin. Requests authenticated with an `Authorization` header are exempt,
+/// since browsers never attach one on their own. Requests with neither header pass only
+/// if they carry no session cookies, so scripts and command-line clients keep working.
+pub async fn verify_origin(request: Request, next: Next) -> Result<Response, ApiError> {
+    let method = request.method();
+    if method == Method::GET || method == Method::HEAD || method == Method::OPTIONS {
+        return Ok(next.run(request).await);
+    }
+
+    let headers = request.headers();
+    if bearer_token(headers).is_some() {
+        return Ok(next.run(request).await);
+    }
+
+    let origin = headers
+        .get(header::ORIGIN)
+        .and_then(|origin| origin.to_str().ok())
+        .map(|origin| origin_of(origin).unwrap_or_default())
+        .or_else(|| {
+            headers
+                .get(header::REFERER)
+                .and_then(|referer| referer.to_str().ok())
+                .map(|referer| origin_of(referer).unwrap_or_default())
+        });
+
+    let allowed = match origin {
+        Some(origin) => is_trusted_origin(&origin, headers),
+        None => [AUTH_COOKIE, SECURE_AUTH_COOKIE, REFRESH_COOKIE]
+            .iter()
+            .all(|name| cookie_value(headers, name).is_none()),
+    };
+
+    if !allowed {
+        tracing::warn!("Refused cross-site {} {}", method, request.uri().path());
+        return Err(ApiError::Forbidden("Cross-site request refused".to_string()));
+    }
+
+    Ok(next.run(request).await)
+}
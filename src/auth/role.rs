@@ -0,0 +1,68 @@
+use std::{marker::PhantomData, ops::Deref};
+
+use axum::{extract::FromRequestParts, http::request::Parts};
+
+use crate::api::ApiError;
+use crate::auth::AuthenticatedUser;
+use crate::models::Role;
+
+/// Marker for the minimum role a route requires
+pub trait RequiredRole {
+    const ROLE: Role;
+}
+
+pub struct Reviewer;
+
+impl RequiredRole for Reviewer {
+    const ROLE: Role = Role::Reviewer;
+}
+
+pub struct Admin;
+
+impl RequiredRole for Admin {
+    const ROLE: Role = Role::Admin;
+}
+
+/// Extractor that only succeeds for authenticated users granted role `R`
+///
+/// Responds with 401 when the request is not authenticated and 403 when the
+/// user's role is insufficient.
+pub struct RequireRole<R: RequiredRole> {
+    user: AuthenticatedUser,
+    _role: PhantomData<R>,
+}
+
+impl<R: RequiredRole> Deref for RequireRole<R> {
+    type Target = AuthenticatedUser;
+
+    fn deref(&self) -> &Self::Target {
+        &self.user
+    }
+}
+
+impl<S, R> FromRequestParts<S> for RequireRole<R>
+where
+    S: Send + Sync,
+    R: RequiredRole,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let user = AuthenticatedUser::from_request_parts(parts, state).await?;
+
+        if !user.has_role(R::ROLE) {
+            return Err(ApiError::Forbidden("Insufficient permissions".to_string()));
+        }
+
+        Ok(Self {
+            user,
+            _role: PhantomData,
+        })
+    }
+}
+
+/// Extractor for administrator-only routes
+pub type AdminUser = RequireRole<Admin>;
+
+/// Extractor for routes open to reviewers and administrators
+pub type ReviewerUser = RequireRole<Reviewer>;
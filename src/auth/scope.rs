@@ -0,0 +1,110 @@
+use std::{marker::PhantomData, ops::Deref};
+
+use axum::{
+    extract::{FromRequestParts, OptionalFromRequestParts},
+    http::request::Parts,
+};
+
+use crate::api::ApiError;
+use crate::auth::extractor::bearer_token;
+use crate::auth::AuthenticatedUser;
+use crate::models::ApiScope;
+
+/// Marker for the API token scope a route requires
+pub trait RequiredScope {
+    const SCOPE: ApiScope;
+}
+
+pub struct QueriesRead;
+
+impl RequiredScope for QueriesRead {
+    const SCOPE: ApiScope = ApiScope::QueriesRead;
+}
+
+pub struct QueriesWrite;
+
+impl RequiredScope for QueriesWrite {
+    const SCOPE: ApiScope = ApiScope::QueriesWrite;
+}
+
+pub struct ExploreRead;
+
+impl RequiredScope for ExploreRead {
+    const SCOPE: ApiScope = ApiScope::ExploreRead;
+}
+
+/// Extractor for routes that also accept personal API tokens
+///
+/// Browser sessions always pass. API tokens pass only if they were granted
+/// scope `R`, otherwise the request is rejected with 403.
+pub struct RequireScope<R: RequiredScope> {
+    user: AuthenticatedUser,
+    _scope: PhantomData<R>,
+}
+
+impl<R: RequiredScope> Deref for RequireScope<R> {
+    type Target = AuthenticatedUser;
+
+    fn deref(&self) -> &Self::Target {
+        &self.user
+    }
+}
+
+impl<S, R> FromRequestParts<S> for RequireScope<R>
+where
+    S: Send + Sync,
+    R: RequiredScope,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let user = AuthenticatedUser::authenticate(parts).await?;
+
+        if !user.has_scope(R::SCOPE) {
+            return Err(ApiError::Forbidden(format!(
+                "API token is missing the {} scope",
+                R::SCOPE.as_str()
+            )));
+        }
+
+        Ok(Self {
+            user,
+            _scope: PhantomData,
+        })
+    }
+}
+
+/// Lets public routes check API tokens when one is sent
+///
+/// Requests without an `Authorization: Bearer` header stay anonymous, even if
+/// they carry a session cookie. A bearer credential that is invalid or lacks
+/// scope `R` is rejected rather than silently ignored.
+impl<S, R> OptionalFromRequestParts<S> for RequireScope<R>
+where
+    S: Send + Sync,
+    R: RequiredScope,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        if bearer_token(&parts.headers).is_none() {
+            return Ok(None);
+        }
+
+        <Self as FromRequestParts<S>>::from_request_parts(parts, state)
+            .await
+            .map(Some)
+    }
+}
+
+/// Extractor for reading the user's queries
+pub type QueryReader = RequireScope<QueriesRead>;
+
+/// Extractor for submitting queries
+pub type QueryWriter = RequireScope<QueriesWrite>;
+
+/// Extractor for the explore data routes
+pub type ExploreReader = RequireScope<ExploreRead>;
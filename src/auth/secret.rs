@@ -0,0 +1,18 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+/// Length in bytes of generated secrets before hex encoding
+const SECRET_BYTES: usize = 32;
+
+/// Generate a random hex-encoded secret suitable for use as a bearer credential
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; SECRET_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Hash a secret for storage. Secrets are random and high-entropy,
+/// so a fast unsalted hash is sufficient to protect them at rest
+pub fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
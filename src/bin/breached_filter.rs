@@ -0,0 +1,52 @@
+//! Build a breached-password filter for `PASSWORD_BREACHED_FILTER`
+//!
+//! Usage: `cargo run --bin breached_filter -- <passwords.txt> <output.bloom> [false positive rate]`
+//!
+//! The input has one password per line, such as a list of the most common
+//! passwords from known breaches.
+
+use std::io::BufRead;
+
+use glad_web::models::BreachedPasswords;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 {
+        eprintln!("Usage: {} <passwords.txt> <output.bloom> [false positive rate]", args[0]);
+        std::process::exit(2);
+    }
+
+    let false_positive_rate = match args.get(3).map(|rate| rate.parse::<f64>()) {
+        None => 0.001,
+        Some(Ok(rate)) if rate > 0.0 && rate < 1.0 => rate,
+        Some(_) => {
+            eprintln!("The false positive rate must be between 0 and 1");
+            std::process::exit(2);
+        }
+    };
+
+    let file = std::fs::File::open(&args[1]).unwrap_or_else(|e| {
+        eprintln!("Failed to open {}: {}", args[1], e);
+        std::process::exit(1);
+    });
+    let passwords: Vec<String> = std::io::BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .map(|line| line.trim_end_matches('\r').to_string())
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    let filter = BreachedPasswords::build(&passwords, false_positive_rate);
+    let bytes = filter.to_bytes();
+    if let Err(e) = std::fs::write(&args[2], &bytes) {
+        eprintln!("Failed to write {}: {}", args[2], e);
+        std::process::exit(1);
+    }
+
+    println!(
+        "Wrote {} passwords to {} ({} bytes)",
+        passwords.len(),
+        args[2],
+        bytes.len()
+    );
+}
@@ -0,0 +1,72 @@
+//! A local endpoint for trying out webhooks
+//!
+//! Usage: `cargo run --bin webhook_stub -- <port> <secret> [status]`
+//!
+//! Start the server with `WEBHOOK_ALLOWED_HOSTS=localhost`, then register
+//! `http://localhost:<port>/`, or any path under it, as a webhook with the same
+//! secret. Each request is printed with whether its signature is
+//! valid, and answered with `status` (200 by default), so failures and retries
+//! can be tried too.
+
+use axum::{body::Bytes, http::HeaderMap, http::StatusCode, routing::post, Router};
+
+use glad_web::models::Webhook;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 {
+        eprintln!("Usage: {} <port> <secret> [status]", args[0]);
+        std::process::exit(2);
+    }
+
+    let port: u16 = args[1].parse().unwrap_or_else(|_| {
+        eprintln!("Invalid port: {}", args[1]);
+        std::process::exit(2);
+    });
+    let secret = args[2].clone();
+    let status = match args.get(3).map(|status| status.parse::<u16>().map(StatusCode::from_u16)) {
+        None => StatusCode::OK,
+        Some(Ok(Ok(status))) => status,
+        Some(_) => {
+            eprintln!("Invalid status: {}", args[3]);
+            std::process::exit(2);
+        }
+    };
+
+    let handler = move |headers: HeaderMap, body: Bytes| async move {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let body = String::from_utf8_lossy(&body);
+        let valid = header("X-Glad-Timestamp")
+            .parse::<i64>()
+            .is_ok_and(|timestamp| Webhook::signature(&secret, timestamp, &body) == header("X-Glad-Signature"));
+
+        println!(
+            "{} delivery {} ({}): {}",
+            header("X-Glad-Event"),
+            header("X-Glad-Delivery"),
+            if valid { "valid signature" } else { "INVALID SIGNATURE" },
+            body
+        );
+        status
+    };
+
+    let runtime = tokio::runtime::Runtime::new().expect("failed to start the runtime");
+    runtime.block_on(async move {
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", port))
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to listen on port {}: {}", port, e);
+                std::process::exit(1);
+            });
+        println!("Listening on http://localhost:{}/, answering {}", port, status);
+        axum::serve(listener, Router::new().fallback(post(handler)))
+            .await
+            .expect("server failed");
+    });
+}
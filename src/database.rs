@@ -23,3 +23,17 @@ pub async fn init_db() -> Result<(), sqlx::Pool<sqlx::Sqlite>> {
 pub fn get_db<'a>() -> &'a sqlx::SqlitePool {
     DB.get().expect("database unitialized")
 }
+
+/// Point the pool at a fresh database file for this test process, migrating it on first use
+/// Tests sharing it must use their own usernames, addresses and so on
+#[cfg(test)]
+pub(crate) async fn test_db() {
+    static INIT: tokio::sync::OnceCell<()> = tokio::sync::OnceCell::const_new();
+    INIT.get_or_init(|| async {
+        let database = std::env::temp_dir().join(format!("glad-lib-test-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&database);
+        std::env::set_var("DATABASE_URL", format!("sqlite://{}?mode=rwc", database.display()));
+        init_db().await.expect("database initialized twice");
+    })
+    .await;
+}
@@ -1,5 +1,6 @@
 pub mod auth;
 pub mod database;
+pub mod mail;
 pub mod models;
 pub mod api;
 pub mod visualization;
@@ -0,0 +1,384 @@
+//! Sending email through a configurable transport
+//!
+//! `MAILER_BACKEND` picks the transport: `smtp` (the default) sends through a
+//! mail server, `file` writes each email to a `.eml` file, and `log` only logs
+//! emails, for development without a mail server.
+
+pub mod templates;
+
+use std::future::Future;
+use std::path::PathBuf;
+
+use mail_send::mail_builder::headers::{raw::Raw, url::URL};
+use mail_send::mail_builder::MessageBuilder;
+use mail_send::SmtpClient;
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+
+/// Name emails are sent from
+const SENDER_NAME: &str = "GLAD";
+
+/// An email ready to be sent
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to_name: String,
+    pub to_address: String,
+    pub subject: String,
+    pub text_body: String,
+    /// Sent alongside the text body when set
+    pub html_body: Option<String>,
+    /// Where mail clients can unsubscribe the recipient with a `POST`, sent in
+    /// the `List-Unsubscribe` and `List-Unsubscribe-Post` headers (RFC 8058)
+    pub unsubscribe_url: Option<String>,
+}
+
+/// Why an email was not sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MailErrorKind {
+    /// The server refused this email for good, e.g. an unknown recipient
+    Rejected,
+    /// The server refused this email for now
+    Deferred,
+    /// The mail server or directory could not be reached, so other emails will fail too
+    Unavailable,
+}
+
+/// A failure to send an email
+#[derive(Debug)]
+pub struct MailError {
+    pub kind: MailErrorKind,
+    pub message: String,
+}
+
+impl MailError {
+    fn unavailable(error: impl std::fmt::Display) -> Self {
+        Self {
+            kind: MailErrorKind::Unavailable,
+            message: error.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for MailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for MailError {}
+
+impl From<mail_send::Error> for MailError {
+    fn from(error: mail_send::Error) -> Self {
+        // A 5xx reply means the server will never take this message, a 4xx that it might later
+        let kind = match &error {
+            mail_send::Error::UnexpectedReply(reply) if reply.code >= 500 => MailErrorKind::Rejected,
+            mail_send::Error::UnexpectedReply(_) => MailErrorKind::Deferred,
+            _ => MailErrorKind::Unavailable,
+        };
+        Self {
+            kind,
+            message: error.to_string(),
+        }
+    }
+}
+
+/// A way of sending email
+///
+/// A mailer may hold a connection open between emails, so send a batch through
+/// one mailer and `close` it when done.
+pub trait Mailer {
+    fn send(&mut self, email: &Email) -> impl Future<Output = Result<(), MailError>> + Send;
+
+    fn close(self) -> impl Future<Output = ()> + Send;
+}
+
+/// How the SMTP connection is secured
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpTls {
+    /// Upgrade a plain connection with STARTTLS
+    StartTls,
+    /// Connect over TLS from the start, usually on port 465
+    Implicit,
+    /// No encryption, only for a relay on a trusted network
+    None,
+}
+
+/// SMTP settings
+#[derive(Debug, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub tls: SmtpTls,
+    /// Login, if the server needs one
+    pub credentials: Option<(String, String)>,
+}
+
+/// Mail settings, from the environment
+#[derive(Debug, Clone)]
+pub enum MailerConfig {
+    Smtp(SmtpConfig),
+    /// Write emails to `.eml` files in a directory
+    File(PathBuf),
+    Log,
+}
+
+/// Read an environment variable, treating an empty value as unset
+fn env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// Address emails are sent from, `MAILER_EMAIL`
+pub fn sender_address() -> String {
+    env("MAILER_EMAIL").unwrap_or_else(|| "glad@localhost".to_string())
+}
+
+impl MailerConfig {
+    /// Load the configuration
+    ///
+    /// SMTP uses `MAILER_SMTP_SERVER`, `MAILER_SMTP_PORT` (587), `MAILER_SMTP_TLS`
+    /// (`starttls`, `implicit` or `none`), and logs in as `MAILER_SMTP_USERNAME`
+    /// (or `MAILER_EMAIL`) with `MAILER_PASSWD` if set. The file backend writes to
+    /// `MAILER_FILE_DIR`.
+    pub fn from_env() -> Result<Self, String> {
+        match env("MAILER_BACKEND").as_deref().unwrap_or("smtp") {
+            "smtp" => {
+                let host = env("MAILER_SMTP_SERVER").ok_or("MAILER_SMTP_SERVER is not set")?;
+                let tls = match env("MAILER_SMTP_TLS").as_deref().unwrap_or("starttls") {
+                    "starttls" => SmtpTls::StartTls,
+                    "implicit" => SmtpTls::Implicit,
+                    "none" => SmtpTls::None,
+                    other => return Err(format!("Unknown MAILER_SMTP_TLS {}", other)),
+                };
+                let default_port = if tls == SmtpTls::Implicit { 465 } else { 587 };
+                let port = match env("MAILER_SMTP_PORT") {
+                    Some(port) => port
+                        .parse()
+                        .map_err(|_| format!("Invalid MAILER_SMTP_PORT {}", port))?,
+                    None => default_port,
+                };
+                let credentials = env("MAILER_PASSWD").map(|password| {
+                    let username = env("MAILER_SMTP_USERNAME").unwrap_or_else(sender_address);
+                    (username, password)
+                });
+
+                Ok(Self::Smtp(SmtpConfig {
+                    host,
+                    port,
+                    tls,
+                    credentials,
+                }))
+            }
+            "file" => Ok(Self::File(PathBuf::from(
+                env("MAILER_FILE_DIR").ok_or("MAILER_FILE_DIR is not set")?,
+            ))),
+            "log" => Ok(Self::Log),
+            other => Err(format!("Unknown MAILER_BACKEND {}", other)),
+        }
+    }
+
+    /// Create a mailer for this configuration
+    pub fn mailer(&self) -> ConfiguredMailer {
+        match self {
+            Self::Smtp(config) => ConfiguredMailer::Smtp(SmtpMailer::new(config.clone())),
+            Self::File(dir) => ConfiguredMailer::File(FileMailer::new(dir.clone())),
+            Self::Log => ConfiguredMailer::Log(LogMailer),
+        }
+    }
+}
+
+/// Build the MIME message for an email
+fn message(email: &Email) -> MessageBuilder<'_> {
+    let message = MessageBuilder::new()
+        .from((SENDER_NAME.to_string(), sender_address()))
+        .to(vec![(email.to_name.as_str(), email.to_address.as_str())])
+        .subject(email.subject.as_str())
+        .text_body(email.text_body.as_str());
+    let message = match &email.unsubscribe_url {
+        Some(unsubscribe_url) => message
+            .header("List-Unsubscribe", URL::new(unsubscribe_url.as_str()))
+            .header("List-Unsubscribe-Post", Raw::new("List-Unsubscribe=One-Click")),
+        None => message,
+    };
+    match &email.html_body {
+        Some(html_body) => message.html_body(html_body.as_str()),
+        None => message,
+    }
+}
+
+enum SmtpConnection {
+    Tls(Box<SmtpClient<TlsStream<TcpStream>>>),
+    Plain(SmtpClient<TcpStream>),
+}
+
+/// Sends email through an SMTP server, reusing one connection for a batch
+pub struct SmtpMailer {
+    config: SmtpConfig,
+    connection: Option<SmtpConnection>,
+}
+
+impl SmtpMailer {
+    pub fn new(config: SmtpConfig) -> Self {
+        Self {
+            config,
+            connection: None,
+        }
+    }
+
+    async fn connect(&self) -> Result<SmtpConnection, MailError> {
+        let mut builder = mail_send::SmtpClientBuilder::new(self.config.host.as_str(), self.config.port)
+            .implicit_tls(self.config.tls == SmtpTls::Implicit);
+        if let Some((username, password)) = &self.config.credentials {
+            builder = builder.credentials((username.as_str(), password.as_str()));
+        }
+
+        Ok(match self.config.tls {
+            SmtpTls::None => SmtpConnection::Plain(builder.connect_plain().await?),
+            SmtpTls::StartTls | SmtpTls::Implicit => SmtpConnection::Tls(Box::new(builder.connect().await?)),
+        })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    async fn send(&mut self, email: &Email) -> Result<(), MailError> {
+        if self.connection.is_none() {
+            self.connection = Some(self.connect().await?);
+        }
+        let Some(connection) = self.connection.as_mut() else {
+            return Err(MailError::unavailable("Not connected"));
+        };
+
+        let result = match connection {
+            SmtpConnection::Tls(client) => client.send(message(email)).await,
+            SmtpConnection::Plain(client) => client.send(message(email)).await,
+        };
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let error = MailError::from(e);
+                // A rejected message leaves the connection usable once reset;
+                // anything else may have broken it, so reconnect next time
+                let reset = match (error.kind, connection) {
+                    (MailErrorKind::Unavailable, _) => false,
+                    (_, SmtpConnection::Tls(client)) => client.rset().await.is_ok(),
+                    (_, SmtpConnection::Plain(client)) => client.rset().await.is_ok(),
+                };
+                if !reset {
+                    self.connection = None;
+                }
+                Err(error)
+            }
+        }
+    }
+
+    async fn close(self) {
+        let _ = match self.connection {
+            Some(SmtpConnection::Tls(client)) => client.quit().await,
+            Some(SmtpConnection::Plain(client)) => client.quit().await,
+            None => Ok(()),
+        };
+    }
+}
+
+/// Writes each email to a `.eml` file, for inspecting emails without sending them
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+}
+
+impl Mailer for FileMailer {
+    async fn send(&mut self, email: &Email) -> Result<(), MailError> {
+        let contents = message(email).write_to_vec().map_err(MailError::unavailable)?;
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(MailError::unavailable)?;
+
+        let name = format!(
+            "{}-{}.eml",
+            sqlx::types::chrono::Utc::now().format("%Y%m%dT%H%M%S%.6f"),
+            uuid::Uuid::new_v4().simple()
+        );
+        let path = self.dir.join(name);
+        tokio::fs::write(&path, contents)
+            .await
+            .map_err(MailError::unavailable)?;
+
+        tracing::debug!("Wrote email to {} to {}", email.to_address, path.display());
+        Ok(())
+    }
+
+    async fn close(self) {}
+}
+
+/// Logs emails instead of sending them, for development
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    async fn send(&mut self, email: &Email) -> Result<(), MailError> {
+        tracing::info!(
+            "Email to {} <{}>\nSubject: {}\n\n{}",
+            email.to_name,
+            email.to_address,
+            email.subject,
+            email.text_body
+        );
+        Ok(())
+    }
+
+    async fn close(self) {}
+}
+
+/// Keeps emails in memory instead of sending them, for tests
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct CapturingMailer {
+    sent: std::sync::Arc<std::sync::Mutex<Vec<Email>>>,
+}
+
+#[cfg(test)]
+impl CapturingMailer {
+    /// The emails sent so far, by this mailer and its clones
+    pub fn sent(&self) -> Vec<Email> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl Mailer for CapturingMailer {
+    async fn send(&mut self, email: &Email) -> Result<(), MailError> {
+        self.sent.lock().unwrap().push(email.clone());
+        Ok(())
+    }
+
+    async fn close(self) {}
+}
+
+/// The mailer chosen by configuration
+pub enum ConfiguredMailer {
+    Smtp(SmtpMailer),
+    File(FileMailer),
+    Log(LogMailer),
+}
+
+impl Mailer for ConfiguredMailer {
+    async fn send(&mut self, email: &Email) -> Result<(), MailError> {
+        match self {
+            Self::Smtp(mailer) => mailer.send(email).await,
+            Self::File(mailer) => mailer.send(email).await,
+            Self::Log(mailer) => mailer.send(email).await,
+        }
+    }
+
+    async fn close(self) {
+        match self {
+            Self::Smtp(mailer) => mailer.close().await,
+            Self::File(mailer) => mailer.close().await,
+            Self::Log(mailer) => mailer.close().await,
+        }
+    }
+}
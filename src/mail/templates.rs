@@ -0,0 +1,226 @@
+//! Localized email templates
+//!
+//! Every email has a `{name}.subject`, `{name}.txt` and `{name}.html` template for
+//! each locale under `templates/email/{locale}/`, and its bodies are wrapped in
+//! that locale's `layout.txt` and `layout.html`. The templates are bundled with
+//! the application; a file with the same path under `EMAIL_TEMPLATE_DIR` is used
+//! instead, read each time an email is rendered.
+//!
+//! `{{name}}` inserts a value, escaped in HTML templates, and `{{{name}}}` inserts
+//! it as is. `{{#name}}...{{/name}}` is kept only when the value is set and not
+//! empty, `{{^name}}...{{/name}}` only when it is not.
+
+use std::path::PathBuf;
+
+use crate::models::Locale;
+
+macro_rules! bundled {
+    ($($file:literal),* $(,)?) => {
+        &[$(
+            (concat!("en/", $file), include_str!(concat!("../../templates/email/en/", $file))),
+            (concat!("es/", $file), include_str!(concat!("../../templates/email/es/", $file))),
+            (concat!("pt/", $file), include_str!(concat!("../../templates/email/pt/", $file))),
+        )*]
+    };
+}
+
+static BUNDLED: &[(&str, &str)] = bundled![
+    "layout.txt",
+    "layout.html",
+    "account_approved.subject",
+    "account_approved.txt",
+    "account_approved.html",
+    "account_rejected.subject",
+    "account_rejected.txt",
+    "account_rejected.html",
+    "account_unlock.subject",
+    "account_unlock.txt",
+    "account_unlock.html",
+    "announcement.subject",
+    "announcement.txt",
+    "announcement.html",
+    "cohort_added.subject",
+    "cohort_added.txt",
+    "cohort_added.html",
+    "email_verification.subject",
+    "email_verification.txt",
+    "email_verification.html",
+    "organization_invitation.subject",
+    "organization_invitation.txt",
+    "organization_invitation.html",
+    "notification_digest.subject",
+    "notification_digest.txt",
+    "notification_digest.html",
+    "password_reset.subject",
+    "password_reset.txt",
+    "password_reset.html",
+    "query_status.subject",
+    "query_status.txt",
+    "query_status.html",
+    "results_expiring.subject",
+    "results_expiring.txt",
+    "results_expiring.html",
+];
+
+/// The emails the application sends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailTemplate {
+    AccountApproved,
+    AccountRejected,
+    AccountUnlock,
+    Announcement,
+    CohortAdded,
+    EmailVerification,
+    NotificationDigest,
+    OrganizationInvitation,
+    PasswordReset,
+    QueryStatus,
+    ResultsExpiring,
+}
+
+/// An email rendered in a user's language
+#[derive(Debug, Clone)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub text_body: String,
+    pub html_body: String,
+}
+
+impl EmailTemplate {
+    pub fn name(self) -> &'static str {
+        match self {
+            EmailTemplate::AccountApproved => "account_approved",
+            EmailTemplate::AccountRejected => "account_rejected",
+            EmailTemplate::AccountUnlock => "account_unlock",
+            EmailTemplate::Announcement => "announcement",
+            EmailTemplate::CohortAdded => "cohort_added",
+            EmailTemplate::EmailVerification => "email_verification",
+            EmailTemplate::NotificationDigest => "notification_digest",
+            EmailTemplate::OrganizationInvitation => "organization_invitation",
+            EmailTemplate::PasswordReset => "password_reset",
+            EmailTemplate::QueryStatus => "query_status",
+            EmailTemplate::ResultsExpiring => "results_expiring",
+        }
+    }
+
+    /// Render the email in a locale
+    /// `site_url` and `subject` are always available to the templates
+    pub fn render(self, locale: Locale, values: &[(&str, &str)]) -> RenderedEmail {
+        let site_url = std::env::var("SITE_BASE_URL").unwrap_or_default();
+        let mut values = values.to_vec();
+        values.push(("site_url", &site_url));
+
+        let subject = render(&load(locale, &format!("{}.subject", self.name())), &values, false);
+        let subject = subject.trim().to_string();
+        values.push(("subject", &subject));
+
+        let text = render(&load(locale, &format!("{}.txt", self.name())), &values, false);
+        let html = render(&load(locale, &format!("{}.html", self.name())), &values, true);
+
+        let mut text_values = values.clone();
+        text_values.push(("content", text.trim()));
+        let text_body = render(&load(locale, "layout.txt"), &text_values, false);
+        let mut html_values = values;
+        html_values.push(("content", html.trim()));
+        let html_body = render(&load(locale, "layout.html"), &html_values, true);
+
+        RenderedEmail {
+            subject,
+            text_body: text_body.trim().to_string(),
+            html_body,
+        }
+    }
+}
+
+/// Read a template, preferring `EMAIL_TEMPLATE_DIR` over the bundled one
+fn load(locale: Locale, file: &str) -> String {
+    let path = format!("{}/{}", locale.code(), file);
+    if let Some(dir) = std::env::var("EMAIL_TEMPLATE_DIR").ok().filter(|dir| !dir.is_empty()) {
+        let override_path = PathBuf::from(dir).join(&path);
+        match std::fs::read_to_string(&override_path) {
+            Ok(template) => return template,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("Failed to read email template {}: {}", override_path.display(), e),
+        }
+    }
+
+    BUNDLED
+        .iter()
+        .find(|(bundled_path, _)| *bundled_path == path)
+        .map(|(_, template)| template.to_string())
+        .expect("every email template is bundled for every locale")
+}
+
+fn value<'a>(values: &[(&str, &'a str)], name: &str) -> Option<&'a str> {
+    values
+        .iter()
+        .rev()
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| *value)
+}
+
+/// Escape a value for HTML, for markup built outside the templates
+pub fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Fill in a template's tags, escaping values for HTML if `html` is set
+fn render(template: &str, values: &[(&str, &str)], html: bool) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let tag = &rest[start..];
+
+        if let Some(end) = tag.strip_prefix("{{{").and_then(|inner| inner.find("}}}")) {
+            let name = tag[3..3 + end].trim();
+            output.push_str(value(values, name).unwrap_or_default());
+            rest = &tag[end + 6..];
+            continue;
+        }
+
+        let Some(end) = tag.find("}}") else {
+            output.push_str(tag);
+            rest = "";
+            break;
+        };
+        let name = tag[2..end].trim();
+        rest = &tag[end + 2..];
+
+        if let Some(section) = name.strip_prefix('#').or_else(|| name.strip_prefix('^')) {
+            let section = section.trim();
+            let close = format!("{{{{/{}}}}}", section);
+            let (inner, after) = match rest.find(&close) {
+                Some(index) => (&rest[..index], &rest[index + close.len()..]),
+                None => (rest, ""),
+            };
+            let is_set = value(values, section).is_some_and(|value| !value.is_empty());
+            if is_set != name.starts_with('^') {
+                output.push_str(&render(inner, values, html));
+            }
+            rest = after;
+        } else if !name.starts_with('/') {
+            let value = value(values, name).unwrap_or_default();
+            if html {
+                output.push_str(&escape_html(value));
+            } else {
+                output.push_str(value);
+            }
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
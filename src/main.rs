@@ -1,15 +1,11 @@
 use axum::{
-    routing::{get, post},
+    routing::{delete, get, post, put},
     Router,
 };
-use tower_http::{cors::CorsLayer, services::ServeDir, trace::TraceLayer};
+use tower_http::{services::ServeDir, trace::TraceLayer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-mod api;
-mod auth;
-mod database;
-mod models;
-mod visualization;
+use glad_web::{api, auth, database, models, visualization};
 
 /// Number of top groups to use for cache warming (matches frontend MAX_DEFAULT_SELECTED_GROUPS)
 const CACHE_WARMING_TOP_GROUPS: usize = 12;
@@ -17,6 +13,18 @@ const CACHE_WARMING_TOP_GROUPS: usize = 12;
 /// Notification check interval in seconds
 const NOTIFICATION_CHECK_INTERVAL_SECONDS: u64 = 60;
 
+/// Digest schedule check interval in seconds, the precision of users' chosen digest times
+const DIGEST_CHECK_INTERVAL_SECONDS: u64 = 60;
+
+/// Email outbox check interval in seconds; queued emails also wake the sender
+const EMAIL_CHECK_INTERVAL_SECONDS: u64 = 30;
+
+/// Webhook delivery check interval in seconds; queued deliveries also wake the sender
+const WEBHOOK_CHECK_INTERVAL_SECONDS: u64 = 30;
+
+/// Retention sweep interval in seconds, for organization queries and deleted accounts
+const RETENTION_CHECK_INTERVAL_SECONDS: u64 = 3600;
+
 /// Warm cache for a specific field combination (single field or comma-separated fields)
 async fn warm_field_cache(
     grouping: &str,
@@ -53,7 +61,7 @@ async fn warm_field_cache(
                 selected_groups: top_k_labels.clone(),
             };
 
-            api::explore::compute_ibd_matrix(axum::extract::Json(matrix_request))
+            let _ = api::explore::compute_ibd_matrix(axum::extract::Json(matrix_request))
                 .await
                 .map_err(|e| format!("Failed to compute matrix: {:?}", e))?;
             Ok(top_k_labels.len())
@@ -76,6 +84,9 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
+    // Load token signing keys, so a bad key configuration fails at startup
+    tracing::info!("Signing tokens with key {}", auth::jwt::key_ring().active().kid);
+
     // Initialize database
     database::init_db()
         .await
@@ -150,26 +161,198 @@ async fn main() {
                     tracing::error!("Failed to process pending notifications: {}", e);
                 }
             }
+
+            match models::Announcement::notify_due().await {
+                Ok(count) if count > 0 => {
+                    tracing::info!("Sent {} announcements as notifications", count);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::error!("Failed to send announcements as notifications: {}", e);
+                }
+            }
         }
     });
 
-    // Create router
-    let app = Router::new()
-        // API routes
+    // Start notification digest scheduler
+    tracing::info!("Starting notification digest scheduler...");
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(
+            DIGEST_CHECK_INTERVAL_SECONDS,
+        ));
+
+        loop {
+            interval.tick().await;
+
+            match models::NotificationDigest::send_due().await {
+                Ok(count) if count > 0 => {
+                    tracing::info!("Sent {} notification digests", count);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::error!("Failed to send notification digests: {}", e);
+                }
+            }
+        }
+    });
+
+    // Start email delivery task
+    tracing::info!("Starting email delivery task...");
+    tokio::spawn(async {
+        loop {
+            match models::EmailOutbox::deliver_due().await {
+                Ok(count) if count > 0 => {
+                    tracing::info!("Sent {} queued emails", count);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::error!("Failed to deliver queued emails: {}", e);
+                }
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(tokio::time::Duration::from_secs(EMAIL_CHECK_INTERVAL_SECONDS)) => {}
+                _ = models::EmailOutbox::queued() => {}
+            }
+        }
+    });
+
+    // Start webhook delivery task
+    tracing::info!("Starting webhook delivery task...");
+    tokio::spawn(async {
+        loop {
+            match models::Webhook::deliver_due().await {
+                Ok(count) if count > 0 => {
+                    tracing::info!("Delivered {} webhook events", count);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::error!("Failed to deliver webhook events: {}", e);
+                }
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(tokio::time::Duration::from_secs(WEBHOOK_CHECK_INTERVAL_SECONDS)) => {}
+                _ = models::Webhook::queued() => {}
+            }
+        }
+    });
+
+    // Start organization retention task
+    tracing::info!("Starting organization retention task...");
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(
+            RETENTION_CHECK_INTERVAL_SECONDS,
+        ));
+
+        loop {
+            interval.tick().await;
+
+            match models::Notification::notify_expiring_results().await {
+                Ok(count) if count > 0 => {
+                    tracing::info!("Warned about {} queries nearing their organization retention", count);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::error!("Failed to warn about expiring organization queries: {}", e);
+                }
+            }
+
+            match models::Query::purge_expired().await {
+                Ok(count) if count > 0 => {
+                    tracing::info!("Purged {} queries past their organization retention", count);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::error!("Failed to purge expired organization queries: {}", e);
+                }
+            }
+
+            match models::AccountDeletion::purge_due().await {
+                Ok(count) if count > 0 => {
+                    tracing::info!("Deleted {} accounts past their deletion grace period", count);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::error!("Failed to delete accounts scheduled for deletion: {}", e);
+                }
+            }
+
+            match models::EmailOutbox::purge_sent().await {
+                Ok(count) if count > 0 => {
+                    tracing::info!("Purged {} sent emails from the outbox", count);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::error!("Failed to purge sent emails: {}", e);
+                }
+            }
+
+            match models::Webhook::purge_deliveries().await {
+                Ok(count) if count > 0 => {
+                    tracing::info!("Purged {} old webhook deliveries", count);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::error!("Failed to purge webhook deliveries: {}", e);
+                }
+            }
+
+            match models::Notification::archive_read().await {
+                Ok(count) if count > 0 => {
+                    tracing::info!("Archived {} read notifications", count);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::error!("Failed to archive read notifications: {}", e);
+                }
+            }
+
+            match models::AuditEvent::purge_expired().await {
+                Ok(count) if count > 0 => {
+                    tracing::info!("Purged {} audit events past their retention period", count);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::error!("Failed to purge audit events: {}", e);
+                }
+            }
+        }
+    });
+
+    // Routes that can be used without logging in
+    let public_routes = Router::new()
         .route("/api/auth/login", post(api::auth::login))
         .route("/api/auth/logout", post(api::auth::logout))
+        .route("/api/auth/refresh", post(api::auth::refresh))
         .route("/api/auth/signup", post(api::auth::signup))
-        .route("/api/auth/me", get(api::auth::me))
         .route("/api/auth/reset-password", post(api::auth::reset_password))
+        .route("/api/auth/verify-email", post(api::auth::verify_email))
+        .route("/api/auth/unlock", post(api::auth::unlock_account))
+        .route(
+            "/api/notifications/unsubscribe",
+            post(api::notifications::unsubscribe),
+        )
+        .route("/api/auth/login/2fa", post(api::auth::login_two_factor))
+        .route("/api/auth/oidc", get(api::oidc::get_config))
+        .route("/api/auth/oidc/login", get(api::oidc::login))
+        .route("/api/auth/oidc/callback", get(api::oidc::callback))
+        .route(
+            "/api/auth/login/2fa/setup",
+            post(api::auth::login_two_factor_setup),
+        )
         .route(
             "/api/auth/reset-password-confirm",
             post(api::auth::reset_password_confirm),
         )
-        .route("/api/auth/settings", post(api::auth::update_settings))
         .route("/api/cohorts", get(api::find::get_cohorts))
-        .route("/api/find-controls", post(api::find::submit_find_controls))
-        .route("/api/queries", get(api::find::get_user_queries))
-        .route("/api/queries/{id}", get(api::find::get_query_details))
+        .route("/api/agreement", get(api::auth::get_agreement))
+        .route("/api/announcements", get(api::announcements::get_active_announcements))
+        .route("/.well-known/jwks.json", get(api::auth::jwks))
+        .route("/api/citations", get(api::publication::get_citations));
+
+    // Explore data is public, but an API token sent along must carry explore:read
+    let explore_routes = Router::new()
         .route("/api/pca-data", get(api::explore::get_pca_data))
         .route(
             "/api/ibd-communities",
@@ -181,7 +364,67 @@ async fn main() {
             "/api/ibd-matrix-asymmetric",
             post(api::explore::compute_asymmetric_ibd_matrix),
         )
-        .route("/api/citations", get(api::publication::get_citations))
+        .route_layer(axum::middleware::from_extractor::<
+            Option<auth::scope::ExploreReader>,
+        >());
+
+    // Routes that also accept personal API tokens with the matching scope
+    let scoped_routes = Router::new()
+        .route("/api/find-controls", post(api::find::submit_find_controls))
+        .route("/api/queries", get(api::find::get_user_queries))
+        .route("/api/queries/{id}", get(api::find::get_query_details));
+
+    // Routes that require a logged-in user; API tokens are rejected
+    let protected_routes = Router::new()
+        .route("/api/auth/me", get(api::auth::me))
+        .route("/api/auth/settings", post(api::auth::update_settings))
+        .route(
+            "/api/auth/verify-email/resend",
+            post(api::auth::resend_verification),
+        )
+        .route("/api/auth/agreement", post(api::auth::accept_agreement))
+        .route("/api/auth/export", get(api::account::export))
+        .route("/api/auth/account", delete(api::account::delete_account))
+        .route(
+            "/api/auth/account/restore",
+            post(api::account::cancel_deletion),
+        )
+        .route(
+            "/api/auth/sessions",
+            get(api::auth::get_sessions).delete(api::auth::revoke_all_sessions),
+        )
+        .route(
+            "/api/auth/sessions/{id}",
+            delete(api::auth::revoke_session),
+        )
+        .route(
+            "/api/auth/tokens",
+            get(api::tokens::get_tokens).post(api::tokens::create_token),
+        )
+        .route("/api/auth/tokens/{id}", delete(api::tokens::revoke_token))
+        .route(
+            "/api/webhooks",
+            get(api::webhooks::get_webhooks).post(api::webhooks::create_webhook),
+        )
+        .route("/api/webhooks/{id}", delete(api::webhooks::delete_webhook))
+        .route(
+            "/api/webhooks/{id}/deliveries",
+            get(api::webhooks::get_deliveries),
+        )
+        .route(
+            "/api/webhooks/{id}/test",
+            post(api::webhooks::send_test_event),
+        )
+        .route(
+            "/api/auth/2fa",
+            get(api::two_factor::get_status).delete(api::two_factor::disable),
+        )
+        .route("/api/auth/2fa/setup", post(api::two_factor::setup))
+        .route("/api/auth/2fa/confirm", post(api::two_factor::confirm))
+        .route(
+            "/api/auth/2fa/recovery-codes",
+            post(api::two_factor::regenerate_recovery_codes),
+        )
         // Notification routes
         .route(
             "/api/notifications",
@@ -200,12 +443,111 @@ async fn main() {
             post(api::notifications::mark_all_as_read),
         )
         .route(
-            "/api/notifications/process-pending",
-            post(api::notifications::process_pending_notifications),
+            "/api/notifications/delete",
+            post(api::notifications::delete_notifications),
+        )
+        .route(
+            "/api/notifications/preferences",
+            get(api::notifications::get_preferences).put(api::notifications::update_preferences),
+        )
+        // Organization routes
+        .route(
+            "/api/organizations",
+            get(api::organizations::get_organizations)
+                .post(api::organizations::create_organization),
+        )
+        .route(
+            "/api/organizations/{id}",
+            get(api::organizations::get_organization),
+        )
+        .route(
+            "/api/organizations/{id}/settings",
+            post(api::organizations::update_organization_settings),
+        )
+        .route(
+            "/api/organizations/{id}/invitations",
+            post(api::organizations::invite_member),
+        )
+        .route(
+            "/api/organizations/{id}/members/{user_id}",
+            delete(api::organizations::remove_member),
+        )
+        .route(
+            "/api/organizations/{id}/members/{user_id}/role",
+            post(api::organizations::update_member_role),
+        )
+        .route("/api/invitations", get(api::organizations::get_invitations))
+        .route(
+            "/api/invitations/{id}/accept",
+            post(api::organizations::accept_invitation),
+        )
+        // Admin routes (role checked per handler)
+        .route("/api/admin/users", get(api::admin::get_users))
+        .route(
+            "/api/admin/users/{id}/disable",
+            post(api::admin::disable_user),
+        )
+        .route("/api/admin/users/{id}/enable", post(api::admin::enable_user))
+        .route(
+            "/api/admin/users/{id}/approve",
+            post(api::admin::approve_user),
         )
+        .route("/api/admin/users/{id}/reject", post(api::admin::reject_user))
+        .route("/api/admin/users/{id}/unlock", post(api::admin::unlock_user))
+        .route(
+            "/api/admin/users/{id}/2fa",
+            delete(api::admin::reset_user_two_factor),
+        )
+        .route(
+            "/api/admin/2fa-policy",
+            get(api::admin::get_two_factor_policy).put(api::admin::update_two_factor_policy),
+        )
+        .route("/api/admin/auth-attempts", get(api::admin::get_auth_attempts))
+        .route("/api/admin/lockouts", get(api::admin::get_lockouts))
+        .route("/api/admin/audit-events", get(api::admin::get_audit_events))
+        .route("/api/admin/emails", get(api::admin::get_email_outbox))
+        .route(
+            "/api/admin/emails/{id}/retry",
+            post(api::admin::retry_email),
+        )
+        .route(
+            "/api/admin/users/{id}/role",
+            post(api::admin::update_user_role),
+        )
+        .route("/api/admin/queries", get(api::admin::get_queries))
+        .route(
+            "/api/admin/queries/{id}/requeue",
+            post(api::admin::requeue_query),
+        )
+        .route("/api/admin/cohorts", post(api::admin::create_cohort))
+        .route(
+            "/api/admin/announcements",
+            get(api::admin::get_announcements).post(api::admin::create_announcement),
+        )
+        .route(
+            "/api/admin/announcements/{id}",
+            put(api::admin::update_announcement).delete(api::admin::delete_announcement),
+        )
+        .route(
+            "/api/admin/agreement",
+            post(api::admin::publish_agreement),
+        )
+        .route(
+            "/api/admin/notifications/process-pending",
+            post(api::admin::process_pending_notifications),
+        )
+        .route_layer(axum::middleware::from_extractor::<auth::AuthenticatedUser>());
+
+    // Create router
+    let app = Router::new()
+        .merge(public_routes)
+        .merge(explore_routes)
+        .merge(scoped_routes)
+        .merge(protected_routes)
         // Static file serving for frontend
         .fallback_service(ServeDir::new("frontend/build"))
         // Middleware
+        .layer(axum::middleware::from_fn(auth::origin::verify_origin))
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(
@@ -216,8 +558,7 @@ async fn main() {
                     tower_http::trace::DefaultOnResponse::new().level(tracing::Level::INFO),
                 ),
         )
-        .layer(CorsLayer::permissive())
-        .layer(axum::middleware::from_fn(auth::middleware::auth_middleware));
+        .layer(auth::origin::cors_layer());
 
     // Start server
     let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
@@ -226,7 +567,10 @@ async fn main() {
 
     tracing::info!("Server listening on http://127.0.0.1:3000");
 
-    axum::serve(listener, app)
-        .await
-        .expect("Failed to start server");
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .await
+    .expect("Failed to start server");
 }
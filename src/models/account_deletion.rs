@@ -0,0 +1,178 @@
+use crate::models::DatabaseError;
+
+/// Deletion of an account at its owner's request
+///
+/// The account's sessions and API tokens are revoked straight away, and until
+/// the grace period has passed it can only be used to cancel: logging in still
+/// works, but submitting queries and creating API tokens are refused. After
+/// that the account is deleted for good, together with its queries and files.
+pub struct AccountDeletion;
+
+impl AccountDeletion {
+    /// Days before a deletion request is carried out
+    pub const GRACE_DAYS: i64 = 30;
+
+    /// Schedule a user's account for deletion and end all of its sessions and API tokens
+    /// Refuses if the user is the last owner of an organization that has other members
+    /// Returns when the account will be deleted
+    pub async fn schedule(user_id: i64) -> Result<String, DatabaseError> {
+        let orphaned = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!: i64" FROM organization_member m
+             WHERE m.user_id = $1 AND m.role = 'owner'
+             AND NOT EXISTS (SELECT 1 FROM organization_member o
+                 WHERE o.organization_id = m.organization_id AND o.user_id != $1 AND o.role = 'owner')
+             AND EXISTS (SELECT 1 FROM organization_member o
+                 WHERE o.organization_id = m.organization_id AND o.user_id != $1)"#,
+            user_id
+        )
+        .fetch_one(crate::database::get_db())
+        .await?;
+        if orphaned > 0 {
+            return Err(DatabaseError::LastOrganizationOwner);
+        }
+
+        let mut tx = crate::database::get_db().begin().await?;
+
+        sqlx::query!(
+            "UPDATE user SET deletion_requested_at = CURRENT_TIMESTAMP
+             WHERE user_id = $1 AND deletion_requested_at IS NULL",
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "UPDATE session SET revoked_at = CURRENT_TIMESTAMP WHERE user_id = $1 AND revoked_at IS NULL",
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "UPDATE api_token SET revoked_at = CURRENT_TIMESTAMP WHERE user_id = $1 AND revoked_at IS NULL",
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Self::scheduled_for(user_id)
+            .await?
+            .ok_or(DatabaseError::UserNotFound)
+    }
+
+    /// When a user's account will be deleted, if deletion was requested
+    pub async fn scheduled_for(user_id: i64) -> Result<Option<String>, sqlx::Error> {
+        let grace = format!("+{} days", Self::GRACE_DAYS);
+        sqlx::query_scalar!(
+            r#"SELECT datetime(deletion_requested_at, $2) AS "scheduled_for: String" FROM user WHERE user_id = $1"#,
+            user_id,
+            grace
+        )
+        .fetch_optional(crate::database::get_db())
+        .await
+        .map(Option::flatten)
+    }
+
+    /// Refuse what an account may not do while its deletion is pending
+    pub async fn ensure_not_scheduled(user_id: i64) -> Result<(), DatabaseError> {
+        match Self::scheduled_for(user_id).await? {
+            Some(_) => Err(DatabaseError::AccountDeletionScheduled),
+            None => Ok(()),
+        }
+    }
+
+    /// Cancel a pending deletion. Returns false if none was scheduled
+    pub async fn cancel(user_id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "UPDATE user SET deletion_requested_at = NULL WHERE user_id = $1 AND deletion_requested_at IS NOT NULL",
+            user_id
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Delete the accounts whose grace period has passed
+    /// Returns the number of accounts deleted
+    pub async fn purge_due() -> Result<usize, sqlx::Error> {
+        let grace = format!("-{} days", Self::GRACE_DAYS);
+        let due = sqlx::query!(
+            r#"SELECT user_id AS "user_id!", username, email FROM user
+             WHERE deletion_requested_at IS NOT NULL AND deletion_requested_at < datetime('now', $1)"#,
+            grace
+        )
+        .fetch_all(crate::database::get_db())
+        .await?;
+
+        let query_root = std::env::var("QUERY_PATH_ROOT").ok();
+        let mut purged_count = 0;
+
+        for user in due {
+            Self::purge(user.user_id, &user.username, &user.email).await?;
+
+            // Uploaded samples and results live under the user's directory
+            if let Some(query_root) = &query_root {
+                let user_dir = std::path::PathBuf::from(query_root).join(user.user_id.to_string());
+                if let Err(e) = tokio::fs::remove_dir_all(&user_dir).await {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        tracing::error!("Failed to remove {}: {}", user_dir.display(), e);
+                    }
+                }
+            }
+
+            tracing::info!("Deleted account {} at its owner's request", user.user_id);
+            purged_count += 1;
+        }
+
+        Ok(purged_count)
+    }
+
+    /// Remove a user and everything they own
+    /// Queries and notifications do not cascade from the user, so they go first
+    async fn purge(user_id: i64, username: &str, email: &str) -> Result<(), sqlx::Error> {
+        let mut tx = crate::database::get_db().begin().await?;
+
+        // Organizations nobody else belongs to would be left empty
+        sqlx::query!(
+            "DELETE FROM organization WHERE organization_id IN (
+                 SELECT organization_id FROM organization_member WHERE user_id = $1)
+             AND NOT EXISTS (SELECT 1 FROM organization_member o
+                 WHERE o.organization_id = organization.organization_id AND o.user_id != $1)",
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!("DELETE FROM notifications WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("DELETE FROM query WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!(
+            "DELETE FROM organization_invitation WHERE email = $1 AND accepted_at IS NULL",
+            email
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        // Login attempts are recorded by the name or address that was typed in
+        let account_email = email.to_lowercase();
+        sqlx::query!(
+            "DELETE FROM auth_attempt WHERE account IN ($1, $2)",
+            username,
+            account_email
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!("DELETE FROM account_lockout WHERE account = $1", username)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query!("DELETE FROM user WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await
+    }
+}
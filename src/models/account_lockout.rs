@@ -0,0 +1,134 @@
+use serde::Serialize;
+
+use crate::auth::secret::{generate_secret, hash_secret};
+use crate::models::DatabaseError;
+
+/// A temporary lockout after too many failed logins
+///
+/// Lockouts end on their own after [`AccountLockout::DURATION_MINUTES`], or
+/// earlier through the unlock link emailed to the owner, a password reset or
+/// an administrator.
+#[derive(Serialize, Clone, Debug)]
+pub struct AccountLockout {
+    pub account_lockout_id: i64,
+    /// The username that was locked
+    pub account: String,
+    pub user_id: Option<i64>,
+    pub created_at: String,
+    pub locked_until: String,
+}
+
+impl AccountLockout {
+    pub const DURATION_MINUTES: i64 = 30;
+
+    /// Lock an account after the failed attempt `auth_attempt_id`
+    /// Returns the secret to embed in the unlock link
+    pub async fn create(account: &str, auth_attempt_id: i64) -> Result<String, sqlx::Error> {
+        let secret = generate_secret();
+        let token_hash = hash_secret(&secret);
+        let lifetime = format!("+{} minutes", Self::DURATION_MINUTES);
+
+        sqlx::query!(
+            "INSERT INTO account_lockout (account, user_id, auth_attempt_id, unlock_token_hash, locked_until)
+             VALUES ($1, (SELECT user_id FROM user WHERE username = $1), $2, $3, datetime('now', $4))",
+            account,
+            auth_attempt_id,
+            token_hash,
+            lifetime
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        Ok(secret)
+    }
+
+    /// Seconds until an account's lockout ends, if it is locked
+    pub async fn retry_after(account: &str) -> Result<Option<i64>, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"SELECT MAX(strftime('%s', locked_until) - strftime('%s', 'now')) AS "retry_after: i64"
+               FROM account_lockout
+               WHERE account = $1 AND unlocked_at IS NULL AND locked_until > CURRENT_TIMESTAMP"#,
+            account
+        )
+        .fetch_one(crate::database::get_db())
+        .await
+    }
+
+    /// Lift a lockout with the secret from an unlock link. Returns the unlocked username
+    pub async fn unlock(secret: &str) -> Result<String, DatabaseError> {
+        let token_hash = hash_secret(secret);
+
+        let account = sqlx::query_scalar!(
+            "UPDATE account_lockout SET unlocked_at = CURRENT_TIMESTAMP
+             WHERE unlock_token_hash = $1 AND unlocked_at IS NULL
+             RETURNING account",
+            token_hash
+        )
+        .fetch_optional(crate::database::get_db())
+        .await?
+        .ok_or(DatabaseError::InvalidUnlockToken)?;
+
+        Ok(account)
+    }
+
+    /// Lift a user's lockouts that are still in effect. Returns the number lifted
+    pub async fn clear_for_user(user_id: i64) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            "UPDATE account_lockout SET unlocked_at = CURRENT_TIMESTAMP
+             WHERE user_id = $1 AND unlocked_at IS NULL AND locked_until > CURRENT_TIMESTAMP",
+            user_id
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// List lockouts that are still in effect, newest first
+    pub async fn active() -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query!(
+            r#"SELECT account_lockout_id AS "account_lockout_id!", account, user_id, created_at, locked_until
+               FROM account_lockout
+               WHERE unlocked_at IS NULL AND locked_until > CURRENT_TIMESTAMP
+               ORDER BY account_lockout_id DESC"#
+        )
+        .map(|row| Self {
+            account_lockout_id: row.account_lockout_id,
+            account: row.account,
+            user_id: row.user_id,
+            created_at: row.created_at.to_string(),
+            locked_until: row.locked_until.to_string(),
+        })
+        .fetch_all(crate::database::get_db())
+        .await
+    }
+
+    /// Queue an email to the owner of a locked account with a link to unlock it
+    pub async fn send_unlock_email(
+        user_id: i64,
+        username: &str,
+        email: &str,
+        unlock_url: &str,
+    ) -> Result<(), sqlx::Error> {
+        let locale = crate::models::User::locale_of(user_id).await?;
+        let minutes = Self::DURATION_MINUTES.to_string();
+        let rendered = crate::mail::templates::EmailTemplate::AccountUnlock.render(
+            locale,
+            &[("username", username), ("minutes", &minutes), ("unlock_url", unlock_url)],
+        );
+
+        crate::models::EmailOutbox::enqueue(crate::models::NewEmail {
+            user_id: Some(user_id),
+            kind: "account_unlock",
+            recipient_name: username,
+            recipient_email: email,
+            subject: rendered.subject,
+            text_body: rendered.text_body,
+            html_body: Some(rendered.html_body),
+            unsubscribe_url: None,
+        })
+        .await?;
+
+        Ok(())
+    }
+}
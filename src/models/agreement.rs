@@ -0,0 +1,78 @@
+use serde::Serialize;
+
+use crate::models::DatabaseError;
+
+/// A version of the data-use agreement users must accept before submitting queries
+#[derive(Serialize, Clone, Debug)]
+pub struct DataUseAgreement {
+    pub version: i64,
+    pub body: String,
+    pub published_at: String,
+}
+
+impl DataUseAgreement {
+    /// Get the agreement currently in force
+    pub async fn current() -> Result<Self, sqlx::Error> {
+        sqlx::query!(
+            r#"SELECT version AS "version!", body, published_at FROM data_use_agreement
+               ORDER BY version DESC LIMIT 1"#
+        )
+        .map(|row| Self {
+            version: row.version,
+            body: row.body,
+            published_at: row.published_at.to_string(),
+        })
+        .fetch_one(crate::database::get_db())
+        .await
+    }
+
+    /// Publish a new version. Every user has to accept it before their next submission
+    pub async fn publish(body: &str) -> Result<i64, DatabaseError> {
+        let body = body.trim();
+        if body.is_empty() {
+            return Err(DatabaseError::InvalidAgreement);
+        }
+
+        let version = sqlx::query_scalar!(
+            r#"INSERT INTO data_use_agreement (version, body)
+               VALUES ((SELECT COALESCE(MAX(version), 0) + 1 FROM data_use_agreement), $1)
+               RETURNING version AS "version!""#,
+            body
+        )
+        .fetch_one(crate::database::get_db())
+        .await?;
+
+        Ok(version)
+    }
+
+    /// Record that a user accepted a version, which must be the current one
+    pub async fn accept(user_id: i64, version: i64) -> Result<(), DatabaseError> {
+        if Self::current().await?.version != version {
+            return Err(DatabaseError::AgreementVersionMismatch);
+        }
+
+        sqlx::query!(
+            "INSERT OR IGNORE INTO data_use_acceptance (user_id, version) VALUES ($1, $2)",
+            user_id,
+            version
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Whether a user has accepted the current version
+    pub async fn has_accepted_current(user_id: i64) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"SELECT EXISTS(
+                   SELECT 1 FROM data_use_acceptance
+                   WHERE user_id = $1
+                   AND version = (SELECT MAX(version) FROM data_use_agreement)
+               ) AS "accepted!: bool""#,
+            user_id
+        )
+        .fetch_one(crate::database::get_db())
+        .await
+    }
+}
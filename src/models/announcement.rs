@@ -0,0 +1,285 @@
+use chrono::{DateTime, NaiveDateTime, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::models::{DatabaseError, NewNotification, Notification, NotificationKind};
+
+/// How prominently an announcement is shown
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum AnnouncementSeverity {
+    #[default]
+    Info,
+    Warning,
+    Critical,
+}
+
+/// Who an announcement is shown to
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum AnnouncementAudience {
+    #[default]
+    All,
+    /// Users with a query that is still pending or processing
+    PendingQueries,
+}
+
+/// A site-wide announcement, shown in a banner between its start and end
+#[derive(Serialize, Clone, Debug)]
+pub struct Announcement {
+    pub announcement_id: i64,
+    pub title: String,
+    pub message: String,
+    pub severity: AnnouncementSeverity,
+    pub audience: AnnouncementAudience,
+    /// Query submissions are refused while it is active
+    pub maintenance: bool,
+    pub starts_at: String,
+    /// Shown until removed if not set
+    pub ends_at: Option<String>,
+    /// Sent as a notification to its audience once it starts
+    pub notify: bool,
+    pub notified_at: Option<String>,
+    pub created_at: String,
+}
+
+/// An announcement as written by an admin
+#[derive(Debug, Deserialize)]
+pub struct NewAnnouncement {
+    pub title: String,
+    pub message: String,
+    #[serde(default)]
+    pub severity: AnnouncementSeverity,
+    #[serde(default)]
+    pub audience: AnnouncementAudience,
+    #[serde(default)]
+    pub maintenance: bool,
+    /// RFC 3339, or `YYYY-MM-DD HH:MM:SS` in UTC. Starts now if not set
+    pub starts_at: Option<String>,
+    pub ends_at: Option<String>,
+    #[serde(default)]
+    pub notify: bool,
+}
+
+impl NewAnnouncement {
+    /// Check the announcement, returning its start and end in UTC
+    fn validate(&self) -> Result<(NaiveDateTime, Option<NaiveDateTime>), DatabaseError> {
+        let title = self.title.trim();
+        let message = self.message.trim();
+        if title.is_empty() || title.len() > 200 || message.is_empty() || message.len() > 2000 {
+            return Err(DatabaseError::InvalidAnnouncementText);
+        }
+
+        let starts_at = match &self.starts_at {
+            Some(starts_at) => parse_time(starts_at)?,
+            None => Utc::now().naive_utc().with_nanosecond(0).unwrap_or_default(),
+        };
+        let ends_at = self.ends_at.as_deref().map(parse_time).transpose()?;
+        if ends_at.is_some_and(|ends_at| ends_at <= starts_at) {
+            return Err(DatabaseError::InvalidAnnouncementSchedule);
+        }
+
+        Ok((starts_at, ends_at))
+    }
+}
+
+/// Parse a time given as RFC 3339 or as `YYYY-MM-DD HH:MM[:SS]` in UTC
+fn parse_time(time: &str) -> Result<NaiveDateTime, DatabaseError> {
+    let time = time.trim();
+    DateTime::parse_from_rfc3339(time)
+        .map(|time| time.naive_utc())
+        .or_else(|_| NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S"))
+        .or_else(|_| NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M"))
+        .map(|time| time.with_nanosecond(0).unwrap_or(time))
+        .map_err(|_| DatabaseError::InvalidAnnouncementSchedule)
+}
+
+impl Announcement {
+    /// Publish an announcement
+    pub async fn create(created_by: i64, announcement: &NewAnnouncement) -> Result<i64, DatabaseError> {
+        let (starts_at, ends_at) = announcement.validate()?;
+        let title = announcement.title.trim();
+        let message = announcement.message.trim();
+
+        let announcement_id = sqlx::query!(
+            "INSERT INTO announcement (title, message, severity, audience, maintenance, starts_at, ends_at, notify, created_by)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            title,
+            message,
+            announcement.severity,
+            announcement.audience,
+            announcement.maintenance,
+            starts_at,
+            ends_at,
+            announcement.notify,
+            created_by
+        )
+        .execute(crate::database::get_db())
+        .await?
+        .last_insert_rowid();
+
+        Ok(announcement_id)
+    }
+
+    /// Replace an announcement
+    /// One already sent as a notification is not sent again
+    pub async fn update(announcement_id: i64, announcement: &NewAnnouncement) -> Result<(), DatabaseError> {
+        let (starts_at, ends_at) = announcement.validate()?;
+        let title = announcement.title.trim();
+        let message = announcement.message.trim();
+
+        let result = sqlx::query!(
+            "UPDATE announcement SET title = $2, message = $3, severity = $4, audience = $5, maintenance = $6,
+                 starts_at = $7, ends_at = $8, notify = $9
+             WHERE announcement_id = $1",
+            announcement_id,
+            title,
+            message,
+            announcement.severity,
+            announcement.audience,
+            announcement.maintenance,
+            starts_at,
+            ends_at,
+            announcement.notify
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(DatabaseError::AnnouncementNotFound);
+        }
+        Ok(())
+    }
+
+    pub async fn delete(announcement_id: i64) -> Result<(), DatabaseError> {
+        let result = sqlx::query!("DELETE FROM announcement WHERE announcement_id = $1", announcement_id)
+            .execute(crate::database::get_db())
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(DatabaseError::AnnouncementNotFound);
+        }
+        Ok(())
+    }
+
+    /// Every announcement, past and future, newest first
+    pub async fn list() -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"SELECT announcement_id AS "announcement_id!", title, message,
+                 severity AS "severity: AnnouncementSeverity", audience AS "audience: AnnouncementAudience",
+                 maintenance, starts_at AS "starts_at: String", ends_at AS "ends_at: String", notify,
+                 notified_at AS "notified_at: String", created_at AS "created_at: String"
+             FROM announcement
+             ORDER BY starts_at DESC, announcement_id DESC"#
+        )
+        .fetch_all(crate::database::get_db())
+        .await
+    }
+
+    /// The announcements active now for a user, or for visitors who are not
+    /// logged in, most severe first
+    pub async fn active_for(user_id: Option<i64>) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"SELECT announcement_id AS "announcement_id!", title, message,
+                 severity AS "severity: AnnouncementSeverity", audience AS "audience: AnnouncementAudience",
+                 maintenance, starts_at AS "starts_at: String", ends_at AS "ends_at: String", notify,
+                 notified_at AS "notified_at: String", created_at AS "created_at: String"
+             FROM announcement
+             WHERE starts_at <= CURRENT_TIMESTAMP AND (ends_at IS NULL OR ends_at > CURRENT_TIMESTAMP)
+             AND (audience = 'all' OR EXISTS (
+                 SELECT 1 FROM query WHERE user_id = $1 AND user_visible_status IN ('pending', 'processing')))
+             ORDER BY CASE severity WHEN 'critical' THEN 0 WHEN 'warning' THEN 1 ELSE 2 END, starts_at DESC"#,
+            user_id
+        )
+        .fetch_all(crate::database::get_db())
+        .await
+    }
+
+    /// The active maintenance announcement that ends last, if any
+    pub async fn maintenance() -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"SELECT announcement_id AS "announcement_id!", title, message,
+                 severity AS "severity: AnnouncementSeverity", audience AS "audience: AnnouncementAudience",
+                 maintenance, starts_at AS "starts_at: String", ends_at AS "ends_at: String", notify,
+                 notified_at AS "notified_at: String", created_at AS "created_at: String"
+             FROM announcement
+             WHERE maintenance = TRUE
+             AND starts_at <= CURRENT_TIMESTAMP AND (ends_at IS NULL OR ends_at > CURRENT_TIMESTAMP)
+             ORDER BY ends_at IS NULL DESC, ends_at DESC
+             LIMIT 1"#
+        )
+        .fetch_optional(crate::database::get_db())
+        .await
+    }
+
+    /// Seconds until the announcement ends, if it has an end
+    pub fn seconds_remaining(&self) -> Option<i64> {
+        let ends_at = NaiveDateTime::parse_from_str(self.ends_at.as_deref()?, "%Y-%m-%d %H:%M:%S").ok()?;
+        Some((ends_at - Utc::now().naive_utc()).num_seconds().max(0))
+    }
+
+    /// Send the announcements that have started, and are marked to be sent, as
+    /// notifications to their audience
+    /// Returns the number of announcements sent
+    pub async fn notify_due() -> Result<usize, sqlx::Error> {
+        let due = sqlx::query_as!(
+            Self,
+            r#"SELECT announcement_id AS "announcement_id!", title, message,
+                 severity AS "severity: AnnouncementSeverity", audience AS "audience: AnnouncementAudience",
+                 maintenance, starts_at AS "starts_at: String", ends_at AS "ends_at: String", notify,
+                 notified_at AS "notified_at: String", created_at AS "created_at: String"
+             FROM announcement
+             WHERE notify = TRUE AND notified_at IS NULL
+             AND starts_at <= CURRENT_TIMESTAMP AND (ends_at IS NULL OR ends_at > CURRENT_TIMESTAMP)"#
+        )
+        .fetch_all(crate::database::get_db())
+        .await?;
+
+        for announcement in &due {
+            // Marked first, so a failure part way through does not notify anyone twice
+            sqlx::query!(
+                "UPDATE announcement SET notified_at = CURRENT_TIMESTAMP WHERE announcement_id = $1",
+                announcement.announcement_id
+            )
+            .execute(crate::database::get_db())
+            .await?;
+
+            let notification = NewNotification {
+                kind: NotificationKind::Announcement,
+                title: announcement.title.clone(),
+                message: announcement.message.clone(),
+                target: Some(("announcement", announcement.announcement_id)),
+                payload: serde_json::json!({
+                    "severity": announcement.severity,
+                    "maintenance": announcement.maintenance,
+                    "ends_at": announcement.ends_at,
+                }),
+            };
+
+            match announcement.audience {
+                AnnouncementAudience::All => {
+                    Notification::broadcast(&notification).await?;
+                }
+                AnnouncementAudience::PendingQueries => {
+                    let user_ids = sqlx::query_scalar!(
+                        r#"SELECT DISTINCT u.user_id AS "user_id!" FROM user u
+                         JOIN query q ON q.user_id = u.user_id
+                         WHERE q.user_visible_status IN ('pending', 'processing')
+                         AND u.status = 'active' AND u.disabled_at IS NULL"#
+                    )
+                    .fetch_all(crate::database::get_db())
+                    .await?;
+                    for user_id in user_ids {
+                        Notification::create(user_id, &notification).await?;
+                    }
+                }
+            }
+        }
+
+        Ok(due.len())
+    }
+}
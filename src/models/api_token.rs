@@ -0,0 +1,208 @@
+use serde::{Deserialize, Serialize};
+
+use crate::auth::secret::{generate_secret, hash_secret};
+use crate::models::DatabaseError;
+
+/// What a personal API token is allowed to do
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApiScope {
+    #[serde(rename = "queries:read")]
+    QueriesRead,
+    #[serde(rename = "queries:write")]
+    QueriesWrite,
+    #[serde(rename = "explore:read")]
+    ExploreRead,
+}
+
+impl ApiScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::QueriesRead => "queries:read",
+            ApiScope::QueriesWrite => "queries:write",
+            ApiScope::ExploreRead => "explore:read",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Option<Self> {
+        match scope {
+            "queries:read" => Some(ApiScope::QueriesRead),
+            "queries:write" => Some(ApiScope::QueriesWrite),
+            "explore:read" => Some(ApiScope::ExploreRead),
+            _ => None,
+        }
+    }
+}
+
+/// Scopes are stored as a space-separated list
+fn join_scopes(scopes: &[ApiScope]) -> String {
+    scopes
+        .iter()
+        .map(ApiScope::as_str)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn split_scopes(scopes: &str) -> Vec<ApiScope> {
+    scopes
+        .split_whitespace()
+        .filter_map(ApiScope::parse)
+        .collect()
+}
+
+/// A named personal access token. The secret itself is only shown once, at creation
+#[derive(Serialize, Clone, Debug)]
+pub struct ApiToken {
+    pub api_token_id: i64,
+    pub name: String,
+    /// The first characters of the token, so users can tell their tokens apart
+    pub token_prefix: String,
+    pub scopes: Vec<ApiScope>,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+    pub expires_at: Option<String>,
+}
+
+/// The owner and scopes of a token presented with a request
+#[derive(Clone, Debug)]
+pub struct ApiTokenGrant {
+    pub api_token_id: i64,
+    pub user_id: i64,
+    pub scopes: Vec<ApiScope>,
+}
+
+impl ApiToken {
+    /// Prefix identifying personal API tokens, as opposed to session JWTs
+    pub const PREFIX: &'static str = "glad_pat_";
+    pub const MAX_EXPIRY_DAYS: i64 = 365;
+    const NAME_MAX_LENGTH: usize = 100;
+    /// Number of characters of the token kept in plain text for display
+    const DISPLAY_PREFIX_LENGTH: usize = 16;
+
+    fn validate(
+        name: &str,
+        scopes: &[ApiScope],
+        expires_in_days: Option<i64>,
+    ) -> Result<(), DatabaseError> {
+        if name.trim().is_empty() || name.len() > Self::NAME_MAX_LENGTH {
+            return Err(DatabaseError::InvalidApiTokenName);
+        }
+        if scopes.is_empty() {
+            return Err(DatabaseError::InvalidApiTokenScopes);
+        }
+        if let Some(days) = expires_in_days {
+            if !(1..=Self::MAX_EXPIRY_DAYS).contains(&days) {
+                return Err(DatabaseError::InvalidApiTokenExpiry);
+            }
+        }
+        Ok(())
+    }
+
+    /// Create a token for a user
+    /// Returns the token id and the plain-text token, which is not stored
+    pub async fn create(
+        user_id: i64,
+        name: &str,
+        scopes: &[ApiScope],
+        expires_in_days: Option<i64>,
+    ) -> Result<(i64, String), DatabaseError> {
+        Self::validate(name, scopes, expires_in_days)?;
+
+        let token = format!("{}{}", Self::PREFIX, generate_secret());
+        let token_prefix: String = token.chars().take(Self::DISPLAY_PREFIX_LENGTH).collect();
+        let token_hash = hash_secret(&token);
+        let mut unique_scopes = Vec::new();
+        for scope in scopes {
+            if !unique_scopes.contains(scope) {
+                unique_scopes.push(*scope);
+            }
+        }
+        let scopes = join_scopes(&unique_scopes);
+        let name = name.trim();
+        let lifetime = expires_in_days.map(|days| format!("+{} days", days));
+
+        let api_token_id = sqlx::query!(
+            "INSERT INTO api_token (user_id, name, token_prefix, token_hash, scopes, expires_at)
+             VALUES ($1, $2, $3, $4, $5, CASE WHEN $6 IS NULL THEN NULL ELSE datetime('now', $6) END)",
+            user_id,
+            name,
+            token_prefix,
+            token_hash,
+            scopes,
+            lifetime
+        )
+        .execute(crate::database::get_db())
+        .await?
+        .last_insert_rowid();
+
+        Ok((api_token_id, token))
+    }
+
+    /// Get a user's tokens that have not been revoked, newest first
+    pub async fn for_user(user_id: i64) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query!(
+            r#"SELECT api_token_id AS "api_token_id!", name, token_prefix, scopes, created_at, last_used_at, expires_at
+               FROM api_token
+               WHERE user_id = $1 AND revoked_at IS NULL
+               ORDER BY created_at DESC, api_token_id DESC"#,
+            user_id
+        )
+        .map(|row| Self {
+            api_token_id: row.api_token_id,
+            name: row.name,
+            token_prefix: row.token_prefix,
+            scopes: split_scopes(&row.scopes),
+            created_at: row.created_at.to_string(),
+            last_used_at: row.last_used_at.map(|t| t.to_string()),
+            expires_at: row.expires_at.map(|t| t.to_string()),
+        })
+        .fetch_all(crate::database::get_db())
+        .await
+    }
+
+    /// Revoke one of a user's tokens
+    pub async fn revoke(api_token_id: i64, user_id: i64) -> Result<(), DatabaseError> {
+        let result = sqlx::query!(
+            "UPDATE api_token SET revoked_at = CURRENT_TIMESTAMP
+             WHERE api_token_id = $1 AND user_id = $2 AND revoked_at IS NULL",
+            api_token_id,
+            user_id
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(DatabaseError::ApiTokenNotFound);
+        }
+        Ok(())
+    }
+
+    /// Look up an active token by its plain-text value and record that it was used
+    pub async fn authenticate(token: &str) -> Result<Option<ApiTokenGrant>, sqlx::Error> {
+        let token_hash = hash_secret(token);
+
+        let Some(row) = sqlx::query!(
+            r#"SELECT api_token_id AS "api_token_id!", user_id, scopes FROM api_token
+               WHERE token_hash = $1 AND revoked_at IS NULL
+                 AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)"#,
+            token_hash
+        )
+        .fetch_optional(crate::database::get_db())
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        sqlx::query!(
+            "UPDATE api_token SET last_used_at = CURRENT_TIMESTAMP WHERE api_token_id = $1",
+            row.api_token_id
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        Ok(Some(ApiTokenGrant {
+            api_token_id: row.api_token_id,
+            user_id: row.user_id,
+            scopes: split_scopes(&row.scopes),
+        }))
+    }
+}
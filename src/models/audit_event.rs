@@ -0,0 +1,204 @@
+use serde::{Deserialize, Serialize};
+
+use crate::auth::ClientInfo;
+
+/// How an audited action turned out
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum AuditOutcome {
+    #[default]
+    Success,
+    /// The action was attempted but did not go through, e.g. a wrong password
+    Failure,
+    /// The actor was not allowed to do it
+    Denied,
+}
+
+/// A security-relevant action, as recorded in the audit log
+#[derive(Serialize, Clone, Debug)]
+pub struct AuditEvent {
+    pub audit_event_id: i64,
+    pub actor_user_id: Option<i64>,
+    /// Username of the actor, or the account name typed in when nobody was logged in
+    pub actor: Option<String>,
+    pub action: String,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub ip_address: String,
+    pub user_agent: String,
+    pub outcome: AuditOutcome,
+    pub detail: Option<String>,
+    pub created_at: String,
+}
+
+/// Filters for listing audit events; unset fields match everything
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditFilter {
+    pub actor_user_id: Option<i64>,
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub ip_address: Option<String>,
+    pub outcome: Option<AuditOutcome>,
+    /// Only events at or after this time, e.g. `2026-10-01` or `2026-10-01 12:00:00`
+    pub since: Option<String>,
+    pub until: Option<String>,
+    /// Only events older than this id, to page through results
+    pub before_id: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// An audit event being put together before it is recorded
+#[derive(Debug, Default)]
+pub struct NewAuditEvent {
+    actor_user_id: Option<i64>,
+    actor: Option<String>,
+    action: &'static str,
+    target_type: Option<&'static str>,
+    target_id: Option<String>,
+    ip_address: String,
+    user_agent: String,
+    outcome: AuditOutcome,
+    detail: Option<String>,
+}
+
+impl NewAuditEvent {
+    pub fn actor(mut self, user_id: i64, username: &str) -> Self {
+        self.actor_user_id = Some(user_id);
+        self.actor = Some(username.to_string());
+        self
+    }
+
+    /// Name the account an anonymous request was about, such as a failed login
+    pub fn account(mut self, account: &str) -> Self {
+        self.actor = Some(account.to_string());
+        self
+    }
+
+    pub fn target(mut self, target_type: &'static str, target_id: impl ToString) -> Self {
+        self.target_type = Some(target_type);
+        self.target_id = Some(target_id.to_string());
+        self
+    }
+
+    pub fn outcome(mut self, outcome: AuditOutcome) -> Self {
+        self.outcome = outcome;
+        self
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// Write the event to the audit log
+    /// A failure to write is logged rather than failing the request it describes
+    pub async fn record(self) {
+        let outcome = self.outcome;
+        let result = sqlx::query!(
+            "INSERT INTO audit_event (actor_user_id, actor, action, target_type, target_id, ip_address, user_agent, outcome, detail)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            self.actor_user_id,
+            self.actor,
+            self.action,
+            self.target_type,
+            self.target_id,
+            self.ip_address,
+            self.user_agent,
+            outcome,
+            self.detail
+        )
+        .execute(crate::database::get_db())
+        .await;
+
+        if let Err(e) = result {
+            tracing::error!("Failed to record audit event {}: {}", self.action, e);
+        }
+    }
+}
+
+impl AuditEvent {
+    /// Days events are kept when `AUDIT_RETENTION_DAYS` is not set
+    pub const DEFAULT_RETENTION_DAYS: i64 = 365;
+    /// The database refuses to delete younger events
+    pub const MIN_RETENTION_DAYS: i64 = 30;
+
+    /// Start an event for an action taken by the client
+    pub fn action(action: &'static str, client: &ClientInfo) -> NewAuditEvent {
+        NewAuditEvent {
+            action,
+            ip_address: client.ip_address.clone(),
+            user_agent: client.user_agent.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// List events matching a filter, newest first
+    pub async fn list(filter: &AuditFilter) -> Result<Vec<Self>, sqlx::Error> {
+        let limit = filter.limit.unwrap_or(100).clamp(1, 1000);
+        sqlx::query!(
+            r#"SELECT audit_event_id AS "audit_event_id!", actor_user_id, actor, action, target_type, target_id,
+                ip_address, user_agent, outcome AS "outcome: AuditOutcome", detail, created_at
+               FROM audit_event
+               WHERE ($1 IS NULL OR actor_user_id = $1) AND ($2 IS NULL OR actor = $2)
+               AND ($3 IS NULL OR action = $3 OR action LIKE $3 || '.%')
+               AND ($4 IS NULL OR target_type = $4) AND ($5 IS NULL OR target_id = $5)
+               AND ($6 IS NULL OR ip_address = $6) AND ($7 IS NULL OR outcome = $7)
+               AND ($8 IS NULL OR created_at >= datetime($8)) AND ($9 IS NULL OR created_at <= datetime($9))
+               AND ($10 IS NULL OR audit_event_id < $10)
+               ORDER BY audit_event_id DESC
+               LIMIT $11"#,
+            filter.actor_user_id,
+            filter.actor,
+            filter.action,
+            filter.target_type,
+            filter.target_id,
+            filter.ip_address,
+            filter.outcome,
+            filter.since,
+            filter.until,
+            filter.before_id,
+            limit
+        )
+        .map(|row| Self {
+            audit_event_id: row.audit_event_id,
+            actor_user_id: row.actor_user_id,
+            actor: row.actor,
+            action: row.action,
+            target_type: row.target_type,
+            target_id: row.target_id,
+            ip_address: row.ip_address,
+            user_agent: row.user_agent,
+            outcome: row.outcome,
+            detail: row.detail,
+            created_at: row.created_at.to_string(),
+        })
+        .fetch_all(crate::database::get_db())
+        .await
+    }
+
+    /// How long events are kept, from `AUDIT_RETENTION_DAYS`
+    pub fn retention_days() -> i64 {
+        std::env::var("AUDIT_RETENTION_DAYS")
+            .ok()
+            .and_then(|days| days.parse().ok())
+            .unwrap_or(Self::DEFAULT_RETENTION_DAYS)
+            .max(Self::MIN_RETENTION_DAYS)
+    }
+
+    /// Delete events older than the retention period
+    /// Returns the number of events removed
+    pub async fn purge_expired() -> Result<u64, sqlx::Error> {
+        let cutoff = format!("-{} days", Self::retention_days());
+        let result = sqlx::query!(
+            "DELETE FROM audit_event WHERE created_at < datetime('now', $1)",
+            cutoff
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,302 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::{AccountLockout, DatabaseError};
+
+/// What an attempt was trying to do
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum AttemptKind {
+    Login,
+    PasswordReset,
+}
+
+/// A recorded login or password reset attempt
+///
+/// Attempts are kept in the database so rate limits survive restarts and
+/// administrators can see where failures come from.
+#[derive(Serialize, Clone, Debug)]
+pub struct AuthAttempt {
+    pub auth_attempt_id: i64,
+    pub kind: AttemptKind,
+    /// The username or email address the attempt was made for
+    pub account: String,
+    pub ip_address: String,
+    pub succeeded: bool,
+    pub created_at: String,
+}
+
+/// Seconds to wait after `failures` consecutive failures, doubling after the free ones
+fn progressive_delay(failures: i64, free_failures: i64) -> i64 {
+    if failures <= free_failures {
+        return 0;
+    }
+    let exponent = (failures - free_failures - 1).min(16) as u32;
+    2_i64.pow(exponent).min(AuthAttempt::MAX_DELAY_SECONDS)
+}
+
+impl AuthAttempt {
+    /// How far back failed logins are counted
+    pub const WINDOW_MINUTES: i64 = 15;
+    /// Failed logins on one account before it is locked
+    pub const LOCKOUT_FAILURES: i64 = 8;
+    /// Failed logins allowed before delays kick in
+    const ACCOUNT_FREE_FAILURES: i64 = 2;
+    const IP_FREE_FAILURES: i64 = 10;
+    const MAX_DELAY_SECONDS: i64 = 300;
+    /// Password reset requests allowed per hour
+    const RESET_WINDOW_MINUTES: i64 = 60;
+    const RESETS_PER_ACCOUNT: i64 = 3;
+    const RESETS_PER_IP: i64 = 10;
+
+    /// Record an attempt. Returns its id
+    pub async fn record(
+        kind: AttemptKind,
+        account: &str,
+        ip_address: &str,
+        succeeded: bool,
+    ) -> Result<i64, sqlx::Error> {
+        let auth_attempt_id = sqlx::query!(
+            "INSERT INTO auth_attempt (kind, account, ip_address, succeeded) VALUES ($1, $2, $3, $4)",
+            kind,
+            account,
+            ip_address,
+            succeeded
+        )
+        .execute(crate::database::get_db())
+        .await?
+        .last_insert_rowid();
+
+        Ok(auth_attempt_id)
+    }
+
+    /// Failed logins on an account since its last successful login or lockout
+    /// Returns the number of failures and the seconds since the latest one
+    async fn account_failures(account: &str) -> Result<(i64, i64), sqlx::Error> {
+        let window = format!("-{} minutes", Self::WINDOW_MINUTES);
+        let row = sqlx::query!(
+            r#"SELECT COUNT(*) AS "failures!: i64",
+                COALESCE(strftime('%s', 'now') - strftime('%s', MAX(created_at)), 0) AS "seconds_since!: i64"
+               FROM auth_attempt
+               WHERE kind = 'login' AND account = $1 AND succeeded = 0
+                 AND created_at > datetime('now', $2)
+                 AND auth_attempt_id > COALESCE((SELECT MAX(auth_attempt_id) FROM auth_attempt
+                     WHERE kind = 'login' AND account = $1 AND succeeded = 1), 0)
+                 AND auth_attempt_id > COALESCE((SELECT MAX(auth_attempt_id) FROM account_lockout
+                     WHERE account = $1), 0)"#,
+            account,
+            window
+        )
+        .fetch_one(crate::database::get_db())
+        .await?;
+
+        Ok((row.failures, row.seconds_since))
+    }
+
+    /// Failed logins from an IP address within the window
+    /// Returns the number of failures and the seconds since the latest one
+    async fn ip_failures(ip_address: &str) -> Result<(i64, i64), sqlx::Error> {
+        let window = format!("-{} minutes", Self::WINDOW_MINUTES);
+        let row = sqlx::query!(
+            r#"SELECT COUNT(*) AS "failures!: i64",
+                COALESCE(strftime('%s', 'now') - strftime('%s', MAX(created_at)), 0) AS "seconds_since!: i64"
+               FROM auth_attempt
+               WHERE kind = 'login' AND ip_address = $1 AND succeeded = 0
+                 AND created_at > datetime('now', $2)"#,
+            ip_address,
+            window
+        )
+        .fetch_one(crate::database::get_db())
+        .await?;
+
+        Ok((row.failures, row.seconds_since))
+    }
+
+    /// Check whether a login for `account` from `ip_address` may be tried now
+    pub async fn check_login(account: &str, ip_address: &str) -> Result<(), DatabaseError> {
+        if let Some(retry_after) = AccountLockout::retry_after(account).await? {
+            return Err(DatabaseError::AccountLocked(retry_after));
+        }
+
+        let (failures, seconds_since) = Self::account_failures(account).await?;
+        let wait = progressive_delay(failures, Self::ACCOUNT_FREE_FAILURES) - seconds_since;
+        if wait > 0 {
+            return Err(DatabaseError::TooManyAttempts(wait));
+        }
+
+        let (failures, seconds_since) = Self::ip_failures(ip_address).await?;
+        let wait = progressive_delay(failures, Self::IP_FREE_FAILURES) - seconds_since;
+        if wait > 0 {
+            return Err(DatabaseError::TooManyAttempts(wait));
+        }
+
+        Ok(())
+    }
+
+    /// Record a failed login, locking the account once it has failed too often
+    /// Returns the unlock secret if this attempt locked the account
+    pub async fn login_failed(account: &str, ip_address: &str) -> Result<Option<String>, sqlx::Error> {
+        let auth_attempt_id = Self::record(AttemptKind::Login, account, ip_address, false).await?;
+
+        let (failures, _) = Self::account_failures(account).await?;
+        if failures < Self::LOCKOUT_FAILURES {
+            return Ok(None);
+        }
+
+        let secret = AccountLockout::create(account, auth_attempt_id).await?;
+        Ok(Some(secret))
+    }
+
+    /// Check whether another password reset may be requested for `account` from `ip_address`
+    pub async fn check_password_reset(account: &str, ip_address: &str) -> Result<(), DatabaseError> {
+        let window = format!("-{} minutes", Self::RESET_WINDOW_MINUTES);
+        let row = sqlx::query!(
+            r#"SELECT
+                COALESCE(SUM(account = $1), 0) AS "account_requests!: i64",
+                COALESCE(SUM(ip_address = $2), 0) AS "ip_requests!: i64",
+                COALESCE(strftime('%s', MIN(CASE WHEN account = $1 THEN created_at END)) - strftime('%s', datetime('now', $3)), 0) AS "account_retry_after!: i64",
+                COALESCE(strftime('%s', MIN(CASE WHEN ip_address = $2 THEN created_at END)) - strftime('%s', datetime('now', $3)), 0) AS "ip_retry_after!: i64"
+               FROM auth_attempt
+               WHERE kind = 'password_reset' AND (account = $1 OR ip_address = $2)
+                 AND created_at > datetime('now', $3)"#,
+            account,
+            ip_address,
+            window
+        )
+        .fetch_one(crate::database::get_db())
+        .await?;
+
+        if row.account_requests >= Self::RESETS_PER_ACCOUNT {
+            return Err(DatabaseError::TooManyAttempts(row.account_retry_after.max(1)));
+        }
+        if row.ip_requests >= Self::RESETS_PER_IP {
+            return Err(DatabaseError::TooManyAttempts(row.ip_retry_after.max(1)));
+        }
+
+        Ok(())
+    }
+
+    /// List recent attempts, newest first, optionally filtered
+    pub async fn list(
+        kind: Option<AttemptKind>,
+        account: Option<&str>,
+        ip_address: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query!(
+            r#"SELECT auth_attempt_id AS "auth_attempt_id!", kind AS "kind: AttemptKind", account, ip_address, succeeded, created_at
+               FROM auth_attempt
+               WHERE ($1 IS NULL OR kind = $1) AND ($2 IS NULL OR account = $2) AND ($3 IS NULL OR ip_address = $3)
+               ORDER BY auth_attempt_id DESC
+               LIMIT $4"#,
+            kind,
+            account,
+            ip_address,
+            limit
+        )
+        .map(|row| Self {
+            auth_attempt_id: row.auth_attempt_id,
+            kind: row.kind,
+            account: row.account,
+            ip_address: row.ip_address,
+            succeeded: row.succeeded,
+            created_at: row.created_at.to_string(),
+        })
+        .fetch_all(crate::database::get_db())
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_doubles_after_the_free_failures_up_to_a_cap() {
+        let free = AuthAttempt::ACCOUNT_FREE_FAILURES;
+        for failures in 0..=free {
+            assert_eq!(progressive_delay(failures, free), 0);
+        }
+        assert_eq!(progressive_delay(free + 1, free), 1);
+        assert_eq!(progressive_delay(free + 2, free), 2);
+        assert_eq!(progressive_delay(free + 3, free), 4);
+        assert_eq!(progressive_delay(free + 9, free), 256);
+        assert_eq!(progressive_delay(free + 10, free), AuthAttempt::MAX_DELAY_SECONDS);
+        assert_eq!(progressive_delay(1000, free), AuthAttempt::MAX_DELAY_SECONDS);
+    }
+
+    #[tokio::test]
+    async fn account_locks_after_too_many_failures() {
+        crate::database::test_db().await;
+        let (account, ip_address) = ("lockout-test", "192.0.2.1");
+
+        for _ in 1..AuthAttempt::LOCKOUT_FAILURES {
+            assert_eq!(AuthAttempt::login_failed(account, ip_address).await.unwrap(), None);
+        }
+        assert!(matches!(
+            AuthAttempt::check_login(account, ip_address).await,
+            Err(DatabaseError::TooManyAttempts(_))
+        ));
+        assert!(AuthAttempt::login_failed(account, ip_address).await.unwrap().is_some());
+        assert!(matches!(
+            AuthAttempt::check_login(account, ip_address).await,
+            Err(DatabaseError::AccountLocked(_))
+        ));
+        // Other accounts are not affected
+        assert!(AuthAttempt::check_login("lockout-test-other", "192.0.2.2").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn successful_login_resets_the_failure_count() {
+        crate::database::test_db().await;
+        let (account, ip_address) = ("success-reset-test", "192.0.2.3");
+
+        for _ in 0..=AuthAttempt::ACCOUNT_FREE_FAILURES {
+            AuthAttempt::login_failed(account, ip_address).await.unwrap();
+        }
+        assert!(matches!(
+            AuthAttempt::check_login(account, ip_address).await,
+            Err(DatabaseError::TooManyAttempts(_))
+        ));
+
+        AuthAttempt::record(AttemptKind::Login, account, ip_address, true).await.unwrap();
+        assert!(AuthAttempt::check_login(account, ip_address).await.is_ok());
+        // Failures before the success no longer count towards a lockout
+        for _ in 1..AuthAttempt::LOCKOUT_FAILURES {
+            assert_eq!(AuthAttempt::login_failed(account, ip_address).await.unwrap(), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn password_resets_are_capped_per_account_and_per_ip() {
+        crate::database::test_db().await;
+        async fn reset(account: &str, ip_address: &str) {
+            AuthAttempt::record(AttemptKind::PasswordReset, account, ip_address, true)
+                .await
+                .unwrap();
+        }
+
+        // Per account, whichever addresses the requests come from
+        let account = "reset-cap@example.org";
+        for i in 0..AuthAttempt::RESETS_PER_ACCOUNT {
+            let ip_address = format!("198.51.100.{}", i);
+            assert!(AuthAttempt::check_password_reset(account, &ip_address).await.is_ok());
+            reset(account, &ip_address).await;
+        }
+        assert!(matches!(
+            AuthAttempt::check_password_reset(account, "198.51.100.99").await,
+            Err(DatabaseError::TooManyAttempts(_))
+        ));
+
+        // Per address, whichever accounts are asked for
+        let ip_address = "203.0.113.1";
+        for i in 0..AuthAttempt::RESETS_PER_IP {
+            reset(&format!("ip-cap-{}@example.org", i), ip_address).await;
+        }
+        assert!(matches!(
+            AuthAttempt::check_password_reset("ip-cap@example.org", ip_address).await,
+            Err(DatabaseError::TooManyAttempts(_))
+        ));
+        assert!(AuthAttempt::check_password_reset("ip-cap@example.org", "203.0.113.2").await.is_ok());
+    }
+}
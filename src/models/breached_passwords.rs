@@ -0,0 +1,164 @@
+use std::path::Path;
+
+use once_cell::sync::OnceCell;
+use ring::digest::{digest, SHA256};
+use sha1::{Digest, Sha1};
+
+/// Identifies a breached-password filter file
+const MAGIC: &[u8; 8] = b"GLADBLM1";
+const HEADER_LENGTH: usize = 20;
+
+/// Filter built from `data/password_data/common_passwords.txt`, used unless
+/// `PASSWORD_BREACHED_FILTER` points at another one
+static BUNDLED_FILTER: &[u8] = include_bytes!("../../data/password_data/breached_passwords.bloom");
+
+static FILTER: OnceCell<BreachedPasswords> = OnceCell::new();
+
+/// Bloom filter of passwords known from data breaches
+///
+/// Passwords are compared case-insensitively. A bloom filter never misses a listed
+/// password but may rarely flag one that is not listed, which only costs the user
+/// another choice of password.
+pub struct BreachedPasswords {
+    hash_count: u32,
+    bits: Vec<u8>,
+}
+
+impl BreachedPasswords {
+    /// The filter in use, loaded on first use
+    /// Panics if `PASSWORD_BREACHED_FILTER` cannot be read, so a typo does not go unnoticed
+    pub fn get() -> &'static Self {
+        FILTER.get_or_init(|| match std::env::var("PASSWORD_BREACHED_FILTER") {
+            Ok(path) => {
+                let bytes = std::fs::read(&path)
+                    .unwrap_or_else(|e| panic!("Failed to read PASSWORD_BREACHED_FILTER {}: {}", path, e));
+                Self::from_bytes(bytes)
+                    .unwrap_or_else(|| panic!("PASSWORD_BREACHED_FILTER {} is not a breached-password filter", path))
+            }
+            Err(_) => Self::from_bytes(BUNDLED_FILTER.to_vec()).expect("bundled filter is valid"),
+        })
+    }
+
+    /// Whether a password is known from a breach, by the filter in use or, if
+    /// `PASSWORD_BREACHED_RANGES` is set, by the Pwned Passwords range files there
+    pub fn is_breached(password: &str) -> bool {
+        Self::get().contains(password)
+            || std::env::var("PASSWORD_BREACHED_RANGES")
+                .is_ok_and(|directory| Self::in_ranges(Path::new(&directory), password))
+    }
+
+    /// Look a password up in a directory of Pwned Passwords range files
+    ///
+    /// There is one file per five-character prefix of the uppercase SHA-1 hash, named
+    /// like `5BAA6.txt`, with a `SUFFIX:COUNT` line for each breached password, as the
+    /// Have I Been Pwned downloader writes them. Only that one file is read.
+    pub fn in_ranges(directory: &Path, password: &str) -> bool {
+        let hash = hex::encode_upper(Sha1::digest(password.as_bytes()));
+        let (prefix, suffix) = hash.split_at(5);
+        let path = directory.join(format!("{}.txt", prefix));
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => contents
+                .lines()
+                .any(|line| line.split(':').next().is_some_and(|hash| hash.eq_ignore_ascii_case(suffix))),
+            Err(e) => {
+                tracing::error!("Failed to read breached-password range {}: {}", path.display(), e);
+                false
+            }
+        }
+    }
+
+    /// Read a filter written by `to_bytes`
+    pub fn from_bytes(mut bytes: Vec<u8>) -> Option<Self> {
+        if bytes.len() <= HEADER_LENGTH || &bytes[..8] != MAGIC {
+            return None;
+        }
+        let hash_count = u32::from_le_bytes(bytes[8..12].try_into().ok()?);
+        let bit_count = u64::from_le_bytes(bytes[12..20].try_into().ok()?);
+        let bits = bytes.split_off(HEADER_LENGTH);
+        if hash_count == 0 || bit_count != bits.len() as u64 * 8 {
+            return None;
+        }
+
+        Some(Self { hash_count, bits })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LENGTH + self.bits.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&self.hash_count.to_le_bytes());
+        bytes.extend_from_slice(&(self.bits.len() as u64 * 8).to_le_bytes());
+        bytes.extend_from_slice(&self.bits);
+        bytes
+    }
+
+    /// Build a filter sized for the given passwords and false positive rate
+    pub fn build(passwords: &[String], false_positive_rate: f64) -> Self {
+        let count = passwords.len().max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let bit_count = (-count * false_positive_rate.ln() / (ln2 * ln2)).ceil().max(64.0) as usize;
+        let byte_count = bit_count.div_ceil(8);
+        let hash_count = ((byte_count * 8) as f64 / count * ln2).round().clamp(1.0, 30.0) as u32;
+
+        let mut filter = Self {
+            hash_count,
+            bits: vec![0; byte_count],
+        };
+        for password in passwords {
+            let indexes: Vec<usize> = filter.indexes(password).collect();
+            for index in indexes {
+                filter.bits[index / 8] |= 1 << (index % 8);
+            }
+        }
+        filter
+    }
+
+    pub fn contains(&self, password: &str) -> bool {
+        self.indexes(password)
+            .all(|index| self.bits[index / 8] & (1 << (index % 8)) != 0)
+    }
+
+    /// Bit positions for a password, by double hashing its SHA-256 digest
+    fn indexes(&self, password: &str) -> impl Iterator<Item = usize> + '_ {
+        let hash = digest(&SHA256, password.to_lowercase().as_bytes());
+        let hash = hash.as_ref();
+        let first = u64::from_le_bytes(hash[0..8].try_into().expect("digest is 32 bytes"));
+        let second = u64::from_le_bytes(hash[8..16].try_into().expect("digest is 32 bytes")) | 1;
+        let bit_count = self.bits.len() as u64 * 8;
+
+        (0..self.hash_count as u64)
+            .map(move |i| (first.wrapping_add(i.wrapping_mul(second)) % bit_count) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_files_are_looked_up_by_sha1_prefix() {
+        let directory = std::env::temp_dir().join(format!("glad-ranges-test-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        // SHA-1 of "password" is 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8
+        std::fs::write(
+            directory.join("5BAA6.txt"),
+            "003D68EB55068C33ACE09247EE4C639306B:3\r\n1E4C9B93F3F0682250B6CF8331B7EE68FD8:10434004\r\n",
+        )
+        .unwrap();
+
+        assert!(BreachedPasswords::in_ranges(&directory, "password"));
+        assert!(!BreachedPasswords::in_ranges(&directory, "Password"));
+        // No range file for this prefix
+        assert!(!BreachedPasswords::in_ranges(&directory, "glad-mango-violin-42"));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn filters_survive_a_round_trip() {
+        let passwords = vec!["letmein".to_string(), "dragon".to_string()];
+        let filter = BreachedPasswords::from_bytes(BreachedPasswords::build(&passwords, 0.001).to_bytes()).unwrap();
+        assert!(filter.contains("letmein"));
+        assert!(filter.contains("DRAGON"));
+        assert!(!filter.contains("glad-mango-violin-42"));
+    }
+}
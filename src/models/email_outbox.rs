@@ -0,0 +1,400 @@
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::mail::{Email, MailErrorKind, Mailer, MailerConfig};
+
+/// Wakes the sender when an email is queued, so it need not wait for its next check
+static QUEUED: Notify = Notify::const_new();
+
+/// Where an email is in its delivery
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum EmailStatus {
+    Pending,
+    Sending,
+    Sent,
+    /// The mail server refused the message for good
+    Bounced,
+    /// Every attempt failed
+    Dead,
+}
+
+/// An email in the outbox, as listed for administrators
+#[derive(Serialize, Clone, Debug)]
+pub struct OutboxEmail {
+    pub email_id: i64,
+    pub user_id: Option<i64>,
+    pub kind: String,
+    pub recipient_name: String,
+    pub recipient_email: String,
+    pub subject: String,
+    #[serde(skip)]
+    pub text_body: String,
+    #[serde(skip)]
+    pub html_body: Option<String>,
+    #[serde(skip)]
+    pub unsubscribe_url: Option<String>,
+    pub status: EmailStatus,
+    pub attempts: i64,
+    pub next_attempt_at: String,
+    pub last_error: Option<String>,
+    pub created_at: String,
+    pub sent_at: Option<String>,
+}
+
+/// Number of emails with a status
+#[derive(Serialize, Clone, Debug)]
+pub struct EmailStatusCount {
+    pub status: EmailStatus,
+    pub count: i64,
+}
+
+/// An email to queue for delivery
+#[derive(Debug, Clone)]
+pub struct NewEmail<'a> {
+    /// The account the email is about, if any
+    pub user_id: Option<i64>,
+    /// What the email is for, e.g. `password_reset`
+    pub kind: &'static str,
+    pub recipient_name: &'a str,
+    pub recipient_email: &'a str,
+    pub subject: String,
+    pub text_body: String,
+    pub html_body: Option<String>,
+    /// One-click unsubscribe address, for notification emails
+    pub unsubscribe_url: Option<String>,
+}
+
+/// Emails queued by request handlers and delivered in the background
+pub struct EmailOutbox;
+
+impl EmailOutbox {
+    /// Emails sent per batch, over one connection
+    pub const BATCH_SIZE: i64 = 50;
+    /// Attempts before an email is moved to the dead letters
+    pub const MAX_ATTEMPTS: i64 = 8;
+    /// Longest wait between attempts
+    const MAX_BACKOFF_MINUTES: i64 = 6 * 60;
+    /// How long a batch may take before its emails are picked up again
+    const LEASE_MINUTES: i64 = 10;
+    /// Days sent emails are kept, since their links may still be valid
+    pub const SENT_RETENTION_DAYS: i64 = 7;
+
+    /// Queue an email and wake the sender
+    pub async fn enqueue(email: NewEmail<'_>) -> Result<i64, sqlx::Error> {
+        let result = sqlx::query!(
+            "INSERT INTO email_outbox (user_id, kind, recipient_name, recipient_email, subject, text_body, html_body,
+                 unsubscribe_url)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            email.user_id,
+            email.kind,
+            email.recipient_name,
+            email.recipient_email,
+            email.subject,
+            email.text_body,
+            email.html_body,
+            email.unsubscribe_url
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        QUEUED.notify_one();
+        Ok(result.last_insert_rowid())
+    }
+
+    /// Wait until an email is queued
+    pub async fn queued() {
+        QUEUED.notified().await
+    }
+
+    /// Take the emails that are due and mark them as being sent
+    pub async fn claim_due(limit: i64) -> Result<Vec<OutboxEmail>, sqlx::Error> {
+        let lease = format!("+{} minutes", Self::LEASE_MINUTES);
+        sqlx::query!(
+            r#"UPDATE email_outbox SET status = 'sending', next_attempt_at = datetime('now', $1)
+             WHERE email_id IN (
+                 SELECT email_id FROM email_outbox
+                 WHERE status IN ('pending', 'sending') AND next_attempt_at <= CURRENT_TIMESTAMP
+                 ORDER BY email_id LIMIT $2)
+             RETURNING email_id AS "email_id!", user_id, kind, recipient_name, recipient_email, subject, text_body, html_body,
+                 unsubscribe_url, status AS "status: EmailStatus", attempts, next_attempt_at, last_error, created_at, sent_at"#,
+            lease,
+            limit
+        )
+        .map(|row| OutboxEmail {
+            email_id: row.email_id,
+            user_id: row.user_id,
+            kind: row.kind,
+            recipient_name: row.recipient_name,
+            recipient_email: row.recipient_email,
+            subject: row.subject,
+            text_body: row.text_body,
+            html_body: row.html_body,
+            unsubscribe_url: row.unsubscribe_url,
+            status: row.status,
+            attempts: row.attempts,
+            next_attempt_at: row.next_attempt_at.to_string(),
+            last_error: row.last_error,
+            created_at: row.created_at.to_string(),
+            sent_at: row.sent_at.map(|date| date.to_string()),
+        })
+        .fetch_all(crate::database::get_db())
+        .await
+    }
+
+    pub async fn mark_sent(email_id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE email_outbox SET status = 'sent', attempts = attempts + 1, sent_at = CURRENT_TIMESTAMP, last_error = NULL
+             WHERE email_id = $1",
+            email_id
+        )
+        .execute(crate::database::get_db())
+        .await?;
+        Ok(())
+    }
+
+    /// Record a failed attempt
+    /// Permanent failures bounce straight away; others are retried with exponential
+    /// backoff until the attempts run out
+    pub async fn mark_failed(email_id: i64, error: &str, permanent: bool) -> Result<EmailStatus, sqlx::Error> {
+        let attempts = sqlx::query_scalar!(
+            "SELECT attempts + 1 FROM email_outbox WHERE email_id = $1",
+            email_id
+        )
+        .fetch_one(crate::database::get_db())
+        .await?;
+
+        let status = if permanent {
+            EmailStatus::Bounced
+        } else if attempts >= Self::MAX_ATTEMPTS {
+            EmailStatus::Dead
+        } else {
+            EmailStatus::Pending
+        };
+        let backoff = format!(
+            "+{} minutes",
+            (1_i64 << (attempts - 1).clamp(0, 16)).min(Self::MAX_BACKOFF_MINUTES)
+        );
+
+        sqlx::query!(
+            "UPDATE email_outbox SET status = $2, attempts = $3, last_error = $4, next_attempt_at = datetime('now', $5)
+             WHERE email_id = $1",
+            email_id,
+            status,
+            attempts,
+            error,
+            backoff
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        Ok(status)
+    }
+
+    /// Emails that bounced or ran out of attempts, newest first
+    pub async fn dead_letters(limit: i64) -> Result<Vec<OutboxEmail>, sqlx::Error> {
+        sqlx::query!(
+            r#"SELECT email_id AS "email_id!", user_id, kind, recipient_name, recipient_email, subject, text_body, html_body,
+                 unsubscribe_url, status AS "status: EmailStatus", attempts, next_attempt_at, last_error, created_at, sent_at
+             FROM email_outbox WHERE status IN ('bounced', 'dead')
+             ORDER BY email_id DESC LIMIT $1"#,
+            limit
+        )
+        .map(|row| OutboxEmail {
+            email_id: row.email_id,
+            user_id: row.user_id,
+            kind: row.kind,
+            recipient_name: row.recipient_name,
+            recipient_email: row.recipient_email,
+            subject: row.subject,
+            text_body: row.text_body,
+            html_body: row.html_body,
+            unsubscribe_url: row.unsubscribe_url,
+            status: row.status,
+            attempts: row.attempts,
+            next_attempt_at: row.next_attempt_at.to_string(),
+            last_error: row.last_error,
+            created_at: row.created_at.to_string(),
+            sent_at: row.sent_at.map(|date| date.to_string()),
+        })
+        .fetch_all(crate::database::get_db())
+        .await
+    }
+
+    /// Counts of emails by status
+    pub async fn counts() -> Result<Vec<EmailStatusCount>, sqlx::Error> {
+        sqlx::query!(
+            r#"SELECT status AS "status: EmailStatus", COUNT(*) AS "count!: i64" FROM email_outbox GROUP BY status"#
+        )
+        .map(|row| EmailStatusCount {
+            status: row.status,
+            count: row.count,
+        })
+        .fetch_all(crate::database::get_db())
+        .await
+    }
+
+    /// Give a dead letter another round of attempts
+    /// Returns false if the email is not a dead letter
+    pub async fn retry(email_id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "UPDATE email_outbox SET status = 'pending', attempts = 0, next_attempt_at = CURRENT_TIMESTAMP
+             WHERE email_id = $1 AND status IN ('bounced', 'dead')",
+            email_id
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        if result.rows_affected() > 0 {
+            QUEUED.notify_one();
+        }
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Delete sent emails past their retention period
+    pub async fn purge_sent() -> Result<u64, sqlx::Error> {
+        let cutoff = format!("-{} days", Self::SENT_RETENTION_DAYS);
+        let result = sqlx::query!(
+            "DELETE FROM email_outbox WHERE status = 'sent' AND sent_at < datetime('now', $1)",
+            cutoff
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Send the emails that are due through the configured mailer
+    /// Returns the number of emails sent
+    pub async fn deliver_due() -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let batch = Self::claim_due(Self::BATCH_SIZE).await?;
+        if batch.is_empty() {
+            return Ok(0);
+        }
+
+        let config = match MailerConfig::from_env() {
+            Ok(config) => config,
+            Err(e) => {
+                let error = format!("Email is not configured: {}", e);
+                for email in &batch {
+                    Self::mark_failed(email.email_id, &error, false).await?;
+                }
+                return Err(error.into());
+            }
+        };
+
+        Ok(Self::send_batch(batch, config.mailer()).await?)
+    }
+
+    /// Send claimed emails over one mailer, recording how each went
+    /// Returns the number of emails sent
+    async fn send_batch(batch: Vec<OutboxEmail>, mut mailer: impl Mailer) -> Result<usize, sqlx::Error> {
+        let mut sent_count = 0;
+        let mut batch = batch.into_iter();
+        while let Some(email) = batch.next() {
+            let message = Email {
+                to_name: email.recipient_name.clone(),
+                to_address: email.recipient_email.clone(),
+                subject: email.subject.clone(),
+                text_body: email.text_body.clone(),
+                html_body: email.html_body.clone(),
+                unsubscribe_url: email.unsubscribe_url.clone(),
+            };
+
+            match mailer.send(&message).await {
+                Ok(()) => {
+                    Self::mark_sent(email.email_id).await?;
+                    sent_count += 1;
+                }
+                Err(e) if e.kind == MailErrorKind::Unavailable => {
+                    // The rest of the batch would fail the same way
+                    tracing::error!("Mail server unavailable: {}", e);
+                    for email in std::iter::once(email).chain(batch.by_ref()) {
+                        Self::record_failure(&email, &e.message, false).await?;
+                    }
+                    break;
+                }
+                Err(e) => {
+                    let permanent = e.kind == MailErrorKind::Rejected;
+                    Self::record_failure(&email, &e.message, permanent).await?;
+                }
+            }
+        }
+        mailer.close().await;
+
+        Ok(sent_count)
+    }
+
+    async fn record_failure(email: &OutboxEmail, error: &str, permanent: bool) -> Result<(), sqlx::Error> {
+        match Self::mark_failed(email.email_id, error, permanent).await? {
+            EmailStatus::Bounced => tracing::warn!(
+                "Email {} to {} bounced: {}",
+                email.email_id,
+                email.recipient_email,
+                error
+            ),
+            EmailStatus::Dead => tracing::error!(
+                "Giving up on email {} to {} after {} attempts: {}",
+                email.email_id,
+                email.recipient_email,
+                Self::MAX_ATTEMPTS,
+                error
+            ),
+            _ => tracing::warn!("Failed to send email {}, will retry: {}", email.email_id, error),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::templates::EmailTemplate;
+    use crate::mail::CapturingMailer;
+    use crate::models::Locale;
+
+    #[tokio::test]
+    async fn queued_reset_email_is_rendered_and_sent() {
+        crate::database::test_db().await;
+
+        let reset_url = "https://glad.example/reset-password?token=abc&next=1";
+        let rendered = EmailTemplate::PasswordReset.render(
+            Locale::default(),
+            &[("username", "alice<admin>"), ("reset_url", reset_url)],
+        );
+        EmailOutbox::enqueue(NewEmail {
+            user_id: None,
+            kind: "password_reset",
+            recipient_name: "alice<admin>",
+            recipient_email: "alice@example.org",
+            subject: rendered.subject,
+            text_body: rendered.text_body,
+            html_body: Some(rendered.html_body),
+            unsubscribe_url: None,
+        })
+        .await
+        .unwrap();
+
+        let mailer = CapturingMailer::default();
+        let batch = EmailOutbox::claim_due(EmailOutbox::BATCH_SIZE).await.unwrap();
+        assert_eq!(EmailOutbox::send_batch(batch, mailer.clone()).await.unwrap(), 1);
+
+        let sent = mailer.sent();
+        assert_eq!(sent.len(), 1);
+        let email = &sent[0];
+        assert_eq!(email.to_address, "alice@example.org");
+        assert!(!email.subject.is_empty());
+        assert!(email.text_body.contains(reset_url));
+        assert!(email.text_body.contains("alice<admin>"));
+        let html_body = email.html_body.as_deref().unwrap();
+        assert!(html_body.contains("https://glad.example/reset-password?token=abc&amp;next=1"));
+        assert!(html_body.contains("alice&lt;admin&gt;"));
+        assert!(!html_body.contains("{{"));
+
+        // Sent emails are not picked up again
+        let counts = EmailOutbox::counts().await.unwrap();
+        assert!(counts.iter().any(|count| count.status == EmailStatus::Sent && count.count == 1));
+        assert!(EmailOutbox::claim_due(EmailOutbox::BATCH_SIZE).await.unwrap().is_empty());
+    }
+}
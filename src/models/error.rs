@@ -8,12 +8,65 @@ pub enum DatabaseError {
     
     // Validation errors
     UsernameTooShort,
-    PasswordTooWeak, 
+    /// With feedback on what to change
+    PasswordTooWeak(Vec<String>),
     InvalidEmail,
     
     // Query-related errors
     QueryNotFound,
     CohortNotFound,
+
+    // Organization-related errors
+    OrganizationNotFound,
+    OrganizationNameAlreadyExists,
+    InvalidOrganizationName,
+    InvalidOrganizationSettings,
+    MemberNotFound,
+    LastOrganizationOwner,
+    InvitationNotFound,
+    InvitationAlreadyExists,
+
+    // Session-related errors
+    InvalidSession,
+    InvalidResetToken,
+    InvalidVerificationToken,
+
+    // Account approval errors
+    InvalidAgreement,
+    AgreementVersionMismatch,
+
+    // API token errors
+    ApiTokenNotFound,
+    InvalidApiTokenName,
+    InvalidApiTokenScopes,
+    InvalidApiTokenExpiry,
+
+    // Webhook errors
+    WebhookNotFound,
+    InvalidWebhookUrl,
+    InternalWebhookUrl,
+    InvalidWebhookDescription,
+    InvalidWebhookSecret,
+
+    // Announcement errors
+    AnnouncementNotFound,
+    InvalidAnnouncementText,
+    InvalidAnnouncementSchedule,
+
+    // Brute-force protection errors, with the seconds to wait before retrying
+    TooManyAttempts(i64),
+    AccountLocked(i64),
+    InvalidUnlockToken,
+
+    // Two-factor authentication errors
+    InvalidTwoFactorCode,
+    TwoFactorAlreadyEnabled,
+    TwoFactorNotEnabled,
+    TwoFactorNotPending,
+    TwoFactorRequired,
+
+    // Account deletion errors
+    AccountDeletionScheduled,
 }
 
 impl From<sqlx::Error> for DatabaseError {
@@ -31,6 +84,10 @@ impl From<sqlx::Error> for DatabaseError {
                         return DatabaseError::UsernameAlreadyExists;
                     } else if error_msg.contains("user.email") {
                         return DatabaseError::EmailAlreadyExists;
+                    } else if error_msg.contains("organization.name") {
+                        return DatabaseError::OrganizationNameAlreadyExists;
+                    } else if error_msg.contains("organization_invitation.") {
+                        return DatabaseError::InvitationAlreadyExists;
                     }
                 }
                 
@@ -52,12 +109,64 @@ impl std::fmt::Display for DatabaseError {
             
             // Validation errors - user-friendly messages
             DatabaseError::UsernameTooShort => write!(f, "Username must be at least 8 characters long"),
-            DatabaseError::PasswordTooWeak => write!(f, "Password must be at least 8 characters long"),
+            DatabaseError::PasswordTooWeak(feedback) => write!(f, "Password is too weak. {}", feedback.join(" ")),
             DatabaseError::InvalidEmail => write!(f, "Please enter a valid email address"),
             
             // Query-related errors
             DatabaseError::QueryNotFound => write!(f, "Query not found"),
             DatabaseError::CohortNotFound => write!(f, "Cohort not found"),
+
+            // Organization-related errors
+            DatabaseError::OrganizationNotFound => write!(f, "Organization not found"),
+            DatabaseError::OrganizationNameAlreadyExists => write!(f, "Organization name already exists"),
+            DatabaseError::InvalidOrganizationName => write!(f, "Organization name must be between 3 and 100 characters long"),
+            DatabaseError::InvalidOrganizationSettings => write!(f, "Quota must not be negative and retention must be at least 1 day"),
+            DatabaseError::MemberNotFound => write!(f, "User is not a member of this organization"),
+            DatabaseError::LastOrganizationOwner => write!(f, "An organization must keep at least one owner"),
+            DatabaseError::InvitationNotFound => write!(f, "Invitation not found"),
+            DatabaseError::InvitationAlreadyExists => write!(f, "This email already has a pending invitation"),
+
+            // Session-related errors
+            DatabaseError::InvalidSession => write!(f, "Session is invalid or has expired"),
+            DatabaseError::InvalidResetToken => write!(f, "Invalid or expired reset token"),
+            DatabaseError::InvalidVerificationToken => write!(f, "Invalid or expired verification link"),
+
+            // Account approval errors
+            DatabaseError::InvalidAgreement => write!(f, "Agreement text must not be empty"),
+            DatabaseError::AgreementVersionMismatch => write!(f, "The data-use agreement has changed, please review the current version"),
+
+            // API token errors
+            DatabaseError::ApiTokenNotFound => write!(f, "API token not found"),
+            DatabaseError::InvalidApiTokenName => write!(f, "Token name must be between 1 and 100 characters long"),
+            DatabaseError::InvalidApiTokenScopes => write!(f, "A token needs at least one scope"),
+            DatabaseError::InvalidApiTokenExpiry => write!(f, "Token expiry must be between 1 and 365 days"),
+
+            // Webhook errors
+            DatabaseError::WebhookNotFound => write!(f, "Webhook not found"),
+            DatabaseError::InvalidWebhookUrl => write!(f, "Webhook URL must be an http or https URL"),
+            DatabaseError::InternalWebhookUrl => write!(f, "Webhook URL must point to a public address"),
+            DatabaseError::InvalidWebhookDescription => write!(f, "Webhook description must be at most 200 characters long"),
+            DatabaseError::InvalidWebhookSecret => write!(f, "Webhook secret must be between 16 and 256 characters long"),
+
+            // Announcement errors
+            DatabaseError::AnnouncementNotFound => write!(f, "Announcement not found"),
+            DatabaseError::InvalidAnnouncementText => write!(f, "Announcement title must be 1 to 200 characters and its message 1 to 2000 characters long"),
+            DatabaseError::InvalidAnnouncementSchedule => write!(f, "Announcement times must be RFC 3339 or YYYY-MM-DD HH:MM:SS in UTC, and it must end after it starts"),
+
+            // Brute-force protection errors
+            DatabaseError::TooManyAttempts(seconds) => write!(f, "Too many attempts, please try again in {} seconds", seconds),
+            DatabaseError::AccountLocked(_) => write!(f, "This account is temporarily locked after too many failed login attempts. Check your email for an unlock link or try again later"),
+            DatabaseError::InvalidUnlockToken => write!(f, "Invalid or already used unlock link"),
+
+            // Two-factor authentication errors
+            DatabaseError::InvalidTwoFactorCode => write!(f, "Invalid two-factor authentication code"),
+            DatabaseError::TwoFactorAlreadyEnabled => write!(f, "Two-factor authentication is already enabled"),
+            DatabaseError::TwoFactorNotEnabled => write!(f, "Two-factor authentication is not enabled"),
+            DatabaseError::TwoFactorNotPending => write!(f, "Start two-factor authentication setup first"),
+            DatabaseError::TwoFactorRequired => write!(f, "Two-factor authentication is required for your role"),
+
+            // Account deletion errors
+            DatabaseError::AccountDeletionScheduled => write!(f, "Your account is scheduled for deletion. Cancel the deletion in your settings to keep using it"),
         }
     }
 }
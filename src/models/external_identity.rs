@@ -0,0 +1,116 @@
+use serde::Serialize;
+
+use crate::auth::secret::generate_secret;
+
+/// An account at an OpenID Connect provider, linked to a local user
+#[derive(Serialize, Clone, Debug)]
+pub struct ExternalIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: String,
+    pub last_login_at: String,
+}
+
+/// A login that was sent to the provider and has not come back yet
+pub struct OidcLogin {
+    pub code_verifier: String,
+    pub nonce: String,
+    pub redirect_to: String,
+}
+
+impl ExternalIdentity {
+    /// Find the user an identity belongs to and record the login
+    pub async fn find_user(issuer: &str, subject: &str) -> Result<Option<i64>, sqlx::Error> {
+        sqlx::query_scalar!(
+            "UPDATE user_identity SET last_login_at = CURRENT_TIMESTAMP
+             WHERE issuer = $1 AND subject = $2
+             RETURNING user_id",
+            issuer,
+            subject
+        )
+        .fetch_optional(crate::database::get_db())
+        .await
+    }
+
+    /// Link an identity to a user
+    pub async fn link(
+        user_id: i64,
+        issuer: &str,
+        subject: &str,
+        email: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "INSERT INTO user_identity (user_id, issuer, subject, email) VALUES ($1, $2, $3, $4)",
+            user_id,
+            issuer,
+            subject,
+            email
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Get the identities linked to a user
+    pub async fn for_user(user_id: i64) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query!(
+            "SELECT issuer, subject, email, created_at, last_login_at FROM user_identity
+             WHERE user_id = $1 ORDER BY created_at",
+            user_id
+        )
+        .map(|row| Self {
+            issuer: row.issuer,
+            subject: row.subject,
+            email: row.email,
+            created_at: row.created_at.to_string(),
+            last_login_at: row.last_login_at.to_string(),
+        })
+        .fetch_all(crate::database::get_db())
+        .await
+    }
+}
+
+impl OidcLogin {
+    /// How long the user has to log in at the provider
+    pub const DURATION_SECONDS: i64 = 600;
+
+    /// Remember a login sent to the provider
+    /// Returns the state to pass along, which identifies the login when the provider redirects back
+    pub async fn create(code_verifier: &str, nonce: &str, redirect_to: &str) -> Result<String, sqlx::Error> {
+        let state = generate_secret();
+        let lifetime = format!("+{} seconds", Self::DURATION_SECONDS);
+
+        // Abandoned logins are cleaned up as new ones start
+        sqlx::query!("DELETE FROM oidc_login WHERE expires_at <= CURRENT_TIMESTAMP")
+            .execute(crate::database::get_db())
+            .await?;
+
+        sqlx::query!(
+            "INSERT INTO oidc_login (state, code_verifier, nonce, redirect_to, expires_at)
+             VALUES ($1, $2, $3, $4, datetime('now', $5))",
+            state,
+            code_verifier,
+            nonce,
+            redirect_to,
+            lifetime
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        Ok(state)
+    }
+
+    /// Use up a pending login. Each state is only accepted once
+    pub async fn consume(state: &str) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            "DELETE FROM oidc_login WHERE state = $1 AND expires_at > CURRENT_TIMESTAMP
+             RETURNING code_verifier, nonce, redirect_to",
+            state
+        )
+        .fetch_optional(crate::database::get_db())
+        .await
+    }
+}
@@ -1,11 +1,50 @@
+mod account_deletion;
+mod account_lockout;
+mod agreement;
+mod announcement;
+mod api_token;
+mod audit_event;
+mod auth_attempt;
+mod breached_passwords;
+mod email_outbox;
 mod error;
+mod external_identity;
 mod notification;
+mod notification_digest;
+mod notification_preference;
+mod organization;
+mod password_policy;
+mod password_reset;
+mod personal_data;
 mod query;
+mod session;
+mod two_factor;
 mod user;
+mod webhook;
 
+pub use account_deletion::AccountDeletion;
+pub use account_lockout::AccountLockout;
+pub use agreement::DataUseAgreement;
+pub use announcement::{Announcement, AnnouncementAudience, AnnouncementSeverity, NewAnnouncement};
+pub use api_token::{ApiScope, ApiToken, ApiTokenGrant};
+pub use audit_event::{AuditEvent, AuditFilter, AuditOutcome, NewAuditEvent};
+pub use auth_attempt::{AttemptKind, AuthAttempt};
+pub use breached_passwords::BreachedPasswords;
+pub use email_outbox::{EmailOutbox, EmailStatus, EmailStatusCount, NewEmail, OutboxEmail};
 pub use error::DatabaseError;
-pub use notification::Notification;
+pub use external_identity::{ExternalIdentity, OidcLogin};
+pub use notification::{NewNotification, Notification, NotificationFilter, NotificationKind, NotificationPage};
+pub use notification_digest::{DigestFrequency, DigestSchedule, NotificationDigest};
+pub use notification_preference::{NotificationChannel, NotificationPreference, UnsubscribeLinks};
+pub use organization::{Organization, OrganizationRole};
+pub use password_policy::{PasswordCheck, PasswordPolicy};
+pub use password_reset::PasswordReset;
+pub use personal_data::PersonalData;
 pub use query::{Cohort, Query};
-pub use user::{User, verify_password};
+pub use session::Session;
+pub use two_factor::{TwoFactor, TwoFactorEnrollment, TwoFactorStatus};
+pub use user::{AccountStatus, Locale, Role, User, UserSummary, verify_password};
+pub use webhook::{DeliveryStatus, Webhook, WebhookDelivery, WebhookEvent};
 
+#[allow(dead_code)]
 const DATE_FORMAT: &str = "%d/%m/%Y %H:%M";
@@ -1,85 +1,269 @@
 use serde::{Deserialize, Serialize};
 
+use crate::mail::templates::EmailTemplate;
+use crate::models::{Locale, NotificationChannel, NotificationPreference, Webhook, WebhookEvent};
+
+/// What a notification is about
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum NotificationKind {
+    QueryStarted,
+    QueryRetrying,
+    QueryCompleted,
+    QueryFailed,
+    /// An organization query is about to be deleted under its retention period
+    ResultsExpiring,
+    AccountApproved,
+    AccountRejected,
+    CohortAdded,
+    Announcement,
+}
+
+impl NotificationKind {
+    pub const ALL: [NotificationKind; 9] = [
+        NotificationKind::QueryStarted,
+        NotificationKind::QueryRetrying,
+        NotificationKind::QueryCompleted,
+        NotificationKind::QueryFailed,
+        NotificationKind::ResultsExpiring,
+        NotificationKind::AccountApproved,
+        NotificationKind::AccountRejected,
+        NotificationKind::CohortAdded,
+        NotificationKind::Announcement,
+    ];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            NotificationKind::QueryStarted => "query_started",
+            NotificationKind::QueryRetrying => "query_retrying",
+            NotificationKind::QueryCompleted => "query_completed",
+            NotificationKind::QueryFailed => "query_failed",
+            NotificationKind::ResultsExpiring => "results_expiring",
+            NotificationKind::AccountApproved => "account_approved",
+            NotificationKind::AccountRejected => "account_rejected",
+            NotificationKind::CohortAdded => "cohort_added",
+            NotificationKind::Announcement => "announcement",
+        }
+    }
+
+    /// Channel used until the user chooses one
+    /// Users are emailed about their account and about results they are about to lose
+    pub fn default_channel(self) -> NotificationChannel {
+        match self {
+            NotificationKind::ResultsExpiring
+            | NotificationKind::AccountApproved
+            | NotificationKind::AccountRejected => NotificationChannel::Email,
+            _ => NotificationChannel::InApp,
+        }
+    }
+
+    fn email_template(self) -> EmailTemplate {
+        match self {
+            NotificationKind::QueryStarted
+            | NotificationKind::QueryRetrying
+            | NotificationKind::QueryCompleted
+            | NotificationKind::QueryFailed => EmailTemplate::QueryStatus,
+            NotificationKind::ResultsExpiring => EmailTemplate::ResultsExpiring,
+            NotificationKind::AccountApproved => EmailTemplate::AccountApproved,
+            NotificationKind::AccountRejected => EmailTemplate::AccountRejected,
+            NotificationKind::CohortAdded => EmailTemplate::CohortAdded,
+            NotificationKind::Announcement => EmailTemplate::Announcement,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Notification {
     pub notification_id: i64,
     pub user_id: i64,
-    pub query_id: i64,
+    pub kind: NotificationKind,
+    /// Set for notifications about a query
+    pub query_id: Option<i64>,
+    /// What the notification is about, e.g. `query` or `cohort`
+    pub target_type: Option<String>,
+    pub target_id: Option<i64>,
     pub title: String,
     pub message: String,
+    /// Details such as the query title, depending on the kind
+    pub payload: serde_json::Value,
     pub is_read: bool,
     pub created_at: String,
+    /// Set once the notification was read long enough ago to be archived
+    pub archived_at: Option<String>,
+}
+
+/// Filters for listing a user's notifications; unset fields match everything
+#[derive(Debug, Default, Deserialize)]
+pub struct NotificationFilter {
+    pub read: Option<bool>,
+    pub kind: Option<NotificationKind>,
+    /// List archived notifications instead of current ones
+    #[serde(default)]
+    pub archived: bool,
+    /// Only notifications older than this id, to page through results
+    pub before_id: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// A page of a user's notifications
+#[derive(Debug, Serialize)]
+pub struct NotificationPage {
+    pub notifications: Vec<Notification>,
+    /// `before_id` for the next page, if there is one
+    pub next_before_id: Option<i64>,
+}
+
+/// A notification to send
+#[derive(Debug, Clone)]
+pub struct NewNotification {
+    pub kind: NotificationKind,
+    pub title: String,
+    pub message: String,
+    /// What the notification is about, e.g. `("query", 12)`
+    pub target: Option<(&'static str, i64)>,
+    /// A JSON object of details, also available to the email templates
+    pub payload: serde_json::Value,
+}
+
+/// A query whose recipients have not yet been told about its current status
+struct QueryStatusChange {
+    query_id: i64,
+    user_id: i64,
+    organization_id: Option<i64>,
+    title: String,
+    retry_count: i64,
+    notified_state: Option<String>,
+    state: String,
 }
 
 impl Notification {
-    /// Create a new notification for a query status change
-    pub async fn create_for_query_status(
-        user_id: i64,
-        query_id: i64,
-        query_title: &str,
-        status: &str,
-    ) -> Result<i64, sqlx::Error> {
-        let (title, message) = match status {
-            "completed" => (
-                "Query Completed".to_string(),
-                format!("Your query '{}' has completed successfully.", query_title),
-            ),
-            "failed" => (
-                "Query Error".to_string(),
-                format!("Your query '{}' has failed.", query_title),
-            ),
-            _ => return Err(sqlx::Error::RowNotFound), // Only create notifications for completed/failed
+    /// Days before an organization query is deleted that its recipients are warned
+    pub const EXPIRY_WARNING_DAYS: i64 = 7;
+    /// Days before a read notification is archived when `NOTIFICATION_ARCHIVE_DAYS` is not set
+    pub const DEFAULT_ARCHIVE_AFTER_DAYS: i64 = 30;
+
+    /// Notify a user through the channel they chose for this kind of notification
+    /// Returns the new notification's id, or None if the user turned the kind off
+    pub async fn create(user_id: i64, notification: &NewNotification) -> Result<Option<i64>, sqlx::Error> {
+        let channel = NotificationPreference::channel(user_id, notification.kind).await?;
+        if channel == NotificationChannel::None {
+            return Ok(None);
+        }
+
+        let (target_type, target_id) = notification.target.unzip();
+        let query_id = match notification.target {
+            Some(("query", query_id)) => Some(query_id),
+            _ => None,
         };
+        let payload = notification.payload.to_string();
 
         let result = sqlx::query!(
-            "INSERT INTO notifications (user_id, query_id, title, message) VALUES ($1, $2, $3, $4)",
+            "INSERT INTO notifications (user_id, kind, query_id, target_type, target_id, title, message, payload)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
             user_id,
+            notification.kind,
             query_id,
-            title,
-            message
+            target_type,
+            target_id,
+            notification.title,
+            notification.message,
+            payload
         )
         .execute(crate::database::get_db())
         .await?;
 
-        let notification_id = result.last_insert_rowid();
-
-        // Send email notification if user has it enabled
-        if let Err(e) = Self::send_email_notification(user_id, query_id, &title, &message).await {
-            tracing::error!("Failed to send email notification: {}", e);
+        if channel == NotificationChannel::Email {
             // Don't fail the notification creation if email fails
+            if let Err(e) = Self::send_email(user_id, notification).await {
+                tracing::error!("Failed to send email notification: {}", e);
+            }
         }
 
-        Ok(notification_id)
+        Ok(Some(result.last_insert_rowid()))
     }
 
-    /// Get all notifications for a user, ordered by most recent first
-    pub async fn for_user(user_id: i64) -> Result<Vec<Self>, sqlx::Error> {
-        sqlx::query!(
-            "SELECT notification_id, user_id, query_id, title, message, is_read, created_at 
-             FROM notifications 
-             WHERE user_id = $1 
-             ORDER BY created_at DESC",
-            user_id
+    /// Notify every active user, e.g. about a new cohort
+    /// Returns the number of users notified
+    pub async fn broadcast(notification: &NewNotification) -> Result<usize, sqlx::Error> {
+        let user_ids = sqlx::query_scalar!(
+            r#"SELECT user_id AS "user_id!" FROM user WHERE status = 'active' AND disabled_at IS NULL"#
+        )
+        .fetch_all(crate::database::get_db())
+        .await?;
+
+        let mut notified_count = 0;
+        for user_id in user_ids {
+            if Self::create(user_id, notification).await?.is_some() {
+                notified_count += 1;
+            }
+        }
+
+        Ok(notified_count)
+    }
+
+    /// List a user's notifications matching a filter, newest first
+    /// Notifications about queries the user can no longer access are left out
+    pub async fn for_user(user_id: i64, filter: &NotificationFilter) -> Result<NotificationPage, sqlx::Error> {
+        let limit = filter.limit.unwrap_or(50).clamp(1, 200);
+        // One extra row tells whether there is another page
+        let fetch_limit = limit + 1;
+        let mut notifications = sqlx::query!(
+            r#"SELECT n.notification_id AS "notification_id!", n.user_id, n.kind AS "kind: NotificationKind", n.query_id,
+                n.target_type, n.target_id, n.title, n.message, n.payload, n.is_read, n.created_at, n.archived_at
+             FROM notifications n
+             LEFT JOIN query q ON q.query_id = n.query_id
+             WHERE n.user_id = $1
+             AND (n.query_id IS NULL OR q.user_id = $1 OR q.organization_id IN (SELECT organization_id FROM organization_member WHERE user_id = $1))
+             AND ($2 IS NULL OR n.is_read = $2) AND ($3 IS NULL OR n.kind = $3)
+             AND (n.archived_at IS NOT NULL) = $4
+             AND ($5 IS NULL OR n.notification_id < $5)
+             ORDER BY n.notification_id DESC
+             LIMIT $6"#,
+            user_id,
+            filter.read,
+            filter.kind,
+            filter.archived,
+            filter.before_id,
+            fetch_limit
         )
         .map(|row| Self {
-            notification_id: row
-                .notification_id
-                .expect("notification_id should not be null"),
+            notification_id: row.notification_id,
             user_id: row.user_id,
+            kind: row.kind,
             query_id: row.query_id,
+            target_type: row.target_type,
+            target_id: row.target_id,
             title: row.title,
             message: row.message,
+            payload: serde_json::from_str(&row.payload).unwrap_or_default(),
             is_read: row.is_read, // SQLite handles booleans directly
             created_at: row.created_at.to_string(),
+            archived_at: row.archived_at.map(|archived_at| archived_at.to_string()),
         })
         .fetch_all(crate::database::get_db())
-        .await
+        .await?;
+
+        let next_before_id = if notifications.len() as i64 > limit {
+            notifications.truncate(limit as usize);
+            notifications.last().map(|notification| notification.notification_id)
+        } else {
+            None
+        };
+
+        Ok(NotificationPage {
+            notifications,
+            next_before_id,
+        })
     }
 
     /// Get count of unread notifications for a user
     pub async fn unread_count_for_user(user_id: i64) -> Result<i64, sqlx::Error> {
         let result = sqlx::query!(
-            "SELECT COUNT(*) as count FROM notifications WHERE user_id = $1 AND is_read = FALSE",
+            "SELECT COUNT(*) as count FROM notifications n
+             LEFT JOIN query q ON q.query_id = n.query_id
+             WHERE n.user_id = $1 AND n.is_read = FALSE
+             AND (n.query_id IS NULL OR q.user_id = $1 OR q.organization_id IN (SELECT organization_id FROM organization_member WHERE user_id = $1))",
             user_id
         )
         .fetch_one(crate::database::get_db())
@@ -88,18 +272,21 @@ impl Notification {
         Ok(result.count)
     }
 
-    /// Mark a notification as read
-    pub async fn mark_as_read(notification_id: i64, user_id: i64) -> Result<bool, sqlx::Error> {
+    /// Mark some of a user's notifications as read
+    /// Returns the number of notifications marked
+    pub async fn mark_as_read(notification_ids: &[i64], user_id: i64) -> Result<u64, sqlx::Error> {
+        let notification_ids = serde_json::to_string(notification_ids).unwrap_or_default();
         let result = sqlx::query!(
-            "UPDATE notifications SET is_read = TRUE 
-             WHERE notification_id = $1 AND user_id = $2",
-            notification_id,
-            user_id
+            "UPDATE notifications SET is_read = TRUE
+             WHERE user_id = $1 AND is_read = FALSE
+             AND notification_id IN (SELECT value FROM json_each($2))",
+            user_id,
+            notification_ids
         )
         .execute(crate::database::get_db())
         .await?;
 
-        Ok(result.rows_affected() > 0)
+        Ok(result.rows_affected())
     }
 
     /// Mark all notifications as read for a user
@@ -114,59 +301,235 @@ impl Notification {
         Ok(result.rows_affected())
     }
 
-    /// Find queries with completed/errored status that don't have notifications yet
-    /// Returns Vec<(query_id, user_id, title, status)>
-    pub async fn find_queries_needing_notifications(
-    ) -> Result<Vec<(i64, i64, String, String)>, sqlx::Error> {
-        sqlx::query!(
+    /// Delete some of a user's notifications
+    /// Returns the number of notifications deleted
+    pub async fn delete(notification_ids: &[i64], user_id: i64) -> Result<u64, sqlx::Error> {
+        let notification_ids = serde_json::to_string(notification_ids).unwrap_or_default();
+        let result = sqlx::query!(
+            "DELETE FROM notifications
+             WHERE user_id = $1 AND notification_id IN (SELECT value FROM json_each($2))",
+            user_id,
+            notification_ids
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// How long after it was sent a read notification is archived, from
+    /// `NOTIFICATION_ARCHIVE_DAYS`
+    pub fn archive_after_days() -> i64 {
+        std::env::var("NOTIFICATION_ARCHIVE_DAYS")
+            .ok()
+            .and_then(|days| days.parse().ok())
+            .unwrap_or(Self::DEFAULT_ARCHIVE_AFTER_DAYS)
+            .max(1)
+    }
+
+    /// Archive read notifications older than the archive age
+    /// Returns the number of notifications archived
+    pub async fn archive_read() -> Result<u64, sqlx::Error> {
+        let cutoff = format!("-{} days", Self::archive_after_days());
+        let result = sqlx::query!(
+            "UPDATE notifications SET archived_at = CURRENT_TIMESTAMP
+             WHERE is_read = TRUE AND archived_at IS NULL AND created_at < datetime('now', $1)",
+            cutoff
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Find queries whose status changed since their recipients were last notified
+    /// A query waiting for a retry is in the state `retry_pending:<retry count>`,
+    /// so each retry is a new state
+    async fn find_query_status_changes() -> Result<Vec<QueryStatusChange>, sqlx::Error> {
+        sqlx::query_as!(
+            QueryStatusChange,
             r#"
-            SELECT q.query_id, q.user_id, q.title, q.user_visible_status
-            FROM query q
-            LEFT JOIN notifications n ON q.query_id = n.query_id
-            WHERE q.user_visible_status IN ('completed', 'failed')
-            AND n.query_id IS NULL
+            SELECT query_id AS "query_id!", user_id, organization_id, title, retry_count, notified_state,
+                state AS "state!: String"
+            FROM (
+                SELECT *, CASE WHEN internal_status = 'retry_pending' THEN 'retry_pending:' || retry_count
+                    ELSE user_visible_status END AS state
+                FROM query
+            )
+            WHERE state != 'pending' AND notified_state IS NOT state
             "#
         )
-        .map(|row| {
-            (
-                row.query_id,
-                row.user_id,
-                row.title,
-                row.user_visible_status,
-            )
-        })
         .fetch_all(crate::database::get_db())
         .await
     }
 
-    /// Process all queries that need notifications
+    /// The notification for a query event, worded for its submitter or, when
+    /// `organization_name` is set, for another member of the organization
+    fn for_query(
+        kind: NotificationKind,
+        query_id: i64,
+        query_title: &str,
+        organization_name: Option<&str>,
+        mut payload: serde_json::Value,
+    ) -> NewNotification {
+        let subject = match organization_name {
+            Some(organization_name) => format!("The {} query '{}'", organization_name, query_title),
+            None => format!("Your query '{}'", query_title),
+        };
+        let detail = |key: &str| match &payload[key] {
+            serde_json::Value::String(value) => value.clone(),
+            value => value.to_string(),
+        };
+        let (title, message) = match kind {
+            NotificationKind::QueryStarted => ("Query Started", format!("{} is being processed.", subject)),
+            NotificationKind::QueryRetrying => (
+                "Query Retrying",
+                format!("{} ran into a problem and will be retried (attempt {}).", subject, detail("attempt")),
+            ),
+            NotificationKind::QueryCompleted => ("Query Completed", format!("{} has completed successfully.", subject)),
+            NotificationKind::ResultsExpiring => (
+                "Results Expiring",
+                format!("{} and its results will be deleted on {}.", subject, detail("expires_at")),
+            ),
+            // Query failures; other kinds are not about a query
+            _ => ("Query Error", format!("{} has failed.", subject)),
+        };
+
+        payload["query_title"] = query_title.into();
+        if let Some(organization_name) = organization_name {
+            payload["organization_name"] = organization_name.into();
+        }
+
+        NewNotification {
+            kind,
+            title: title.to_string(),
+            message,
+            target: Some(("query", query_id)),
+            payload,
+        }
+    }
+
+    /// Notify the submitter of a query and, for organization queries when
+    /// `include_members` is set, every other member of the organization
+    async fn notify_query_recipients(
+        kind: NotificationKind,
+        query_id: i64,
+        user_id: i64,
+        organization_id: Option<i64>,
+        title: &str,
+        payload: serde_json::Value,
+        include_members: bool,
+    ) -> Result<(), sqlx::Error> {
+        Self::create(user_id, &Self::for_query(kind, query_id, title, None, payload.clone())).await?;
+
+        if let Some(organization_id) = organization_id.filter(|_| include_members) {
+            let organization = crate::models::Organization::get(organization_id)
+                .await
+                .map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+            let members = crate::models::Organization::members(organization_id).await?;
+
+            let notification = Self::for_query(kind, query_id, title, Some(&organization.name), payload);
+            for member in members.iter().filter(|m| m.user_id != user_id) {
+                Self::create(member.user_id, &notification).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Notify recipients and webhooks of queries whose status changed
+    /// Starts and retries are only of interest to the submitter; outcomes go to
+    /// the whole organization
     pub async fn process_pending_notifications() -> Result<usize, sqlx::Error> {
-        let queries = Self::find_queries_needing_notifications().await?;
+        let changes = Self::find_query_status_changes().await?;
         let mut created_count = 0;
 
-        for (query_id, user_id, title, status) in queries {
-            match Self::create_for_query_status(user_id, query_id, &title, &status).await {
-                Ok(_) => {
-                    created_count += 1;
-                    tracing::info!(
-                        "Created notification for query {} (status: {})",
-                        query_id,
-                        status
-                    );
+        for change in changes {
+            let kind = match change.state.as_str() {
+                // Back to processing after a retry, which was already announced
+                "processing" if change.notified_state.is_some() => None,
+                "processing" => Some(NotificationKind::QueryStarted),
+                "completed" => Some(NotificationKind::QueryCompleted),
+                "failed" => Some(NotificationKind::QueryFailed),
+                _ => Some(NotificationKind::QueryRetrying),
+            };
+
+            if let Some(kind) = kind {
+                let event = match kind {
+                    NotificationKind::QueryStarted => WebhookEvent::QueryStarted,
+                    NotificationKind::QueryRetrying => WebhookEvent::QueryRetrying,
+                    NotificationKind::QueryCompleted => WebhookEvent::QueryCompleted,
+                    _ => WebhookEvent::QueryFailed,
+                };
+                let data = serde_json::json!({
+                    "query": {
+                        "query_id": change.query_id,
+                        "title": change.title,
+                        "status": change.state.split(':').next(),
+                        "retry_count": change.retry_count,
+                        "organization_id": change.organization_id,
+                    }
+                });
+                if let Err(e) = Webhook::enqueue_query_event(
+                    event,
+                    change.query_id,
+                    change.user_id,
+                    change.organization_id,
+                    data,
+                )
+                .await
+                {
+                    tracing::error!("Failed to queue webhooks for query {}: {}", change.query_id, e);
                 }
-                Err(e) => {
-                    tracing::error!(
-                        "Failed to create notification for query {}: {}",
-                        query_id,
-                        e
-                    );
+
+                let payload = match kind {
+                    NotificationKind::QueryRetrying => serde_json::json!({ "attempt": change.retry_count }),
+                    _ => serde_json::json!({}),
+                };
+                let include_members = matches!(kind, NotificationKind::QueryCompleted | NotificationKind::QueryFailed);
+
+                match Self::notify_query_recipients(
+                    kind,
+                    change.query_id,
+                    change.user_id,
+                    change.organization_id,
+                    &change.title,
+                    payload,
+                    include_members,
+                )
+                .await
+                {
+                    Ok(_) => {
+                        created_count += 1;
+                        tracing::info!(
+                            "Created notification for query {} (status: {})",
+                            change.query_id,
+                            change.state
+                        );
+                    }
+                    Err(e) => {
+                        tracing::error!(
+                            "Failed to create notification for query {}: {}",
+                            change.query_id,
+                            e
+                        );
+                        continue;
+                    }
                 }
             }
+
+            sqlx::query!(
+                "UPDATE query SET notified_state = $2 WHERE query_id = $1",
+                change.query_id,
+                change.state
+            )
+            .execute(crate::database::get_db())
+            .await?;
         }
 
         if created_count > 0 {
             tracing::info!(
-                "Created {} notifications for completed/failed queries",
+                "Created {} notifications for query status changes",
                 created_count
             );
         }
@@ -174,71 +537,119 @@ impl Notification {
         Ok(created_count)
     }
 
-    /// Send email notification if user has email notifications enabled
-    async fn send_email_notification(
+    /// Warn recipients of organization queries that will soon be deleted under
+    /// their organization's retention period
+    /// Returns the number of queries warned about
+    pub async fn notify_expiring_results() -> Result<usize, sqlx::Error> {
+        let expiring = sqlx::query!(
+            r#"SELECT q.query_id AS "query_id!", q.user_id, q.organization_id, q.title,
+                date(q.created_at, '+' || o.retention_days || ' days') AS "expires_at!: String"
+             FROM query q
+             JOIN organization o ON o.organization_id = q.organization_id
+             WHERE o.retention_days IS NOT NULL
+             AND q.user_visible_status IN ('completed', 'failed')
+             AND q.expiry_notified_at IS NULL
+             AND q.created_at < datetime('now', '-' || MAX(o.retention_days - $1, 0) || ' days')"#,
+            Self::EXPIRY_WARNING_DAYS
+        )
+        .fetch_all(crate::database::get_db())
+        .await?;
+
+        let mut warned_count = 0;
+        for query in expiring {
+            let payload = serde_json::json!({ "expires_at": query.expires_at });
+            Self::notify_query_recipients(
+                NotificationKind::ResultsExpiring,
+                query.query_id,
+                query.user_id,
+                query.organization_id,
+                &query.title,
+                payload,
+                true,
+            )
+            .await?;
+
+            sqlx::query!(
+                "UPDATE query SET expiry_notified_at = CURRENT_TIMESTAMP WHERE query_id = $1",
+                query.query_id
+            )
+            .execute(crate::database::get_db())
+            .await?;
+            warned_count += 1;
+        }
+
+        Ok(warned_count)
+    }
+
+    /// Queue the email for a notification, if the user's address is verified
+    async fn send_email(
         user_id: i64,
-        query_id: i64,
-        title: &str,
-        message: &str,
+        notification: &NewNotification,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         use std::env;
 
-        // Get user information including email preferences
         let user_info = sqlx::query!(
-            "SELECT username, email, email_notifications FROM user WHERE user_id = $1",
+            r#"SELECT username, email, email_verified_at, locale AS "locale: Locale" FROM user WHERE user_id = $1"#,
             user_id
         )
         .fetch_one(crate::database::get_db())
         .await?;
 
-        // Only send email if user has enabled email notifications
-        if !user_info.email_notifications {
+        if user_info.email_verified_at.is_none() {
             return Ok(());
         }
 
-        // Get email configuration from environment
-        let mailer_email = env::var("MAILER_EMAIL")?;
-        let mailer_passwd = env::var("MAILER_PASSWD")?;
-        let smtp_server = env::var("MAILER_SMTP_SERVER")?;
         let site_base_url = env::var("SITE_BASE_URL")?;
 
-        // Build email message
-        let query_url = format!("{}/dashboard/query/{}", site_base_url, query_id);
-
-        let email_body = format!(
-            "{}\n\nView your query details: {}\n\nThis is an automated notification from GLAD.",
-            message, query_url
-        );
-
-        // Extract status from title for cleaner subject line
-        let status = if title == "Query Completed" {
-            "Completed"
-        } else {
-            "Failed"
+        // The templates see the payload, plus a flag named after the kind
+        let mut values: Vec<(String, String)> = match &notification.payload {
+            serde_json::Value::Object(payload) => payload
+                .iter()
+                .map(|(key, value)| {
+                    let value = match value {
+                        serde_json::Value::String(value) => value.clone(),
+                        serde_json::Value::Bool(true) => "yes".to_string(),
+                        serde_json::Value::Null | serde_json::Value::Bool(false) => String::new(),
+                        value => value.to_string(),
+                    };
+                    (key.clone(), value)
+                })
+                .collect(),
+            _ => Vec::new(),
         };
+        values.push((notification.kind.code().to_string(), "yes".to_string()));
+        values.push(("username".to_string(), user_info.username.clone()));
+        values.push(("title".to_string(), notification.title.clone()));
+        values.push(("message".to_string(), notification.message.clone()));
+        values.push(("settings_url".to_string(), format!("{}/settings", site_base_url)));
+        if let Some(("query", query_id)) = notification.target {
+            values.push(("query_url".to_string(), format!("{}/dashboard/query/{}", site_base_url, query_id)));
+        }
 
-        let email_message = mail_send::mail_builder::MessageBuilder::new()
-            .from(("GLAD", mailer_email.as_str()))
-            .to(vec![(
-                user_info.username.as_str(),
-                user_info.email.as_str(),
-            )])
-            .subject(format!("GLAD - Query Status: {}", status))
-            .text_body(email_body);
-
-        // Send email
-        let mut client = mail_send::SmtpClientBuilder::new(smtp_server.as_str(), 587)
-            .implicit_tls(false)
-            .credentials((mailer_email.as_str(), mailer_passwd.as_str()))
-            .connect()
-            .await?;
+        let unsubscribe = NotificationPreference::unsubscribe_links(&user_info.username, notification.kind.code());
+        if let Some(unsubscribe) = &unsubscribe {
+            values.push(("unsubscribe_url".to_string(), unsubscribe.page_url.clone()));
+        }
+
+        let values: Vec<(&str, &str)> = values.iter().map(|(key, value)| (key.as_str(), value.as_str())).collect();
+        let email = notification.kind.email_template().render(user_info.locale, &values);
 
-        client.send(email_message).await?;
+        crate::models::EmailOutbox::enqueue(crate::models::NewEmail {
+            user_id: Some(user_id),
+            kind: notification.kind.code(),
+            recipient_name: &user_info.username,
+            recipient_email: &user_info.email,
+            subject: email.subject,
+            text_body: email.text_body,
+            html_body: Some(email.html_body),
+            unsubscribe_url: unsubscribe.map(|unsubscribe| unsubscribe.one_click_url),
+        })
+        .await?;
 
         tracing::info!(
-            "Email notification sent to user {} for query {}",
+            "Email notification queued for user {} ({})",
             user_info.username,
-            query_id
+            notification.kind.code()
         );
         Ok(())
     }
@@ -0,0 +1,336 @@
+use serde::{Deserialize, Serialize};
+use chrono::{Datelike, Duration, NaiveDateTime, NaiveTime, Utc};
+
+use crate::mail::templates::{escape_html, EmailTemplate};
+use crate::models::{Locale, NotificationPreference};
+
+/// How often a user's digest is sent
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum DigestFrequency {
+    Daily,
+    Weekly,
+}
+
+/// When a user's digest is sent
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DigestSchedule {
+    pub frequency: DigestFrequency,
+    /// `HH:MM` in the user's time zone
+    pub local_time: String,
+    /// Day of weekly digests, 0 for Sunday to 6 for Saturday
+    pub weekday: i64,
+    /// The user's time zone, as minutes ahead of UTC
+    pub utc_offset_minutes: i64,
+    #[serde(skip_deserializing)]
+    pub next_digest_at: Option<String>,
+}
+
+impl Default for DigestSchedule {
+    fn default() -> Self {
+        Self {
+            frequency: DigestFrequency::Daily,
+            local_time: "08:00".to_string(),
+            weekday: 1,
+            utc_offset_minutes: 0,
+            next_digest_at: None,
+        }
+    }
+}
+
+impl DigestSchedule {
+    /// Check the schedule, returning the local time of day
+    pub fn validate(&self) -> Result<NaiveTime, String> {
+        if !(0..=6).contains(&self.weekday) {
+            return Err("Weekday must be between 0 (Sunday) and 6 (Saturday)".to_string());
+        }
+        if !(-840..=840).contains(&self.utc_offset_minutes) {
+            return Err("UTC offset must be between -840 and 840 minutes".to_string());
+        }
+        NaiveTime::parse_from_str(&self.local_time, "%H:%M")
+            .map_err(|_| "Digest time must be in HH:MM format".to_string())
+    }
+
+    /// The first time after `now` (in UTC) the digest is due
+    pub fn next_after(&self, now: NaiveDateTime) -> NaiveDateTime {
+        let time = self.validate().unwrap_or_default();
+        let offset = Duration::minutes(self.utc_offset_minutes);
+        let local_now = now + offset;
+
+        let mut next = local_now.date().and_time(time);
+        let step = match self.frequency {
+            DigestFrequency::Daily => Duration::days(1),
+            DigestFrequency::Weekly => {
+                let today = i64::from(local_now.weekday().num_days_from_sunday());
+                next += Duration::days((self.weekday - today).rem_euclid(7));
+                Duration::weeks(1)
+            }
+        };
+        if next <= local_now {
+            next += step;
+        }
+
+        next - offset
+    }
+
+    /// A user's schedule, or the default one if they have not set it
+    pub async fn for_user(user_id: i64) -> Result<Self, sqlx::Error> {
+        let schedule = sqlx::query!(
+            r#"SELECT frequency AS "frequency: DigestFrequency", local_time, weekday, utc_offset_minutes, next_digest_at
+             FROM notification_digest_schedule WHERE user_id = $1"#,
+            user_id
+        )
+        .map(|row| Self {
+            frequency: row.frequency,
+            local_time: row.local_time,
+            weekday: row.weekday,
+            utc_offset_minutes: row.utc_offset_minutes,
+            next_digest_at: Some(row.next_digest_at.to_string()),
+        })
+        .fetch_optional(crate::database::get_db())
+        .await?;
+
+        Ok(schedule.unwrap_or_default())
+    }
+
+    /// Set a user's schedule and when their next digest is due
+    pub async fn set(user_id: i64, schedule: &Self) -> Result<(), sqlx::Error> {
+        let next_digest_at = schedule.next_after(Utc::now().naive_utc());
+        sqlx::query!(
+            "INSERT INTO notification_digest_schedule (user_id, frequency, local_time, weekday, utc_offset_minutes, next_digest_at)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (user_id) DO UPDATE SET frequency = excluded.frequency, local_time = excluded.local_time,
+                 weekday = excluded.weekday, utc_offset_minutes = excluded.utc_offset_minutes,
+                 next_digest_at = excluded.next_digest_at",
+            user_id,
+            schedule.frequency,
+            schedule.local_time,
+            schedule.weekday,
+            schedule.utc_offset_minutes,
+            next_digest_at
+        )
+        .execute(crate::database::get_db())
+        .await?;
+        Ok(())
+    }
+
+    /// Give a user the default schedule if they have none, so digests start
+    /// as soon as a kind is moved to the digest channel
+    pub async fn ensure(user_id: i64) -> Result<(), sqlx::Error> {
+        let exists = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM notification_digest_schedule WHERE user_id = $1",
+            user_id
+        )
+        .fetch_one(crate::database::get_db())
+        .await?;
+
+        if exists == 0 {
+            Self::set(user_id, &Self::default()).await?;
+        }
+        Ok(())
+    }
+}
+
+/// A notification claimed for a digest
+struct DigestItem {
+    title: String,
+    message: String,
+}
+
+/// Summary emails of the notifications on the digest channel
+pub struct NotificationDigest;
+
+impl NotificationDigest {
+    /// Most notifications listed in one digest; the rest wait for the next one
+    pub const MAX_ITEMS: i64 = 100;
+
+    /// Send the digests that are due
+    /// Returns the number of digests sent
+    pub async fn send_due() -> Result<usize, sqlx::Error> {
+        let due = sqlx::query!(
+            r#"SELECT s.user_id AS "user_id!", s.frequency AS "frequency: DigestFrequency", s.local_time, s.weekday, s.utc_offset_minutes
+             FROM notification_digest_schedule s
+             JOIN user u ON u.user_id = s.user_id
+             WHERE s.next_digest_at <= CURRENT_TIMESTAMP AND u.disabled_at IS NULL"#
+        )
+        .map(|row| {
+            (
+                row.user_id,
+                DigestSchedule {
+                    frequency: row.frequency,
+                    local_time: row.local_time,
+                    weekday: row.weekday,
+                    utc_offset_minutes: row.utc_offset_minutes,
+                    next_digest_at: None,
+                },
+            )
+        })
+        .fetch_all(crate::database::get_db())
+        .await?;
+
+        let mut sent_count = 0;
+        for (user_id, schedule) in due {
+            match Self::send(user_id, schedule.frequency).await {
+                Ok(true) => sent_count += 1,
+                Ok(false) => {}
+                Err(e) => {
+                    tracing::error!("Failed to send notification digest to user {}: {}", user_id, e);
+                    continue;
+                }
+            }
+
+            let next_digest_at = schedule.next_after(Utc::now().naive_utc());
+            sqlx::query!(
+                "UPDATE notification_digest_schedule SET next_digest_at = $2 WHERE user_id = $1",
+                user_id,
+                next_digest_at
+            )
+            .execute(crate::database::get_db())
+            .await?;
+        }
+
+        Ok(sent_count)
+    }
+
+    /// Claim a user's unread notifications on the digest channel and email them
+    /// Returns false if there was nothing to send
+    async fn send(
+        user_id: i64,
+        frequency: DigestFrequency,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let user_info = sqlx::query!(
+            r#"SELECT username, email, email_verified_at, locale AS "locale: Locale" FROM user WHERE user_id = $1"#,
+            user_id
+        )
+        .fetch_one(crate::database::get_db())
+        .await?;
+
+        if user_info.email_verified_at.is_none() {
+            return Ok(false);
+        }
+
+        // Claiming sets digest_id in one statement, so a notification is only
+        // ever part of one digest
+        let mut tx = crate::database::get_db().begin().await?;
+        let digest_id = sqlx::query!(
+            "INSERT INTO notification_digest (user_id, notification_count) VALUES ($1, 0)",
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?
+        .last_insert_rowid();
+
+        let items = sqlx::query_as!(
+            DigestItem,
+            r#"UPDATE notifications SET digest_id = $2
+             WHERE notification_id IN (
+                 SELECT notification_id FROM notifications
+                 WHERE user_id = $1 AND is_read = FALSE AND digest_id IS NULL
+                 AND kind IN (SELECT kind FROM notification_preference WHERE user_id = $1 AND channel = 'digest')
+                 ORDER BY notification_id LIMIT $3)
+             RETURNING title, message"#,
+            user_id,
+            digest_id,
+            Self::MAX_ITEMS
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        if items.is_empty() {
+            tx.rollback().await?;
+            return Ok(false);
+        }
+
+        let count = items.len() as i64;
+        sqlx::query!(
+            "UPDATE notification_digest SET notification_count = $2 WHERE digest_id = $1",
+            digest_id,
+            count
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        let site_base_url = std::env::var("SITE_BASE_URL").unwrap_or_default();
+        let items_text = items
+            .iter()
+            .map(|item| format!("* {}: {}", item.title, item.message))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let items_html = items
+            .iter()
+            .map(|item| {
+                format!(
+                    "<li><strong>{}</strong>: {}</li>",
+                    escape_html(&item.title),
+                    escape_html(&item.message)
+                )
+            })
+            .collect::<String>();
+        let count_text = count.to_string();
+        let settings_url = format!("{}/settings", site_base_url);
+        let dashboard_url = format!("{}/dashboard", site_base_url);
+        let frequency_flag = match frequency {
+            DigestFrequency::Daily => "daily",
+            DigestFrequency::Weekly => "weekly",
+        };
+        let unsubscribe =
+            NotificationPreference::unsubscribe_links(&user_info.username, NotificationPreference::DIGEST_CATEGORY);
+        let unsubscribe_page_url = unsubscribe
+            .as_ref()
+            .map(|unsubscribe| unsubscribe.page_url.clone())
+            .unwrap_or_default();
+        let email = EmailTemplate::NotificationDigest.render(
+            user_info.locale,
+            &[
+                ("username", &user_info.username),
+                ("count", &count_text),
+                (frequency_flag, "yes"),
+                ("items_text", &items_text),
+                ("items_html", &items_html),
+                ("dashboard_url", &dashboard_url),
+                ("settings_url", &settings_url),
+                ("unsubscribe_url", &unsubscribe_page_url),
+            ],
+        );
+
+        let queued = crate::models::EmailOutbox::enqueue(crate::models::NewEmail {
+            user_id: Some(user_id),
+            kind: NotificationPreference::DIGEST_CATEGORY,
+            recipient_name: &user_info.username,
+            recipient_email: &user_info.email,
+            subject: email.subject,
+            text_body: email.text_body,
+            html_body: Some(email.html_body),
+            unsubscribe_url: unsubscribe.map(|unsubscribe| unsubscribe.one_click_url),
+        })
+        .await;
+
+        match queued {
+            Ok(email_id) => {
+                sqlx::query!(
+                    "UPDATE notification_digest SET email_id = $2 WHERE digest_id = $1",
+                    digest_id,
+                    email_id
+                )
+                .execute(crate::database::get_db())
+                .await?;
+            }
+            Err(e) => {
+                // Release the notifications for the next digest
+                sqlx::query!("DELETE FROM notification_digest WHERE digest_id = $1", digest_id)
+                    .execute(crate::database::get_db())
+                    .await?;
+                return Err(e.into());
+            }
+        }
+
+        tracing::info!(
+            "Notification digest of {} notifications queued for user {}",
+            count,
+            user_info.username
+        );
+        Ok(true)
+    }
+}
@@ -0,0 +1,157 @@
+use serde::{Deserialize, Serialize};
+
+use crate::auth::jwt::{encode_token, TokenClaims, TokenPurpose};
+use crate::models::NotificationKind;
+
+/// How a user hears about a kind of notification
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum NotificationChannel {
+    /// Only in the notification panel
+    InApp,
+    /// In the notification panel and by email
+    Email,
+    /// In the notification panel and in the user's daily or weekly digest email
+    Digest,
+    /// Not at all
+    None,
+}
+
+/// A user's channel for one kind of notification
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+pub struct NotificationPreference {
+    pub kind: NotificationKind,
+    pub channel: NotificationChannel,
+}
+
+/// Links in a notification email to stop that kind of email
+#[derive(Debug, Clone)]
+pub struct UnsubscribeLinks {
+    /// Page asking the user to confirm, for the link in the email's body
+    pub page_url: String,
+    /// Endpoint mail clients `POST` to, for the `List-Unsubscribe` header
+    pub one_click_url: String,
+}
+
+impl NotificationPreference {
+    /// Days the unsubscribe links in an email stay valid
+    pub const UNSUBSCRIBE_LINK_DAYS: i64 = 60;
+    /// Unsubscribe category of digest emails; other emails use their notification kind
+    pub const DIGEST_CATEGORY: &'static str = "notification_digest";
+
+    /// Signed links that stop one category of email for a user
+    /// Returns None if `SITE_BASE_URL` is not set
+    pub fn unsubscribe_links(username: &str, category: &str) -> Option<UnsubscribeLinks> {
+        let site_base_url = std::env::var("SITE_BASE_URL").ok()?;
+        let lifetime = Self::UNSUBSCRIBE_LINK_DAYS * 24 * 60 * 60;
+        let token = encode_token(TokenClaims {
+            sub: username.to_string(),
+            exp: (sqlx::types::chrono::Utc::now().timestamp() + lifetime) as usize,
+            purpose: TokenPurpose::Unsubscribe,
+            sid: None,
+            jti: None,
+            email: None,
+            category: Some(category.to_string()),
+        })
+        .inspect_err(|e| tracing::error!("Failed to sign unsubscribe link: {}", e))
+        .ok()?;
+
+        Some(UnsubscribeLinks {
+            page_url: format!("{}/unsubscribe?token={}", site_base_url, token),
+            one_click_url: format!("{}/api/notifications/unsubscribe?token={}", site_base_url, token),
+        })
+    }
+
+    /// Stop emailing a user one category of notification, keeping it in the
+    /// notification panel
+    /// Returns false if the category is unknown
+    pub async fn unsubscribe(user_id: i64, category: &str) -> Result<bool, sqlx::Error> {
+        if category == Self::DIGEST_CATEGORY {
+            sqlx::query!(
+                "UPDATE notification_preference SET channel = 'in_app' WHERE user_id = $1 AND channel = 'digest'",
+                user_id
+            )
+            .execute(crate::database::get_db())
+            .await?;
+            return Ok(true);
+        }
+
+        let Some(kind) = NotificationKind::ALL.into_iter().find(|kind| kind.code() == category) else {
+            return Ok(false);
+        };
+        if Self::channel(user_id, kind).await? == NotificationChannel::Email {
+            Self::set(
+                user_id,
+                &[Self {
+                    kind,
+                    channel: NotificationChannel::InApp,
+                }],
+            )
+            .await?;
+        }
+        Ok(true)
+    }
+
+    /// The channel a user chose for a kind, or the kind's default
+    pub async fn channel(user_id: i64, kind: NotificationKind) -> Result<NotificationChannel, sqlx::Error> {
+        let channel = sqlx::query_scalar!(
+            r#"SELECT channel AS "channel: NotificationChannel" FROM notification_preference
+             WHERE user_id = $1 AND kind = $2"#,
+            user_id,
+            kind
+        )
+        .fetch_optional(crate::database::get_db())
+        .await?;
+
+        Ok(channel.unwrap_or(kind.default_channel()))
+    }
+
+    /// A user's channel for every kind, with defaults for those not chosen
+    pub async fn for_user(user_id: i64) -> Result<Vec<Self>, sqlx::Error> {
+        let chosen = sqlx::query!(
+            r#"SELECT kind AS "kind: NotificationKind", channel AS "channel: NotificationChannel"
+             FROM notification_preference WHERE user_id = $1"#,
+            user_id
+        )
+        .map(|row| Self {
+            kind: row.kind,
+            channel: row.channel,
+        })
+        .fetch_all(crate::database::get_db())
+        .await?;
+
+        Ok(NotificationKind::ALL
+            .iter()
+            .map(|kind| {
+                chosen
+                    .iter()
+                    .find(|preference| preference.kind == *kind)
+                    .copied()
+                    .unwrap_or(Self {
+                        kind: *kind,
+                        channel: kind.default_channel(),
+                    })
+            })
+            .collect())
+    }
+
+    /// Choose the channels for some kinds, leaving the others as they are
+    pub async fn set(user_id: i64, preferences: &[Self]) -> Result<(), sqlx::Error> {
+        let mut tx = crate::database::get_db().begin().await?;
+
+        for preference in preferences {
+            sqlx::query!(
+                "INSERT INTO notification_preference (user_id, kind, channel) VALUES ($1, $2, $3)
+                 ON CONFLICT (user_id, kind) DO UPDATE SET channel = excluded.channel",
+                user_id,
+                preference.kind,
+                preference.channel
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await
+    }
+}
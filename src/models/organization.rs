@@ -0,0 +1,422 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::DatabaseError;
+
+/// Role of a user within an organization, ordered from least to most privileged
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum OrganizationRole {
+    Viewer,
+    Member,
+    Owner,
+}
+
+impl OrganizationRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrganizationRole::Viewer => "viewer",
+            OrganizationRole::Member => "member",
+            OrganizationRole::Owner => "owner",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Self> {
+        match role {
+            "viewer" => Some(OrganizationRole::Viewer),
+            "member" => Some(OrganizationRole::Member),
+            "owner" => Some(OrganizationRole::Owner),
+            _ => None,
+        }
+    }
+
+    /// Members and owners can submit queries to the organization workspace
+    pub fn can_submit(&self) -> bool {
+        *self >= OrganizationRole::Member
+    }
+
+    /// Only owners can change settings, invite and manage members
+    pub fn can_manage(&self) -> bool {
+        *self == OrganizationRole::Owner
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Organization {
+    pub organization_id: i64,
+    pub name: String,
+    pub query_quota: Option<i64>,
+    pub retention_days: Option<i64>,
+    pub created_at: String,
+}
+
+/// An organization together with the requesting user's role in it
+#[derive(Serialize, Clone, Debug)]
+pub struct Membership {
+    #[serde(flatten)]
+    pub organization: Organization,
+    pub role: OrganizationRole,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct OrganizationMember {
+    pub user_id: i64,
+    pub username: String,
+    pub email: String,
+    pub role: OrganizationRole,
+    pub created_at: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct OrganizationInvitation {
+    pub invitation_id: i64,
+    pub organization_id: i64,
+    pub organization_name: String,
+    pub email: String,
+    pub role: OrganizationRole,
+    pub created_at: String,
+}
+
+fn parse_role(role: &str) -> OrganizationRole {
+    // The CHECK constraint on the role columns guarantees a known value
+    OrganizationRole::parse(role).unwrap_or(OrganizationRole::Viewer)
+}
+
+impl Organization {
+    /// Number of days over which an organization's query quota is counted
+    pub const QUOTA_WINDOW_DAYS: i64 = 30;
+
+    pub fn validate_name(name: &str) -> Result<(), DatabaseError> {
+        let length = name.trim().len();
+        if !(3..=100).contains(&length) {
+            return Err(DatabaseError::InvalidOrganizationName);
+        }
+        Ok(())
+    }
+
+    /// Create an organization and make the creating user its owner
+    pub async fn create(name: &str, owner_user_id: i64) -> Result<i64, DatabaseError> {
+        Self::validate_name(name)?;
+        let name = name.trim();
+
+        let mut tx = crate::database::get_db().begin().await?;
+
+        let organization_id = sqlx::query!("INSERT INTO organization (name) VALUES ($1)", name)
+            .execute(&mut *tx)
+            .await?
+            .last_insert_rowid();
+
+        sqlx::query!(
+            "INSERT INTO organization_member (organization_id, user_id, role) VALUES ($1, $2, 'owner')",
+            organization_id,
+            owner_user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(organization_id)
+    }
+
+    pub async fn get(organization_id: i64) -> Result<Self, DatabaseError> {
+        sqlx::query!(
+            "SELECT organization_id, name, query_quota, retention_days, created_at FROM organization WHERE organization_id = $1",
+            organization_id
+        )
+        .map(|row| Self {
+            organization_id: row.organization_id,
+            name: row.name,
+            query_quota: row.query_quota,
+            retention_days: row.retention_days,
+            created_at: row.created_at.to_string(),
+        })
+        .fetch_one(crate::database::get_db())
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => DatabaseError::OrganizationNotFound,
+            _ => DatabaseError::from(e),
+        })
+    }
+
+    /// Get all organizations a user belongs to, with the user's role in each
+    pub async fn for_user(user_id: i64) -> Result<Vec<Membership>, sqlx::Error> {
+        sqlx::query!(
+            "SELECT o.organization_id AS \"organization_id!\", o.name, o.query_quota, o.retention_days, o.created_at, m.role
+             FROM organization o
+             JOIN organization_member m ON m.organization_id = o.organization_id
+             WHERE m.user_id = $1
+             ORDER BY o.name",
+            user_id
+        )
+        .map(|row| Membership {
+            organization: Self {
+                organization_id: row.organization_id,
+                name: row.name,
+                query_quota: row.query_quota,
+                retention_days: row.retention_days,
+                created_at: row.created_at.to_string(),
+            },
+            role: parse_role(&row.role),
+        })
+        .fetch_all(crate::database::get_db())
+        .await
+    }
+
+    /// Get the role of a user in an organization, or None if they are not a member
+    pub async fn role_for_user(
+        organization_id: i64,
+        user_id: i64,
+    ) -> Result<Option<OrganizationRole>, sqlx::Error> {
+        let role = sqlx::query_scalar!(
+            "SELECT role FROM organization_member WHERE organization_id = $1 AND user_id = $2",
+            organization_id,
+            user_id
+        )
+        .fetch_optional(crate::database::get_db())
+        .await?;
+
+        Ok(role.as_deref().map(parse_role))
+    }
+
+    pub async fn update_settings(&self) -> Result<(), DatabaseError> {
+        Self::validate_name(&self.name)?;
+        if matches!(self.query_quota, Some(quota) if quota < 0)
+            || matches!(self.retention_days, Some(days) if days <= 0)
+        {
+            return Err(DatabaseError::InvalidOrganizationSettings);
+        }
+
+        let name = self.name.trim();
+        sqlx::query!(
+            "UPDATE organization SET name = $2, query_quota = $3, retention_days = $4 WHERE organization_id = $1",
+            self.organization_id,
+            name,
+            self.query_quota,
+            self.retention_days
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn members(organization_id: i64) -> Result<Vec<OrganizationMember>, sqlx::Error> {
+        sqlx::query!(
+            "SELECT u.user_id AS \"user_id!\", u.username, u.email, m.role, m.created_at
+             FROM organization_member m
+             JOIN user u ON u.user_id = m.user_id
+             WHERE m.organization_id = $1
+             ORDER BY u.username",
+            organization_id
+        )
+        .map(|row| OrganizationMember {
+            user_id: row.user_id,
+            username: row.username,
+            email: row.email,
+            role: parse_role(&row.role),
+            created_at: row.created_at.to_string(),
+        })
+        .fetch_all(crate::database::get_db())
+        .await
+    }
+
+    async fn owner_count(organization_id: i64) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM organization_member WHERE organization_id = $1 AND role = 'owner'",
+            organization_id
+        )
+        .fetch_one(crate::database::get_db())
+        .await
+    }
+
+    /// Change a member's role, refusing to demote the last remaining owner
+    pub async fn set_member_role(
+        organization_id: i64,
+        user_id: i64,
+        role: OrganizationRole,
+    ) -> Result<(), DatabaseError> {
+        let current = Self::role_for_user(organization_id, user_id)
+            .await?
+            .ok_or(DatabaseError::MemberNotFound)?;
+
+        if current == OrganizationRole::Owner
+            && role != OrganizationRole::Owner
+            && Self::owner_count(organization_id).await? <= 1
+        {
+            return Err(DatabaseError::LastOrganizationOwner);
+        }
+
+        let role = role.as_str();
+        sqlx::query!(
+            "UPDATE organization_member SET role = $3 WHERE organization_id = $1 AND user_id = $2",
+            organization_id,
+            user_id,
+            role
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Remove a member, refusing to remove the last remaining owner
+    pub async fn remove_member(organization_id: i64, user_id: i64) -> Result<(), DatabaseError> {
+        let current = Self::role_for_user(organization_id, user_id)
+            .await?
+            .ok_or(DatabaseError::MemberNotFound)?;
+
+        if current == OrganizationRole::Owner && Self::owner_count(organization_id).await? <= 1 {
+            return Err(DatabaseError::LastOrganizationOwner);
+        }
+
+        sqlx::query!(
+            "DELETE FROM organization_member WHERE organization_id = $1 AND user_id = $2",
+            organization_id,
+            user_id
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Count the queries submitted to an organization within the quota window
+    pub async fn recent_query_count(organization_id: i64) -> Result<i64, sqlx::Error> {
+        let window = format!("-{} days", Self::QUOTA_WINDOW_DAYS);
+        sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM query WHERE organization_id = $1 AND created_at >= datetime('now', $2)",
+            organization_id,
+            window
+        )
+        .fetch_one(crate::database::get_db())
+        .await
+    }
+
+    /// Invite an email address to join an organization with the given role
+    pub async fn invite(
+        organization_id: i64,
+        email: &str,
+        role: OrganizationRole,
+        invited_by: i64,
+    ) -> Result<i64, DatabaseError> {
+        let role = role.as_str();
+        let result = sqlx::query!(
+            "INSERT INTO organization_invitation (organization_id, email, role, invited_by) VALUES ($1, $2, $3, $4)",
+            organization_id,
+            email,
+            role,
+            invited_by
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    /// Get pending invitations addressed to an email
+    pub async fn pending_invitations(
+        email: &str,
+    ) -> Result<Vec<OrganizationInvitation>, sqlx::Error> {
+        sqlx::query!(
+            "SELECT i.invitation_id AS \"invitation_id!\", i.organization_id, o.name AS organization_name, i.email, i.role, i.created_at
+             FROM organization_invitation i
+             JOIN organization o ON o.organization_id = i.organization_id
+             WHERE i.email = $1 AND i.accepted_at IS NULL
+             ORDER BY i.created_at DESC",
+            email
+        )
+        .map(|row| OrganizationInvitation {
+            invitation_id: row.invitation_id,
+            organization_id: row.organization_id,
+            organization_name: row.organization_name,
+            email: row.email,
+            role: parse_role(&row.role),
+            created_at: row.created_at.to_string(),
+        })
+        .fetch_all(crate::database::get_db())
+        .await
+    }
+
+    /// Accept a pending invitation addressed to the user's email
+    /// Only a verified address can claim an invitation
+    /// Returns the organization the user joined
+    pub async fn accept_invitation(invitation_id: i64, user_id: i64) -> Result<i64, DatabaseError> {
+        let mut tx = crate::database::get_db().begin().await?;
+
+        let invitation = sqlx::query!(
+            "SELECT i.organization_id, i.role FROM organization_invitation i
+             JOIN user u ON u.email = i.email
+             WHERE i.invitation_id = $1 AND u.user_id = $2
+             AND u.email_verified_at IS NOT NULL AND i.accepted_at IS NULL",
+            invitation_id,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(DatabaseError::InvitationNotFound)?;
+
+        // Keep the higher role if the user is already a member
+        sqlx::query!(
+            "INSERT INTO organization_member (organization_id, user_id, role) VALUES ($1, $2, $3)
+             ON CONFLICT (organization_id, user_id) DO UPDATE SET role = CASE
+                WHEN organization_member.role = 'owner' OR excluded.role = 'owner' THEN 'owner'
+                WHEN organization_member.role = 'member' OR excluded.role = 'member' THEN 'member'
+                ELSE 'viewer'
+             END",
+            invitation.organization_id,
+            user_id,
+            invitation.role
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "UPDATE organization_invitation SET accepted_at = CURRENT_TIMESTAMP WHERE invitation_id = $1",
+            invitation_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(invitation.organization_id)
+    }
+
+    /// Queue an invitation email to the invited address
+    pub async fn send_invitation_email(
+        organization_name: &str,
+        email: &str,
+        invited_by: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        use std::env;
+
+        let site_base_url = env::var("SITE_BASE_URL")?;
+
+        let settings_url = format!("{}/settings", site_base_url);
+
+        // Invitees who already have an account get the invitation in their language
+        let locale = crate::models::User::locale_for_email(email).await?;
+        let rendered = crate::mail::templates::EmailTemplate::OrganizationInvitation.render(
+            locale,
+            &[
+                ("invited_by", invited_by),
+                ("organization_name", organization_name),
+                ("settings_url", &settings_url),
+            ],
+        );
+
+        crate::models::EmailOutbox::enqueue(crate::models::NewEmail {
+            user_id: None,
+            kind: "organization_invitation",
+            recipient_name: "User",
+            recipient_email: email,
+            subject: rendered.subject,
+            text_body: rendered.text_body,
+            html_body: Some(rendered.html_body),
+            unsubscribe_url: None,
+        })
+        .await?;
+
+        Ok(())
+    }
+}
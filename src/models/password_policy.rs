@@ -0,0 +1,482 @@
+use std::collections::HashSet;
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::models::BreachedPasswords;
+
+/// Common passwords and words, also used to spot dictionary words inside a password
+static COMMON_PASSWORDS: &str = include_str!("../../data/password_data/common_passwords.txt");
+
+/// Alphabetic entries of the common password list, lowercased
+static DICTIONARY: Lazy<HashSet<&'static str>> = Lazy::new(|| {
+    COMMON_PASSWORDS
+        .lines()
+        .map(str::trim)
+        .filter(|word| word.len() >= DICTIONARY_MIN_LENGTH && word.chars().all(|c| c.is_ascii_lowercase()))
+        .collect()
+});
+
+const DICTIONARY_MIN_LENGTH: usize = 4;
+const DICTIONARY_MAX_LENGTH: usize = 20;
+
+/// Keyboard rows and columns, walked in either direction
+const KEYBOARD_PATTERNS: &[&str] = &[
+    "`1234567890-=",
+    "qwertyuiop[]\\",
+    "asdfghjkl;'",
+    "zxcvbnm,./",
+    "1qaz2wsx3edc4rfv5tgb6yhn7ujm8ik,9ol.0p;/",
+    "qwertzuiop",
+    "azertyuiop",
+];
+
+/// Shortest run counted as a keyboard pattern, sequence or repeat
+const KEYBOARD_MIN_LENGTH: usize = 4;
+const SEQUENCE_MIN_LENGTH: usize = 3;
+const REPEAT_MIN_LENGTH: usize = 3;
+
+/// Password rules applied wherever a password is chosen
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    /// Estimated bits of entropy a password needs
+    pub min_entropy_bits: f64,
+    /// Reject passwords found in the breached-password filter
+    pub reject_breached: bool,
+}
+
+/// Result of checking a password against the policy
+#[derive(Debug, Clone, Serialize)]
+pub struct PasswordCheck {
+    pub entropy_bits: f64,
+    /// What to change, empty if the password is acceptable
+    pub feedback: Vec<String>,
+}
+
+impl PasswordCheck {
+    pub fn is_acceptable(&self) -> bool {
+        self.feedback.is_empty()
+    }
+}
+
+/// A recognised pattern covering part of a password
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PatternKind {
+    UserInput,
+    Dictionary,
+    Keyboard,
+    Sequence,
+    Repeat,
+    Year,
+}
+
+impl PatternKind {
+    fn feedback(self) -> &'static str {
+        match self {
+            PatternKind::UserInput => "Do not use your username or email address in your password.",
+            PatternKind::Dictionary => "Avoid common words and passwords, even with letters swapped for numbers or symbols.",
+            PatternKind::Keyboard => "Avoid keyboard patterns like qwerty or asdf.",
+            PatternKind::Sequence => "Avoid sequences like abc or 1234.",
+            PatternKind::Repeat => "Avoid repeated characters like aaa or 111.",
+            PatternKind::Year => "Avoid years and dates, which are easy to guess.",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PatternMatch {
+    kind: PatternKind,
+    start: usize,
+    end: usize,
+    bits: f64,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            min_entropy_bits: 30.0,
+            reject_breached: true,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Load the policy from `PASSWORD_MIN_LENGTH`, `PASSWORD_MIN_ENTROPY_BITS` and
+    /// `PASSWORD_REJECT_BREACHED`, falling back to the defaults
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            min_length: std::env::var("PASSWORD_MIN_LENGTH")
+                .ok()
+                .and_then(|length| length.parse().ok())
+                .unwrap_or(default.min_length)
+                .max(1),
+            min_entropy_bits: std::env::var("PASSWORD_MIN_ENTROPY_BITS")
+                .ok()
+                .and_then(|bits| bits.parse().ok())
+                .unwrap_or(default.min_entropy_bits),
+            reject_breached: std::env::var("PASSWORD_REJECT_BREACHED")
+                .map(|value| value != "false" && value != "0")
+                .unwrap_or(default.reject_breached),
+        }
+    }
+
+    /// Check a password, given things about the user an attacker would try first
+    pub fn check(&self, password: &str, user_inputs: &[&str]) -> PasswordCheck {
+        let estimate = estimate_entropy(password, user_inputs);
+        let mut feedback = Vec::new();
+
+        let length = password.chars().count();
+        if length < self.min_length {
+            feedback.push(format!("Use at least {} characters.", self.min_length));
+        }
+
+        if self.reject_breached && BreachedPasswords::is_breached(password) {
+            feedback.push(
+                "This password has appeared in a data breach. Choose one you have not used before."
+                    .to_string(),
+            );
+        } else if estimate.bits < self.min_entropy_bits {
+            let mut kinds = Vec::new();
+            for kind in estimate.patterns {
+                if !kinds.contains(&kind) {
+                    kinds.push(kind);
+                    feedback.push(kind.feedback().to_string());
+                }
+            }
+            feedback.push(
+                "Make it harder to guess: add another uncommon word or a few more characters."
+                    .to_string(),
+            );
+        }
+
+        PasswordCheck {
+            entropy_bits: (estimate.bits * 10.0).round() / 10.0,
+            feedback,
+        }
+    }
+}
+
+struct Estimate {
+    bits: f64,
+    patterns: Vec<PatternKind>,
+}
+
+/// Estimate how many guesses a password takes, in bits
+///
+/// Guessable parts (dictionary words, keyboard walks, sequences, repeats, years and
+/// the user's own name) are costed as the attacker would enumerate them; the rest
+/// costs the size of the character pool per character.
+fn estimate_entropy(password: &str, user_inputs: &[&str]) -> Estimate {
+    let chars: Vec<char> = password.chars().collect();
+    let lower: Vec<char> = chars.iter().flat_map(|c| c.to_lowercase()).collect();
+    // Lowercasing can change the length of some characters; skip patterns then
+    let lower = if lower.len() == chars.len() { lower } else { chars.clone() };
+    let unleet: Vec<char> = lower.iter().map(|&c| unleet(c)).collect();
+
+    let mut candidates = Vec::new();
+    find_user_inputs(&unleet, user_inputs, &mut candidates);
+    find_dictionary_words(&chars, &lower, &unleet, &mut candidates);
+    find_keyboard_patterns(&lower, &mut candidates);
+    find_sequences(&lower, &mut candidates);
+    find_repeats(&chars, &mut candidates);
+    find_years(&chars, &mut candidates);
+
+    // Prefer the longest patterns, then the cheapest
+    candidates.sort_by(|a, b| {
+        (b.end - b.start)
+            .cmp(&(a.end - a.start))
+            .then(a.bits.total_cmp(&b.bits))
+    });
+
+    let mut covered = vec![false; chars.len()];
+    let mut bits = 0.0;
+    let mut patterns = Vec::new();
+    for candidate in candidates {
+        if covered[candidate.start..candidate.end].iter().any(|&c| c) {
+            continue;
+        }
+        covered[candidate.start..candidate.end].fill(true);
+        bits += candidate.bits;
+        patterns.push(candidate.kind);
+    }
+
+    let pool = pool_size(&chars);
+    let uncovered = covered.iter().filter(|&&c| !c).count();
+    bits += uncovered as f64 * pool.log2();
+
+    Estimate { bits, patterns }
+}
+
+/// Number of characters an attacker would try per position
+fn pool_size(chars: &[char]) -> f64 {
+    let mut pool = 0.0;
+    if chars.iter().any(|c| c.is_ascii_lowercase()) {
+        pool += 26.0;
+    }
+    if chars.iter().any(|c| c.is_ascii_uppercase()) {
+        pool += 26.0;
+    }
+    if chars.iter().any(|c| c.is_ascii_digit()) {
+        pool += 10.0;
+    }
+    if chars.iter().any(|c| c.is_ascii() && !c.is_ascii_alphanumeric()) {
+        pool += 33.0;
+    }
+    if chars.iter().any(|c| !c.is_ascii()) {
+        pool += 100.0;
+    }
+    f64::max(pool, 2.0)
+}
+
+/// Undo common letter substitutions such as `p@ssw0rd`
+fn unleet(c: char) -> char {
+    match c {
+        '@' | '4' => 'a',
+        '8' => 'b',
+        '(' => 'c',
+        '3' => 'e',
+        '6' | '9' => 'g',
+        '1' | '!' | '|' => 'i',
+        '0' => 'o',
+        '$' | '5' => 's',
+        '7' | '+' => 't',
+        '2' => 'z',
+        _ => c,
+    }
+}
+
+fn find_all(haystack: &[char], needle: &[char]) -> Vec<usize> {
+    if needle.is_empty() || needle.len() > haystack.len() {
+        return Vec::new();
+    }
+    (0..=haystack.len() - needle.len())
+        .filter(|&start| haystack[start..start + needle.len()] == *needle)
+        .collect()
+}
+
+fn find_user_inputs(password: &[char], user_inputs: &[&str], candidates: &mut Vec<PatternMatch>) {
+    for input in user_inputs {
+        // Only the local part of an email address is likely to be reused
+        let input = input.split('@').next().unwrap_or_default().to_lowercase();
+        // Along with the whole input, look for its words, e.g. `alice` in `alice_smith1`
+        let parts = std::iter::once(input.as_str())
+            .chain(input.split(|c: char| !c.is_alphabetic()))
+            .filter(|part| part.chars().count() >= 3);
+
+        for part in parts {
+            let part: Vec<char> = part.chars().map(unleet).collect();
+            for start in find_all(password, &part) {
+                candidates.push(PatternMatch {
+                    kind: PatternKind::UserInput,
+                    start,
+                    end: start + part.len(),
+                    bits: 1.0,
+                });
+            }
+        }
+    }
+}
+
+fn find_dictionary_words(
+    chars: &[char],
+    lower: &[char],
+    unleet: &[char],
+    candidates: &mut Vec<PatternMatch>,
+) {
+    let dictionary_bits = (DICTIONARY.len().max(2) as f64).log2();
+    for start in 0..unleet.len() {
+        for end in start + DICTIONARY_MIN_LENGTH..=unleet.len().min(start + DICTIONARY_MAX_LENGTH) {
+            let word: String = unleet[start..end].iter().collect();
+            if !DICTIONARY.contains(word.as_str()) {
+                continue;
+            }
+            let mut bits = dictionary_bits;
+            if chars[start..end].iter().any(|c| c.is_uppercase()) {
+                bits += 1.0;
+            }
+            if lower[start..end] != unleet[start..end] {
+                bits += 1.0;
+            }
+            candidates.push(PatternMatch {
+                kind: PatternKind::Dictionary,
+                start,
+                end,
+                bits,
+            });
+        }
+    }
+}
+
+fn find_keyboard_patterns(lower: &[char], candidates: &mut Vec<PatternMatch>) {
+    let rows: Vec<Vec<char>> = KEYBOARD_PATTERNS
+        .iter()
+        .flat_map(|row| [row.chars().collect(), row.chars().rev().collect()])
+        .collect();
+    let starting_positions: usize = rows.iter().map(Vec::len).sum();
+
+    let mut start = 0;
+    while start < lower.len() {
+        // Longest walk from here along any row
+        let mut longest = 0;
+        for row in &rows {
+            for offset in find_all(row, &lower[start..start + 1]) {
+                let length = lower[start..]
+                    .iter()
+                    .zip(&row[offset..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                longest = longest.max(length);
+            }
+        }
+        if longest >= KEYBOARD_MIN_LENGTH {
+            candidates.push(PatternMatch {
+                kind: PatternKind::Keyboard,
+                start,
+                end: start + longest,
+                bits: (starting_positions as f64).log2() + (longest as f64).log2(),
+            });
+            start += longest;
+        } else {
+            start += 1;
+        }
+    }
+}
+
+fn find_sequences(lower: &[char], candidates: &mut Vec<PatternMatch>) {
+    let mut start = 0;
+    while start + 1 < lower.len() {
+        let step = lower[start + 1] as i64 - lower[start] as i64;
+        let mut end = start + 1;
+        if step == 1 || step == -1 {
+            while end < lower.len() && lower[end] as i64 - lower[end - 1] as i64 == step {
+                end += 1;
+            }
+        }
+        let length = end - start;
+        if length >= SEQUENCE_MIN_LENGTH {
+            let alphabet: f64 = if lower[start].is_ascii_digit() { 10.0 } else { 26.0 };
+            candidates.push(PatternMatch {
+                kind: PatternKind::Sequence,
+                start,
+                end,
+                bits: alphabet.log2() + 1.0 + (length as f64).log2(),
+            });
+            start = end;
+        } else {
+            start += 1;
+        }
+    }
+}
+
+fn find_repeats(chars: &[char], candidates: &mut Vec<PatternMatch>) {
+    let mut start = 0;
+    while start < chars.len() {
+        let end = start + chars[start..].iter().take_while(|&&c| c == chars[start]).count();
+        let length = end - start;
+        if length >= REPEAT_MIN_LENGTH {
+            candidates.push(PatternMatch {
+                kind: PatternKind::Repeat,
+                start,
+                end,
+                bits: pool_size(&chars[start..start + 1]).log2() + (length as f64).log2(),
+            });
+        }
+        start = end;
+    }
+}
+
+fn find_years(chars: &[char], candidates: &mut Vec<PatternMatch>) {
+    for start in 0..chars.len().saturating_sub(3) {
+        let digits: String = chars[start..start + 4].iter().collect();
+        let is_year = digits.chars().all(|c| c.is_ascii_digit())
+            && (digits.starts_with("19") || digits.starts_with("20"));
+        if is_year {
+            candidates.push(PatternMatch {
+                kind: PatternKind::Year,
+                start,
+                end: start + 4,
+                bits: 200f64.log2(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BREACHED: &str = "This password has appeared in a data breach. Choose one you have not used before.";
+    const USER_INPUTS: &[&str] = &["alicealice", "alice@example.org"];
+
+    fn without_breach_check() -> PasswordPolicy {
+        PasswordPolicy {
+            reject_breached: false,
+            ..PasswordPolicy::default()
+        }
+    }
+
+    #[test]
+    fn breached_passwords_are_refused() {
+        for password in ["password1", "qwertyuiop", "P@ssw0rd"] {
+            let check = PasswordPolicy::default().check(password, USER_INPUTS);
+            assert_eq!(check.feedback, vec![BREACHED.to_string()], "{}", password);
+        }
+    }
+
+    #[test]
+    fn weak_passwords_get_feedback_on_their_patterns() {
+        let policy = without_breach_check();
+        let feedback = |password| policy.check(password, USER_INPUTS).feedback;
+        let dictionary = PatternKind::Dictionary.feedback().to_string();
+
+        // Also on the common password list, which is checked before keyboard walks
+        for password in ["password1", "qwertyuiop", "P@ssw0rd"] {
+            assert!(feedback(password).contains(&dictionary), "{}", password);
+        }
+        let username_and_year = feedback("alicealice2024");
+        assert!(username_and_year.contains(&PatternKind::UserInput.feedback().to_string()));
+        assert!(username_and_year.contains(&PatternKind::Year.feedback().to_string()));
+        assert!(feedback("zxcvbnm,./").contains(&PatternKind::Keyboard.feedback().to_string()));
+    }
+
+    #[test]
+    fn long_random_passphrases_pass() {
+        for password in ["Orchid lantern basalt rowing", "tKv7-Wq2p-Lm9x", "glad-mango-violin-42"] {
+            let check = PasswordPolicy::default().check(password, USER_INPUTS);
+            assert!(check.is_acceptable(), "{}: {:?}", password, check.feedback);
+        }
+    }
+
+    #[test]
+    fn thresholds_come_from_the_environment() {
+        std::env::set_var("PASSWORD_MIN_LENGTH", "24");
+        std::env::set_var("PASSWORD_MIN_ENTROPY_BITS", "150");
+        std::env::set_var("PASSWORD_REJECT_BREACHED", "false");
+        let policy = PasswordPolicy::from_env();
+        for name in ["PASSWORD_MIN_LENGTH", "PASSWORD_MIN_ENTROPY_BITS", "PASSWORD_REJECT_BREACHED"] {
+            std::env::remove_var(name);
+        }
+
+        assert_eq!(policy.min_length, 24);
+        assert_eq!(policy.min_entropy_bits, 150.0);
+        assert!(!policy.reject_breached);
+        // Fine by default, but now too short and too easy to guess
+        let check = policy.check("glad-mango-violin-42", USER_INPUTS);
+        assert_eq!(check.feedback[0], "Use at least 24 characters.");
+        assert!(check.feedback.len() > 1);
+        assert!(policy.check("Orchid lantern basalt rowing", USER_INPUTS).is_acceptable());
+        // Breached passwords only get pattern feedback
+        assert!(!policy.check("password1", USER_INPUTS).feedback.contains(&BREACHED.to_string()));
+
+        // Unset or invalid values fall back to the defaults
+        std::env::set_var("PASSWORD_MIN_LENGTH", "many");
+        let policy = PasswordPolicy::from_env();
+        std::env::remove_var("PASSWORD_MIN_LENGTH");
+        assert_eq!(policy.min_length, 8);
+        assert_eq!(policy.min_entropy_bits, 30.0);
+        assert!(policy.reject_breached);
+    }
+}
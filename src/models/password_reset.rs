@@ -0,0 +1,140 @@
+use crate::auth::secret::{generate_secret, hash_secret};
+use crate::models::user::hash_password;
+use crate::models::DatabaseError;
+
+/// Single-use password reset tokens
+///
+/// Each token is tied to the user's password hash when it was requested, so it
+/// stops working as soon as the password changes, whether through the reset
+/// itself or from the settings page.
+pub struct PasswordReset;
+
+impl PasswordReset {
+    /// How long a reset link stays valid
+    pub const DURATION_SECONDS: i64 = 3600;
+
+    /// Start a password reset for a user, replacing any earlier unused requests
+    /// Returns the secret to embed in the reset link
+    pub async fn create(user_id: i64) -> Result<String, DatabaseError> {
+        let secret = generate_secret();
+        let token_hash = hash_secret(&secret);
+        let lifetime = format!("+{} seconds", Self::DURATION_SECONDS);
+
+        let mut tx = crate::database::get_db().begin().await?;
+
+        let password = sqlx::query_scalar!("SELECT password FROM user WHERE user_id = $1", user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        let password_fingerprint = hash_secret(&password);
+
+        sqlx::query!(
+            "DELETE FROM password_reset WHERE user_id = $1 AND used_at IS NULL",
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "INSERT INTO password_reset (user_id, token_hash, password_fingerprint, expires_at)
+             VALUES ($1, $2, $3, datetime('now', $4))",
+            user_id,
+            token_hash,
+            password_fingerprint,
+            lifetime
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(secret)
+    }
+
+    /// The username a reset token was issued for, if it can still be used
+    /// Leaves the token unused, so the link keeps working if the new password is refused
+    pub async fn username_for(secret: &str) -> Result<String, DatabaseError> {
+        let token_hash = hash_secret(secret);
+
+        let reset = sqlx::query!(
+            r#"SELECT r.password_fingerprint, u.username, u.password
+               FROM password_reset r JOIN user u ON u.user_id = r.user_id
+               WHERE r.token_hash = $1 AND r.used_at IS NULL AND r.expires_at > CURRENT_TIMESTAMP"#,
+            token_hash
+        )
+        .fetch_optional(crate::database::get_db())
+        .await?
+        .ok_or(DatabaseError::InvalidResetToken)?;
+
+        if reset.password_fingerprint != hash_secret(&reset.password) {
+            return Err(DatabaseError::InvalidResetToken);
+        }
+        Ok(reset.username)
+    }
+
+    /// Use up a reset token and set the new password, in one transaction
+    /// Check the password against the policy first. Fails if the token was used,
+    /// or the password changed, since it was looked up
+    pub async fn complete(secret: &str, password: &str) -> Result<(), DatabaseError> {
+        let token_hash = hash_secret(secret);
+        let password_hash = hash_password(password.to_string())
+            .await
+            .expect("Failed to hash password");
+
+        let mut tx = crate::database::get_db().begin().await?;
+
+        let reset = sqlx::query!(
+            r#"SELECT r.password_reset_id AS "password_reset_id!", r.password_fingerprint,
+                 u.user_id AS "user_id!", u.password
+               FROM password_reset r JOIN user u ON u.user_id = r.user_id
+               WHERE r.token_hash = $1 AND r.used_at IS NULL AND r.expires_at > CURRENT_TIMESTAMP"#,
+            token_hash
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(DatabaseError::InvalidResetToken)?;
+
+        if reset.password_fingerprint != hash_secret(&reset.password) {
+            return Err(DatabaseError::InvalidResetToken);
+        }
+
+        let result = sqlx::query!(
+            "UPDATE password_reset SET used_at = CURRENT_TIMESTAMP
+             WHERE password_reset_id = $1 AND used_at IS NULL",
+            reset.password_reset_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(DatabaseError::InvalidResetToken);
+        }
+
+        sqlx::query!(
+            "UPDATE user SET password = $2 WHERE user_id = $1",
+            reset.user_id,
+            password_hash
+        )
+        .execute(&mut *tx)
+        .await?;
+        // Other links requested before the change must not work afterwards
+        sqlx::query!(
+            "DELETE FROM password_reset WHERE user_id = $1 AND used_at IS NULL",
+            reset.user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Void every outstanding reset token of a user
+    pub async fn invalidate_for_user(user_id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "DELETE FROM password_reset WHERE user_id = $1 AND used_at IS NULL",
+            user_id
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        Ok(())
+    }
+}
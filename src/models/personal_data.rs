@@ -0,0 +1,177 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::models::{
+    ApiToken, DigestSchedule, ExternalIdentity, NotificationPreference, Organization, Session, TwoFactor, User,
+    Webhook,
+};
+
+/// Everything stored about a user, gathered for a data export
+pub struct PersonalData {
+    pub profile: serde_json::Value,
+    pub queries: serde_json::Value,
+    pub notifications: serde_json::Value,
+    /// Files to include, by their name in the archive
+    pub files: Vec<(String, PathBuf)>,
+}
+
+impl PersonalData {
+    pub async fn collect(username: &str) -> Result<Self, crate::models::DatabaseError> {
+        let user = User::get(username.to_string()).await?;
+        let user_id = user.user_id();
+        let db = crate::database::get_db();
+
+        let account = sqlx::query!(
+            "SELECT created_at, email_verified_at, deletion_requested_at FROM user WHERE user_id = $1",
+            user_id
+        )
+        .fetch_one(db)
+        .await?;
+        let agreements = sqlx::query!(
+            "SELECT version, accepted_at FROM data_use_acceptance WHERE user_id = $1 ORDER BY version",
+            user_id
+        )
+        .map(|row| serde_json::json!({ "version": row.version, "accepted_at": row.accepted_at.to_string() }))
+        .fetch_all(db)
+        .await?;
+
+        let profile = serde_json::json!({
+            "username": user.username(),
+            "email": user.email(),
+            "pending_email": user.pending_email(),
+            "bio": user.bio(),
+            "notification_preferences": NotificationPreference::for_user(user_id).await?,
+            "notification_digest": DigestSchedule::for_user(user_id).await?,
+            "locale": user.locale(),
+            "role": user.role(),
+            "status": user.status(),
+            "created_at": account.created_at.to_string(),
+            "email_verified_at": account.email_verified_at.map(|date| date.to_string()),
+            "deletion_requested_at": account.deletion_requested_at.map(|date| date.to_string()),
+            "data_use_agreements": agreements,
+            "two_factor_enabled": TwoFactor::is_enabled(user_id).await?,
+            "external_identities": ExternalIdentity::for_user(user_id).await?,
+            "organizations": Organization::for_user(user_id).await?,
+            "sessions": Session::for_user(user_id).await?,
+            "api_tokens": ApiToken::for_user(user_id).await?,
+            "webhooks": Webhook::for_user(user_id)
+                .await?
+                .into_iter()
+                .filter(|webhook| webhook.user_id == Some(user_id))
+                .collect::<Vec<_>>(),
+        });
+
+        let query_rows = sqlx::query!(
+            r#"SELECT query_id AS "query_id!", organization_id, title, description, file_path, self_described_latino,
+                n_controls, created_at, user_visible_status, status_updated_at, result_file_path,
+                (SELECT GROUP_CONCAT(c.cohort_name, ',') FROM query_cohort qc JOIN cohort c ON c.cohort_id = qc.cohort_id
+                 WHERE qc.query_id = query.query_id) AS "excluded_cohorts: String"
+             FROM query WHERE user_id = $1 ORDER BY created_at"#,
+            user_id
+        )
+        .fetch_all(db)
+        .await?;
+
+        let query_root = std::env::var("QUERY_PATH_ROOT").ok().map(PathBuf::from);
+        let mut files = Vec::new();
+        let mut queries = Vec::new();
+        for row in query_rows {
+            if let Some(query_root) = &query_root {
+                let query_dir = query_root.join(user_id.to_string()).join(row.query_id.to_string());
+                collect_files(&query_dir, &format!("queries/{}", row.query_id), &mut files);
+
+                // Results may be written outside the query's own directory
+                if let Some(result_file_path) = &row.result_file_path {
+                    let result_path = query_root.join(result_file_path);
+                    if !result_path.starts_with(&query_dir) && result_path.is_file() {
+                        let name = result_path.file_name().unwrap_or_default().to_string_lossy();
+                        files.push((format!("queries/{}/results/{}", row.query_id, name), result_path));
+                    }
+                }
+            }
+
+            queries.push(serde_json::json!({
+                "query_id": row.query_id,
+                "organization_id": row.organization_id,
+                "title": row.title,
+                "description": row.description,
+                "self_described_latino": row.self_described_latino != 0,
+                "n_controls": row.n_controls,
+                "excluded_cohorts": row.excluded_cohorts
+                    .map(|cohorts| cohorts.split(',').map(str::to_string).collect::<Vec<_>>())
+                    .unwrap_or_default(),
+                "status": row.user_visible_status,
+                "created_at": row.created_at.to_string(),
+                "status_updated_at": row.status_updated_at.to_string(),
+            }));
+        }
+
+        let notifications = sqlx::query!(
+            "SELECT query_id, title, message, is_read, created_at FROM notifications WHERE user_id = $1 ORDER BY created_at",
+            user_id
+        )
+        .map(|row| {
+            serde_json::json!({
+                "query_id": row.query_id,
+                "title": row.title,
+                "message": row.message,
+                "is_read": row.is_read,
+                "created_at": row.created_at.to_string(),
+            })
+        })
+        .fetch_all(db)
+        .await?;
+
+        Ok(Self {
+            profile,
+            queries: serde_json::Value::Array(queries),
+            notifications: serde_json::Value::Array(notifications),
+            files,
+        })
+    }
+
+    /// Write the export as a ZIP archive
+    /// Reads the files from disk, so run it off the async runtime
+    pub fn to_zip(&self) -> zip::result::ZipResult<Vec<u8>> {
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        let mut archive = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+
+        for (name, value) in [
+            ("profile.json", &self.profile),
+            ("queries.json", &self.queries),
+            ("notifications.json", &self.notifications),
+        ] {
+            archive.start_file(name, options)?;
+            archive.write_all(&serde_json::to_vec_pretty(value).unwrap_or_default())?;
+        }
+
+        for (name, path) in &self.files {
+            match std::fs::read(path) {
+                Ok(contents) => {
+                    archive.start_file(name.as_str(), options)?;
+                    archive.write_all(&contents)?;
+                }
+                Err(e) => tracing::error!("Failed to read {} for export: {}", path.display(), e),
+            }
+        }
+
+        Ok(archive.finish()?.into_inner())
+    }
+}
+
+/// Add the files in a directory and its subdirectories under `prefix`
+fn collect_files(dir: &Path, prefix: &str, files: &mut Vec<(String, PathBuf)>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+        if path.is_dir() {
+            collect_files(&path, &name, files);
+        } else {
+            files.push((name, path));
+        }
+    }
+}
@@ -16,12 +16,26 @@ impl Cohort {
             .fetch_all(crate::database::get_db())
             .await
     }
+
+    pub async fn insert(cohort_name: &str) -> Result<Self, sqlx::Error> {
+        let cohort_id = sqlx::query!("INSERT INTO cohort (cohort_name) VALUES ($1)", cohort_name)
+            .execute(crate::database::get_db())
+            .await?
+            .last_insert_rowid();
+
+        Ok(Self {
+            cohort_id,
+            cohort_name: cohort_name.to_string(),
+        })
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Default, Debug)]
 pub struct Query {
     pub query_id: i64,
     pub user_id: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub organization_id: Option<i64>,
     pub title: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
@@ -33,8 +47,10 @@ pub struct Query {
 }
 
 impl Query {
+    #[allow(clippy::too_many_arguments)]
     pub async fn insert(
-        username: String,
+        user_id: i64,
+        organization_id: Option<i64>,
         title: String,
         description: Option<String>,
         self_described_latino: bool,
@@ -44,12 +60,6 @@ impl Query {
     ) -> Result<i64, sqlx::Error> {
         let self_described_latino = self_described_latino as i32;
         let n_controls = n_controls as i32;
-        let logged_user_id =
-            sqlx::query!("SELECT user_id FROM user WHERE username=$1", username)
-                .map(|x| x.user_id)
-                .fetch_one(crate::database::get_db())
-                .await
-                .expect(&format!("Could not retrieve user_id using username {}", username));
         let excluded_cohort_ids: Vec<i32> = if excluded_cohorts.is_empty() {
             Vec::new()
         } else {
@@ -68,8 +78,9 @@ impl Query {
                 .expect("Could not retrieve cohort ids")
         };
         let query_id = sqlx::query!(
-            "INSERT INTO query(user_id, title, description, file_path, self_described_latino, n_controls) VALUES ($1, $2, $3, $4, $5, $6)",
-            logged_user_id,
+            "INSERT INTO query(user_id, organization_id, title, description, file_path, self_described_latino, n_controls) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            user_id,
+            organization_id,
             title,
             description,
             file_path,
@@ -98,26 +109,21 @@ impl Query {
         Ok(query_id)
     }
 
-    pub async fn for_user_profile(username: String) -> Result<Vec<Self>, sqlx::Error> {
-        let logged_user_id =
-            sqlx::query!("SELECT user_id FROM user WHERE username=$1", username)
-                .map(|x| x.user_id)
-                .fetch_one(crate::database::get_db())
-                .await
-                .expect(&format!("Could not retrieve user_id using username {}", username));
+    /// Get the queries a user submitted or can see through their organizations
+    pub async fn for_user_profile(user_id: i64) -> Result<Vec<Self>, sqlx::Error> {
         sqlx::query!(
-            "SELECT query_id, user_id, title, description, self_described_latino, n_controls, user_visible_status, created_at, status_updated_at FROM query WHERE user_id=$1 ORDER BY created_at DESC",
-            logged_user_id,
+            "SELECT query_id, user_id, organization_id, title, description, self_described_latino, n_controls, user_visible_status, created_at, status_updated_at FROM query
+             WHERE user_id=$1 OR organization_id IN (SELECT organization_id FROM organization_member WHERE user_id=$1)
+             ORDER BY created_at DESC",
+            user_id,
         )
         .map(|x| Self {
             query_id: x.query_id,
             user_id: x.user_id,
+            organization_id: x.organization_id,
             title: x.title,
             description: x.description,
-            self_described_latino: match x.self_described_latino {
-                    0 => false,
-                    _ => true,
-                },
+            self_described_latino: x.self_described_latino != 0,
             n_controls: x.n_controls as usize,
             status: x.user_visible_status,
             created_at: x.created_at.to_string(),
@@ -129,18 +135,16 @@ impl Query {
 
     pub async fn for_query(query_id: i64) -> Result<Self, sqlx::Error> {
         sqlx::query!(
-            "SELECT query_id, user_id, title, description, self_described_latino, n_controls, user_visible_status, created_at, status_updated_at FROM query WHERE query_id=$1",
+            "SELECT query_id, user_id, organization_id, title, description, self_described_latino, n_controls, user_visible_status, created_at, status_updated_at FROM query WHERE query_id=$1",
             query_id,
         )
         .map(|x| Self {
             query_id: x.query_id,
             user_id: x.user_id,
+            organization_id: x.organization_id,
             title: x.title,
             description: x.description,
-            self_described_latino: match x.self_described_latino {
-                    0 => false,
-                    _ => true,
-                },
+            self_described_latino: x.self_described_latino != 0,
             n_controls: x.n_controls as usize,
             status: x.user_visible_status,
             created_at: x.created_at.to_string(),
@@ -150,9 +154,114 @@ impl Query {
         .await
     }
 
+    /// Get all queries, optionally filtered by user-visible status, most recent first
+    pub async fn all(status: Option<String>) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query!(
+            "SELECT query_id, user_id, organization_id, title, description, self_described_latino, n_controls, user_visible_status, created_at, status_updated_at FROM query
+             WHERE $1 IS NULL OR user_visible_status = $1
+             ORDER BY created_at DESC",
+            status,
+        )
+        .map(|x| Self {
+            query_id: x.query_id,
+            user_id: x.user_id,
+            organization_id: x.organization_id,
+            title: x.title,
+            description: x.description,
+            self_described_latino: x.self_described_latino != 0,
+            n_controls: x.n_controls as usize,
+            status: x.user_visible_status,
+            created_at: x.created_at.to_string(),
+            status_updated_at: x.status_updated_at.to_string(),
+        })
+        .fetch_all(crate::database::get_db())
+        .await
+    }
+
     pub async fn delete(query_id: i64) -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
         sqlx::query!("DELETE FROM query WHERE query_id=$1", query_id)
             .execute(crate::database::get_db())
             .await
     }
+
+    /// Reset a finished query so the matching pipeline picks it up again
+    /// Existing notifications are removed so the user is notified of the new outcome
+    /// Returns false if the query is still pending or processing
+    pub async fn requeue(query_id: i64) -> Result<bool, sqlx::Error> {
+        let mut tx = crate::database::get_db().begin().await?;
+
+        let result = sqlx::query!(
+            "UPDATE query SET user_visible_status = 'pending', internal_status = 'pending',
+                status_updated_at = CURRENT_TIMESTAMP, retry_count = 0,
+                last_error_message = NULL, result_file_path = NULL, notified_state = NULL, expiry_notified_at = NULL
+             WHERE query_id = $1 AND internal_status IN ('retry_pending', 'failed_permanent', 'completed')",
+            query_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        sqlx::query!("DELETE FROM notifications WHERE query_id = $1", query_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Check whether a user can view a query, either as its submitter
+    /// or as a member of the organization that owns it
+    pub async fn is_accessible_by(&self, user_id: i64) -> Result<bool, sqlx::Error> {
+        if self.user_id == user_id {
+            return Ok(true);
+        }
+        match self.organization_id {
+            Some(organization_id) => Ok(
+                crate::models::Organization::role_for_user(organization_id, user_id)
+                    .await?
+                    .is_some(),
+            ),
+            None => Ok(false),
+        }
+    }
+
+    /// Delete organization queries older than their organization's retention period,
+    /// together with their uploaded and result files
+    /// Returns the number of queries removed
+    pub async fn purge_expired() -> Result<usize, sqlx::Error> {
+        let expired = sqlx::query!(
+            "SELECT q.query_id, q.user_id FROM query q
+             JOIN organization o ON o.organization_id = q.organization_id
+             WHERE o.retention_days IS NOT NULL
+             AND q.user_visible_status IN ('completed', 'failed')
+             AND q.created_at < datetime('now', '-' || o.retention_days || ' days')"
+        )
+        .fetch_all(crate::database::get_db())
+        .await?;
+
+        let query_root = std::env::var("QUERY_PATH_ROOT").ok();
+        let mut purged_count = 0;
+
+        for query in expired {
+            if let Some(query_root) = &query_root {
+                let query_dir = std::path::PathBuf::from(query_root)
+                    .join(query.user_id.to_string())
+                    .join(query.query_id.to_string());
+                if let Err(e) = tokio::fs::remove_dir_all(&query_dir).await {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        tracing::error!("Failed to remove {}: {}", query_dir.display(), e);
+                        continue;
+                    }
+                }
+            }
+            Self::delete(query.query_id).await?;
+            purged_count += 1;
+        }
+
+        Ok(purged_count)
+    }
 }
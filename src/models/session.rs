@@ -0,0 +1,227 @@
+use serde::Serialize;
+
+use crate::auth::secret::{generate_secret, hash_secret};
+use crate::models::DatabaseError;
+
+/// A login on one device, kept alive by a rotating refresh token
+#[derive(Serialize, Clone, Debug)]
+pub struct Session {
+    pub session_id: String,
+    pub user_agent: String,
+    pub ip_address: String,
+    pub created_at: String,
+    pub last_used_at: String,
+    pub expires_at: String,
+}
+
+/// Refresh tokens are `<session_id>.<secret>`; only a hash of the secret is stored
+fn split_refresh_token(refresh_token: &str) -> Option<(&str, &str)> {
+    refresh_token
+        .split_once('.')
+        .filter(|(session_id, secret)| !session_id.is_empty() && !secret.is_empty())
+}
+
+impl Session {
+    /// How long a session lasts before the user has to log in again
+    pub const DURATION_DAYS: i64 = 30;
+
+    /// Start a new session for a user
+    /// Returns the session id and the refresh token to hand to the client
+    pub async fn create(
+        user_id: i64,
+        user_agent: &str,
+        ip_address: &str,
+    ) -> Result<(String, String), sqlx::Error> {
+        let session_id = uuid::Uuid::new_v4().to_string();
+        let secret = generate_secret();
+        let secret_hash = hash_secret(&secret);
+        let lifetime = format!("+{} days", Self::DURATION_DAYS);
+
+        sqlx::query!(
+            "INSERT INTO session (session_id, user_id, refresh_token_hash, user_agent, ip_address, expires_at)
+             VALUES ($1, $2, $3, $4, $5, datetime('now', $6))",
+            session_id,
+            user_id,
+            secret_hash,
+            user_agent,
+            ip_address,
+            lifetime
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        let refresh_token = format!("{}.{}", session_id, secret);
+        Ok((session_id, refresh_token))
+    }
+
+    /// Exchange a refresh token for a new one, invalidating the old token
+    /// Returns the session id, the user id and the new refresh token
+    ///
+    /// Presenting a token that was already rotated means it was copied, so the
+    /// whole session is revoked.
+    pub async fn rotate(
+        refresh_token: &str,
+        ip_address: &str,
+    ) -> Result<(String, i64, String), DatabaseError> {
+        let (session_id, secret) =
+            split_refresh_token(refresh_token).ok_or(DatabaseError::InvalidSession)?;
+
+        let session = sqlx::query!(
+            "SELECT user_id, refresh_token_hash FROM session
+             WHERE session_id = $1 AND revoked_at IS NULL AND expires_at > CURRENT_TIMESTAMP",
+            session_id
+        )
+        .fetch_optional(crate::database::get_db())
+        .await?
+        .ok_or(DatabaseError::InvalidSession)?;
+
+        if session.refresh_token_hash != hash_secret(secret) {
+            tracing::warn!(
+                "Refresh token reuse detected for session {}, revoking",
+                session_id
+            );
+            Self::revoke(session_id, session.user_id).await?;
+            return Err(DatabaseError::InvalidSession);
+        }
+
+        let new_secret = generate_secret();
+        let new_secret_hash = hash_secret(&new_secret);
+        let old_secret_hash = hash_secret(secret);
+
+        // Compare against the old hash so concurrent refreshes cannot both succeed
+        let result = sqlx::query!(
+            "UPDATE session SET refresh_token_hash = $2, last_used_at = CURRENT_TIMESTAMP, ip_address = $4
+             WHERE session_id = $1 AND refresh_token_hash = $3 AND revoked_at IS NULL",
+            session_id,
+            new_secret_hash,
+            old_secret_hash,
+            ip_address
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(DatabaseError::InvalidSession);
+        }
+
+        Ok((
+            session_id.to_string(),
+            session.user_id,
+            format!("{}.{}", session_id, new_secret),
+        ))
+    }
+
+    /// Revoke the session a refresh token belongs to, if the token is current
+    pub async fn revoke_by_refresh_token(refresh_token: &str) -> Result<bool, sqlx::Error> {
+        let Some((session_id, secret)) = split_refresh_token(refresh_token) else {
+            return Ok(false);
+        };
+        let secret_hash = hash_secret(secret);
+
+        let result = sqlx::query!(
+            "UPDATE session SET revoked_at = CURRENT_TIMESTAMP
+             WHERE session_id = $1 AND refresh_token_hash = $2 AND revoked_at IS NULL",
+            session_id,
+            secret_hash
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Get the active sessions of a user, most recently used first
+    pub async fn for_user(user_id: i64) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query!(
+            "SELECT session_id, user_agent, ip_address, created_at, last_used_at, expires_at FROM session
+             WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+             ORDER BY last_used_at DESC",
+            user_id
+        )
+        .map(|row| Self {
+            session_id: row.session_id,
+            user_agent: row.user_agent,
+            ip_address: row.ip_address,
+            created_at: row.created_at.to_string(),
+            last_used_at: row.last_used_at.to_string(),
+            expires_at: row.expires_at.to_string(),
+        })
+        .fetch_all(crate::database::get_db())
+        .await
+    }
+
+    /// Revoke one of a user's sessions. Returns false if no active session matched
+    pub async fn revoke(session_id: &str, user_id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "UPDATE session SET revoked_at = CURRENT_TIMESTAMP
+             WHERE session_id = $1 AND user_id = $2 AND revoked_at IS NULL",
+            session_id,
+            user_id
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Whether a user's session is active and was started by logging in within the last `seconds`
+    /// Refreshing a session does not count, only a new login
+    pub async fn started_within(session_id: &str, user_id: i64, seconds: i64) -> Result<bool, sqlx::Error> {
+        let window = format!("-{} seconds", seconds);
+        let session = sqlx::query_scalar!(
+            "SELECT session_id FROM session
+             WHERE session_id = $1 AND user_id = $2 AND revoked_at IS NULL
+               AND created_at > datetime('now', $3)",
+            session_id,
+            user_id,
+            window
+        )
+        .fetch_optional(crate::database::get_db())
+        .await?;
+        Ok(session.is_some())
+    }
+
+    /// Revoke every active session of a user
+    pub async fn revoke_all_for_user(user_id: i64) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            "UPDATE session SET revoked_at = CURRENT_TIMESTAMP WHERE user_id = $1 AND revoked_at IS NULL",
+            user_id
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::User;
+
+    #[tokio::test]
+    async fn replaying_a_rotated_token_revokes_the_session() {
+        crate::database::test_db().await;
+        let (user_id, _) = User::create_external("rotation-test", "rotation-test@example.org")
+            .await
+            .unwrap();
+        let (session_id, first_token) = Session::create(user_id, "test", "192.0.2.10").await.unwrap();
+
+        let (rotated_session_id, rotated_user_id, second_token) =
+            Session::rotate(&first_token, "192.0.2.10").await.unwrap();
+        assert_eq!(rotated_session_id, session_id);
+        assert_eq!(rotated_user_id, user_id);
+        assert_ne!(second_token, first_token);
+
+        // The old token was copied somewhere: refuse it and end the session
+        assert!(matches!(
+            Session::rotate(&first_token, "198.51.100.20").await,
+            Err(DatabaseError::InvalidSession)
+        ));
+        assert!(Session::for_user(user_id).await.unwrap().is_empty());
+        assert!(matches!(
+            Session::rotate(&second_token, "192.0.2.10").await,
+            Err(DatabaseError::InvalidSession)
+        ));
+    }
+}
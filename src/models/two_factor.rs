@@ -0,0 +1,373 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Serialize;
+use sha1::Sha1;
+
+use crate::auth::secret::hash_secret;
+use crate::models::{DatabaseError, Role};
+
+/// Length in bytes of TOTP shared secrets, as recommended by RFC 4226
+const SECRET_BYTES: usize = 20;
+const PERIOD_SECONDS: i64 = 30;
+const DIGITS: u32 = 6;
+/// Time steps of clock drift accepted on either side of the current one
+const ALLOWED_DRIFT: i64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+const ISSUER: &str = "GLAD";
+
+/// A new TOTP secret, shown once so it can be added to an authenticator app
+#[derive(Serialize, Clone, Debug)]
+pub struct TwoFactorEnrollment {
+    pub secret: String,
+    /// `otpauth://` URI to render as a QR code
+    pub provisioning_uri: String,
+}
+
+/// The two-factor state of an account
+#[derive(Serialize, Clone, Debug)]
+pub struct TwoFactorStatus {
+    pub enabled: bool,
+    /// Whether the user's role requires two-factor authentication
+    pub required: bool,
+    pub recovery_codes_remaining: i64,
+}
+
+/// HOTP value for a counter (RFC 4226, section 5.3)
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    binary % 10_u32.pow(DIGITS)
+}
+
+/// The time step a code was generated for, if it is valid around `now` (RFC 6238)
+fn matching_step(secret: &str, code: &str, now: i64) -> Option<i64> {
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let secret = data_encoding::BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+
+    let current = now / PERIOD_SECONDS;
+    (current - ALLOWED_DRIFT..=current + ALLOWED_DRIFT).find(|step| hotp(&secret, *step as u64) == code)
+}
+
+/// Recovery codes are compared without separators or case
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .collect::<String>()
+        .to_lowercase()
+}
+
+fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; 5];
+    OsRng.fill_bytes(&mut bytes);
+    let code = hex::encode(bytes);
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+fn now() -> i64 {
+    sqlx::types::chrono::Utc::now().timestamp()
+}
+
+/// TOTP second factor with single-use recovery codes
+pub struct TwoFactor;
+
+impl TwoFactor {
+    /// Whether a user has confirmed two-factor authentication
+    pub async fn is_enabled(user_id: i64) -> Result<bool, sqlx::Error> {
+        let enabled = sqlx::query_scalar!(
+            "SELECT 1 FROM user_totp WHERE user_id = $1 AND confirmed_at IS NOT NULL",
+            user_id
+        )
+        .fetch_optional(crate::database::get_db())
+        .await?;
+
+        Ok(enabled.is_some())
+    }
+
+    pub async fn status(user_id: i64, role: Role) -> Result<TwoFactorStatus, sqlx::Error> {
+        let recovery_codes_remaining = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!: i64" FROM totp_recovery_code WHERE user_id = $1 AND used_at IS NULL"#,
+            user_id
+        )
+        .fetch_one(crate::database::get_db())
+        .await?;
+
+        Ok(TwoFactorStatus {
+            enabled: Self::is_enabled(user_id).await?,
+            required: Self::is_required_for(role).await?,
+            recovery_codes_remaining,
+        })
+    }
+
+    /// Generate a new secret for a user, replacing any unconfirmed one
+    pub async fn begin_enrollment(
+        user_id: i64,
+        username: &str,
+    ) -> Result<TwoFactorEnrollment, DatabaseError> {
+        if Self::is_enabled(user_id).await? {
+            return Err(DatabaseError::TwoFactorAlreadyEnabled);
+        }
+
+        let mut bytes = [0u8; SECRET_BYTES];
+        OsRng.fill_bytes(&mut bytes);
+        let secret = data_encoding::BASE32_NOPAD.encode(&bytes);
+
+        sqlx::query!(
+            "INSERT OR REPLACE INTO user_totp (user_id, secret) VALUES ($1, $2)",
+            user_id,
+            secret
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        let label = utf8_percent_encode(&format!("{}:{}", ISSUER, username), NON_ALPHANUMERIC).to_string();
+        let provisioning_uri = format!(
+            "otpauth://totp/{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+            label, secret, ISSUER, DIGITS, PERIOD_SECONDS
+        );
+
+        Ok(TwoFactorEnrollment {
+            secret,
+            provisioning_uri,
+        })
+    }
+
+    /// Turn on two-factor authentication once the user enters a valid code
+    /// Returns the recovery codes, which are only shown this once
+    pub async fn confirm_enrollment(user_id: i64, code: &str) -> Result<Vec<String>, DatabaseError> {
+        let mut tx = crate::database::get_db().begin().await?;
+
+        let secret = sqlx::query_scalar!(
+            "SELECT secret FROM user_totp WHERE user_id = $1 AND confirmed_at IS NULL",
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(DatabaseError::TwoFactorNotPending)?;
+
+        let step = matching_step(&secret, code.trim(), now())
+            .ok_or(DatabaseError::InvalidTwoFactorCode)?;
+
+        sqlx::query!(
+            "UPDATE user_totp SET confirmed_at = CURRENT_TIMESTAMP, last_used_step = $1 WHERE user_id = $2",
+            step,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let codes = Self::replace_recovery_codes(&mut tx, user_id).await?;
+
+        tx.commit().await?;
+        Ok(codes)
+    }
+
+    async fn replace_recovery_codes(
+        tx: &mut sqlx::SqliteConnection,
+        user_id: i64,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query!("DELETE FROM totp_recovery_code WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let codes: Vec<String> = (0..RECOVERY_CODE_COUNT).map(|_| generate_recovery_code()).collect();
+        for code in &codes {
+            let code_hash = hash_secret(&normalize_recovery_code(code));
+            sqlx::query!(
+                "INSERT INTO totp_recovery_code (user_id, code_hash) VALUES ($1, $2)",
+                user_id,
+                code_hash
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        Ok(codes)
+    }
+
+    /// Replace a user's recovery codes with a fresh set
+    pub async fn regenerate_recovery_codes(user_id: i64) -> Result<Vec<String>, DatabaseError> {
+        if !Self::is_enabled(user_id).await? {
+            return Err(DatabaseError::TwoFactorNotEnabled);
+        }
+
+        let mut tx = crate::database::get_db().begin().await?;
+        let codes = Self::replace_recovery_codes(&mut tx, user_id).await?;
+        tx.commit().await?;
+
+        Ok(codes)
+    }
+
+    /// Check a TOTP code or use up a recovery code
+    /// Each TOTP code is accepted only once
+    pub async fn verify(user_id: i64, code: &str) -> Result<bool, sqlx::Error> {
+        let code = code.trim();
+
+        let Some(secret) = sqlx::query_scalar!(
+            "SELECT secret FROM user_totp WHERE user_id = $1 AND confirmed_at IS NOT NULL",
+            user_id
+        )
+        .fetch_optional(crate::database::get_db())
+        .await?
+        else {
+            return Ok(false);
+        };
+
+        if let Some(step) = matching_step(&secret, code, now()) {
+            // Checking and recording the step in one statement means two
+            // requests with the same code cannot both get through
+            let result = sqlx::query!(
+                "UPDATE user_totp SET last_used_step = $1 WHERE user_id = $2 AND last_used_step < $1",
+                step,
+                user_id
+            )
+            .execute(crate::database::get_db())
+            .await?;
+            return Ok(result.rows_affected() > 0);
+        }
+
+        let code_hash = hash_secret(&normalize_recovery_code(code));
+        let result = sqlx::query!(
+            "UPDATE totp_recovery_code SET used_at = CURRENT_TIMESTAMP
+             WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+            user_id,
+            code_hash
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Remove a user's second factor and recovery codes
+    /// Returns false if two-factor authentication was not set up
+    pub async fn disable(user_id: i64) -> Result<bool, sqlx::Error> {
+        let mut tx = crate::database::get_db().begin().await?;
+
+        sqlx::query!("DELETE FROM totp_recovery_code WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+        let result = sqlx::query!("DELETE FROM user_totp WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Roles whose members must use two-factor authentication
+    pub async fn required_roles() -> Result<Vec<Role>, sqlx::Error> {
+        sqlx::query_scalar!(r#"SELECT role AS "role: Role" FROM two_factor_policy ORDER BY role"#)
+            .fetch_all(crate::database::get_db())
+            .await
+    }
+
+    /// Replace the set of roles that must use two-factor authentication
+    pub async fn set_required_roles(roles: &[Role]) -> Result<(), sqlx::Error> {
+        let mut tx = crate::database::get_db().begin().await?;
+
+        sqlx::query!("DELETE FROM two_factor_policy")
+            .execute(&mut *tx)
+            .await?;
+        for role in roles {
+            sqlx::query!("INSERT OR IGNORE INTO two_factor_policy (role) VALUES ($1)", role)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Whether a user with `role` must use two-factor authentication
+    /// Requiring it for a role also requires it for every higher role
+    pub async fn is_required_for(role: Role) -> Result<bool, sqlx::Error> {
+        let required = Self::required_roles().await?;
+        Ok(role.granted().iter().any(|granted| required.contains(granted)))
+    }
+
+    /// Users that have not confirmed two-factor authentication, with their role
+    pub async fn unenrolled_users() -> Result<Vec<(i64, Role)>, sqlx::Error> {
+        sqlx::query!(
+            r#"SELECT user_id AS "user_id!", role AS "role: Role" FROM user
+               WHERE user_id NOT IN (SELECT user_id FROM user_totp WHERE confirmed_at IS NOT NULL)"#
+        )
+        .map(|row| (row.user_id, row.role))
+        .fetch_all(crate::database::get_db())
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The SHA-1 seed of RFC 6238, Appendix B
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    /// Times and 8-digit codes from RFC 6238, Appendix B (SHA-1)
+    const RFC_VECTORS: [(i64, &str); 6] = [
+        (59, "94287082"),
+        (1111111109, "07081804"),
+        (1111111111, "14050471"),
+        (1234567890, "89005924"),
+        (2000000000, "69279037"),
+        (20000000000, "65353130"),
+    ];
+
+    /// Our codes are the last `DIGITS` digits of the RFC's 8-digit ones
+    fn expected(code: &str) -> &str {
+        &code[code.len() - DIGITS as usize..]
+    }
+
+    #[test]
+    fn hotp_matches_rfc_6238_vectors() {
+        for (time, code) in RFC_VECTORS {
+            let value = hotp(RFC_SECRET, (time / PERIOD_SECONDS) as u64);
+            assert_eq!(format!("{:06}", value), expected(code), "time {}", time);
+        }
+    }
+
+    #[test]
+    fn matching_step_accepts_rfc_6238_codes_within_drift() {
+        let secret = data_encoding::BASE32_NOPAD.encode(RFC_SECRET);
+        for (time, code) in RFC_VECTORS {
+            let step = time / PERIOD_SECONDS;
+            let code = expected(code);
+            assert_eq!(matching_step(&secret, code, time), Some(step), "time {}", time);
+            assert_eq!(
+                matching_step(&secret, code, time + ALLOWED_DRIFT * PERIOD_SECONDS),
+                Some(step),
+                "time {} one step later",
+                time
+            );
+            assert_eq!(
+                matching_step(&secret, code, time + (ALLOWED_DRIFT + 1) * PERIOD_SECONDS),
+                None,
+                "time {} past the drift",
+                time
+            );
+        }
+    }
+
+    #[test]
+    fn matching_step_rejects_malformed_codes() {
+        let secret = data_encoding::BASE32_NOPAD.encode(RFC_SECRET);
+        assert_eq!(matching_step(&secret, "28708", 59), None);
+        assert_eq!(matching_step(&secret, "2870820", 59), None);
+        assert_eq!(matching_step(&secret, "28708a", 59), None);
+        assert_eq!(matching_step("not base32!", "287082", 59), None);
+    }
+}
@@ -7,21 +7,121 @@ use tokio::task;
 
 // Validation constants
 pub const USERNAME_MIN_LENGTH: usize = 8;
-pub const PASSWORD_MIN_LENGTH: usize = 8;
+
+/// Site-wide role of a user, ordered from least to most privileged
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    Reviewer,
+    Admin,
+}
+
+impl Role {
+    /// All roles held by a user with this role, since higher roles imply lower ones
+    pub fn granted(self) -> Vec<Role> {
+        [Role::User, Role::Reviewer, Role::Admin]
+            .into_iter()
+            .filter(|role| *role <= self)
+            .collect()
+    }
+}
+
+/// Whether an administrator has let the account in
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum AccountStatus {
+    #[default]
+    PendingApproval,
+    Active,
+    Rejected,
+}
+
+/// Language a user's emails are written in
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    Es,
+    Pt,
+}
+
+impl Locale {
+    pub fn code(self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::Es => "es",
+            Locale::Pt => "pt",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code.to_ascii_lowercase().as_str() {
+            "en" => Some(Locale::En),
+            "es" => Some(Locale::Es),
+            "pt" => Some(Locale::Pt),
+            _ => None,
+        }
+    }
+
+    /// The first supported language in an `Accept-Language` header, ignoring weights
+    pub fn from_accept_language(header: &str) -> Option<Self> {
+        header
+            .split(',')
+            .filter_map(|range| range.split(';').next())
+            .filter_map(|tag| tag.trim().split('-').next())
+            .find_map(Self::from_code)
+    }
+}
 
 #[derive(Debug, Default, Deserialize, Serialize, Clone)]
 pub struct User {
+    #[serde(skip)]
+    user_id: i64,
     username: String,
+    /// A new plain-text password to hash and store on insert or update, never the stored hash
     #[serde(skip_serializing)]
     password: Option<String>,
     email: String,
     bio: Option<String>,
-    email_notifications: bool,
+    locale: Locale,
+    role: Role,
+    #[serde(skip)]
+    email_verified: bool,
+    /// A new address waiting for verification before it replaces `email`
+    #[serde(skip)]
+    pending_email: Option<String>,
+    #[serde(skip)]
+    status: AccountStatus,
+    #[serde(skip)]
+    disabled: bool,
+}
+
+/// A user as listed for administrators
+#[derive(Debug, Serialize, Clone)]
+pub struct UserSummary {
+    pub user_id: i64,
+    pub username: String,
+    pub email: String,
+    pub role: Role,
+    pub status: AccountStatus,
+    pub disabled: bool,
+    pub query_count: i64,
+    pub created_at: String,
 }
 
 static EMAIL_REGEX: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
 
 impl User {
+    #[inline]
+    pub fn user_id(&self) -> i64 {
+        self.user_id
+    }
     #[inline]
     pub fn username(&self) -> String {
         self.username.to_string()
@@ -35,20 +135,44 @@ impl User {
         self.bio.clone()
     }
     #[inline]
-    pub fn email_notifications(&self) -> bool {
-        self.email_notifications
+    pub fn locale(&self) -> Locale {
+        self.locale
+    }
+    #[inline]
+    pub fn role(&self) -> Role {
+        self.role
+    }
+    #[inline]
+    pub fn email_verified(&self) -> bool {
+        self.email_verified
+    }
+    #[inline]
+    pub fn pending_email(&self) -> Option<String> {
+        self.pending_email.clone()
+    }
+    #[inline]
+    pub fn status(&self) -> AccountStatus {
+        self.status
+    }
+    #[inline]
+    pub fn disabled(&self) -> bool {
+        self.disabled
     }
 
-    /// Validate password meets minimum requirements
-    pub fn validate_password(password: &str) -> Result<(), crate::models::DatabaseError> {
-        if password.len() < PASSWORD_MIN_LENGTH {
-            return Err(crate::models::DatabaseError::PasswordTooWeak);
+    /// Check a password against the password policy
+    /// `user_inputs` are things like the username that make a password easy to guess
+    pub fn validate_password(password: &str, user_inputs: &[&str]) -> Result<(), crate::models::DatabaseError> {
+        let check = crate::models::PasswordPolicy::from_env().check(password, user_inputs);
+        if !check.is_acceptable() {
+            return Err(crate::models::DatabaseError::PasswordTooWeak(check.feedback));
         }
         Ok(())
     }
 
+    /// Set a new password, checked against the policy
+    /// Set the username and email first, so the password can be checked against them
     pub fn set_password(mut self, password: String) -> Result<Self, crate::models::DatabaseError> {
-        Self::validate_password(&password)?;
+        Self::validate_password(&password, &[&self.username, &self.email])?;
         self.password = Some(password);
         Ok(self)
     }
@@ -61,7 +185,7 @@ impl User {
         Ok(self)
     }
 
-    fn validate_email(email: &str) -> bool {
+    pub fn validate_email(email: &str) -> bool {
         EMAIL_REGEX
             .get_or_init(|| regex::Regex::new(r"^[\w\-\.]+@([\w-]+\.)+\w{2,4}$").unwrap())
             .is_match(email)
@@ -84,15 +208,50 @@ impl User {
         Ok(self)
     }
 
-    pub fn set_email_notifications(mut self, email_notifications: bool) -> Self {
-        self.email_notifications = email_notifications;
+    pub fn set_locale(mut self, locale: Locale) -> Self {
+        self.locale = locale;
         self
     }
 
+    /// Language to write a user's emails in, the default if the user is gone
+    pub async fn locale_of(user_id: i64) -> Result<Locale, sqlx::Error> {
+        let locale = sqlx::query_scalar!(
+            r#"SELECT locale AS "locale: Locale" FROM user WHERE user_id = $1"#,
+            user_id
+        )
+        .fetch_optional(crate::database::get_db())
+        .await?;
+        Ok(locale.unwrap_or_default())
+    }
+
+    /// Whether a user can log in with a password, rather than only through single sign-on
+    pub async fn has_password(user_id: i64) -> Result<bool, sqlx::Error> {
+        let has_password = sqlx::query_scalar!(
+            r#"SELECT password != '' AS "has_password!: bool" FROM user WHERE user_id = $1"#,
+            user_id
+        )
+        .fetch_one(crate::database::get_db())
+        .await?;
+        Ok(has_password)
+    }
+
+    /// Language to write emails to an address in, if it belongs to an account
+    pub async fn locale_for_email(email: &str) -> Result<Locale, sqlx::Error> {
+        let locale = sqlx::query_scalar!(
+            r#"SELECT locale AS "locale: Locale" FROM user WHERE email = $1"#,
+            email
+        )
+        .fetch_optional(crate::database::get_db())
+        .await?;
+        Ok(locale.unwrap_or_default())
+    }
+
     pub async fn get(username: String) -> Result<Self, crate::models::DatabaseError> {
         sqlx::query_as!(
             Self,
-            "SELECT username, email, bio, password, email_notifications FROM user WHERE username=$1",
+            r#"SELECT user_id AS "user_id!", username, email, bio, NULL AS "password?: String", locale AS "locale: Locale", role AS "role: Role",
+                email_verified_at IS NOT NULL AS "email_verified!: bool", pending_email,
+                status AS "status: AccountStatus", disabled_at IS NOT NULL AS "disabled!: bool" FROM user WHERE username=$1"#,
             username
         )
         .fetch_one(crate::database::get_db())
@@ -103,10 +262,28 @@ impl User {
         })
     }
 
+    pub async fn get_by_id(user_id: i64) -> Result<Self, crate::models::DatabaseError> {
+        sqlx::query_as!(
+            Self,
+            r#"SELECT user_id AS "user_id!", username, email, bio, NULL AS "password?: String", locale AS "locale: Locale", role AS "role: Role",
+                email_verified_at IS NOT NULL AS "email_verified!: bool", pending_email,
+                status AS "status: AccountStatus", disabled_at IS NOT NULL AS "disabled!: bool" FROM user WHERE user_id=$1"#,
+            user_id
+        )
+        .fetch_one(crate::database::get_db())
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => crate::models::DatabaseError::UserNotFound,
+            _ => crate::models::DatabaseError::from(e),
+        })
+    }
+
     pub async fn get_email(email: String) -> Result<Self, sqlx::Error> {
         sqlx::query_as!(
             Self,
-            "SELECT username, email, bio, password, email_notifications FROM user WHERE email=$1",
+            r#"SELECT user_id AS "user_id!", username, email, bio, NULL AS "password?: String", locale AS "locale: Locale", role AS "role: Role",
+                email_verified_at IS NOT NULL AS "email_verified!: bool", pending_email,
+                status AS "status: AccountStatus", disabled_at IS NOT NULL AS "disabled!: bool" FROM user WHERE email=$1"#,
             email
         )
         .fetch_one(crate::database::get_db())
@@ -125,42 +302,92 @@ impl User {
         let bio = self.bio.as_deref().unwrap_or("");
         
         sqlx::query!(
-            "INSERT INTO user(username, bio, email, password, email_notifications) VALUES ($1, $2, $3, $4, $5)",
+            "INSERT INTO user(username, bio, email, password, locale) VALUES ($1, $2, $3, $4, $5)",
             self.username,
             bio,
             self.email,
             password,
-            self.email_notifications,
+            self.locale,
         )
         .execute(crate::database::get_db())
         .await
         .map_err(crate::models::DatabaseError::from)
     }
 
+    /// Create an account for someone who logged in with an external identity provider
+    /// The provider vouches for the email address. The account has no password and
+    /// still needs approval like any other signup. Returns the new user id and username
+    pub async fn create_external(
+        preferred_username: &str,
+        email: &str,
+    ) -> Result<(i64, String), crate::models::DatabaseError> {
+        let mut base: String = preferred_username
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+            .collect();
+        if base.len() < USERNAME_MIN_LENGTH {
+            base.push_str(&"0".repeat(USERNAME_MIN_LENGTH - base.len()));
+        }
+
+        for attempt in 1..=100 {
+            let username = if attempt == 1 {
+                base.clone()
+            } else {
+                format!("{}{}", base, attempt)
+            };
+            let user = User::default()
+                .set_username(username.clone())?
+                .set_email(email.to_string())?;
+
+            match user.insert().await {
+                Ok(result) => {
+                    let user_id = result.last_insert_rowid();
+                    sqlx::query!(
+                        "UPDATE user SET email_verified_at = CURRENT_TIMESTAMP WHERE user_id = $1",
+                        user_id
+                    )
+                    .execute(crate::database::get_db())
+                    .await?;
+                    return Ok((user_id, username));
+                }
+                Err(crate::models::DatabaseError::UsernameAlreadyExists) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(crate::models::DatabaseError::UsernameAlreadyExists)
+    }
+
     pub async fn update(&self) -> Result<sqlx::sqlite::SqliteQueryResult, sqlx::Error> {
         match &self.password {
             Some(password) => {
                 let password = hash_password(password.to_string())
                     .await
                     .expect("Failed to hash password");
-                sqlx::query!(
-                    "UPDATE user SET bio=$2, email=$3, password=$4, email_notifications=$5 WHERE username=$1",
+                let result = sqlx::query!(
+                    "UPDATE user SET bio=$2, email=$3, password=$4, locale=$5, pending_email=$6 WHERE username=$1",
                     self.username,
                     self.bio,
                     self.email,
                     password,
-                    self.email_notifications,
+                    self.locale,
+                    self.pending_email,
                 )
                 .execute(crate::database::get_db())
-                .await
+                .await?;
+
+                // Reset links requested before the change must not work afterwards
+                crate::models::PasswordReset::invalidate_for_user(self.user_id).await?;
+                Ok(result)
             }
             None => {
                 sqlx::query!(
-                    "UPDATE user SET bio=$2, email=$3, email_notifications=$4 WHERE username=$1",
+                    "UPDATE user SET bio=$2, email=$3, locale=$4, pending_email=$5 WHERE username=$1",
                     self.username,
                     self.bio,
                     self.email,
-                    self.email_notifications,
+                    self.locale,
+                    self.pending_email,
                 )
                 .execute(crate::database::get_db())
                 .await
@@ -169,8 +396,162 @@ impl User {
     }
 }
 
+impl User {
+    /// List all users with their role, status and number of submitted queries
+    pub async fn list_all(status: Option<AccountStatus>) -> Result<Vec<UserSummary>, sqlx::Error> {
+        sqlx::query!(
+            r#"SELECT u.user_id AS "user_id!", u.username, u.email, u.role AS "role: Role",
+                u.status AS "status: AccountStatus", u.disabled_at, u.created_at,
+                (SELECT COUNT(*) FROM query q WHERE q.user_id = u.user_id) AS "query_count!: i64"
+             FROM user u
+             WHERE $1 IS NULL OR u.status = $1
+             ORDER BY u.created_at DESC"#,
+            status
+        )
+        .map(|row| UserSummary {
+            user_id: row.user_id,
+            username: row.username,
+            email: row.email,
+            role: row.role,
+            status: row.status,
+            disabled: row.disabled_at.is_some(),
+            query_count: row.query_count,
+            created_at: row.created_at.to_string(),
+        })
+        .fetch_all(crate::database::get_db())
+        .await
+    }
+
+    /// Enable or disable an account. Returns false if the user does not exist
+    pub async fn set_disabled(user_id: i64, disabled: bool) -> Result<bool, sqlx::Error> {
+        let result = if disabled {
+            sqlx::query!(
+                "UPDATE user SET disabled_at = COALESCE(disabled_at, CURRENT_TIMESTAMP) WHERE user_id = $1",
+                user_id
+            )
+            .execute(crate::database::get_db())
+            .await?
+        } else {
+            sqlx::query!("UPDATE user SET disabled_at = NULL WHERE user_id = $1", user_id)
+                .execute(crate::database::get_db())
+                .await?
+        };
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Change a user's role. Returns false if the user does not exist
+    pub async fn set_role(user_id: i64, role: Role) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!("UPDATE user SET role = $2 WHERE user_id = $1", user_id, role)
+            .execute(crate::database::get_db())
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Approve or reject an account. Returns false if the user does not exist
+    pub async fn set_status(user_id: i64, status: AccountStatus) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!("UPDATE user SET status = $2 WHERE user_id = $1", user_id, status)
+            .execute(crate::database::get_db())
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Hold a new email address until the user verifies it
+    /// Saved by [`User::update`]; check it is free with [`User::check_email_available`] first
+    pub fn set_pending_email(mut self, email: String) -> Result<Self, crate::models::DatabaseError> {
+        if !Self::validate_email(&email) {
+            return Err(crate::models::DatabaseError::InvalidEmail);
+        }
+        self.pending_email = Some(email);
+        Ok(self)
+    }
+
+    /// Fail if another account already uses an email address
+    pub async fn check_email_available(&self, email: &str) -> Result<(), crate::models::DatabaseError> {
+        let taken = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!: i64" FROM user WHERE email = $1 AND user_id != $2"#,
+            email,
+            self.user_id
+        )
+        .fetch_one(crate::database::get_db())
+        .await?;
+        if taken > 0 {
+            return Err(crate::models::DatabaseError::EmailAlreadyExists);
+        }
+
+        Ok(())
+    }
+
+    /// Mark an address as verified for a user
+    /// Verifying the pending address makes it the user's email
+    pub async fn verify_email(
+        username: &str,
+        email: &str,
+    ) -> Result<(), crate::models::DatabaseError> {
+        let mut tx = crate::database::get_db().begin().await?;
+
+        let user = sqlx::query!(
+            r#"SELECT user_id AS "user_id!", email, pending_email FROM user WHERE username = $1"#,
+            username
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(crate::models::DatabaseError::InvalidVerificationToken)?;
+
+        if user.email == email {
+            sqlx::query!(
+                "UPDATE user SET email_verified_at = COALESCE(email_verified_at, CURRENT_TIMESTAMP) WHERE user_id = $1",
+                user.user_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        } else if user.pending_email.as_deref() == Some(email) {
+            sqlx::query!(
+                "UPDATE user SET email = pending_email, pending_email = NULL, email_verified_at = CURRENT_TIMESTAMP WHERE user_id = $1",
+                user.user_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        } else {
+            // The link was for an address the user has since replaced
+            return Err(crate::models::DatabaseError::InvalidVerificationToken);
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Queue an email with a verification link to an address
+    pub async fn send_verification_email(
+        user_id: i64,
+        username: &str,
+        email: &str,
+        verify_url: &str,
+    ) -> Result<(), sqlx::Error> {
+        let locale = Self::locale_of(user_id).await?;
+        let rendered = crate::mail::templates::EmailTemplate::EmailVerification
+            .render(locale, &[("username", username), ("verify_url", verify_url)]);
+
+        crate::models::EmailOutbox::enqueue(crate::models::NewEmail {
+            user_id: Some(user_id),
+            kind: "email_verification",
+            recipient_name: username,
+            recipient_email: email,
+            subject: rendered.subject,
+            text_body: rendered.text_body,
+            html_body: Some(rendered.html_body),
+            unsubscribe_url: None,
+        })
+        .await?;
+
+        Ok(())
+    }
+}
+
 // Hash a password in a separate blocking thread
-async fn hash_password(password: String) -> Result<String, String> {
+pub(crate) async fn hash_password(password: String) -> Result<String, String> {
     task::spawn_blocking(move || {
         let salt = SaltString::generate(&mut OsRng);
         let argon2 = Argon2::default();
@@ -184,7 +565,7 @@ async fn hash_password(password: String) -> Result<String, String> {
 }
 
 pub async fn verify_password(password: String, password_hash: String) -> Result<(), String> {
-    Ok(task::spawn_blocking(move || -> Result<(), String> {
+    task::spawn_blocking(move || -> Result<(), String> {
         let hash =
             PasswordHash::new(&password_hash).map_err(|e| format!("invalid password hash: {e}"))?;
 
@@ -192,5 +573,5 @@ pub async fn verify_password(password: String, password_hash: String) -> Result<
             .map_err(|_| "password did not match".to_string())
     })
     .await
-    .map_err(|e| e.to_string())??)
+    .map_err(|e| e.to_string())?
 }
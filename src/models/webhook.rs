@@ -0,0 +1,579 @@
+use std::net::{IpAddr, SocketAddr};
+
+use hmac::{Hmac, Mac};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::sync::Notify;
+
+use crate::auth::secret::generate_secret;
+use crate::models::DatabaseError;
+
+/// Wakes the sender when a delivery is queued, so it need not wait for its next check
+static QUEUED: Notify = Notify::const_new();
+
+/// Hosts webhooks may be sent to even though they are internal, from the
+/// comma-separated `WEBHOOK_ALLOWED_HOSTS`, e.g. `localhost` for the stub endpoint
+fn allowed_hosts() -> Vec<String> {
+    std::env::var("WEBHOOK_ALLOWED_HOSTS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|host| host.trim().trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase())
+        .filter(|host| !host.is_empty())
+        .collect()
+}
+
+/// Whether an address is on the public internet, rather than this machine, a
+/// private network or the cloud metadata service
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+/// What a webhook is told about
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookEvent {
+    #[serde(rename = "query.started")]
+    QueryStarted,
+    #[serde(rename = "query.retrying")]
+    QueryRetrying,
+    #[serde(rename = "query.completed")]
+    QueryCompleted,
+    #[serde(rename = "query.failed")]
+    QueryFailed,
+    /// Sent on request, to check a webhook is set up correctly
+    #[serde(rename = "ping")]
+    Ping,
+}
+
+impl WebhookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::QueryStarted => "query.started",
+            WebhookEvent::QueryRetrying => "query.retrying",
+            WebhookEvent::QueryCompleted => "query.completed",
+            WebhookEvent::QueryFailed => "query.failed",
+            WebhookEvent::Ping => "ping",
+        }
+    }
+}
+
+/// Where a webhook delivery is
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Pending,
+    Sending,
+    Delivered,
+    /// Every attempt failed
+    Failed,
+}
+
+/// A registered webhook. The secret is only shown once, at creation
+#[derive(Serialize, Clone, Debug)]
+pub struct Webhook {
+    pub webhook_id: i64,
+    pub user_id: Option<i64>,
+    pub organization_id: Option<i64>,
+    pub url: String,
+    pub description: String,
+    pub created_at: String,
+}
+
+/// A delivery of an event to a webhook, as listed in its delivery log
+#[derive(Serialize, Clone, Debug)]
+pub struct WebhookDelivery {
+    pub delivery_id: i64,
+    pub webhook_id: i64,
+    pub event: String,
+    pub payload: serde_json::Value,
+    pub status: DeliveryStatus,
+    pub attempts: i64,
+    pub next_attempt_at: String,
+    /// HTTP status of the latest attempt, if the endpoint answered
+    pub response_status: Option<i64>,
+    pub last_error: Option<String>,
+    pub created_at: String,
+    pub delivered_at: Option<String>,
+}
+
+/// A delivery claimed for sending
+struct DueDelivery {
+    delivery_id: i64,
+    event: String,
+    payload: String,
+    url: String,
+    secret: String,
+}
+
+impl Webhook {
+    /// Prefix of generated secrets
+    pub const SECRET_PREFIX: &'static str = "whsec_";
+    /// Attempts before a delivery is given up on
+    pub const MAX_ATTEMPTS: i64 = 8;
+    /// Longest wait between attempts
+    const MAX_BACKOFF_MINUTES: i64 = 6 * 60;
+    /// How long a batch may take before its deliveries are picked up again
+    const LEASE_MINUTES: i64 = 5;
+    /// Deliveries sent per batch
+    const BATCH_SIZE: i64 = 50;
+    /// How long an endpoint has to answer
+    const TIMEOUT_SECONDS: u64 = 10;
+    /// Days deliveries are kept in the log
+    pub const DELIVERY_RETENTION_DAYS: i64 = 30;
+    const URL_MAX_LENGTH: usize = 2000;
+    const DESCRIPTION_MAX_LENGTH: usize = 200;
+    const SECRET_MIN_LENGTH: usize = 16;
+    const SECRET_MAX_LENGTH: usize = 256;
+
+    fn validate(url: &str, description: &str, secret: Option<&str>) -> Result<(), DatabaseError> {
+        let parsed = Url::parse(url).map_err(|_| DatabaseError::InvalidWebhookUrl)?;
+        if !matches!(parsed.scheme(), "http" | "https")
+            || parsed.host_str().is_none()
+            || url.len() > Self::URL_MAX_LENGTH
+        {
+            return Err(DatabaseError::InvalidWebhookUrl);
+        }
+        if description.len() > Self::DESCRIPTION_MAX_LENGTH {
+            return Err(DatabaseError::InvalidWebhookDescription);
+        }
+        if let Some(secret) = secret {
+            if !(Self::SECRET_MIN_LENGTH..=Self::SECRET_MAX_LENGTH).contains(&secret.len()) {
+                return Err(DatabaseError::InvalidWebhookSecret);
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolve a webhook URL's host to the addresses it may be sent to
+    ///
+    /// Internal addresses are refused unless the host is in `WEBHOOK_ALLOWED_HOSTS`, so
+    /// webhooks cannot be used to reach services behind the firewall. Deliveries connect
+    /// to exactly these addresses, so the host cannot resolve differently once checked.
+    async fn destination(url: &Url) -> Result<Vec<SocketAddr>, String> {
+        let port = url.port_or_known_default().unwrap_or(80);
+        let host = url
+            .host_str()
+            .ok_or("URL has no host")?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_ascii_lowercase();
+        let addresses: Vec<SocketAddr> = match host.parse::<IpAddr>() {
+            Ok(ip) => vec![SocketAddr::new(ip, port)],
+            Err(_) => tokio::net::lookup_host((host.as_str(), port))
+                .await
+                .map_err(|e| format!("Could not resolve {}: {}", host, e))?
+                .collect(),
+        };
+
+        if addresses.is_empty() {
+            return Err(format!("{} has no addresses", host));
+        }
+        if !allowed_hosts().contains(&host) {
+            if let Some(address) = addresses.iter().find(|address| !is_public(address.ip())) {
+                return Err(format!("{} resolves to the internal address {}", host, address.ip()));
+            }
+        }
+        Ok(addresses)
+    }
+
+    /// Register a webhook for a user's queries or, if `organization_id` is set,
+    /// for an organization's
+    /// Returns the webhook id and its secret, which is generated if not given
+    pub async fn create(
+        created_by: i64,
+        organization_id: Option<i64>,
+        url: &str,
+        description: &str,
+        secret: Option<&str>,
+    ) -> Result<(i64, String), DatabaseError> {
+        let url = url.trim();
+        let description = description.trim();
+        Self::validate(url, description, secret)?;
+        let parsed = Url::parse(url).map_err(|_| DatabaseError::InvalidWebhookUrl)?;
+        if let Err(reason) = Self::destination(&parsed).await {
+            tracing::debug!("Refused webhook URL {}: {}", url, reason);
+            return Err(DatabaseError::InternalWebhookUrl);
+        }
+
+        let secret = match secret {
+            Some(secret) => secret.to_string(),
+            None => format!("{}{}", Self::SECRET_PREFIX, generate_secret()),
+        };
+        let user_id = match organization_id {
+            Some(_) => None,
+            None => Some(created_by),
+        };
+
+        let webhook_id = sqlx::query!(
+            "INSERT INTO webhook (user_id, organization_id, url, secret, description, created_by)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+            user_id,
+            organization_id,
+            url,
+            secret,
+            description,
+            created_by
+        )
+        .execute(crate::database::get_db())
+        .await?
+        .last_insert_rowid();
+
+        Ok((webhook_id, secret))
+    }
+
+    /// A user's own webhooks and those of the organizations they own
+    pub async fn for_user(user_id: i64) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"SELECT webhook_id AS "webhook_id!", user_id, organization_id, url, description,
+                 created_at AS "created_at: String"
+             FROM webhook
+             WHERE user_id = $1
+             OR organization_id IN (SELECT organization_id FROM organization_member WHERE user_id = $1 AND role = 'owner')
+             ORDER BY webhook_id"#,
+            user_id
+        )
+        .fetch_all(crate::database::get_db())
+        .await
+    }
+
+    /// A webhook the user may manage: their own, or one of an organization they own
+    pub async fn get_for_user(webhook_id: i64, user_id: i64) -> Result<Self, DatabaseError> {
+        Self::for_user(user_id)
+            .await?
+            .into_iter()
+            .find(|webhook| webhook.webhook_id == webhook_id)
+            .ok_or(DatabaseError::WebhookNotFound)
+    }
+
+    /// Delete a webhook and its delivery log
+    pub async fn delete(webhook_id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM webhook WHERE webhook_id = $1", webhook_id)
+            .execute(crate::database::get_db())
+            .await?;
+        Ok(())
+    }
+
+    /// The signature sent in `X-Glad-Signature`: the hex HMAC-SHA256, keyed with
+    /// the webhook's secret, of the timestamp, a period and the request body
+    pub fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+        mac.update(format!("{}.{}", timestamp, body).as_bytes());
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    /// Queue an event for one webhook and wake the sender
+    /// Returns the delivery id
+    pub async fn enqueue(webhook_id: i64, event: WebhookEvent, data: serde_json::Value) -> Result<i64, sqlx::Error> {
+        let payload = serde_json::json!({
+            "event": event,
+            "created_at": chrono::Utc::now().to_rfc3339(),
+            "data": data,
+        })
+        .to_string();
+        let event_name = event.as_str();
+
+        let delivery_id = sqlx::query!(
+            "INSERT INTO webhook_delivery (webhook_id, event, payload) VALUES ($1, $2, $3)",
+            webhook_id,
+            event_name,
+            payload
+        )
+        .execute(crate::database::get_db())
+        .await?
+        .last_insert_rowid();
+
+        QUEUED.notify_one();
+        Ok(delivery_id)
+    }
+
+    /// Queue an event about a query for the submitter's webhooks and, for
+    /// organization queries, the organization's
+    /// Returns the number of deliveries queued
+    pub async fn enqueue_query_event(
+        event: WebhookEvent,
+        query_id: i64,
+        user_id: i64,
+        organization_id: Option<i64>,
+        data: serde_json::Value,
+    ) -> Result<usize, sqlx::Error> {
+        let webhook_ids = sqlx::query_scalar!(
+            r#"SELECT webhook_id AS "webhook_id!" FROM webhook WHERE user_id = $1 OR organization_id = $2"#,
+            user_id,
+            organization_id
+        )
+        .fetch_all(crate::database::get_db())
+        .await?;
+
+        for webhook_id in &webhook_ids {
+            if let Err(e) = Self::enqueue(*webhook_id, event, data.clone()).await {
+                tracing::error!("Failed to queue {} for query {} to webhook {}: {}", event.as_str(), query_id, webhook_id, e);
+            }
+        }
+
+        Ok(webhook_ids.len())
+    }
+
+    /// Wait until a delivery is queued
+    pub async fn queued() {
+        QUEUED.notified().await
+    }
+
+    /// A webhook's deliveries, newest first
+    pub async fn deliveries(webhook_id: i64, limit: i64) -> Result<Vec<WebhookDelivery>, sqlx::Error> {
+        sqlx::query!(
+            r#"SELECT delivery_id AS "delivery_id!", webhook_id, event, payload, status AS "status: DeliveryStatus",
+                 attempts, next_attempt_at, response_status, last_error, created_at, delivered_at
+             FROM webhook_delivery WHERE webhook_id = $1
+             ORDER BY delivery_id DESC LIMIT $2"#,
+            webhook_id,
+            limit
+        )
+        .map(|row| WebhookDelivery {
+            delivery_id: row.delivery_id,
+            webhook_id: row.webhook_id,
+            event: row.event,
+            payload: serde_json::from_str(&row.payload).unwrap_or_default(),
+            status: row.status,
+            attempts: row.attempts,
+            next_attempt_at: row.next_attempt_at.to_string(),
+            response_status: row.response_status,
+            last_error: row.last_error,
+            created_at: row.created_at.to_string(),
+            delivered_at: row.delivered_at.map(|date| date.to_string()),
+        })
+        .fetch_all(crate::database::get_db())
+        .await
+    }
+
+    /// Delete finished deliveries past their retention period
+    pub async fn purge_deliveries() -> Result<u64, sqlx::Error> {
+        let cutoff = format!("-{} days", Self::DELIVERY_RETENTION_DAYS);
+        let result = sqlx::query!(
+            "DELETE FROM webhook_delivery WHERE status IN ('delivered', 'failed') AND created_at < datetime('now', $1)",
+            cutoff
+        )
+        .execute(crate::database::get_db())
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Send the deliveries that are due
+    /// Returns the number delivered
+    pub async fn deliver_due() -> Result<usize, sqlx::Error> {
+        let lease = format!("+{} minutes", Self::LEASE_MINUTES);
+        let batch = sqlx::query_as!(
+            DueDelivery,
+            r#"UPDATE webhook_delivery SET status = 'sending', next_attempt_at = datetime('now', $1)
+             WHERE delivery_id IN (
+                 SELECT delivery_id FROM webhook_delivery
+                 WHERE status IN ('pending', 'sending') AND next_attempt_at <= CURRENT_TIMESTAMP
+                 ORDER BY delivery_id LIMIT $2)
+             RETURNING delivery_id AS "delivery_id!", event, payload,
+                 (SELECT url FROM webhook WHERE webhook.webhook_id = webhook_delivery.webhook_id) AS "url!: String",
+                 (SELECT secret FROM webhook WHERE webhook.webhook_id = webhook_delivery.webhook_id) AS "secret!: String""#,
+            lease,
+            Self::BATCH_SIZE
+        )
+        .fetch_all(crate::database::get_db())
+        .await?;
+        if batch.is_empty() {
+            return Ok(0);
+        }
+
+        let mut delivered_count = 0;
+        for delivery in batch {
+            // Checked on every attempt, since where a host points can change after registration
+            let destination = match Url::parse(&delivery.url) {
+                Ok(url) => Self::destination(&url).await.map(|addresses| (url, addresses)),
+                Err(e) => Err(e.to_string()),
+            };
+            let (url, addresses) = match destination {
+                Ok(destination) => destination,
+                Err(error) => {
+                    Self::mark_failed(delivery.delivery_id, None, &error).await?;
+                    continue;
+                }
+            };
+            let client = reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(Self::TIMEOUT_SECONDS))
+                .redirect(reqwest::redirect::Policy::none())
+                .resolve_to_addrs(url.host_str().unwrap_or_default(), &addresses)
+                .build()
+                .map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+
+            let timestamp = chrono::Utc::now().timestamp();
+            let response = client
+                .post(url)
+                .header("Content-Type", "application/json")
+                .header("User-Agent", "GLAD-Webhooks/1.0")
+                .header("X-Glad-Event", &delivery.event)
+                .header("X-Glad-Delivery", delivery.delivery_id)
+                .header("X-Glad-Timestamp", timestamp)
+                .header("X-Glad-Signature", Self::signature(&delivery.secret, timestamp, &delivery.payload))
+                .body(delivery.payload.clone())
+                .send()
+                .await;
+
+            match response {
+                Ok(response) if response.status().is_success() => {
+                    let response_status = i64::from(response.status().as_u16());
+                    sqlx::query!(
+                        "UPDATE webhook_delivery SET status = 'delivered', attempts = attempts + 1, response_status = $2,
+                             last_error = NULL, delivered_at = CURRENT_TIMESTAMP
+                         WHERE delivery_id = $1",
+                        delivery.delivery_id,
+                        response_status
+                    )
+                    .execute(crate::database::get_db())
+                    .await?;
+                    delivered_count += 1;
+                }
+                Ok(response) => {
+                    let response_status = i64::from(response.status().as_u16());
+                    let error = format!("Endpoint answered {}", response.status());
+                    Self::mark_failed(delivery.delivery_id, Some(response_status), &error).await?;
+                }
+                Err(e) => {
+                    Self::mark_failed(delivery.delivery_id, None, &e.to_string()).await?;
+                }
+            }
+        }
+
+        Ok(delivered_count)
+    }
+
+    /// Record a failed attempt, retrying with exponential backoff until the
+    /// attempts run out
+    async fn mark_failed(delivery_id: i64, response_status: Option<i64>, error: &str) -> Result<(), sqlx::Error> {
+        let attempts = sqlx::query_scalar!(
+            "SELECT attempts + 1 FROM webhook_delivery WHERE delivery_id = $1",
+            delivery_id
+        )
+        .fetch_one(crate::database::get_db())
+        .await?;
+
+        let status = if attempts >= Self::MAX_ATTEMPTS {
+            tracing::warn!("Giving up on webhook delivery {} after {} attempts: {}", delivery_id, attempts, error);
+            DeliveryStatus::Failed
+        } else {
+            tracing::debug!("Webhook delivery {} failed, will retry: {}", delivery_id, error);
+            DeliveryStatus::Pending
+        };
+        let backoff = format!(
+            "+{} minutes",
+            (1_i64 << (attempts - 1).clamp(0, 16)).min(Self::MAX_BACKOFF_MINUTES)
+        );
+
+        sqlx::query!(
+            "UPDATE webhook_delivery SET status = $2, attempts = $3, response_status = $4, last_error = $5,
+                 next_attempt_at = datetime('now', $6)
+             WHERE delivery_id = $1",
+            delivery_id,
+            status,
+            attempts,
+            response_status,
+            error,
+            backoff
+        )
+        .execute(crate::database::get_db())
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_is_hmac_sha256_of_timestamp_and_body() {
+        let body = r#"{"event":"ping","data":{"webhook_id":1}}"#;
+        assert_eq!(
+            Webhook::signature("whsec_0123456789abcdef", 1767225600, body),
+            "sha256=c5260b15a096e09eece06711d64c1cf9101ab23d1333ea85f5f67d0e48e18f92"
+        );
+        assert_ne!(
+            Webhook::signature("whsec_0123456789abcdef", 1767225601, body),
+            Webhook::signature("whsec_0123456789abcdef", 1767225600, body)
+        );
+    }
+
+    #[test]
+    fn internal_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["93.184.216.34", "1.1.1.1", "2606:4700:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn destinations_are_checked_after_resolving() {
+        async fn destination(url: &str) -> Result<Vec<SocketAddr>, String> {
+            Webhook::destination(&Url::parse(url).unwrap()).await
+        }
+
+        std::env::remove_var("WEBHOOK_ALLOWED_HOSTS");
+        for url in [
+            "http://localhost:4000/",
+            "http://127.0.0.1/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://10.0.0.5:8080/hook",
+            "http://[::1]/",
+        ] {
+            assert!(destination(url).await.is_err(), "{}", url);
+        }
+        assert_eq!(
+            destination("https://93.184.216.34/hook").await.unwrap(),
+            vec!["93.184.216.34:443".parse().unwrap()]
+        );
+
+        std::env::set_var("WEBHOOK_ALLOWED_HOSTS", "localhost, [::1]");
+        assert!(destination("http://localhost:4000/").await.is_ok());
+        assert!(destination("http://[::1]:4000/").await.is_ok());
+        assert!(destination("http://127.0.0.1:4000/").await.is_err());
+        std::env::remove_var("WEBHOOK_ALLOWED_HOSTS");
+    }
+}
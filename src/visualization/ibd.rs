@@ -21,7 +21,7 @@ impl IbdComputation {
         let matrix = VISUALIZATION_CACHE
             .ibd_matrix
             .as_ref()
-            .ok_or_else(|| ApiError::InternalServerError)?;
+            .ok_or(ApiError::InternalServerError)?;
 
         let n_groups = groups.len();
         let mut result_matrix: Vec<Vec<f32>> = vec![vec![0.0; n_groups]; n_groups];
@@ -130,14 +130,14 @@ impl IbdComputation {
         let matrix_t = VISUALIZATION_CACHE
             .ibd_matrix_t
             .as_ref()
-            .ok_or_else(|| ApiError::InternalServerError)?;
+            .ok_or(ApiError::InternalServerError)?;
 
         let results: Vec<f32> = group_pairs
             .into_iter()
             .map(|(group_a, group_b)| {
                 Self::compute_group_mean_adaptive(
                     &matrix,
-                    &matrix_t,
+                    matrix_t,
                     &group_a.individuals,
                     &group_b.individuals,
                 )
@@ -148,6 +148,7 @@ impl IbdComputation {
     }
 
     /// Optimized blocking computation of IBD matrix using adaptive approach
+    #[allow(dead_code)]
     fn compute_matrix_blocking(
         groups_data: Vec<(String, usize, Vec<usize>)>,
         matrix: CsMat<f32>,
@@ -162,7 +163,7 @@ impl IbdComputation {
         let matrix_t = VISUALIZATION_CACHE
             .ibd_matrix_t
             .as_ref()
-            .ok_or_else(|| ApiError::InternalServerError)?;
+            .ok_or(ApiError::InternalServerError)?;
 
         // Parallel computation of upper triangle + diagonal only
         let n_groups = groups_data.len();
@@ -174,21 +175,17 @@ impl IbdComputation {
                 let (_, _, individuals_b) = &groups_data[j];
 
                 // Use adaptive approach: choose matrix orientation based on group sizes
-                Self::compute_group_mean_adaptive(&matrix, &matrix_t, individuals_a, individuals_b)
+                Self::compute_group_mean_adaptive(&matrix, matrix_t, individuals_a, individuals_b)
             })
             .collect();
 
         // Reconstruct full symmetric matrix from upper triangle
         let mut computed_matrix: Vec<Vec<f32>> = vec![vec![0.0; n_groups]; n_groups];
-        let mut idx = 0;
-        for i in 0..n_groups {
-            for j in i..n_groups {
-                let value = upper_triangle[idx];
-                computed_matrix[i][j] = value;
-                if i != j {
-                    computed_matrix[j][i] = value;
-                }
-                idx += 1;
+        let pairs = (0..n_groups).flat_map(|i| (i..n_groups).map(move |j| (i, j)));
+        for ((i, j), value) in pairs.zip(upper_triangle) {
+            computed_matrix[i][j] = value;
+            if i != j {
+                computed_matrix[j][i] = value;
             }
         }
 
@@ -304,7 +301,7 @@ impl IbdComputation {
         let matrix = VISUALIZATION_CACHE
             .ibd_matrix
             .as_ref()
-            .ok_or_else(|| ApiError::InternalServerError)?;
+            .ok_or(ApiError::InternalServerError)?;
 
         let n_row_groups = row_groups.len();
         let n_column_groups = column_groups.len();
@@ -236,7 +236,7 @@ impl VisualizationCache {
             {
                 self.community_to_individuals
                     .entry(community_name.clone())
-                    .or_insert_with(Vec::new)
+                    .or_default()
                     .push(ibd_matrix_index);
             }
         }
@@ -321,7 +321,7 @@ impl VisualizationCache {
             .collect();
 
         // Sort by size in descending order (largest first)
-        self.communities_by_size.sort_by(|a, b| b.size.cmp(&a.size));
+        self.communities_by_size.sort_by_key(|c| std::cmp::Reverse(c.size));
 
         info!(
             "Sorted {} communities by size",
@@ -346,7 +346,7 @@ impl VisualizationCache {
         // Step 2: Only process individuals with all valid field values
         let mut group_map: HashMap<String, Vec<usize>> = HashMap::new();
 
-        for (index, individual) in self.individuals.iter().enumerate() {
+        for individual in self.individuals.iter() {
             // Check if individual has valid values for all fields
             if self.individual_has_valid_values(individual, fields, &valid_field_values) {
                 if let Some(group_key) = self.create_group_key(individual, fields) {
@@ -354,7 +354,7 @@ impl VisualizationCache {
                     if let Some(ibd_matrix_index) = individual.ibd_matrix_index {
                         group_map
                             .entry(group_key)
-                            .or_insert_with(Vec::new)
+                            .or_default()
                             .push(ibd_matrix_index);
                     }
                 }
@@ -374,7 +374,7 @@ impl VisualizationCache {
             .collect();
 
         // Sort by size (largest first)
-        groups.sort_by(|a, b| b.size.cmp(&a.size));
+        groups.sort_by_key(|g| std::cmp::Reverse(g.size));
 
         groups
     }
@@ -393,7 +393,7 @@ impl VisualizationCache {
                 if let Some(value) = self.get_field_value(individual, field) {
                     *field_value_counts
                         .entry(field.clone())
-                        .or_insert_with(HashMap::new)
+                        .or_default()
                         .entry(value)
                         .or_insert(0) += 1;
                 }
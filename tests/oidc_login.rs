@@ -0,0 +1,388 @@
+//! Single sign-on against a mock OpenID Connect provider
+//!
+//! The provider serves discovery, authorization, token and JWKS endpoints and
+//! signs ID tokens with a fresh ES256 key, so the whole relying-party side runs:
+//! login, the redirect through the provider, the code exchange, ID token
+//! validation, account linking and handing over to the second factor.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    extract::{Form, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use data_encoding::BASE64URL_NOPAD;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use sha2::{Digest, Sha256};
+
+use glad_web::api::account::{self, DeleteAccountRequest};
+use glad_web::api::oidc::{self, CallbackParams, LoginParams};
+use glad_web::auth::{AuthenticatedUser, ClientInfo};
+use glad_web::api::auth::{self, TwoFactorSetupRequest};
+use glad_web::models::{ExternalIdentity, Role, Session, TwoFactor, User};
+
+const CLIENT_ID: &str = "glad";
+const KEY_ID: &str = "mock-key";
+
+/// Who the provider says logs in next
+#[derive(Clone)]
+struct Identity {
+    sub: String,
+    email: String,
+    email_verified: bool,
+    preferred_username: String,
+}
+
+/// An authorization code waiting to be redeemed
+struct Grant {
+    identity: Identity,
+    nonce: String,
+    code_challenge: String,
+}
+
+struct MockProvider {
+    issuer: String,
+    pkcs8: Vec<u8>,
+    public_key: Vec<u8>,
+    identity: Mutex<Option<Identity>>,
+    grants: Mutex<HashMap<String, Grant>>,
+}
+
+type Provider = Arc<MockProvider>;
+
+async fn discovery(State(provider): State<Provider>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "issuer": provider.issuer,
+        "authorization_endpoint": format!("{}/authorize", provider.issuer),
+        "token_endpoint": format!("{}/token", provider.issuer),
+        "jwks_uri": format!("{}/jwks", provider.issuer),
+    }))
+}
+
+/// Log in whoever the test chose and send the browser back with a code
+async fn authorize(State(provider): State<Provider>, Query(params): Query<HashMap<String, String>>) -> Response {
+    if params.get("client_id").map(String::as_str) != Some(CLIENT_ID)
+        || params.get("code_challenge_method").map(String::as_str) != Some("S256")
+    {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    let identity = provider.identity.lock().unwrap().clone().expect("no identity to log in");
+    let code = format!("code-{}", provider.grants.lock().unwrap().len());
+    provider.grants.lock().unwrap().insert(
+        code.clone(),
+        Grant {
+            identity,
+            nonce: params["nonce"].clone(),
+            code_challenge: params["code_challenge"].clone(),
+        },
+    );
+    let mut redirect = reqwest::Url::parse(&params["redirect_uri"]).unwrap();
+    redirect
+        .query_pairs_mut()
+        .append_pair("code", &code)
+        .append_pair("state", &params["state"]);
+    Redirect::to(redirect.as_str()).into_response()
+}
+
+/// Redeem a code once, checking the PKCE verifier, for a signed ID token
+async fn token(State(provider): State<Provider>, Form(form): Form<HashMap<String, String>>) -> Response {
+    let Some(grant) = provider.grants.lock().unwrap().remove(&form["code"]) else {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_grant"}))).into_response();
+    };
+    let challenge = BASE64URL_NOPAD.encode(&Sha256::digest(form["code_verifier"].as_bytes()));
+    if challenge != grant.code_challenge {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "invalid_grant"}))).into_response();
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let claims = serde_json::json!({
+        "iss": provider.issuer,
+        "aud": CLIENT_ID,
+        "sub": grant.identity.sub,
+        "iat": now,
+        "exp": now + 300,
+        "nonce": grant.nonce,
+        "email": grant.identity.email,
+        "email_verified": grant.identity.email_verified,
+        "preferred_username": grant.identity.preferred_username,
+    });
+    let mut header = Header::new(Algorithm::ES256);
+    header.kid = Some(KEY_ID.to_string());
+    let id_token = jsonwebtoken::encode(&header, &claims, &EncodingKey::from_ec_der(&provider.pkcs8)).unwrap();
+
+    Json(serde_json::json!({
+        "access_token": "mock-access-token",
+        "token_type": "Bearer",
+        "id_token": id_token,
+    }))
+    .into_response()
+}
+
+async fn jwks(State(provider): State<Provider>) -> Json<serde_json::Value> {
+    // An uncompressed P-256 point is 0x04 followed by the x and y coordinates
+    let (x, y) = provider.public_key[1..].split_at(32);
+    Json(serde_json::json!({
+        "keys": [{
+            "kty": "EC",
+            "crv": "P-256",
+            "use": "sig",
+            "alg": "ES256",
+            "kid": KEY_ID,
+            "x": BASE64URL_NOPAD.encode(x),
+            "y": BASE64URL_NOPAD.encode(y),
+        }]
+    }))
+}
+
+async fn start_provider() -> Provider {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new()).unwrap();
+    let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &SystemRandom::new()).unwrap();
+    let provider = Arc::new(MockProvider {
+        issuer: format!("http://{}", listener.local_addr().unwrap()),
+        pkcs8: pkcs8.as_ref().to_vec(),
+        public_key: key_pair.public_key().as_ref().to_vec(),
+        identity: Mutex::new(None),
+        grants: Mutex::new(HashMap::new()),
+    });
+
+    let app = Router::new()
+        .route("/.well-known/openid-configuration", get(discovery))
+        .route("/authorize", get(authorize))
+        .route("/token", post(token))
+        .route("/jwks", get(jwks))
+        .with_state(provider.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    provider
+}
+
+fn location(response: &Response) -> String {
+    response
+        .headers()
+        .get(header::LOCATION)
+        .expect("response is a redirect")
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+fn set_cookies(response: &Response) -> Vec<String> {
+    response
+        .headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .map(|value| value.to_str().unwrap().to_string())
+        .collect()
+}
+
+/// Go through the whole login as `identity` and return the callback's response
+async fn sign_in(provider: &Provider, identity: Identity) -> Response {
+    *provider.identity.lock().unwrap() = Some(identity);
+
+    let response = oidc::login(Query(LoginParams {
+        redirect: Some("/queries".to_string()),
+        reauthenticate: false,
+    }))
+        .await
+        .unwrap();
+    let authorization_url = location(&response);
+    assert!(authorization_url.starts_with(&format!("{}/authorize?", provider.issuer)));
+    let state_cookie = set_cookies(&response)
+        .into_iter()
+        .find(|cookie| cookie.starts_with("oidc_state="))
+        .expect("login sets the state cookie");
+
+    // The browser follows the redirect to the provider, which sends it back
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let provider_response = client.get(&authorization_url).send().await.unwrap();
+    let callback_url = reqwest::Url::parse(provider_response.headers()[header::LOCATION].to_str().unwrap()).unwrap();
+    assert_eq!(callback_url.path(), "/api/auth/oidc/callback");
+    let params: HashMap<String, String> = callback_url.query_pairs().into_owned().collect();
+
+    let mut headers = HeaderMap::new();
+    let cookie = state_cookie.split(';').next().unwrap();
+    headers.insert(header::COOKIE, HeaderValue::from_str(cookie).unwrap());
+    let client_info = ClientInfo {
+        ip_address: "127.0.0.1".to_string(),
+        user_agent: "oidc-test".to_string(),
+    };
+    oidc::callback(
+        client_info,
+        headers,
+        Query(CallbackParams {
+            code: params.get("code").cloned(),
+            state: params.get("state").cloned(),
+            error: None,
+            error_description: None,
+        }),
+    )
+    .await
+    .unwrap()
+}
+
+fn has_session(response: &Response) -> bool {
+    set_cookies(response).iter().any(|cookie| cookie.starts_with("token="))
+}
+
+#[tokio::test]
+async fn login_callback_and_account_linking() {
+    let database = std::env::temp_dir().join(format!("glad-oidc-test-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&database);
+    let provider = start_provider().await;
+    std::env::set_var("DATABASE_URL", format!("sqlite://{}?mode=rwc", database.display()));
+    std::env::set_var("JWT_SECRET", "oidc-test-secret");
+    std::env::set_var("OIDC_ISSUER_URL", &provider.issuer);
+    std::env::set_var("OIDC_CLIENT_ID", CLIENT_ID);
+    std::env::set_var("OIDC_REDIRECT_URL", "http://localhost:3000/api/auth/oidc/callback");
+    glad_web::database::init_db().await.unwrap();
+
+    // An existing account with a verified address is linked on first login
+    User::default()
+        .set_username("alicealice".to_string())
+        .unwrap()
+        .set_email("alice@example.org".to_string())
+        .unwrap()
+        .set_password("glad-mango-violin-42".to_string())
+        .unwrap()
+        .insert()
+        .await
+        .unwrap();
+    User::verify_email("alicealice", "alice@example.org").await.unwrap();
+    let alice = User::get("alicealice".to_string()).await.unwrap();
+
+    let alice_identity = Identity {
+        sub: "alice-at-provider".to_string(),
+        email: "alice@example.org".to_string(),
+        email_verified: true,
+        preferred_username: "alice".to_string(),
+    };
+    let response = sign_in(&provider, alice_identity.clone()).await;
+    assert_eq!(location(&response), "/queries");
+    assert!(has_session(&response));
+    assert_eq!(
+        ExternalIdentity::find_user(&provider.issuer, "alice-at-provider").await.unwrap(),
+        Some(alice.user_id())
+    );
+
+    // Later logins find the account through the link, even if the address changed at the provider
+    let response = sign_in(
+        &provider,
+        Identity {
+            email: "alice@elsewhere.example".to_string(),
+            ..alice_identity.clone()
+        },
+    )
+    .await;
+    assert_eq!(location(&response), "/queries");
+    assert!(has_session(&response));
+
+    // A local account whose address is unverified is not handed to whoever registered it at the provider
+    User::default()
+        .set_username("bobbobbob".to_string())
+        .unwrap()
+        .set_email("bob@example.org".to_string())
+        .unwrap()
+        .set_password("glad-mango-violin-42".to_string())
+        .unwrap()
+        .insert()
+        .await
+        .unwrap();
+    let response = sign_in(
+        &provider,
+        Identity {
+            sub: "mallory-at-provider".to_string(),
+            email: "bob@example.org".to_string(),
+            email_verified: true,
+            preferred_username: "mallory".to_string(),
+        },
+    )
+    .await;
+    assert!(location(&response).starts_with("/login?error="));
+    assert!(!has_session(&response));
+    assert_eq!(
+        ExternalIdentity::find_user(&provider.issuer, "mallory-at-provider").await.unwrap(),
+        None
+    );
+
+    // An address the provider has not verified is refused outright
+    let response = sign_in(
+        &provider,
+        Identity {
+            sub: "carol-at-provider".to_string(),
+            email: "carol@example.org".to_string(),
+            email_verified: false,
+            preferred_username: "carol".to_string(),
+        },
+    )
+    .await;
+    assert!(location(&response).starts_with("/login?error="));
+
+    // A new verified identity gets its own account, linked to it
+    let response = sign_in(
+        &provider,
+        Identity {
+            sub: "dave-at-provider".to_string(),
+            email: "dave@example.org".to_string(),
+            email_verified: true,
+            preferred_username: "dave".to_string(),
+        },
+    )
+    .await;
+    assert_eq!(location(&response), "/queries");
+    let dave = User::get_email("dave@example.org".to_string()).await.unwrap();
+    assert!(dave.email_verified());
+    assert_eq!(
+        ExternalIdentity::find_user(&provider.issuer, "dave-at-provider").await.unwrap(),
+        Some(dave.user_id())
+    );
+
+    // Without a password, sensitive changes need a fresh login at the provider
+    let response = oidc::login(Query(LoginParams {
+        redirect: Some("/settings".to_string()),
+        reauthenticate: true,
+    }))
+    .await
+    .unwrap();
+    assert!(location(&response).contains("prompt=login"));
+    let session_id = Session::for_user(dave.user_id()).await.unwrap()[0].session_id.clone();
+    let as_dave = |session_id| AuthenticatedUser {
+        user_id: dave.user_id(),
+        username: dave.username(),
+        roles: vec![Role::User],
+        session_id,
+        scopes: None,
+    };
+    let no_password = || Json(DeleteAccountRequest { password: String::new() });
+    let client_info = ClientInfo::default();
+    assert!(account::delete_account(as_dave(None), client_info.clone(), no_password()).await.is_err());
+    assert!(account::delete_account(as_dave(Some(session_id)), client_info, no_password()).await.is_ok());
+
+    // The second factor still applies, and its challenge stays out of the URL
+    TwoFactor::set_required_roles(&[Role::Admin]).await.unwrap();
+    User::set_role(alice.user_id(), Role::Admin).await.unwrap();
+    let response = sign_in(&provider, alice_identity).await;
+    assert!(location(&response).starts_with("/login?two_factor=1&setup_required=true&"));
+    assert!(!has_session(&response));
+    let challenge_cookie = set_cookies(&response)
+        .into_iter()
+        .find(|cookie| cookie.starts_with("two_factor_challenge="))
+        .expect("the challenge is sent in a cookie");
+    assert!(challenge_cookie.contains("HttpOnly"));
+
+    let mut headers = HeaderMap::new();
+    let cookie = challenge_cookie.split(';').next().unwrap();
+    headers.insert(header::COOKIE, HeaderValue::from_str(cookie).unwrap());
+    let enrollment = auth::login_two_factor_setup(headers, Json(TwoFactorSetupRequest { challenge: String::new() })).await;
+    assert!(enrollment.is_ok());
+
+    let _ = std::fs::remove_file(&database);
+}